@@ -0,0 +1,57 @@
+//! Timeline of the TWAMP-Control messages exchanged by a
+//! [`ControlClient`](crate::ControlClient), kept when
+//! [auditing](crate::ControlClient::with_audit) so failed negotiations can be diagnosed after
+//! the fact.
+
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Whether a TWAMP-Control message was sent or received by the Control-Client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A TWAMP-Control message, as parsed or before being encoded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlEvent {
+    /// When the message was sent or received.
+    pub at: SystemTime,
+    pub direction: Direction,
+    /// Name of the message, e.g. `"Accept-Session"`.
+    pub msg_type: &'static str,
+    /// Fields of the message, in their `Debug` form.
+    pub content: String,
+}
+
+impl fmt::Display for ControlEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = self.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(
+            f,
+            "{:.6} {:?} {}: {}",
+            at.as_secs_f64(),
+            self.direction,
+            self.msg_type,
+            self.content
+        )
+    }
+}
+
+/// Messages exchanged before TWAMP-Control failed, attached as context to the error so it can be
+/// downcast to or printed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlTimeline(pub Vec<ControlEvent>);
+
+impl fmt::Display for ControlTimeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TWAMP-Control failed after:")?;
+        for event in &self.0 {
+            write!(f, "\n  {}", event)?;
+        }
+        Ok(())
+    }
+}
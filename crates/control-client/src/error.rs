@@ -0,0 +1,109 @@
+use std::{fmt, io, net::SocketAddr, time::Duration};
+
+use twamp_control::{accept::Accept, constants::Messages};
+
+/// Failures of TWAMP-Control that callers may want to tell apart, e.g. by downcasting the
+/// `anyhow::Error` returned by [`ControlClient`](crate::ControlClient).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlClientError {
+    /// Accept-Session did not arrive within the
+    /// [configured timeout](crate::ControlClient::with_accept_session_timeout).
+    SessionSetupTimedOut { elapsed: Duration },
+
+    /// The Server answered Server-Start, Accept-Session or Start-Ack, named by `msg_type`, with
+    /// something other than [`Accept::Ok`]. Callers may retry on e.g.
+    /// [`Accept::TemporaryResourceLimitation`].
+    SessionRejected {
+        msg_type: &'static str,
+        accept: Accept,
+    },
+
+    /// The Server did not send the named message within its
+    /// [read timeout](crate::ControlClient::with_read_timeout).
+    Timeout(ControlMessage),
+
+    /// No attempt to [connect](crate::ControlClient::connect) to the Server at `addr`
+    /// succeeded. `failures` holds why each one failed, in order.
+    ConnectFailed {
+        addr: SocketAddr,
+        failures: Vec<ConnectFailure>,
+    },
+}
+
+/// Why an attempt to connect to the Server failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectFailure {
+    pub kind: io::ErrorKind,
+    pub message: String,
+}
+
+impl From<&io::Error> for ConnectFailure {
+    fn from(error: &io::Error) -> Self {
+        ConnectFailure {
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Messages the Control-Client reads from the Server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlMessage {
+    ServerGreeting,
+    ServerStart,
+    AcceptSession,
+    StartAck,
+}
+
+impl ControlMessage {
+    /// Name of the message as used in tracing events and the audit timeline.
+    pub fn msg_type(self) -> &'static str {
+        match self {
+            ControlMessage::ServerGreeting => "Server Greeting",
+            ControlMessage::ServerStart => "Server-Start",
+            ControlMessage::AcceptSession => "Accept-Session",
+            ControlMessage::StartAck => "Start-Ack",
+        }
+    }
+}
+
+impl From<ControlMessage> for Messages {
+    fn from(message: ControlMessage) -> Self {
+        match message {
+            ControlMessage::ServerGreeting => Messages::ServerGreeting,
+            ControlMessage::ServerStart => Messages::ServerStart,
+            ControlMessage::AcceptSession => Messages::AcceptSession,
+            ControlMessage::StartAck => Messages::StartAck,
+        }
+    }
+}
+
+impl fmt::Display for ControlMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.msg_type())
+    }
+}
+
+impl fmt::Display for ControlClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlClientError::SessionSetupTimedOut { elapsed } => {
+                write!(f, "No Accept-Session after {:?}", elapsed)
+            }
+            ControlClientError::SessionRejected { msg_type, accept } => {
+                write!(f, "{} returned {:?}", msg_type, accept)
+            }
+            ControlClientError::Timeout(message) => write!(f, "No {} in time", message),
+            ControlClientError::ConnectFailed { addr, failures } => {
+                write!(f, "Connecting to {} failed", addr)?;
+                for (attempt, failure) in failures.iter().enumerate() {
+                    let sep = if attempt == 0 { ": " } else { ", " };
+                    write!(f, "{}attempt {}: {}", sep, attempt + 1, failure.message)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ControlClientError {}
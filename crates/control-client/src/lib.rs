@@ -1,21 +1,100 @@
+pub mod audit;
+pub mod error;
+
 use anyhow::{anyhow, Result};
+use audit::{ControlEvent, Direction};
 use deku::prelude::*;
-use std::mem::size_of;
-use std::net::IpAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use error::{ConnectFailure, ControlClientError, ControlMessage};
+use futures_util::{SinkExt, StreamExt};
+use std::fmt::Debug;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use timestamp::timestamp::{TimeStamp, TimestampFormat};
 use tokio::sync::oneshot;
+use tokio_util::codec::Framed;
 use tracing::*;
 use twamp_control::accept::Accept;
 use twamp_control::accept_session::AcceptSession;
+use twamp_control::auth::{ControlCipher, SessionKeys, SharedSecret};
+use twamp_control::codec::{ControlCodec, Frame};
+use twamp_control::compliance::Compliance;
+use twamp_control::constants::TRACING_TARGET;
 use twamp_control::request_tw_session::RequestTwSession;
-use twamp_control::security_mode::Mode;
+use twamp_control::rng::{OsRngSource, RngSource};
+use twamp_control::security_mode::{Mode, ModeExtension};
 use twamp_control::server_greeting::ServerGreeting;
 use twamp_control::server_start::ServerStart;
 use twamp_control::set_up_response::SetUpResponse;
 use twamp_control::start_ack::StartAck;
 use twamp_control::start_sessions::StartSessions;
 use twamp_control::stop_sessions::StopSessions;
+use twamp_control::stream::ControlStream;
+use twamp_control::timers::SessionTimeout;
+use twamp_runtime::net::TcpStream;
+use twamp_runtime::time::{sleep, timeout, Instant};
+use twamp_test::keys::TestKeys;
+use twamp_test::reflect_octets::ReflectOctets;
+
+/// Largest greeting Count accepted by default. RFC 4656 recommends Servers use at most this.
+pub const DEFAULT_MAX_COUNT: u32 = 32768;
+
+/// Retry Start-Sessions when the Server answers with [`Accept::TemporaryResourceLimitation`].
+///
+/// The wait before each retry starts at `backoff` and doubles after every attempt up to
+/// `max_delay`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StartRetry {
+    /// Maximum number of retries after the first Start-Sessions.
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub max_delay: Duration,
+}
+
+impl StartRetry {
+    /// Wait before retrying after `attempt` refused Start-Sessions.
+    fn delay(&self, attempt: u32) -> Duration {
+        doubled(self.backoff, attempt.saturating_sub(1), self.max_delay)
+    }
+}
+
+/// `backoff` doubled `doublings` times, at most `max_delay`.
+fn doubled(backoff: Duration, doublings: u32, max_delay: Duration) -> Duration {
+    backoff
+        .saturating_mul(2u32.saturating_pow(doublings))
+        .min(max_delay)
+}
+
+/// Retry connecting to the Server when [connecting](ControlClient::connect) fails, e.g. while
+/// a responder restarts.
+///
+/// The wait before each retry starts at `backoff`, doubles after every attempt up to
+/// `max_delay`, and is then shortened by a random amount of up to half, so that agents
+/// restarted together don't reconnect in lockstep.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectRetry {
+    /// Maximum number of connection attempts, including the first.
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub max_delay: Duration,
+}
+
+impl ConnectRetry {
+    /// Wait before retrying after `attempt` failed attempts, with `jitter` picking how much of
+    /// the second half of it to drop.
+    fn delay(&self, attempt: u32, jitter: u32) -> Duration {
+        let delay = doubled(self.backoff, attempt.saturating_sub(1), self.max_delay);
+        delay - (delay / 2).mul_f64(f64::from(jitter) / f64::from(u32::MAX))
+    }
+}
+
+/// A test session requested by the Control-Client and accepted by the Server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AcceptedSession {
+    pub request: RequestTwSession,
+    pub accept: AcceptSession,
+}
 
 /// Control-Client is responsible for initiating and handling TWAMP-Control with a Server.
 ///
@@ -24,195 +103,1367 @@ use twamp_control::stop_sessions::StopSessions;
 /// -   [Send Set-Up-Response](Self::send_set_up_response)
 /// -   [Read Server-Start](Self::read_server_start)
 /// -   [Send Request-TW-Session](Self::send_request_tw_session)
+///
+/// [`do_twamp_control`](Self::do_twamp_control) runs a single test session. Several can share
+/// one connection by calling [`set_up`](Self::set_up), then
+/// [`request_session`](Self::request_session) for each of them, and
+/// [`start_sessions`](Self::start_sessions) to start them all at once.
 #[derive(Debug)]
-pub struct ControlClient {
-    /// TCP stream on which TWAMP-Control is being used.
-    pub stream: Option<TcpStream>,
+pub struct ControlClient<S = TcpStream> {
+    /// Stream on which TWAMP-Control is being used, TCP unless wrapped, framed into messages.
+    pub stream: Option<Framed<S, ControlCodec>>,
+    /// Require the Session-Reflector to use the same port as the Session-Sender.
+    symmetric_ports: bool,
+    /// Greeting received from the Server, once read.
+    server_greeting: Option<ServerGreeting>,
+    /// Security mode chosen in Set-Up-Response.
+    mode: Mode,
+    /// Server-Start received from the Server, once read.
+    server_start: Option<ServerStart>,
+    /// Whether and how to retry a temporarily refused Start-Sessions.
+    start_retry: Option<StartRetry>,
+    /// Whether and how to retry failed connections to the Server.
+    connect_retry: Option<ConnectRetry>,
+    /// Largest greeting Count the Control-Client is willing to accept.
+    max_count: u32,
+    /// How long to wait for Accept-Session, if not indefinitely.
+    accept_session_timeout: Option<Duration>,
+    /// How long to wait for each message read from the Server, if not indefinitely.
+    read_timeouts: Vec<(ControlMessage, Duration)>,
+    /// Start-Time to request, if sessions should not start straight away.
+    start_time: Option<SystemTime>,
+    /// Request-TW-Session sent to the Server, once sent.
+    request_tw_session: Option<RequestTwSession>,
+    /// Accept-Session received from the Server, once read.
+    accept_session: Option<AcceptSession>,
+    /// Sessions accepted so far, in the order they were requested.
+    sessions: Vec<AcceptedSession>,
+    /// Secret to use a keyed mode with, if any.
+    shared_secret: Option<SharedSecret>,
+    /// Keyed mode to choose with the shared secret.
+    keyed_mode: Mode,
+    /// Modes to choose from in order of preference, if not just the keyed or unauthenticated
+    /// mode.
+    mode_preference: Option<Vec<Mode>>,
+    /// Session keys sent in the Token, once a keyed mode is chosen.
+    session_keys: Option<SessionKeys>,
+    /// Messages exchanged so far, if auditing.
+    timeline: Option<Vec<ControlEvent>>,
+    /// Source of the session keys and Client-IV.
+    rng: Arc<dyn RngSource>,
+    /// Octets the Session-Reflector is asked to reflect, if any.
+    reflect_octets: Option<ReflectOctets>,
+    /// How strictly messages from the Server are parsed.
+    compliance: Compliance,
+    /// Number of Packets to announce in Request-TW-Session, if not zero as TWAMP requires.
+    number_of_packets: Option<u32>,
+    /// Session-Reflector address to request, if not the Server's.
+    receiver_address: Option<IpAddr>,
+    /// Format the Session-Sender timestamps test packets in.
+    timestamp_format: TimestampFormat,
+    /// Padding Length to request, the padding of the test packets the Session-Sender sends.
+    padding_length: u32,
 }
 
 impl ControlClient {
+    /// A `ControlClient` running TWAMP-Control over TCP. Build one over another
+    /// [`ControlStream`] with [`Default`].
     pub fn new() -> Self {
-        Self { stream: None }
+        Self::default()
+    }
+
+    /// Connect to the Server at `addr`, retrying failed attempts as configured by
+    /// [`with_connect_retry`](ControlClient::with_connect_retry). Fails with
+    /// [`ControlClientError::ConnectFailed`] holding every attempt's error once none are left.
+    pub async fn connect(&self, addr: SocketAddr) -> Result<TcpStream> {
+        let max_attempts = self
+            .connect_retry
+            .map_or(1, |retry| retry.max_attempts.max(1));
+        let mut failures = Vec::new();
+        loop {
+            let error = match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => e,
+            };
+            failures.push(ConnectFailure::from(&error));
+            let attempt = failures.len() as u32;
+            match self.connect_retry {
+                Some(retry) if attempt < max_attempts => {
+                    let mut jitter = [0; 4];
+                    self.rng.fill_bytes(&mut jitter);
+                    let backoff = retry.delay(attempt, u32::from_be_bytes(jitter));
+                    warn!(
+                        target: TRACING_TARGET,
+                        %addr,
+                        attempt,
+                        max_attempts,
+                        ?backoff,
+                        %error,
+                        "Connecting to the Server failed, retrying"
+                    );
+                    sleep(backoff).await;
+                }
+                _ => return Err(ControlClientError::ConnectFailed { addr, failures }.into()),
+            }
+        }
+    }
+}
+
+impl<S: ControlStream> ControlClient<S> {
+    /// Refuse to start the session unless Accept-Session echoes the Sender Port back as the
+    /// Session-Reflector's port. Some middleboxes only pass test traffic whose source and
+    /// destination ports match.
+    pub fn with_symmetric_ports(mut self) -> Self {
+        self.symmetric_ports = true;
+        self
+    }
+
+    /// Retry Start-Sessions according to `start_retry` if the Server is temporarily out of
+    /// resources, rather than failing straight away.
+    pub fn with_start_retry(mut self, start_retry: StartRetry) -> Self {
+        self.start_retry = Some(start_retry);
+        self
+    }
+
+    /// Retry connecting to the Server according to `connect_retry` rather than failing on the
+    /// first refused or timed out attempt.
+    pub fn with_connect_retry(mut self, connect_retry: ConnectRetry) -> Self {
+        self.connect_retry = Some(connect_retry);
+        self
+    }
+
+    /// Refuse Servers whose greeting asks for a Count above `max_count`, bounding the cost of
+    /// key derivation in keyed modes. Defaults to [`DEFAULT_MAX_COUNT`].
+    pub fn with_max_count(mut self, max_count: u32) -> Self {
+        self.max_count = max_count;
+        self
+    }
+
+    /// Give up with [`ControlClientError::SessionSetupTimedOut`] if Accept-Session does not
+    /// arrive within `accept_session_timeout` of sending Request-TW-Session. Some Servers take
+    /// seconds to allocate reflector resources, so this is separate from other reads.
+    pub fn with_accept_session_timeout(mut self, accept_session_timeout: Duration) -> Self {
+        self.accept_session_timeout = Some(accept_session_timeout);
+        self
+    }
+
+    /// Give up with [`ControlClientError::Timeout`] if `message` does not arrive within
+    /// `read_timeout` of starting to read it, e.g. when a responder accepts the connection but
+    /// never sends its Server Greeting.
+    /// [`with_accept_session_timeout`](Self::with_accept_session_timeout) takes precedence for
+    /// Accept-Session.
+    pub fn with_read_timeout(mut self, message: ControlMessage, read_timeout: Duration) -> Self {
+        self.read_timeouts.retain(|(m, _)| *m != message);
+        self.read_timeouts.push((message, read_timeout));
+        self
+    }
+
+    /// Ask for sessions to start at `start_time` rather than as soon as Start-Sessions is sent.
+    /// [`start_sessions`](Self::start_sessions) fails if `start_time` has passed by then.
+    pub fn with_start_time(mut self, start_time: SystemTime) -> Self {
+        self.start_time = Some(start_time);
+        self
+    }
+
+    /// Use [authenticated mode](Mode::Authenticated) with `shared_secret`, refusing Servers
+    /// that do not offer it.
+    pub fn with_shared_secret(mut self, shared_secret: SharedSecret) -> Self {
+        self.shared_secret = Some(shared_secret);
+        self
+    }
+
+    /// Use [encrypted mode](Mode::Encrypted) rather than authenticated mode with the
+    /// [shared secret](Self::with_shared_secret), so TWAMP-Test packets are encrypted whole.
+    pub fn with_encryption(mut self) -> Self {
+        self.keyed_mode = Mode::Encrypted;
+        self
+    }
+
+    /// Choose the first of `modes` offered in the Server Greeting, e.g.
+    /// `[Mode::Encrypted, Mode::Authenticated, Mode::Unauthenticated]` for the strongest one
+    /// both sides support. Keyed modes are skipped without a
+    /// [shared secret](Self::with_shared_secret), and [`set_up`](Self::set_up) fails if none of
+    /// `modes` is offered. Overrides [`with_encryption`](Self::with_encryption).
+    pub fn with_mode_preference(mut self, modes: &[Mode]) -> Self {
+        self.mode_preference = Some(modes.to_vec());
+        self
+    }
+
+    /// Parse messages from the Server according to `compliance`, e.g. to talk to Servers that
+    /// put garbage in MBZ fields.
+    pub fn with_compliance(mut self, compliance: Compliance) -> Self {
+        self.compliance = compliance;
+        self
+    }
+
+    /// Announce `number_of_packets` in Request-TW-Session rather than zero, for servers also
+    /// speaking OWAMP that refuse sessions without a count. TWAMP Servers may refuse it, see
+    /// [`RequestTwSession::with_number_of_packets`].
+    pub fn with_number_of_packets(mut self, number_of_packets: u32) -> Self {
+        self.number_of_packets = Some(number_of_packets);
+        self
+    }
+
+    /// Request Session-Reflectors at `receiver_address` rather than at the Server's address.
+    /// RFC 5357 lets the Session-Reflector run on another interface or host than the Server.
+    /// It must be of the same IP version as the Session-Sender's address.
+    pub fn with_receiver_address(mut self, receiver_address: IpAddr) -> Self {
+        self.receiver_address = Some(receiver_address);
+        self
+    }
+
+    /// Refuse Servers not advertising [`ModeExtension::PtpTimestamp`] if `timestamp_format` is
+    /// PTP, as the Session-Sender will timestamp test packets in it.
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    /// Request sessions whose test packets carry `padding_length` octets of padding, so the
+    /// Server refuses those with more than it accepts and its Session-Reflectors read all of it.
+    pub fn with_padding_length(mut self, padding_length: u32) -> Self {
+        self.padding_length = padding_length;
+        self
+    }
+
+    /// Draw the session keys and Client-IV from `rng` rather than the OS CSPRNG.
+    pub fn with_rng(mut self, rng: Arc<dyn RngSource>) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Ask for `reflect_octets` in Request-TW-Session, refusing sessions whose Accept-Session
+    /// does not echo them, as Servers without RFC 6038 support leave them zero.
+    pub fn with_reflect_octets(mut self, reflect_octets: ReflectOctets) -> Self {
+        self.reflect_octets = Some(reflect_octets);
+        self
+    }
+
+    /// Keep a [timeline](Self::timeline) of every message sent and received, with its parsed
+    /// contents.
+    pub fn with_audit(mut self) -> Self {
+        self.timeline = Some(Vec::new());
+        self
     }
+
+    /// Messages exchanged so far in the order they were sent or received, empty unless
+    /// [auditing](Self::with_audit).
+    pub fn timeline(&self) -> &[ControlEvent] {
+        self.timeline.as_deref().unwrap_or_default()
+    }
+
+    /// Add a message to the timeline, if auditing.
+    fn record(&mut self, direction: Direction, msg_type: &'static str, content: &impl Debug) {
+        if let Some(timeline) = &mut self.timeline {
+            timeline.push(ControlEvent {
+                at: SystemTime::now(),
+                direction,
+                msg_type,
+                content: format!("{:?}", content),
+            });
+        }
+    }
+
+    /// Greeting received from the Server, if it has been read.
+    pub fn server_greeting(&self) -> Option<&ServerGreeting> {
+        self.server_greeting.as_ref()
+    }
+
+    /// Security mode chosen in Set-Up-Response.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Server-Start received from the Server, if it has been read.
+    pub fn server_start(&self) -> Option<&ServerStart> {
+        self.server_start.as_ref()
+    }
+
+    /// Request-TW-Session sent to the Server, if it has been sent.
+    pub fn request_tw_session(&self) -> Option<&RequestTwSession> {
+        self.request_tw_session.as_ref()
+    }
+
+    /// Accept-Session received from the Server, if it has been read.
+    pub fn accept_session(&self) -> Option<&AcceptSession> {
+        self.accept_session.as_ref()
+    }
+
+    /// Sessions accepted by the Server so far, in the order they were requested.
+    pub fn sessions(&self) -> &[AcceptedSession] {
+        &self.sessions
+    }
+
+    /// Keys protecting the test session in keyed modes, once Accept-Session has been read.
+    pub fn test_keys(&self) -> Option<TestKeys> {
+        self.session_test_keys(self.accept_session.as_ref()?)
+    }
+
+    /// Keys protecting the test session accepted with `accept_session`, in keyed modes.
+    pub fn session_test_keys(&self, accept_session: &AcceptSession) -> Option<TestKeys> {
+        let keys = self.session_keys.as_ref()?;
+        let test_keys = TestKeys::derive(&keys.aes, &keys.hmac, &accept_session.sid);
+        Some(match self.mode {
+            Mode::Encrypted => test_keys.with_encryption(),
+            _ => test_keys,
+        })
+    }
+
+    /// Decode `buf` as `msg_type` according to the [compliance](Self::with_compliance).
+    fn decode<'a, T: DekuRead<'a, bool>>(&self, buf: &'a [u8], msg_type: &str) -> Result<T> {
+        Ok(self.compliance.read(
+            buf,
+            |e| debug!(target: TRACING_TARGET, msg_type, "Ignoring non-zero MBZ: {}", e),
+        )?)
+    }
+
+    /// Reads `message` from the Server, within its [read timeout](Self::with_read_timeout), if
+    /// any. In keyed modes it is decrypted and its HMAC checked once the Server-IV is read.
+    async fn read_message(&mut self, message: ControlMessage) -> Result<Frame> {
+        let read_timeout = self
+            .read_timeouts
+            .iter()
+            .find_map(|(m, read_timeout)| (*m == message).then_some(*read_timeout));
+        let stream = self.stream.as_mut().unwrap();
+        stream.codec_mut().expect(message.into());
+        let read = match read_timeout {
+            Some(read_timeout) => match timeout(read_timeout, stream.next()).await {
+                Ok(read) => read,
+                Err(_) => {
+                    warn!(
+                        target: TRACING_TARGET,
+                        msg_type = message.msg_type(),
+                        ?read_timeout,
+                        "Timed out"
+                    );
+                    return Err(ControlClientError::Timeout(message).into());
+                }
+            },
+            None => stream.next().await,
+        };
+        let frame = read.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Server closed the connection before {}", message),
+            )
+        })??;
+        Ok(frame)
+    }
+
+    /// Sends the encoded `message` to the Server, signed and encrypted in keyed modes once
+    /// Set-Up-Response is sent.
+    async fn send_message(&mut self, message: Vec<u8>) -> Result<()> {
+        self.stream.as_mut().unwrap().send(message).await?;
+        Ok(())
+    }
+
+    /// The most preferred mode offered in `server_greeting` that can be used.
+    fn choose_mode(&self, server_greeting: &ServerGreeting) -> Result<Mode> {
+        let preference = match &self.mode_preference {
+            Some(modes) => modes.clone(),
+            None if self.shared_secret.is_some() => vec![self.keyed_mode],
+            None => vec![Mode::Unauthenticated],
+        };
+        preference
+            .iter()
+            .copied()
+            .filter(|mode| *mode != Mode::Reserved)
+            .filter(|mode| !mode.is_keyed() || self.shared_secret.is_some())
+            .find(|mode| server_greeting.has_mode(*mode))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Server offers {:?}, none of {:?}",
+                    server_greeting.modes(),
+                    preference
+                )
+            })
+    }
+
     /// Initiates TCP connection and starts the [TWAMP-Control](twamp_control) protocol with
     /// Server, handling communication until the test ends or connection is killed/stopped.
+    #[allow(clippy::too_many_arguments)]
     pub async fn do_twamp_control(
         &mut self,
-        twamp_control: TcpStream,
-        start_session_tx: oneshot::Sender<()>,
+        twamp_control: S,
+        start_session_tx: oneshot::Sender<Option<TestKeys>>,
         reflector_port_tx: oneshot::Sender<u16>,
         responder_reflect_port: u16,
         controller_port: u16,
-        reflector_timeout: u64,
+        reflector_timeout: SessionTimeout,
         twamp_test_complete_rx: oneshot::Receiver<()>,
     ) -> Result<()> {
-        self.stream = Some(twamp_control);
-        self.read_server_greeting().await?;
-        self.send_set_up_response().await?;
-        self.read_server_start().await?;
-        self.send_request_tw_session(responder_reflect_port, controller_port, reflector_timeout)
+        self.set_up(twamp_control).await?;
+        let accept_session = self
+            .request_session(responder_reflect_port, controller_port, reflector_timeout)
             .await?;
-        let accept_session = self.read_accept_session().await?;
         if accept_session.accept != Accept::Ok {
-            return Err(anyhow!("Did not receive Ok in Accept-Session"));
+            return Err(ControlClientError::SessionRejected {
+                msg_type: "Accept-Session",
+                accept: accept_session.accept,
+            }
+            .into());
         };
-
-        debug!("Responder provided port: {}", accept_session.port);
         reflector_port_tx.send(accept_session.port).unwrap();
-        self.send_start_sessions().await?;
-        let start_ack = self.read_start_ack().await?;
-        if start_ack.accept != Accept::Ok {
-            return Err(anyhow!("Start-Ack should be zero"));
-        }
-        start_session_tx.send(()).unwrap();
+        self.start_sessions().await?;
+        start_session_tx.send(self.test_keys()).unwrap();
         // testing
-        debug!(
-            "Waiting for Session-Sender to complete, Control-Client will then send Stop-Sessions."
-        );
+        debug!(target: TRACING_TARGET, "Waiting for Session-Sender to complete");
         let _ = twamp_test_complete_rx.await;
-        debug!("Received confirmation that TWAMP-Test is complete. Sending Stop-Sessions");
+        debug!(target: TRACING_TARGET, "Session-Sender complete");
         self.send_stop_sessions().await?;
         Ok(())
     }
 
+    /// Read the Server Greeting from `twamp_control` and set up the connection, so sessions can
+    /// be requested.
+    pub async fn set_up(&mut self, twamp_control: S) -> Result<()> {
+        self.stream = Some(Framed::new(twamp_control, ControlCodec::new()));
+        let server_greeting = self.read_server_greeting().await?;
+        if server_greeting.count() > self.max_count {
+            return Err(anyhow!(
+                "Server greeting Count {} is above the maximum of {}",
+                server_greeting.count(),
+                self.max_count
+            ));
+        }
+        if self.timestamp_format == TimestampFormat::Ptp
+            && !server_greeting
+                .extensions()
+                .contains(&ModeExtension::PtpTimestamp)
+        {
+            return Err(anyhow!("Server does not support PTP timestamps"));
+        }
+        self.send_set_up_response().await?;
+        let server_start = self.read_server_start().await?;
+        if *server_start.accept() != Accept::Ok {
+            return Err(ControlClientError::SessionRejected {
+                msg_type: "Server-Start",
+                accept: *server_start.accept(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Request a test session once the connection is [set up](Self::set_up), returning the
+    /// Server's Accept-Session. Accepted sessions are kept in [`sessions`](Self::sessions) until
+    /// [`start_sessions`](Self::start_sessions) starts them together.
+    pub async fn request_session(
+        &mut self,
+        session_reflector_port: u16,
+        controller_port: u16,
+        timeout: SessionTimeout,
+    ) -> Result<AcceptSession> {
+        let request_tw_session =
+            self.build_request_tw_session(session_reflector_port, controller_port, timeout)?;
+        self.request_session_with(request_tw_session).await
+    }
+
+    /// Request a test session with a Request-TW-Session built by the caller, e.g. from
+    /// [`build_request_tw_session`](Self::build_request_tw_session) with more fields set, as
+    /// [`request_session`](Self::request_session) does.
+    pub async fn request_session_with(
+        &mut self,
+        request_tw_session: RequestTwSession,
+    ) -> Result<AcceptSession> {
+        let controller_port = request_tw_session.sender_port;
+        let request_tw_session = self.send_request_tw_session(request_tw_session).await?;
+        let accept_session = self.await_accept_session().await?;
+        if accept_session.accept != Accept::Ok {
+            return Ok(accept_session);
+        }
+        if accept_session.port == 0 {
+            return Err(anyhow!(
+                "Accept-Session has no port for the Session-Reflector at {}",
+                request_tw_session.receiver_address()
+            ));
+        }
+        debug!(target: TRACING_TARGET, port = accept_session.port, "Reflector port accepted");
+        if self.symmetric_ports && accept_session.port != controller_port {
+            return Err(anyhow!(
+                "Accept-Session port {} does not match sender port {}",
+                accept_session.port,
+                controller_port
+            ));
+        }
+        if let Some(reflect_octets) = self
+            .reflect_octets
+            .filter(|r| r.octets != accept_session.reflected_octets)
+        {
+            return Err(anyhow!(
+                "Accept-Session reflected octets {:#06x} instead of {:#06x}",
+                accept_session.reflected_octets,
+                reflect_octets.octets
+            ));
+        }
+        self.sessions.push(AcceptedSession {
+            request: request_tw_session,
+            accept: accept_session.clone(),
+        });
+        Ok(accept_session)
+    }
+
+    /// Reads Accept-Session within the [Accept-Session
+    /// timeout](Self::with_accept_session_timeout), if any.
+    async fn await_accept_session(&mut self) -> Result<AcceptSession> {
+        let Some(accept_session_timeout) = self.accept_session_timeout else {
+            return self.read_accept_session().await;
+        };
+        let requested_at = Instant::now();
+        match timeout(accept_session_timeout, self.read_accept_session()).await {
+            Ok(accept_session) => accept_session,
+            Err(_) => {
+                let elapsed = requested_at.elapsed();
+                warn!(
+                    target: TRACING_TARGET,
+                    msg_type = "Accept-Session",
+                    ?elapsed,
+                    "Timed out"
+                );
+                Err(ControlClientError::SessionSetupTimedOut { elapsed }.into())
+            }
+        }
+    }
+
+    /// Sends Start-Sessions until the Server acknowledges it, retrying temporary refusals as
+    /// configured by [`with_start_retry`](Self::with_start_retry). Starts every accepted
+    /// session at once.
+    pub async fn start_sessions(&mut self) -> Result<()> {
+        if let Some(start_time) = self.start_time.filter(|t| *t < SystemTime::now()) {
+            return Err(anyhow!(
+                "Start-Time {:?} is before Start-Sessions",
+                start_time.duration_since(UNIX_EPOCH).unwrap_or_default()
+            ));
+        }
+        let mut attempt = 0;
+        loop {
+            self.send_start_sessions().await?;
+            let start_ack = self.read_start_ack().await?;
+            match (start_ack.accept, self.start_retry) {
+                (Accept::Ok, _) => return Ok(()),
+                (Accept::TemporaryResourceLimitation, Some(retry)) if attempt < retry.attempts => {
+                    attempt += 1;
+                    let backoff = retry.delay(attempt);
+                    warn!(
+                        target: TRACING_TARGET,
+                        attempt,
+                        max_attempts = retry.attempts,
+                        ?backoff,
+                        "Start-Sessions temporarily refused, retrying"
+                    );
+                    sleep(backoff).await;
+                }
+                (accept, _) => {
+                    return Err(ControlClientError::SessionRejected {
+                        msg_type: "Start-Ack",
+                        accept,
+                    }
+                    .into())
+                }
+            }
+        }
+    }
+
     /// Reads from TWAMP-Control stream assuming the bytes to be received will be of a
     /// `ServerGreeting`. Converts those bytes into a `ServerGreeting` struct and returns it.
     pub async fn read_server_greeting(&mut self) -> Result<ServerGreeting> {
-        let mut buf = [0; size_of::<ServerGreeting>()];
-        info!("Reading ServerGreeting");
-        self.stream.as_mut().unwrap().read_exact(&mut buf).await?;
-        let (_rest, server_greeting) = ServerGreeting::from_bytes((&buf, 0)).unwrap();
-        debug!("Server greeting: {:?}", server_greeting);
-        info!("Done reading ServerGreeting");
+        debug!(target: TRACING_TARGET, msg_type = "Server Greeting", "Reading");
+        let frame = self.read_message(ControlMessage::ServerGreeting).await?;
+        let server_greeting: ServerGreeting = self.decode(&frame.bytes, "Server Greeting")?;
+        trace!(target: TRACING_TARGET, msg_type = "Server Greeting", content = ?server_greeting);
+        info!(
+            target: TRACING_TARGET,
+            msg_type = "Server Greeting",
+            modes = ?server_greeting.modes(),
+            count = server_greeting.count(),
+            "Read"
+        );
+        self.record(Direction::Received, "Server Greeting", &server_greeting);
+        self.server_greeting = Some(server_greeting.clone());
         Ok(server_greeting)
     }
 
     /// Creates a `SetUpResponse`, converts to bytes and sends it out on `TWAMP-Control`.
+    ///
+    /// Chooses the most preferred mode the Server Greeting offers, see
+    /// [`with_mode_preference`](Self::with_mode_preference). In keyed modes, sends freshly
+    /// generated session keys in the Token, and everything sent afterwards is encrypted.
     pub async fn send_set_up_response(&mut self) -> Result<()> {
-        info!("Preparing to send Set-Up-Response");
-        let set_up_response = SetUpResponse::new(Mode::Unauthenticated);
-        debug!("Set-Up-Response: {:?}", set_up_response);
-        let encoded = set_up_response.unwrap().to_bytes().unwrap();
-        self.stream
-            .as_mut()
-            .unwrap()
-            .write_all(&encoded[..])
+        let server_greeting = self
+            .server_greeting
+            .clone()
+            .ok_or_else(|| anyhow!("Server Greeting has not been read"))?;
+        let mode = self.choose_mode(&server_greeting)?;
+        let mut sealed = None;
+        let set_up_response = match &self.shared_secret {
+            Some(shared_secret) if mode.is_keyed() => {
+                let key =
+                    shared_secret.derive_key(&server_greeting.salt(), server_greeting.count());
+                let session_keys = SessionKeys::random_from(&*self.rng);
+                let token = session_keys.to_token(&server_greeting.challenge(), &key);
+                let mut client_iv = [0; 16];
+                self.rng.fill_bytes(&mut client_iv);
+                let cipher = ControlCipher::new(&session_keys.aes, &client_iv);
+                sealed = Some((session_keys.clone(), cipher));
+                self.session_keys = Some(session_keys);
+                SetUpResponse::keyed(mode, shared_secret.key_id_field(), token, client_iv)
+                    .map_err(|e| anyhow!(e))?
+            }
+            _ => SetUpResponse::new(mode).map_err(|e| anyhow!(e))?,
+        };
+        self.mode = mode;
+        debug!(target: TRACING_TARGET, msg_type = "Set-Up-Response", mode = ?self.mode, "Sending");
+        trace!(target: TRACING_TARGET, msg_type = "Set-Up-Response", content = ?set_up_response);
+        self.send_message(set_up_response.to_bytes().unwrap())
             .await?;
-        info!("Set-Up-Response sent");
+        // Everything sent after Set-Up-Response is encrypted in keyed modes.
+        if let Some((keys, cipher)) = sealed {
+            self.stream
+                .as_mut()
+                .unwrap()
+                .codec_mut()
+                .seal_with(keys, cipher);
+        }
+        info!(target: TRACING_TARGET, msg_type = "Set-Up-Response", "Sent");
+        self.record(Direction::Sent, "Set-Up-Response", &set_up_response);
         Ok(())
     }
 
     /// Reads from `TWAMP-Control` stream assuming the bytes to be received will be of a
     /// `ServerStart`. Converts those bytes into a `ServerStart` struct and returns it.
     pub async fn read_server_start(&mut self) -> Result<ServerStart> {
-        let mut buf = [0; size_of::<ServerStart>()];
-        info!("Reading Server-Start");
-        self.stream.as_mut().unwrap().read_exact(&mut buf).await?;
-        let (_rest, server_start) = ServerStart::from_bytes((&buf, 0)).unwrap();
-        debug!("Server-Start: {:?}", server_start);
-        info!("Done reading Server-Start");
+        debug!(target: TRACING_TARGET, msg_type = "Server-Start", "Reading");
+        let Frame { bytes: mut buf, .. } = self.read_message(ControlMessage::ServerStart).await?;
+        // Encryption starts after the Server-IV, and only if the Server accepted the Token.
+        if let Some(keys) = self
+            .session_keys
+            .as_ref()
+            .filter(|_| buf[15] == Accept::Ok.into())
+        {
+            let mut cipher = ControlCipher::new(&keys.aes, buf[16..32].try_into().unwrap());
+            cipher.decrypt(&mut buf[32..]);
+            let keys = keys.clone();
+            self.stream
+                .as_mut()
+                .unwrap()
+                .codec_mut()
+                .open_with(keys, cipher);
+        }
+        let server_start: ServerStart = self.decode(&buf, "Server-Start")?;
+        trace!(target: TRACING_TARGET, msg_type = "Server-Start", content = ?server_start);
+        info!(target: TRACING_TARGET, msg_type = "Server-Start", "Read");
+        self.record(Direction::Received, "Server-Start", &server_start);
+        self.server_start = Some(server_start.clone());
         Ok(server_start)
     }
 
-    /// Creates a `Request-Tw-Session`, converts to bytes and sends it out on `TWAMP-Control`.
-    pub async fn send_request_tw_session(
-        &mut self,
+    /// Request-TW-Session between the ends of the TWAMP-Control connection, with the
+    /// Start-Time, octets to reflect and Number of Packets this Control-Client is configured
+    /// with. Further fields can be set on it before it is sent with
+    /// [`request_session_with`](Self::request_session_with).
+    pub fn build_request_tw_session(
+        &self,
         session_reflector_port: u16,
         controller_port: u16,
-        timeout: u64,
+        timeout: SessionTimeout,
     ) -> Result<RequestTwSession> {
-        info!("Preparing to send Request-TW-Session");
-        let stream = self.stream.as_ref().unwrap();
-        let sender_address = match stream.local_addr().unwrap().ip() {
-            IpAddr::V4(ip) => ip,
-            IpAddr::V6(ip) => panic!("da hail did v6 come from: {ip}"),
-        };
-        let receiver_address = match stream.peer_addr().unwrap().ip() {
-            IpAddr::V4(ip) => ip,
-            IpAddr::V6(ip) => panic!("da hail did v6 come from: {ip}"),
+        let stream = self
+            .stream
+            .as_ref()
+            .ok_or_else(|| anyhow!("Not connected to a Server"))?
+            .get_ref();
+        // IPv4 peers of dual-stack sockets show up as IPv4-mapped, but are sent as IPv4.
+        let sender_address = stream.local_addr()?.ip().to_canonical();
+        let receiver_address = match self.receiver_address {
+            Some(receiver_address) => receiver_address.to_canonical(),
+            None => stream.peer_addr()?.ip().to_canonical(),
         };
-        debug!(
-            "Request-TW-Session reflector port: {}",
-            session_reflector_port
-        );
-        let request_tw_session = RequestTwSession::new(
+        if sender_address.is_ipv4() != receiver_address.is_ipv4() {
+            return Err(anyhow!(
+                "Session-Reflector address {} is not of the same IP version as {}",
+                receiver_address,
+                sender_address
+            ));
+        }
+        let start_time = self
+            .start_time
+            .map(|start_time| start_time.duration_since(UNIX_EPOCH))
+            .transpose()?
+            .map(|since_epoch| TimeStamp::try_from(since_epoch).map_err(|e| anyhow!(e)))
+            .transpose()?;
+        let mut request_tw_session = RequestTwSession::new(
             sender_address,
             controller_port,
             receiver_address,
             session_reflector_port,
-            None,
+            start_time,
             timeout,
+        )
+        .with_padding_length(self.padding_length);
+        if let Some(reflect_octets) = self.reflect_octets {
+            request_tw_session = request_tw_session
+                .with_reflect_octets(reflect_octets.octets, reflect_octets.length);
+        }
+        if let Some(number_of_packets) = self.number_of_packets {
+            request_tw_session = request_tw_session.with_number_of_packets(number_of_packets);
+        }
+        Ok(request_tw_session)
+    }
+
+    /// Converts `request_tw_session` to bytes and sends it out on `TWAMP-Control`.
+    pub async fn send_request_tw_session(
+        &mut self,
+        request_tw_session: RequestTwSession,
+    ) -> Result<RequestTwSession> {
+        debug!(target: TRACING_TARGET, msg_type = "Request-TW-Session", "Sending");
+        trace!(
+            target: TRACING_TARGET,
+            msg_type = "Request-TW-Session",
+            content = ?request_tw_session
         );
-        debug!("request-tw-session: {:?}", request_tw_session);
-        let encoded = request_tw_session.to_bytes().unwrap();
-        self.stream
-            .as_mut()
-            .unwrap()
-            .write_all(&encoded[..])
+        self.send_message(request_tw_session.to_bytes().unwrap())
             .await?;
-        info!("Request-TW-Session sent");
+        info!(target: TRACING_TARGET, msg_type = "Request-TW-Session", "Sent");
+        self.record(Direction::Sent, "Request-TW-Session", &request_tw_session);
+        self.request_tw_session = Some(request_tw_session.clone());
         Ok(request_tw_session)
     }
 
     /// Reads from `TWAMP-Control` stream assuming the bytes to be received will be of a
     /// `AcceptSession`. Converts those bytes into a `AcceptSession` struct and returns it.
     pub async fn read_accept_session(&mut self) -> Result<AcceptSession> {
-        let mut buf = [0; size_of::<AcceptSession>()];
-        info!("Reading Accept-Session");
-        self.stream.as_mut().unwrap().read_exact(&mut buf).await?;
-        let (_rest, accept_session) = AcceptSession::from_bytes((&buf, 0)).unwrap();
-        debug!("Accept-Session: {:?}", accept_session);
-        info!("Read Accept-Session");
-
+        debug!(target: TRACING_TARGET, msg_type = "Accept-Session", "Reading");
+        let frame = self.read_message(ControlMessage::AcceptSession).await?;
+        let accept_session: AcceptSession = self.decode(&frame.bytes, "Accept-Session")?;
+        trace!(target: TRACING_TARGET, msg_type = "Accept-Session", content = ?accept_session);
+        info!(target: TRACING_TARGET, msg_type = "Accept-Session", "Read");
+        self.record(Direction::Received, "Accept-Session", &accept_session);
+        self.accept_session = Some(accept_session.clone());
         Ok(accept_session)
     }
 
     /// Creates a `Start-Sessions`, converts to bytes and sends it out on `TWAMP-Control`.
     pub async fn send_start_sessions(&mut self) -> Result<()> {
-        info!("Preparing to send Start-Sessions");
         let start_sessions = StartSessions::new();
-        debug!("Start-Sessions: {:?}", start_sessions);
-        let encoded = start_sessions.to_bytes().unwrap();
-        self.stream
-            .as_mut()
-            .unwrap()
-            .write_all(&encoded[..])
+        debug!(target: TRACING_TARGET, msg_type = "Start-Sessions", "Sending");
+        trace!(target: TRACING_TARGET, msg_type = "Start-Sessions", content = ?start_sessions);
+        self.send_message(start_sessions.to_bytes().unwrap())
             .await?;
-        info!("Start-Sessions sent");
+        info!(target: TRACING_TARGET, msg_type = "Start-Sessions", "Sent");
+        self.record(Direction::Sent, "Start-Sessions", &start_sessions);
         Ok(())
     }
 
     /// Reads from `TWAMP-Control` stream assuming the bytes to be received will be of a
     /// `Start-Ack`. Converts those bytes into a `Start-Ack` struct and returns it.
     pub async fn read_start_ack(&mut self) -> Result<StartAck> {
-        let mut buf = [0; size_of::<StartAck>()];
-        info!("Reading Start-Ack");
-        self.stream.as_mut().unwrap().read_exact(&mut buf).await?;
-        let (_rest, start_ack) = StartAck::from_bytes((&buf, 0)).unwrap();
-        debug!("Start-Ack: {:?}", start_ack);
-        info!("Done reading Start-Ack");
+        debug!(target: TRACING_TARGET, msg_type = "Start-Ack", "Reading");
+        let frame = self.read_message(ControlMessage::StartAck).await?;
+        let start_ack: StartAck = self.decode(&frame.bytes, "Start-Ack")?;
+        trace!(target: TRACING_TARGET, msg_type = "Start-Ack", content = ?start_ack);
+        info!(target: TRACING_TARGET, msg_type = "Start-Ack", "Read");
+        self.record(Direction::Received, "Start-Ack", &start_ack);
         Ok(start_ack)
     }
 
-    /// Creates a `Stop-Sessions`, converts to bytes and sends it out on `TWAMP-Control`.
+    /// Creates a `Stop-Sessions` for every [accepted session](Self::sessions), converts to bytes
+    /// and sends it out on `TWAMP-Control`.
     pub async fn send_stop_sessions(&mut self) -> Result<()> {
-        info!("Preparing to send Stop-Sessions");
-        let stop_sessions = StopSessions::new(Accept::Ok);
-        debug!("Stop-Sessions: {:?}", stop_sessions);
-        let encoded = stop_sessions.to_bytes().unwrap();
-        self.stream
-            .as_mut()
-            .unwrap()
-            .write_all(&encoded[..])
-            .await?;
-        info!("Stop-Sessions sent");
+        let stop_sessions =
+            StopSessions::new(Accept::Ok).with_number_of_sessions(self.sessions.len() as u32);
+        debug!(target: TRACING_TARGET, msg_type = "Stop-Sessions", "Sending");
+        trace!(target: TRACING_TARGET, msg_type = "Stop-Sessions", content = ?stop_sessions);
+        self.send_message(stop_sessions.to_bytes().unwrap()).await?;
+        info!(target: TRACING_TARGET, msg_type = "Stop-Sessions", "Sent");
+        self.record(Direction::Sent, "Stop-Sessions", &stop_sessions);
         Ok(())
     }
 }
 
-impl Default for ControlClient {
+impl<S> Default for ControlClient<S> {
     /// Construct an empty `ControlClient` with no context.
     fn default() -> Self {
-        ControlClient { stream: None }
+        ControlClient {
+            stream: None,
+            symmetric_ports: false,
+            server_greeting: None,
+            mode: Mode::Unauthenticated,
+            server_start: None,
+            start_retry: None,
+            connect_retry: None,
+            max_count: DEFAULT_MAX_COUNT,
+            accept_session_timeout: None,
+            read_timeouts: Vec::new(),
+            start_time: None,
+            request_tw_session: None,
+            accept_session: None,
+            sessions: Vec::new(),
+            shared_secret: None,
+            keyed_mode: Mode::Authenticated,
+            mode_preference: None,
+            session_keys: None,
+            timeline: None,
+            rng: Arc::new(OsRngSource),
+            reflect_octets: None,
+            compliance: Compliance::Strict,
+            number_of_packets: None,
+            receiver_address: None,
+            timestamp_format: TimestampFormat::Ntp,
+            padding_length: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use twamp_runtime::net::TcpListener;
+    use twamp_runtime::task::spawn;
+
+    #[tokio::test]
+    async fn accept_session_times_out() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        let mut handshake = ServerGreeting::new(&[Mode::Unauthenticated])
+            .to_bytes()
+            .unwrap();
+        handshake.extend(
+            ServerStart::new(Accept::Ok, Duration::ZERO)
+                .to_bytes()
+                .unwrap(),
+        );
+        server.write_all(&handshake).await.unwrap();
+
+        let (start_session_tx, _start_session_rx) = oneshot::channel();
+        let (reflector_port_tx, _reflector_port_rx) = oneshot::channel();
+        let (_twamp_test_complete_tx, twamp_test_complete_rx) = oneshot::channel();
+        let error = ControlClient::new()
+            .with_accept_session_timeout(Duration::from_millis(50))
+            .do_twamp_control(
+                stream,
+                start_session_tx,
+                reflector_port_tx,
+                0,
+                0,
+                SessionTimeout::DEFAULT,
+                twamp_test_complete_rx,
+            )
+            .await
+            .unwrap_err();
+        let Ok(ControlClientError::SessionSetupTimedOut { elapsed }) = error.downcast() else {
+            panic!("Accept-Session did not time out");
+        };
+        assert!(elapsed >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn chooses_strongest_mode_both_support() {
+        let server_greeting = ServerGreeting::new(&[Mode::Unauthenticated, Mode::Authenticated]);
+        let preference = [Mode::Encrypted, Mode::Authenticated, Mode::Unauthenticated];
+        let control_client = ControlClient::new().with_mode_preference(&preference);
+        assert_eq!(
+            control_client.choose_mode(&server_greeting).unwrap(),
+            Mode::Unauthenticated
+        );
+        let control_client =
+            control_client.with_shared_secret(SharedSecret::new("client", b"secret").unwrap());
+        assert_eq!(
+            control_client.choose_mode(&server_greeting).unwrap(),
+            Mode::Authenticated
+        );
+        let server_greeting = ServerGreeting::new(&[Mode::Encrypted]);
+        assert!(ControlClient::new()
+            .with_mode_preference(&preference)
+            .choose_mode(&server_greeting)
+            .is_err());
+        assert!(ControlClient::new().choose_mode(&server_greeting).is_err());
+    }
+
+    #[tokio::test]
+    async fn server_greeting_times_out() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (_server, _) = listener.accept().await.unwrap();
+
+        let error = ControlClient::new()
+            .with_read_timeout(ControlMessage::ServerGreeting, Duration::from_millis(50))
+            .set_up(stream)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast::<ControlClientError>().unwrap(),
+            ControlClientError::Timeout(ControlMessage::ServerGreeting)
+        );
+    }
+
+    #[tokio::test]
+    async fn start_ack_times_out() {
+        let mut replies = ServerStart::new(Accept::Ok, Duration::ZERO)
+            .to_bytes()
+            .unwrap();
+        replies.extend(AcceptSession::new(Accept::Ok, 2, 0, 0).to_bytes().unwrap());
+        let (control_client, _server) = connect_to_replies(replies).await.unwrap();
+        let mut control_client =
+            control_client.with_read_timeout(ControlMessage::StartAck, Duration::from_millis(50));
+        control_client
+            .request_session(2, 1, SessionTimeout::DEFAULT)
+            .await
+            .unwrap();
+        let error = control_client.start_sessions().await.unwrap_err();
+        assert_eq!(
+            error.downcast::<ControlClientError>().unwrap(),
+            ControlClientError::Timeout(ControlMessage::StartAck)
+        );
+    }
+
+    #[tokio::test]
+    async fn audit_records_timeline() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        let mut handshake = ServerGreeting::new(&[Mode::Unauthenticated])
+            .to_bytes()
+            .unwrap();
+        handshake.extend(
+            ServerStart::new(Accept::Ok, Duration::ZERO)
+                .to_bytes()
+                .unwrap(),
+        );
+        handshake.extend(
+            AcceptSession::new(Accept::NotSupported, 0, 0, 0)
+                .to_bytes()
+                .unwrap(),
+        );
+        server.write_all(&handshake).await.unwrap();
+
+        let mut control_client = ControlClient::new().with_audit();
+        control_client.set_up(stream).await.unwrap();
+        let accept_session = control_client
+            .request_session(2, 1, SessionTimeout::DEFAULT)
+            .await
+            .unwrap();
+        assert_eq!(accept_session.accept, Accept::NotSupported);
+        let timeline: Vec<_> = control_client
+            .timeline()
+            .iter()
+            .map(|event| (event.direction, event.msg_type))
+            .collect();
+        assert_eq!(
+            timeline,
+            [
+                (Direction::Received, "Server Greeting"),
+                (Direction::Sent, "Set-Up-Response"),
+                (Direction::Received, "Server-Start"),
+                (Direction::Sent, "Request-TW-Session"),
+                (Direction::Received, "Accept-Session"),
+            ]
+        );
+        assert!(control_client.timeline()[4]
+            .content
+            .contains("NotSupported"));
+    }
+
+    /// Set up a connection to a Server that sends `replies` after its greeting, returning the
+    /// Server's end along with the Control-Client.
+    async fn connect_to_replies(replies: Vec<u8>) -> Result<(ControlClient, TcpStream)> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        let mut handshake = ServerGreeting::new(&[Mode::Unauthenticated])
+            .to_bytes()
+            .unwrap();
+        handshake.extend(replies);
+        server.write_all(&handshake).await.unwrap();
+        let mut control_client = ControlClient::new();
+        control_client.set_up(stream).await?;
+        Ok((control_client, server))
+    }
+
+    #[tokio::test]
+    async fn sends_request_built_by_caller() {
+        let mut replies = ServerStart::new(Accept::Ok, Duration::ZERO)
+            .to_bytes()
+            .unwrap();
+        replies.extend(AcceptSession::new(Accept::Ok, 2, 0, 0).to_bytes().unwrap());
+        let (mut control_client, mut server) = connect_to_replies(replies).await.unwrap();
+        let request_tw_session = control_client
+            .build_request_tw_session(2, 1, SessionTimeout::DEFAULT)
+            .unwrap()
+            .with_padding_length(64)
+            .with_dscp(46);
+        let accept_session = control_client
+            .request_session_with(request_tw_session)
+            .await
+            .unwrap();
+        assert_eq!(accept_session.accept, Accept::Ok);
+
+        let mut buf = [0u8; 164 + 112];
+        server.read_exact(&mut buf).await.unwrap();
+        let (_rest, sent) = RequestTwSession::from_bytes((&buf[164..], 0)).unwrap();
+        assert_eq!(sent.padding_length, 64);
+        assert_eq!(sent.dscp(), 46);
+        assert_eq!(control_client.sessions()[0].request, sent);
+    }
+
+    #[tokio::test]
+    async fn requests_configured_padding_length() {
+        let replies = ServerStart::new(Accept::Ok, Duration::ZERO)
+            .to_bytes()
+            .unwrap();
+        let (control_client, _server) = connect_to_replies(replies).await.unwrap();
+        let request_tw_session = control_client
+            .with_padding_length(1400)
+            .build_request_tw_session(2, 1, SessionTimeout::DEFAULT)
+            .unwrap();
+        assert_eq!(request_tw_session.padding_length, 1400);
+    }
+
+    /// TCP wrapped the way a TLS stream would be, counting the bytes written through it.
+    #[derive(Debug)]
+    struct Wrapped(TcpStream, usize);
+
+    impl tokio::io::AsyncRead for Wrapped {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::pin::Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl tokio::io::AsyncWrite for Wrapped {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            let written = std::pin::Pin::new(&mut self.0).poll_write(cx, buf);
+            if let std::task::Poll::Ready(Ok(n)) = written {
+                self.1 += n;
+            }
+            written
+        }
+
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::pin::Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::pin::Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+
+    impl ControlStream for Wrapped {
+        fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+            self.0.local_addr()
+        }
+
+        fn peer_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+            self.0.peer_addr()
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_over_a_wrapped_stream() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        let mut handshake = ServerGreeting::new(&[Mode::Unauthenticated])
+            .to_bytes()
+            .unwrap();
+        handshake.extend(
+            ServerStart::new(Accept::Ok, Duration::ZERO)
+                .to_bytes()
+                .unwrap(),
+        );
+        handshake.extend(AcceptSession::new(Accept::Ok, 2, 0, 0).to_bytes().unwrap());
+        server.write_all(&handshake).await.unwrap();
+
+        let mut control_client = ControlClient::<Wrapped>::default();
+        control_client.set_up(Wrapped(stream, 0)).await.unwrap();
+        let accept_session = control_client
+            .request_session(2, 1, SessionTimeout::DEFAULT)
+            .await
+            .unwrap();
+        assert_eq!(accept_session.accept, Accept::Ok);
+        assert_eq!(
+            control_client.stream.as_ref().unwrap().get_ref().1,
+            164 + 112
+        );
+        assert_eq!(
+            control_client.sessions()[0].request.receiver_address(),
+            Ipv4Addr::LOCALHOST
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_server_start_carries_accept() {
+        let server_start = ServerStart::new(Accept::Failure, Duration::ZERO);
+        let error = connect_to_replies(server_start.to_bytes().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast::<ControlClientError>().unwrap(),
+            ControlClientError::SessionRejected {
+                msg_type: "Server-Start",
+                accept: Accept::Failure
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_start_sessions_carries_accept() {
+        let mut replies = ServerStart::new(Accept::Ok, Duration::ZERO)
+            .to_bytes()
+            .unwrap();
+        replies.extend(AcceptSession::new(Accept::Ok, 2, 0, 0).to_bytes().unwrap());
+        replies.extend(
+            StartAck::new(Accept::TemporaryResourceLimitation)
+                .to_bytes()
+                .unwrap(),
+        );
+        let (mut control_client, _server) = connect_to_replies(replies).await.unwrap();
+        control_client
+            .request_session(2, 1, SessionTimeout::DEFAULT)
+            .await
+            .unwrap();
+        let error = control_client.start_sessions().await.unwrap_err();
+        assert_eq!(
+            error.downcast::<ControlClientError>().unwrap(),
+            ControlClientError::SessionRejected {
+                msg_type: "Start-Ack",
+                accept: Accept::TemporaryResourceLimitation
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn reflect_octets_not_echoed_are_refused() {
+        let mut replies = ServerStart::new(Accept::Ok, Duration::ZERO)
+            .to_bytes()
+            .unwrap();
+        replies.extend(AcceptSession::new(Accept::Ok, 2, 0, 0).to_bytes().unwrap());
+        let (control_client, _server) = connect_to_replies(replies).await.unwrap();
+        let mut control_client = control_client.with_reflect_octets(ReflectOctets::new(0xabcd, 8));
+        assert!(control_client
+            .request_session(2, 1, SessionTimeout::DEFAULT)
+            .await
+            .is_err());
+        assert!(control_client.sessions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ptp_timestamps_need_server_support() {
+        for (extensions, supported) in [
+            (Vec::new(), false),
+            (vec![ModeExtension::PtpTimestamp], true),
+        ] {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let stream = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (mut server, _) = listener.accept().await.unwrap();
+            let mut handshake = ServerGreeting::new(&[Mode::Unauthenticated])
+                .with_extensions(&extensions)
+                .to_bytes()
+                .unwrap();
+            handshake.extend(
+                ServerStart::new(Accept::Ok, Duration::ZERO)
+                    .to_bytes()
+                    .unwrap(),
+            );
+            server.write_all(&handshake).await.unwrap();
+            let mut control_client =
+                ControlClient::new().with_timestamp_format(TimestampFormat::Ptp);
+            assert_eq!(control_client.set_up(stream).await.is_ok(), supported);
+        }
+    }
+
+    #[tokio::test]
+    async fn start_time_before_start_sessions_is_refused() {
+        let server_start = ServerStart::new(Accept::Ok, Duration::ZERO);
+        let (control_client, _server) = connect_to_replies(server_start.to_bytes().unwrap())
+            .await
+            .unwrap();
+        let mut control_client = control_client.with_start_time(SystemTime::now());
+        assert!(control_client.start_sessions().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn several_sessions_start_together() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        let server = spawn(async move {
+            let mut handshake = ServerGreeting::new(&[Mode::Unauthenticated])
+                .to_bytes()
+                .unwrap();
+            handshake.extend(
+                ServerStart::new(Accept::Ok, Duration::ZERO)
+                    .to_bytes()
+                    .unwrap(),
+            );
+            server.write_all(&handshake).await.unwrap();
+            let mut set_up_response = [0u8; 164];
+            server.read_exact(&mut set_up_response).await.unwrap();
+            for port in [2, 4] {
+                let mut request_tw_session = [0u8; 112];
+                server.read_exact(&mut request_tw_session).await.unwrap();
+                let mut accept_session = AcceptSession::new(Accept::Ok, port, 0, 0);
+                accept_session.sid = [port as u8; 16];
+                server
+                    .write_all(&accept_session.to_bytes().unwrap())
+                    .await
+                    .unwrap();
+            }
+            let mut start_sessions = [0u8; 32];
+            server.read_exact(&mut start_sessions).await.unwrap();
+            server
+                .write_all(&StartAck::new(Accept::Ok).to_bytes().unwrap())
+                .await
+                .unwrap();
+            start_sessions
+        });
+
+        let mut control_client = ControlClient::new();
+        control_client.set_up(stream).await.unwrap();
+        for (reflector_port, sender_port) in [(2, 1), (4, 3)] {
+            let accept_session = control_client
+                .request_session(reflector_port, sender_port, SessionTimeout::DEFAULT)
+                .await
+                .unwrap();
+            assert_eq!(accept_session.port, reflector_port);
+        }
+        control_client.start_sessions().await.unwrap();
+        assert_eq!(
+            server.await.unwrap().to_vec(),
+            StartSessions::new().to_bytes().unwrap()
+        );
+        let sessions = control_client.sessions();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[1].request.sender_port, 3);
+        assert_eq!(sessions[1].accept.sid, [4; 16]);
+        assert_eq!(control_client.session_test_keys(&sessions[0].accept), None);
+    }
+
+    #[tokio::test]
+    async fn wrong_hmac_on_accept_session_is_rejected() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        let shared_secret = SharedSecret::new("probe", "secret").unwrap();
+        let server_secret = shared_secret.clone();
+        let server = spawn(async move {
+            let greeting = ServerGreeting::new(&[Mode::Authenticated]);
+            server
+                .write_all(&greeting.to_bytes().unwrap())
+                .await
+                .unwrap();
+            let mut set_up_response = [0u8; 164];
+            server.read_exact(&mut set_up_response).await.unwrap();
+            let (_rest, set_up_response) =
+                SetUpResponse::from_bytes((&set_up_response, 0)).unwrap();
+            let key = server_secret.derive_key(&greeting.salt(), greeting.count());
+            let (_challenge, keys) = SessionKeys::from_token(set_up_response.token(), &key);
+            let server_start = ServerStart::new(Accept::Ok, Duration::ZERO);
+            let mut cipher = ControlCipher::new(&keys.aes, server_start.server_iv());
+            let mut encoded = server_start.to_bytes().unwrap();
+            cipher.encrypt(&mut encoded[32..]);
+            server.write_all(&encoded).await.unwrap();
+            let mut request_tw_session = [0u8; 112];
+            server.read_exact(&mut request_tw_session).await.unwrap();
+            // Encrypted, but with the HMAC field left zero.
+            let mut accept_session = AcceptSession::new(Accept::Ok, 2, 0, 0).to_bytes().unwrap();
+            cipher.encrypt(&mut accept_session);
+            server.write_all(&accept_session).await.unwrap();
+            server
+        });
+
+        let mut control_client = ControlClient::new().with_shared_secret(shared_secret);
+        control_client.set_up(stream).await.unwrap();
+        let error = control_client
+            .request_session(2, 1, SessionTimeout::DEFAULT)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("wrong HMAC"));
+        assert!(control_client.sessions().is_empty());
+        drop(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn connect_retries_then_reports_every_failure() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let client = ControlClient::new().with_connect_retry(ConnectRetry {
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+        });
+        let error = client.connect(addr).await.unwrap_err();
+        match error.downcast_ref::<ControlClientError>() {
+            Some(ControlClientError::ConnectFailed { addr: a, failures }) => {
+                assert_eq!(*a, addr);
+                assert_eq!(failures.len(), 3);
+                assert!(failures
+                    .iter()
+                    .all(|f| f.kind == std::io::ErrorKind::ConnectionRefused));
+            }
+            other => panic!("Unexpected error {:?}", other),
+        }
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        assert!(client.connect(listener.local_addr().unwrap()).await.is_ok());
+    }
+
+    #[test]
+    fn start_backoff_doubles_up_to_max_delay() {
+        let retry = StartRetry {
+            attempts: u32::MAX,
+            backoff: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+        };
+        assert_eq!(retry.delay(1), Duration::from_millis(100));
+        assert_eq!(retry.delay(3), Duration::from_millis(400));
+        assert_eq!(retry.delay(9), Duration::from_millis(500));
+        assert_eq!(retry.delay(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn connect_backoff_doubles_up_to_max_delay_with_jitter() {
+        let retry = ConnectRetry {
+            max_attempts: 10,
+            backoff: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+        };
+        assert_eq!(retry.delay(1, 0), Duration::from_millis(100));
+        assert_eq!(retry.delay(3, 0), Duration::from_millis(400));
+        assert_eq!(retry.delay(9, 0), Duration::from_millis(500));
+        assert_eq!(retry.delay(9, u32::MAX), Duration::from_millis(250));
+        assert!(retry.delay(1, u32::MAX / 2) < Duration::from_millis(100));
     }
 }
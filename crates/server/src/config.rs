@@ -0,0 +1,125 @@
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+use twamp_control::{
+    compliance::Compliance, request_tw_session::RequestTwSession, server_greeting::ChallengePolicy,
+    timers::Servwait,
+};
+use twamp_test::{constants::ETHERNET_MTU, twamp_test_unauth::TwampTestPacketUnauth};
+
+use crate::{policy::SessionPolicy, secrets::SecretStore};
+
+/// How a [`Server`](crate::Server) answers Start-Sessions once its sessions have started.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DuplicateStartSessions {
+    /// Answer with another Start-Ack `Ok`, leaving the sessions running.
+    #[default]
+    Acknowledge,
+
+    /// Answer with Start-Ack `Failure` and close the connection, ending the sessions.
+    Refuse,
+}
+
+/// Behaviour of a [`Server`](crate::Server) that is not negotiated over TWAMP-Control.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerConfig {
+    /// How the Challenge and Salt of the Server Greeting are filled in.
+    pub challenge_policy: ChallengePolicy,
+
+    /// Request-TW-Sessions asking for more padding, or for more octets to be reflected, than
+    /// this many bytes are refused with `NotSupported`. Defaults to what fits in a single
+    /// 1500 byte MTU over IPv4.
+    pub max_padding_length: u32,
+
+    /// Secrets that Control-Clients may use keyed modes with, by KeyID. A single
+    /// [`SharedSecret`](twamp_control::auth::SharedSecret) is a store of its own. Only
+    /// unauthenticated mode is offered without one.
+    pub secrets: Option<Arc<dyn SecretStore>>,
+
+    /// How long after the first accepted Accept-Session to wait for Start-Sessions. The
+    /// connection is closed and the sessions' ports released if it does not arrive in time.
+    /// Waits as long as the Control-Client keeps the connection open if `None`.
+    pub start_sessions_deadline: Option<Duration>,
+
+    /// Decides whether to accept each Request-TW-Session that TWAMP allows and that fits
+    /// within this config, e.g. [`SessionLimits`](crate::policy::SessionLimits). All of them
+    /// are accepted if `None`.
+    pub session_policy: Option<Arc<dyn SessionPolicy>>,
+
+    /// What to do when Start-Sessions arrives again after the sessions have started.
+    pub duplicate_start_sessions: DuplicateStartSessions,
+
+    /// SERVWAIT of [RFC 5357](https://datatracker.ietf.org/doc/html/rfc5357#section-3.1): how
+    /// long to wait for the next TWAMP-Control message until the sessions start. The connection
+    /// is closed and any sessions' ports released if none arrives in time. Defaults to the
+    /// RFC's 900 seconds, waits as long as the Control-Client keeps the connection open if
+    /// `None`.
+    pub servwait: Option<Servwait>,
+
+    /// How strictly TWAMP-Control messages from Control-Clients are parsed. Lenient Servers
+    /// also accept Request-TW-Sessions with fields TWAMP requires to be zero set, such as
+    /// Number of Packets.
+    pub compliance: Compliance,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            challenge_policy: ChallengePolicy::default(),
+            max_padding_length: TwampTestPacketUnauth::max_padding_length(
+                ETHERNET_MTU,
+                Ipv4Addr::UNSPECIFIED.into(),
+            ) as u32,
+            secrets: None,
+            start_sessions_deadline: None,
+            session_policy: None,
+            duplicate_start_sessions: DuplicateStartSessions::default(),
+            servwait: Some(Servwait::DEFAULT),
+            compliance: Compliance::Strict,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Whether the session described by `request_tw_session` fits within this config.
+    pub fn accepts(&self, request_tw_session: &RequestTwSession) -> bool {
+        request_tw_session.padding_length <= self.max_padding_length
+            && u32::from(request_tw_session.length_of_padding_to_reflect())
+                <= self.max_padding_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use twamp_control::timers::SessionTimeout;
+
+    fn request_with_padding(padding_length: u32) -> RequestTwSession {
+        let mut request = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            0,
+            Ipv4Addr::LOCALHOST,
+            0,
+            None,
+            SessionTimeout::DEFAULT,
+        );
+        request.padding_length = padding_length;
+        request
+    }
+
+    #[test]
+    fn default_accepts_padding_within_mtu() {
+        let config = ServerConfig::default();
+        assert!(config.accepts(&request_with_padding(0)));
+        assert!(config.accepts(&request_with_padding(1458)));
+        assert!(!config.accepts(&request_with_padding(1459)));
+    }
+
+    #[test]
+    fn refuses_padding_above_configured_limit() {
+        let config = ServerConfig {
+            max_padding_length: 100,
+            ..Default::default()
+        };
+        assert!(!config.accepts(&request_with_padding(101)));
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt;
+
+use twamp_control::command_number::CommandNumber;
+
+/// Failures of TWAMP-Control that callers may want to tell apart, e.g. by downcasting the
+/// `anyhow::Error` returned by [`Server`](crate::Server).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerError {
+    /// The Control-Client sent a Command Number that is not defined.
+    UnknownCommand(u8),
+
+    /// The Control-Client sent a command TWAMP does not use, e.g. OWAMP's Fetch-Session, or one
+    /// that is out of place, e.g. Stop-Sessions before Start-Sessions.
+    UnexpectedCommand(CommandNumber),
+
+    /// Start-Sessions did not arrive within the
+    /// [deadline](crate::config::ServerConfig::start_sessions_deadline) after a session was
+    /// accepted.
+    StartSessionsExpired,
+
+    /// No TWAMP-Control message arrived within [SERVWAIT](crate::config::ServerConfig::servwait)
+    /// before the sessions started.
+    ServwaitExpired,
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::UnknownCommand(command) => write!(f, "Unknown command {}", command),
+            ServerError::UnexpectedCommand(command) => write!(f, "Unexpected {:?}", command),
+            ServerError::StartSessionsExpired => {
+                write!(f, "Start-Sessions did not arrive in time")
+            }
+            ServerError::ServwaitExpired => {
+                write!(f, "No TWAMP-Control message within SERVWAIT")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
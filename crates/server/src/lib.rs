@@ -1,213 +1,1541 @@
-use anyhow::{anyhow, Result};
+pub mod config;
+pub mod error;
+pub mod policy;
+pub mod secrets;
+
+use anyhow::{anyhow, Context, Result};
+use config::{DuplicateStartSessions, ServerConfig};
 use deku::prelude::*;
-use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::oneshot;
+use error::ServerError;
+use futures_util::{SinkExt, StreamExt};
+use policy::SessionRequest;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::select;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_util::codec::Framed;
 use tracing::*;
 use twamp_control::accept::Accept;
 use twamp_control::accept_session::AcceptSession;
-use twamp_control::constants::Messages;
+use twamp_control::auth::{ControlCipher, SessionKeys};
+use twamp_control::codec::{CodecError, ControlCodec, Frame};
+use twamp_control::compliance::Compliance;
+use twamp_control::constants::{Messages, TRACING_TARGET};
 use twamp_control::request_tw_session::RequestTwSession;
-use twamp_control::security_mode::Mode;
+use twamp_control::rng::{OsRngSource, RngSource};
+use twamp_control::security_mode::{Mode, ModeExtension};
 use twamp_control::server_start::ServerStart;
 use twamp_control::start_ack::StartAck;
 use twamp_control::start_sessions::StartSessions;
 use twamp_control::stop_sessions::StopSessions;
+use twamp_control::stream::ControlStream;
 use twamp_control::{server_greeting::ServerGreeting, set_up_response::SetUpResponse};
+use twamp_runtime::net::TcpStream;
+use twamp_runtime::time::{timeout_at, Instant};
+use twamp_test::keys::TestKeys;
 
 /// Server is responsible for handling incoming [TWAMP-Control](twamp_control) connection from a
 /// Control-Client.
 #[derive(Debug)]
-pub struct Server {
-    socket: TcpStream,
+pub struct Server<S = TcpStream> {
+    /// Stream TWAMP-Control is served on, TCP unless wrapped, framed into messages.
+    socket: Framed<S, ControlCodec>,
+    config: ServerConfig,
     server_greeting: Option<ServerGreeting>,
     set_up_response: Option<SetUpResponse>,
     server_start: Option<ServerStart>,
-    request_tw_session: Option<RequestTwSession>,
-    accept_session: Option<AcceptSession>,
+    /// Sessions accepted so far, in the order they were requested.
+    sessions: Vec<AcceptSession>,
     start_sessions: Option<StartSessions>,
     start_ack: Option<StartAck>,
+    /// Session keys from the Token, in keyed modes.
+    session_keys: Option<SessionKeys>,
+    /// Source of the Challenge, Salt, Server-IV and SIDs.
+    rng: Arc<dyn RngSource>,
+    /// Set to `true` to stop serving, see [`with_shutdown`](Self::with_shutdown).
+    shutdown: Option<watch::Receiver<bool>>,
 }
 
-impl Server {
-    fn up_next(&self) -> Messages {
-        if self.set_up_response.is_none() {
-            Messages::SetUpResponse
-        } else if self.request_tw_session.is_none() {
-            Messages::RequestTwSession
-        } else if self.start_sessions.is_none() {
-            Messages::StartSessions
-        } else if self.start_ack.is_some() {
-            Messages::StopSessions
-        } else {
-            panic!("Next message to expect should be defined");
+impl<S: ControlStream> Server<S> {
+    /// Whether `message` may be read now. After Set-Up-Response, the Control-Client may request
+    /// sessions, then start them, then stop them or send Start-Sessions again. Other commands
+    /// close the connection.
+    fn check_command(&self, message: Messages) -> Result<()> {
+        let started = self.start_ack.is_some();
+        let expected = match message {
+            Messages::SetUpResponse => self.set_up_response.is_none(),
+            Messages::RequestTwSession => !started,
+            Messages::StartSessions => !self.sessions.is_empty(),
+            Messages::StopSessions => started,
+            _ => false,
+        };
+        if expected {
+            return Ok(());
         }
+        let command = message
+            .command_number()
+            .expect("Only commands are read after Set-Up-Response");
+        warn!(target: TRACING_TARGET, ?command, started, "Unexpected command");
+        Err(ServerError::UnexpectedCommand(command).into())
     }
 
-    pub fn new(socket: TcpStream) -> Self {
+    pub fn new(socket: S) -> Self {
+        // Set-Up-Response is the only message without a Command Number the Server reads.
+        let mut codec = ControlCodec::new();
+        codec.expect(Messages::SetUpResponse);
         Server {
-            socket,
+            socket: Framed::new(socket, codec),
+            config: ServerConfig::default(),
             server_greeting: None,
             set_up_response: None,
             server_start: None,
-            request_tw_session: None,
-            accept_session: None,
+            sessions: Vec::new(),
             start_sessions: None,
             start_ack: None,
+            session_keys: None,
+            rng: Arc::new(OsRngSource),
+            shutdown: None,
+        }
+    }
+
+    /// Use the provided config instead of [`ServerConfig::default`].
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Draw the Challenge, Salt, Server-IV and SIDs from `rng` rather than the OS CSPRNG.
+    pub fn with_rng(mut self, rng: Arc<dyn RngSource>) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Stop serving once `shutdown` is `true`, sending Stop-Sessions first if sessions were
+    /// started. See [`stop_all_sessions`](Self::stop_all_sessions).
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Security modes offered in the Server Greeting.
+    fn modes(&self) -> Vec<Mode> {
+        match self.config.secrets {
+            Some(_) => vec![Mode::Unauthenticated, Mode::Authenticated, Mode::Encrypted],
+            None => vec![Mode::Unauthenticated],
+        }
+    }
+
+    /// Keys protecting each accepted test session in keyed modes, in the order they were
+    /// accepted. `None` for every session in unauthenticated mode.
+    pub fn test_keys(&self) -> Vec<Option<TestKeys>> {
+        self.sessions
+            .iter()
+            .map(|accept_session| self.session_test_keys(&accept_session.sid))
+            .collect()
+    }
+
+    fn session_test_keys(&self, sid: &[u8; 16]) -> Option<TestKeys> {
+        let keys = self.session_keys.as_ref()?;
+        let test_keys = TestKeys::derive(&keys.aes, &keys.hmac, sid);
+        match self.set_up_response.as_ref()?.mode() {
+            Mode::Encrypted => Some(test_keys.with_encryption()),
+            _ => Some(test_keys),
         }
     }
 
+    /// Check the mode chosen in Set-Up-Response, and in keyed modes its KeyID and Token, keeping
+    /// the session keys if they are valid.
+    fn authenticate(&mut self, set_up_response: &SetUpResponse) -> Accept {
+        let mode = set_up_response.mode();
+        if !self.modes().contains(&mode) {
+            warn!(target: TRACING_TARGET, ?mode, "Mode was not offered");
+            return Accept::NotSupported;
+        }
+        if !mode.is_keyed() {
+            return Accept::Ok;
+        }
+        let (Some(secrets), Some(server_greeting)) = (&self.config.secrets, &self.server_greeting)
+        else {
+            return Accept::InternalError;
+        };
+        let Some(shared_secret) = secrets.lookup(set_up_response.key_id()) else {
+            warn!(target: TRACING_TARGET, "Unknown KeyID");
+            return Accept::Failure;
+        };
+        let key = shared_secret.derive_key(&server_greeting.salt(), server_greeting.count());
+        let (challenge, session_keys) = SessionKeys::from_token(set_up_response.token(), &key);
+        if challenge != server_greeting.challenge() {
+            warn!(target: TRACING_TARGET, "Token does not carry the Challenge");
+            return Accept::Failure;
+        }
+        let cipher = ControlCipher::new(&session_keys.aes, set_up_response.client_iv());
+        self.socket
+            .codec_mut()
+            .open_with(session_keys.clone(), cipher);
+        self.session_keys = Some(session_keys);
+        Accept::Ok
+    }
+
+    /// Accept-Session value and reason to refuse `request_tw_session` with, `None` if it can be
+    /// accepted. Requests TWAMP does not allow are a `Failure`, valid ones this Server will not
+    /// serve are `NotSupported`.
+    fn refusal(&self, request_tw_session: &RequestTwSession) -> Option<(Accept, &'static str)> {
+        if !matches!(request_tw_session.ipvn(), 4 | 6) {
+            Some((Accept::Failure, "IPVN is neither 4 nor 6"))
+        } else if self.config.compliance == Compliance::Strict && !request_tw_session.mbz_is_zero()
+        {
+            Some((Accept::Failure, "Fields that must be zero are not"))
+        } else if request_tw_session.sender_port == 0 {
+            Some((Accept::Failure, "Sender Port is zero"))
+        } else if request_tw_session.conf_sender() != 0 || request_tw_session.conf_receiver() != 0 {
+            Some((Accept::NotSupported, "Conf-Sender or Conf-Receiver is set"))
+        } else if request_tw_session.timeout().is_err() {
+            Some((Accept::NotSupported, "Timeout outside supported bounds"))
+        } else if !self.config.accepts(request_tw_session) {
+            Some((Accept::NotSupported, "Padding above configured limit"))
+        } else {
+            None
+        }
+    }
+
+    /// Whether Stop-Sessions stops as many sessions as were accepted. A mismatch ends the
+    /// connection, unless the config is [lenient](ServerConfig::compliance).
+    fn check_number_of_sessions(&self, stop_sessions: &StopSessions) -> Result<()> {
+        let in_progress = self.sessions.len();
+        if stop_sessions.number_of_sessions() as usize == in_progress {
+            return Ok(());
+        }
+        warn!(
+            target: TRACING_TARGET,
+            number_of_sessions = stop_sessions.number_of_sessions(),
+            in_progress,
+            "Stop-Sessions does not match the sessions in progress"
+        );
+        match self.config.compliance {
+            Compliance::Lenient => Ok(()),
+            Compliance::Strict => Err(anyhow!(
+                "Stop-Sessions for {} sessions, {} in progress",
+                stop_sessions.number_of_sessions(),
+                in_progress
+            )),
+        }
+    }
+
+    /// SID of the test session: the Session-Reflector's IPv4 address, the time, and random
+    /// bytes, as suggested by RFC 4656. An IPv6 address is folded into 4 bytes with XOR.
+    fn new_sid(&self) -> [u8; 16] {
+        let mut sid = [0u8; 16];
+        match self
+            .socket
+            .get_ref()
+            .local_addr()
+            .map(|addr| addr.ip().to_canonical())
+        {
+            Ok(IpAddr::V4(addr)) => sid[..4].copy_from_slice(&addr.octets()),
+            Ok(IpAddr::V6(addr)) => {
+                for chunk in addr.octets().chunks_exact(4) {
+                    sid[..4].iter_mut().zip(chunk).for_each(|(s, c)| *s ^= c);
+                }
+            }
+            Err(_) => (),
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        sid[4..8].copy_from_slice(&(now.as_secs() as u32).to_be_bytes());
+        sid[8..12].copy_from_slice(&now.subsec_nanos().to_be_bytes());
+        self.rng.fill_bytes(&mut sid[12..]);
+        sid
+    }
+
+    /// Serve the Control-Client until Stop-Sessions or until it closes the connection.
+    ///
+    /// Every accepted Request-TW-Session is passed on `req_tw_tx` with its
+    /// [effective addresses](Self::with_effective_addresses), and its Accept-Session
+    /// waits for the Session-Reflector port on `ref_port_rx`, or for why the session is
+    /// refused if no port could be allocated. Start-Sessions passes the
+    /// [`test_keys`](Self::test_keys) of all accepted sessions on `start_ack_tx`, in the order
+    /// the sessions were requested.
     pub async fn handle_control_client(
         &mut self,
-        req_tw_tx: oneshot::Sender<RequestTwSession>,
-        ref_port_rx: oneshot::Receiver<u16>,
-        start_ack_tx: oneshot::Sender<()>,
+        req_tw_tx: mpsc::UnboundedSender<RequestTwSession>,
+        mut ref_port_rx: mpsc::UnboundedReceiver<Result<u16, Accept>>,
+        start_ack_tx: oneshot::Sender<Vec<Option<TestKeys>>>,
         stop_session_tx: oneshot::Sender<()>,
-        timeout_tx: oneshot::Sender<u64>,
     ) -> Result<()> {
         self.server_greeting = Some(self.send_server_greeting().await?);
 
         // Wrap `oneshot::Sender` in an Option to make rust happy by knowing we won't access
         // Sender after one use, which is moved in next iteration of loop.
-        let mut ref_req_port_tx_opt = Some(req_tw_tx);
-        let mut ref_port_rx_opt = Some(ref_port_rx);
         let mut start_ack_tx_opt = Some(start_ack_tx);
         let mut stop_session_tx_opt = Some(stop_session_tx);
-        let mut timeout_tx_opt = Some(timeout_tx);
+        let mut shutdown = self.shutdown.take();
+        // When Start-Sessions must have arrived by, once a session has been accepted.
+        let mut start_sessions_by: Option<Instant> = None;
         loop {
-            let mut buf = [0u8; 512];
-            let bytes_read = self.socket.read(&mut buf).await?;
-            debug!("bytes read: {}", bytes_read);
-
-            if bytes_read == 0 {
-                debug!("Control-Client closed connection");
+            let idle_by = self
+                .config
+                .servwait
+                .filter(|_| self.start_ack.is_none())
+                .map(|servwait| Instant::now() + servwait.as_duration());
+            // Whichever of the Start-Sessions deadline and SERVWAIT expires first.
+            let deadline = [
+                start_sessions_by.map(|by| (by, ServerError::StartSessionsExpired)),
+                idle_by.map(|by| (by, ServerError::ServwaitExpired)),
+            ]
+            .into_iter()
+            .flatten()
+            .min_by_key(|(by, _)| *by);
+            let read = async {
+                let frame = match deadline {
+                    Some((deadline, expired)) => timeout_at(deadline, self.socket.next())
+                        .await
+                        .map_err(|_| {
+                            warn!(
+                                target: TRACING_TARGET,
+                                sessions = self.sessions.len(),
+                                "{}, releasing sessions",
+                                expired
+                            );
+                            expired
+                        })?,
+                    None => self.socket.next().await,
+                };
+                Ok::<_, anyhow::Error>(frame)
+            };
+            let frame = select! {
+                frame = read => frame?,
+                () = shutdown_requested(&mut shutdown) => {
+                    info!(target: TRACING_TARGET, "Shutting down");
+                    if self.start_ack.is_some() {
+                        self.stop_all_sessions(Accept::Ok).await?;
+                    }
+                    break;
+                }
+            };
+            let Some(frame) = frame else {
+                debug!(target: TRACING_TARGET, "Control-Client closed connection");
                 break;
-            }
-            match self.up_next() {
+            };
+            let Frame {
+                message,
+                bytes: buf,
+            } = frame.map_err(server_error)?;
+            trace!(
+                target: TRACING_TARGET,
+                msg_type = message.msg_type(),
+                "Read from Control-Client"
+            );
+            self.check_command(message)?;
+            match message {
                 Messages::SetUpResponse => {
-                    self.set_up_response = Some(self.read_set_up_response(&buf).await?);
-                    self.server_start = Some(self.send_server_start().await?);
+                    let set_up_response = self.read_set_up_response(&buf).await?;
+                    let accept = self.authenticate(&set_up_response);
+                    self.set_up_response = Some(set_up_response);
+                    self.server_start = Some(self.send_server_start(accept).await?);
+                    if accept != Accept::Ok {
+                        return Err(anyhow!("Refused Set-Up-Response with {:?}", accept));
+                    }
                 }
                 Messages::RequestTwSession => {
-                    self.request_tw_session = Some(self.read_request_tw_session(&buf).await?);
-                    if let Some(sender) = ref_req_port_tx_opt.take() {
-                        sender
-                            .send(self.request_tw_session.to_owned().unwrap())
-                            .unwrap();
+                    let request_tw_session = self.read_request_tw_session(&buf).await?;
+                    if let Some((accept, reason)) = self.refusal(&request_tw_session) {
+                        warn!(
+                            target: TRACING_TARGET,
+                            ?accept,
+                            reason,
+                            "Refusing Request-TW-Session"
+                        );
+                        self.send_accept_session(accept, 0, 0).await?;
+                        continue;
+                    }
+                    let request_tw_session = self.with_effective_addresses(request_tw_session)?;
+                    if let Some(policy) = self.config.session_policy.clone() {
+                        let accept = policy
+                            .decide(SessionRequest {
+                                request: &request_tw_session,
+                                peer: self.socket.get_ref().peer_addr()?,
+                                sessions: self.sessions.len(),
+                            })
+                            .await;
+                        if accept != Accept::Ok {
+                            warn!(
+                                target: TRACING_TARGET,
+                                ?accept,
+                                "Session policy refused Request-TW-Session"
+                            );
+                            self.send_accept_session(accept, 0, 0).await?;
+                            continue;
+                        }
+                    }
+                    let reflected_octets = request_tw_session.octets_to_be_reflected();
+                    req_tw_tx
+                        .send(request_tw_session)
+                        .map_err(|_| anyhow!("Session-Reflectors are gone"))?;
+                    let final_port = match ref_port_rx
+                        .recv()
+                        .await
+                        .context("Session-Reflectors are gone")?
+                    {
+                        Ok(final_port) => final_port,
+                        Err(accept) => {
+                            warn!(
+                                target: TRACING_TARGET,
+                                ?accept,
+                                "No Session-Reflector port, refusing Request-TW-Session"
+                            );
+                            self.send_accept_session(accept, 0, 0).await?;
+                            continue;
+                        }
                     };
-                    if let Some(final_port) = ref_port_rx_opt.take() {
-                        let final_port = final_port.await.unwrap();
-                        self.accept_session = Some(self.send_accept_session(final_port).await?);
+                    let accept_session = self
+                        .send_accept_session(Accept::Ok, final_port, reflected_octets)
+                        .await?;
+                    self.sessions.push(accept_session);
+                    debug!(
+                        target: TRACING_TARGET,
+                        sessions = self.sessions.len(),
+                        "Session accepted"
+                    );
+                    if start_sessions_by.is_none() {
+                        start_sessions_by = self
+                            .config
+                            .start_sessions_deadline
+                            .map(|deadline| Instant::now() + deadline);
                     }
-                    if let Some(timeout) = timeout_tx_opt.take() {
-                        timeout
-                            .send(self.request_tw_session.to_owned().unwrap().timeout)
-                            .unwrap();
+                }
+                Messages::StartSessions if self.start_ack.is_some() => {
+                    self.read_start_sessions(&buf).await?;
+                    let policy = self.config.duplicate_start_sessions;
+                    warn!(target: TRACING_TARGET, ?policy, "Start-Sessions received again");
+                    if policy == DuplicateStartSessions::Refuse {
+                        self.send_start_ack(Accept::Failure).await?;
+                        return Err(anyhow!("Start-Sessions received again"));
                     }
+                    self.send_start_ack(Accept::Ok).await?;
                 }
                 Messages::StartSessions => {
                     self.start_sessions = Some(self.read_start_sessions(&buf).await?);
-                    self.start_ack = Some(self.send_start_ack().await?);
+                    start_sessions_by = None;
+                    self.start_ack = Some(self.send_start_ack(Accept::Ok).await?);
                     if let Some(start_ack_tx_val) = start_ack_tx_opt.take() {
-                        start_ack_tx_val.send(()).unwrap();
+                        let _ = start_ack_tx_val.send(self.test_keys());
                     }
                 }
                 Messages::StopSessions => {
-                    info!("Reading Stop-Sessions");
-                    self.read_stop_sessions(&buf).await.unwrap();
+                    let stop_sessions = self.read_stop_sessions(&buf).await?;
+                    self.check_number_of_sessions(&stop_sessions)?;
                     if let Some(stop_session_tx_val) = stop_session_tx_opt.take() {
-                        stop_session_tx_val.send(()).unwrap();
+                        let _ = stop_session_tx_val.send(());
                     }
                     break;
                 }
+                _ => unreachable!("Only Set-Up-Response and commands are read"),
             }
         }
 
         Ok(())
     }
 
+    /// Creates a `Stop-Sessions` for every accepted session, converts to bytes and sends it out
+    /// on `TWAMP-Control`, so the Control-Client knows they ended. A non-zero `accept` tells it
+    /// they ended abnormally.
+    pub async fn stop_all_sessions(&mut self, accept: Accept) -> Result<StopSessions> {
+        debug!(target: TRACING_TARGET, msg_type = "Stop-Sessions", "Sending");
+        let stop_sessions =
+            StopSessions::new(accept).with_number_of_sessions(self.sessions.len() as u32);
+        trace!(target: TRACING_TARGET, msg_type = "Stop-Sessions", content = ?stop_sessions);
+        self.socket.send(stop_sessions.to_bytes().unwrap()).await?;
+        info!(target: TRACING_TARGET, msg_type = "Stop-Sessions", "Sent");
+        Ok(stop_sessions)
+    }
+
     /// Creates a `ServerGreeting`, converts to bytes and sends it out on `TWAMP-Control`.
     pub async fn send_server_greeting(&mut self) -> Result<ServerGreeting> {
-        info!("Sending ServerGreeting");
-        let server_greeting = ServerGreeting::new(&[Mode::Unauthenticated]);
-        debug!("ServerGreeting: {:?}", server_greeting);
-        let encoded = server_greeting.to_bytes().unwrap();
-        self.socket.write_all(&encoded[..]).await?;
-        info!("Sent ServerGreeting");
+        debug!(target: TRACING_TARGET, msg_type = "Server Greeting", "Sending");
+        // Session-Reflectors of twamp-rs reflect test packets timestamped in either format.
+        let server_greeting = ServerGreeting::new(&self.modes())
+            .with_extensions(&[ModeExtension::PtpTimestamp])
+            .with_challenge_policy_from(self.config.challenge_policy, &*self.rng);
+        trace!(target: TRACING_TARGET, msg_type = "Server Greeting", content = ?server_greeting);
+        self.socket
+            .send(server_greeting.to_bytes().unwrap())
+            .await?;
+        info!(target: TRACING_TARGET, msg_type = "Server Greeting", "Sent");
         Ok(server_greeting)
     }
 
     /// Reads from `TWAMP-Control` stream assuming the bytes to be received will be of a
     /// `Set-Up-Response`. Converts those bytes into a `Set-Up-Response` struct and returns it.
     pub async fn read_set_up_response(&mut self, buf: &[u8]) -> Result<SetUpResponse> {
-        info!("Reading Set-Up-Response");
-        let (_rest, set_up_response) = SetUpResponse::from_bytes((buf, 0)).unwrap();
-        debug!("Set-Up-Response: {:?}", set_up_response);
-        info!("Read Set-Up-Response");
+        debug!(target: TRACING_TARGET, msg_type = "Set-Up-Response", "Reading");
+        let (_rest, set_up_response) = SetUpResponse::from_bytes((buf, 0))?;
+        trace!(target: TRACING_TARGET, msg_type = "Set-Up-Response", content = ?set_up_response);
+        info!(target: TRACING_TARGET, msg_type = "Set-Up-Response", "Read");
         Ok(set_up_response)
     }
 
     /// Creates a `Server-Start`, converts to bytes and sends it out on `TWAMP-Control`.
-    pub async fn send_server_start(&mut self) -> Result<ServerStart> {
-        info!("Sending Server-Start");
-        let server_start = ServerStart::new(Accept::Ok, Duration::new(123456, 789));
-        debug!("Server-Start: {:?}", server_start);
-        let encoded = server_start.to_bytes().unwrap();
-        self.socket.write_all(&encoded[..]).await?;
-        info!("Sent Server-Start");
+    pub async fn send_server_start(&mut self, accept: Accept) -> Result<ServerStart> {
+        debug!(target: TRACING_TARGET, msg_type = "Server-Start", "Sending");
+        let mut server_iv = [0; 16];
+        self.rng.fill_bytes(&mut server_iv);
+        let server_start =
+            ServerStart::new(accept, Duration::new(123456, 789)).with_server_iv(server_iv);
+        trace!(target: TRACING_TARGET, msg_type = "Server-Start", content = ?server_start);
+        let mut encoded = server_start.to_bytes().unwrap();
+        // Encryption starts after the Server-IV, and only if the Token was accepted.
+        let sealed = self.session_keys.clone().filter(|_| accept == Accept::Ok);
+        let cipher = sealed.as_ref().map(|keys| {
+            let mut cipher = ControlCipher::new(&keys.aes, server_start.server_iv());
+            cipher.encrypt(&mut encoded[32..]);
+            cipher
+        });
+        self.socket.send(encoded).await?;
+        if let (Some(keys), Some(cipher)) = (sealed, cipher) {
+            self.socket.codec_mut().seal_with(keys, cipher);
+        }
+        info!(target: TRACING_TARGET, msg_type = "Server-Start", "Sent");
         Ok(server_start)
     }
 
     /// Reads from `TWAMP-Control` stream assuming the bytes to be received will be of a
     /// `Request-TW-Session`. Converts those bytes into a `Request-TW-Session` struct and returns it.
     pub async fn read_request_tw_session(&mut self, buf: &[u8]) -> Result<RequestTwSession> {
-        debug!("Reading Request-TW-Session");
-        let (_rest, request_tw_session) = RequestTwSession::from_bytes((buf, 0)).unwrap();
-        debug!("Request-TW-Session: {:?}", request_tw_session);
-        info!("Read Request-TW-Session");
+        debug!(target: TRACING_TARGET, msg_type = "Request-TW-Session", "Reading");
+        let (_rest, request_tw_session) = RequestTwSession::from_bytes((buf, 0))?;
+        trace!(
+            target: TRACING_TARGET,
+            msg_type = "Request-TW-Session",
+            content = ?request_tw_session
+        );
+        info!(target: TRACING_TARGET, msg_type = "Request-TW-Session", "Read");
         Ok(request_tw_session)
     }
 
+    /// `request_tw_session` with unspecified Sender and Receiver Addresses replaced by those of
+    /// the TWAMP-Control connection, as
+    /// [RFC 4656](https://datatracker.ietf.org/doc/html/rfc4656#section-3.5) has them stand for:
+    /// the Control-Client's and the Server's respectively.
+    pub fn with_effective_addresses(
+        &self,
+        request_tw_session: RequestTwSession,
+    ) -> Result<RequestTwSession> {
+        let sender_address = request_tw_session.sender_address();
+        let receiver_address = request_tw_session.receiver_address();
+        if !sender_address.is_unspecified() && !receiver_address.is_unspecified() {
+            return Ok(request_tw_session);
+        }
+        let effective = |addr: IpAddr, connection: IpAddr| {
+            if addr.is_unspecified() {
+                connection.to_canonical()
+            } else {
+                addr.to_canonical()
+            }
+        };
+        let socket = self.socket.get_ref();
+        let sender_address = effective(sender_address, socket.peer_addr()?.ip());
+        let receiver_address = effective(receiver_address, socket.local_addr()?.ip());
+        debug!(
+            target: TRACING_TARGET,
+            %sender_address,
+            %receiver_address,
+            "Using addresses of TWAMP-Control connection"
+        );
+        Ok(request_tw_session.with_addresses(sender_address, receiver_address))
+    }
+
     /// Creates a `Accept-Session`, converts to bytes and sends it out on `TWAMP-Control`.
-    pub async fn send_accept_session(&mut self, receiver_port: u16) -> Result<AcceptSession> {
-        info!("Sending Accept-Session");
-        let accept_session = AcceptSession::new(Accept::Ok, receiver_port, 0, 0);
-        debug!("Accept-Session: {:?}", accept_session);
-        let encoded = accept_session.to_bytes().unwrap();
-        self.socket.write_all(&encoded[..]).await?;
-        debug!("Sent Accept-Session");
+    /// `reflected_octets` echoes the Octets to be Reflected of the Request-TW-Session.
+    pub async fn send_accept_session(
+        &mut self,
+        accept: Accept,
+        receiver_port: u16,
+        reflected_octets: u16,
+    ) -> Result<AcceptSession> {
+        debug!(target: TRACING_TARGET, msg_type = "Accept-Session", "Sending");
+        let mut accept_session = AcceptSession::new(accept, receiver_port, reflected_octets, 0);
+        if accept == Accept::Ok {
+            accept_session.sid = self.new_sid();
+        }
+        trace!(target: TRACING_TARGET, msg_type = "Accept-Session", content = ?accept_session);
+        self.socket.send(accept_session.to_bytes().unwrap()).await?;
+        info!(target: TRACING_TARGET, msg_type = "Accept-Session", "Sent");
         Ok(accept_session)
     }
 
+    /// Decode `buf` as `msg_type` according to the config's
+    /// [compliance](ServerConfig::compliance).
+    fn decode<'a, T: DekuRead<'a, bool>>(&self, buf: &'a [u8], msg_type: &str) -> Result<T> {
+        Ok(self.config.compliance.read(
+            buf,
+            |e| debug!(target: TRACING_TARGET, msg_type, "Ignoring non-zero MBZ: {}", e),
+        )?)
+    }
+
     /// Reads from `TWAMP-Control` stream assuming the bytes to be received will be of a
     /// `Start-Sessions`. Converts those bytes into a `Start-Sessions` struct and returns it.
     pub async fn read_start_sessions(&mut self, buf: &[u8]) -> Result<StartSessions> {
-        debug!("Reading Start-Sessions");
-        let (_rest, start_sessions) = StartSessions::from_bytes((buf, 0)).unwrap();
-        debug!("Start-Sessions: {:?}", start_sessions);
-        info!("Read Start-Sessions");
+        debug!(target: TRACING_TARGET, msg_type = "Start-Sessions", "Reading");
+        let start_sessions: StartSessions = self.decode(buf, "Start-Sessions")?;
+        trace!(target: TRACING_TARGET, msg_type = "Start-Sessions", content = ?start_sessions);
+        info!(target: TRACING_TARGET, msg_type = "Start-Sessions", "Read");
         Ok(start_sessions)
     }
 
     /// Creates a `Start-Ack`, converts to bytes and sends it out on `TWAMP-Control`.
-    pub async fn send_start_ack(&mut self) -> Result<StartAck> {
-        info!("Sending Start-Ack");
-        let start_ack = StartAck::new(Accept::Ok);
-        debug!("Start-Ack: {:?}", start_ack);
-        let encoded = start_ack.to_bytes().unwrap();
-        self.socket.write_all(&encoded[..]).await?;
-        info!("Sent Start-Ack");
+    pub async fn send_start_ack(&mut self, accept: Accept) -> Result<StartAck> {
+        debug!(target: TRACING_TARGET, msg_type = "Start-Ack", "Sending");
+        let start_ack = StartAck::new(accept);
+        trace!(target: TRACING_TARGET, msg_type = "Start-Ack", content = ?start_ack);
+        self.socket.send(start_ack.to_bytes().unwrap()).await?;
+        info!(target: TRACING_TARGET, msg_type = "Start-Ack", "Sent");
         Ok(start_ack)
     }
 
     /// Reads from `TWAMP-Control` stream assuming the bytes to be received will be of a
     /// `Stop-Sessions`. Converts those bytes into a `Stop-Sessions` struct and returns it.
     pub async fn read_stop_sessions(&mut self, buf: &[u8]) -> Result<StopSessions> {
-        debug!("Reading Stop-Sessions");
-        let (_rest, stop_sessions) = StopSessions::from_bytes((buf, 0)).unwrap();
-        debug!("Stop-Sessions: {:?}", stop_sessions);
-        info!("Read Stop-Sessions");
+        debug!(target: TRACING_TARGET, msg_type = "Stop-Sessions", "Reading");
+        let stop_sessions: StopSessions = self.decode(buf, "Stop-Sessions")?;
+        trace!(target: TRACING_TARGET, msg_type = "Stop-Sessions", content = ?stop_sessions);
+        info!(target: TRACING_TARGET, msg_type = "Stop-Sessions", "Read");
         Ok(stop_sessions)
     }
 }
+
+/// Error of the Server for a message the Control-Client sent that [`ControlCodec`] could not read.
+fn server_error(e: CodecError) -> anyhow::Error {
+    match e {
+        CodecError::UnknownCommand(command) => {
+            warn!(target: TRACING_TARGET, command, "Unknown command");
+            ServerError::UnknownCommand(command).into()
+        }
+        CodecError::Unsupported(command) => {
+            warn!(target: TRACING_TARGET, ?command, "Unexpected command");
+            ServerError::UnexpectedCommand(command).into()
+        }
+        e => e.into(),
+    }
+}
+
+/// Wait until `shutdown` is `true`, or forever without one.
+async fn shutdown_requested(shutdown: &mut Option<watch::Receiver<bool>>) {
+    if let Some(shutdown) = shutdown {
+        if shutdown.wait_for(|shutdown| *shutdown).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy::SessionLimits;
+    use secrets::MemorySecretStore;
+    use std::net::Ipv4Addr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use twamp_control::auth::SharedSecret;
+    use twamp_control::command_number::CommandNumber;
+    use twamp_control::timers::{Servwait, SessionTimeout};
+    use twamp_runtime::net::TcpListener;
+    use twamp_runtime::task::{spawn, JoinHandle};
+
+    #[tokio::test]
+    async fn handle_messages_sent_in_a_single_segment() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let (req_tw_tx, mut req_tw_rx) = mpsc::unbounded_channel();
+        let (ref_port_tx, ref_port_rx) = mpsc::unbounded_channel();
+        let (start_ack_tx, start_ack_rx) = oneshot::channel();
+        let (stop_sessions_tx, stop_sessions_rx) = oneshot::channel();
+        let server = spawn(async move {
+            Server::new(socket)
+                .handle_control_client(req_tw_tx, ref_port_rx, start_ack_tx, stop_sessions_tx)
+                .await
+        });
+
+        let mut greeting = [0u8; 64];
+        client.read_exact(&mut greeting).await.unwrap();
+        let request_tw_session = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            2,
+            None,
+            SessionTimeout::DEFAULT,
+        );
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        segment.extend(request_tw_session.to_bytes().unwrap());
+        segment.extend(StartSessions::new().to_bytes().unwrap());
+        segment.extend(StopSessions::new(Accept::Ok).to_bytes().unwrap());
+        client.write_all(&segment).await.unwrap();
+
+        assert_eq!(req_tw_rx.recv().await.unwrap(), request_tw_session);
+        ref_port_tx.send(Ok(2)).unwrap();
+        start_ack_rx.await.unwrap();
+        stop_sessions_rx.await.unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn several_sessions_are_accepted_before_start_sessions() {
+        let Served {
+            mut client,
+            mut req_tw_rx,
+            ref_port_tx,
+            start_ack_rx,
+            server,
+            ..
+        } = serve(ServerConfig::default()).await;
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        for sender_port in [1, 3] {
+            segment.extend(
+                RequestTwSession::new(
+                    Ipv4Addr::LOCALHOST,
+                    sender_port,
+                    Ipv4Addr::LOCALHOST,
+                    2,
+                    None,
+                    SessionTimeout::DEFAULT,
+                )
+                .to_bytes()
+                .unwrap(),
+            );
+        }
+        segment.extend(StartSessions::new().to_bytes().unwrap());
+        client.write_all(&segment).await.unwrap();
+        for (sender_port, reflector_port) in [(1, 2), (3, 4)] {
+            assert_eq!(req_tw_rx.recv().await.unwrap().sender_port, sender_port);
+            ref_port_tx.send(Ok(reflector_port)).unwrap();
+        }
+        assert_eq!(start_ack_rx.await.unwrap(), vec![None, None]);
+
+        let mut replies = [0u8; 48 + 48 * 2 + 32];
+        client.read_exact(&mut replies).await.unwrap();
+        let (_rest, first) = AcceptSession::from_bytes((&replies[48..96], 0)).unwrap();
+        let (_rest, second) = AcceptSession::from_bytes((&replies[96..144], 0)).unwrap();
+        assert_eq!((first.port, second.port), (2, 4));
+        assert_ne!(first.sid, second.sid);
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn unexpected_command_before_start_sessions_ends_the_connection() {
+        let Served {
+            mut client,
+            mut req_tw_rx,
+            ref_port_tx,
+            server,
+            ..
+        } = serve(ServerConfig::default()).await;
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        segment.extend(
+            RequestTwSession::new(
+                Ipv4Addr::LOCALHOST,
+                1,
+                Ipv4Addr::LOCALHOST,
+                2,
+                None,
+                SessionTimeout::DEFAULT,
+            )
+            .to_bytes()
+            .unwrap(),
+        );
+        segment.extend(StopSessions::new(Accept::Ok).to_bytes().unwrap());
+        client.write_all(&segment).await.unwrap();
+        req_tw_rx.recv().await.unwrap();
+        ref_port_tx.send(Ok(2)).unwrap();
+        assert!(server.await.unwrap().is_err());
+    }
+
+    /// Send Set-Up-Response and a Request-TW-Session with `byte` set at `offset`, returning the
+    /// Accept-Session value it is answered with.
+    async fn request_with_byte(offset: usize, byte: u8) -> Accept {
+        let Served {
+            mut client, server, ..
+        } = serve(ServerConfig::default()).await;
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let mut request_tw_session = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            2,
+            None,
+            SessionTimeout::DEFAULT,
+        )
+        .to_bytes()
+        .unwrap();
+        request_tw_session[offset] = byte;
+        segment.extend(request_tw_session);
+        client.write_all(&segment).await.unwrap();
+        let mut replies = [0u8; 48 + 48];
+        client.read_exact(&mut replies).await.unwrap();
+        let (_rest, accept_session) = AcceptSession::from_bytes((&replies[48..], 0)).unwrap();
+        drop(client);
+        server.await.unwrap().unwrap();
+        accept_session.accept
+    }
+
+    #[tokio::test]
+    async fn malformed_request_tw_session_is_refused() {
+        // IPVN of 5.
+        assert_eq!(request_with_byte(1, 5).await, Accept::Failure);
+        // Last MBZ octet.
+        assert_eq!(request_with_byte(95, 1).await, Accept::Failure);
+        // Sender Port of zero.
+        assert_eq!(request_with_byte(13, 0).await, Accept::Failure);
+    }
+
+    #[tokio::test]
+    async fn owamp_request_tw_session_is_not_supported() {
+        // Conf-Sender.
+        assert_eq!(request_with_byte(2, 1).await, Accept::NotSupported);
+        // Conf-Receiver.
+        assert_eq!(request_with_byte(3, 1).await, Accept::NotSupported);
+    }
+
+    #[tokio::test]
+    async fn timeout_outside_bounds_is_not_supported() {
+        // Highest octet of the Timeout.
+        assert_eq!(request_with_byte(76, 1).await, Accept::NotSupported);
+    }
+
+    #[tokio::test]
+    async fn session_policy_refusal_is_sent_in_accept_session() {
+        let Served {
+            mut client,
+            mut req_tw_rx,
+            server,
+            ..
+        } = serve(ServerConfig {
+            session_policy: Some(Arc::new(SessionLimits {
+                receiver_ports: Some(5000..=5010),
+                ..Default::default()
+            })),
+            ..Default::default()
+        })
+        .await;
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        segment.extend(
+            RequestTwSession::new(
+                Ipv4Addr::LOCALHOST,
+                1,
+                Ipv4Addr::LOCALHOST,
+                2,
+                None,
+                SessionTimeout::DEFAULT,
+            )
+            .to_bytes()
+            .unwrap(),
+        );
+        client.write_all(&segment).await.unwrap();
+        let mut replies = [0u8; 48 + 48];
+        client.read_exact(&mut replies).await.unwrap();
+        let (_rest, accept_session) = AcceptSession::from_bytes((&replies[48..], 0)).unwrap();
+        assert_eq!(accept_session.accept, Accept::NotSupported);
+        drop(client);
+        server.await.unwrap().unwrap();
+        assert!(req_tw_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn unspecified_addresses_are_those_of_the_connection() {
+        let Served {
+            mut client,
+            mut req_tw_rx,
+            ref_port_tx,
+            server,
+            ..
+        } = serve(ServerConfig::default()).await;
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        for (sender_address, receiver_address) in [
+            (Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED),
+            (Ipv4Addr::new(127, 0, 0, 2), Ipv4Addr::UNSPECIFIED),
+        ] {
+            segment.extend(
+                RequestTwSession::new(
+                    sender_address,
+                    1,
+                    receiver_address,
+                    2,
+                    None,
+                    SessionTimeout::DEFAULT,
+                )
+                .to_bytes()
+                .unwrap(),
+            );
+        }
+        client.write_all(&segment).await.unwrap();
+        let effective = req_tw_rx.recv().await.unwrap();
+        assert_eq!(
+            effective.sender_address(),
+            client.local_addr().unwrap().ip()
+        );
+        assert_eq!(
+            effective.receiver_address(),
+            client.peer_addr().unwrap().ip()
+        );
+        ref_port_tx.send(Ok(2)).unwrap();
+        let effective = req_tw_rx.recv().await.unwrap();
+        assert_eq!(effective.sender_address(), Ipv4Addr::new(127, 0, 0, 2));
+        assert_eq!(
+            effective.receiver_address(),
+            client.peer_addr().unwrap().ip()
+        );
+        ref_port_tx.send(Ok(2)).unwrap();
+        drop(client);
+        let _ = server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn session_without_reflector_port_is_refused() {
+        let Served {
+            mut client,
+            mut req_tw_rx,
+            ref_port_tx,
+            server,
+            ..
+        } = serve(ServerConfig::default()).await;
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        segment.extend(
+            RequestTwSession::new(
+                Ipv4Addr::LOCALHOST,
+                1,
+                Ipv4Addr::LOCALHOST,
+                2,
+                None,
+                SessionTimeout::DEFAULT,
+            )
+            .to_bytes()
+            .unwrap(),
+        );
+        client.write_all(&segment).await.unwrap();
+        req_tw_rx.recv().await.unwrap();
+        ref_port_tx
+            .send(Err(Accept::TemporaryResourceLimitation))
+            .unwrap();
+        let mut replies = [0u8; 48 + 48];
+        client.read_exact(&mut replies).await.unwrap();
+        let (_rest, accept_session) = AcceptSession::from_bytes((&replies[48..], 0)).unwrap();
+        assert_eq!(accept_session.accept, Accept::TemporaryResourceLimitation);
+        assert_eq!(accept_session.port, 0);
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn accept_session_echoes_octets_to_be_reflected() {
+        let Served {
+            mut client,
+            mut req_tw_rx,
+            ref_port_tx,
+            server,
+            ..
+        } = serve(ServerConfig::default()).await;
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let request_tw_session = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            2,
+            None,
+            SessionTimeout::DEFAULT,
+        )
+        .with_reflect_octets(0xabcd, 8);
+        segment.extend(request_tw_session.to_bytes().unwrap());
+        client.write_all(&segment).await.unwrap();
+        assert_eq!(req_tw_rx.recv().await.unwrap(), request_tw_session);
+        ref_port_tx.send(Ok(2)).unwrap();
+        let mut replies = [0u8; 48 + 48];
+        client.read_exact(&mut replies).await.unwrap();
+        let (_rest, accept_session) = AcceptSession::from_bytes((&replies[48..], 0)).unwrap();
+        assert_eq!(accept_session.accept, Accept::Ok);
+        assert_eq!(accept_session.reflected_octets, 0xabcd);
+        drop(client);
+        let _ = server.await.unwrap();
+    }
+
+    /// Send Set-Up-Response followed by a block starting with `command`, returning how the
+    /// Server ended.
+    async fn send_command(command: u8) -> anyhow::Error {
+        let Served {
+            mut client, server, ..
+        } = serve(ServerConfig::default()).await;
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        segment.push(command);
+        segment.extend([0u8; 15]);
+        client.write_all(&segment).await.unwrap();
+        server.await.unwrap().unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn unknown_command_ends_the_connection() {
+        let e = send_command(0xff).await;
+        assert_eq!(
+            e.downcast_ref::<ServerError>(),
+            Some(&ServerError::UnknownCommand(0xff))
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_session_ends_the_connection() {
+        let e = send_command(CommandNumber::FetchSession.into()).await;
+        assert_eq!(
+            e.downcast_ref::<ServerError>(),
+            Some(&ServerError::UnexpectedCommand(CommandNumber::FetchSession))
+        );
+    }
+
+    /// Set up an unauthenticated session and start it, reading everything the Server sends.
+    async fn start_session(served: &mut Served) {
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        segment.extend(
+            RequestTwSession::new(
+                Ipv4Addr::LOCALHOST,
+                1,
+                Ipv4Addr::LOCALHOST,
+                2,
+                None,
+                SessionTimeout::DEFAULT,
+            )
+            .to_bytes()
+            .unwrap(),
+        );
+        segment.extend(StartSessions::new().to_bytes().unwrap());
+        served.client.write_all(&segment).await.unwrap();
+        served.req_tw_rx.recv().await.unwrap();
+        served.ref_port_tx.send(Ok(2)).unwrap();
+        let mut replies = [0u8; 48 + 48 + 32];
+        served.client.read_exact(&mut replies).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn duplicate_start_sessions_is_acknowledged() {
+        let mut served = serve(ServerConfig::default()).await;
+        start_session(&mut served).await;
+        let mut segment = StartSessions::new().to_bytes().unwrap();
+        segment.extend(StopSessions::new(Accept::Ok).to_bytes().unwrap());
+        served.client.write_all(&segment).await.unwrap();
+        let mut start_ack = [0u8; 32];
+        served.client.read_exact(&mut start_ack).await.unwrap();
+        let (_rest, start_ack) = StartAck::from_bytes((&start_ack, 0)).unwrap();
+        assert_eq!(start_ack.accept, Accept::Ok);
+        served.server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn stop_sessions_for_other_number_of_sessions_ends_the_connection() {
+        let mut served = serve(ServerConfig::default()).await;
+        start_session(&mut served).await;
+        let mut segment = StartSessions::new().to_bytes().unwrap();
+        let stop_sessions = StopSessions::new(Accept::Ok).with_number_of_sessions(2);
+        segment.extend(stop_sessions.to_bytes().unwrap());
+        served.client.write_all(&segment).await.unwrap();
+        let mut start_ack = [0u8; 32];
+        served.client.read_exact(&mut start_ack).await.unwrap();
+        assert!(served.server.await.unwrap().is_err());
+
+        let mut served = serve(ServerConfig {
+            compliance: Compliance::Lenient,
+            ..Default::default()
+        })
+        .await;
+        start_session(&mut served).await;
+        served.client.write_all(&segment).await.unwrap();
+        served.server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn duplicate_start_sessions_is_refused() {
+        let mut served = serve(ServerConfig {
+            duplicate_start_sessions: DuplicateStartSessions::Refuse,
+            ..Default::default()
+        })
+        .await;
+        start_session(&mut served).await;
+        let start_sessions = StartSessions::new().to_bytes().unwrap();
+        served.client.write_all(&start_sessions).await.unwrap();
+        let mut start_ack = [0u8; 32];
+        served.client.read_exact(&mut start_ack).await.unwrap();
+        let (_rest, start_ack) = StartAck::from_bytes((&start_ack, 0)).unwrap();
+        assert_eq!(start_ack.accept, Accept::Failure);
+        assert!(served.server.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn request_tw_session_after_start_sessions_ends_the_connection() {
+        let mut served = serve(ServerConfig::default()).await;
+        start_session(&mut served).await;
+        let request_tw_session = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            3,
+            Ipv4Addr::LOCALHOST,
+            4,
+            None,
+            SessionTimeout::DEFAULT,
+        );
+        served
+            .client
+            .write_all(&request_tw_session.to_bytes().unwrap())
+            .await
+            .unwrap();
+        assert!(served.server.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn handle_messages_split_across_segments() {
+        let mut served = serve(ServerConfig::default()).await;
+        let request_tw_session = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            2,
+            None,
+            SessionTimeout::DEFAULT,
+        );
+        let mut bytes = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        bytes.extend(request_tw_session.to_bytes().unwrap());
+        bytes.extend(StartSessions::new().to_bytes().unwrap());
+        bytes.extend(StopSessions::new(Accept::Ok).to_bytes().unwrap());
+        served.client.set_nodelay(true).unwrap();
+        // Segments that never line up with message boundaries.
+        for chunk in bytes.chunks(7) {
+            served.client.write_all(chunk).await.unwrap();
+            served.client.flush().await.unwrap();
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(served.req_tw_rx.recv().await.unwrap(), request_tw_session);
+        served.ref_port_tx.send(Ok(2)).unwrap();
+        assert_eq!(served.start_ack_rx.await.unwrap(), vec![None]);
+        served.server.await.unwrap().unwrap();
+    }
+
+    /// Client's end of a connection served by [`serve_with_secret`].
+    struct Served {
+        client: TcpStream,
+        greeting: ServerGreeting,
+        req_tw_rx: mpsc::UnboundedReceiver<RequestTwSession>,
+        ref_port_tx: mpsc::UnboundedSender<Result<u16, Accept>>,
+        start_ack_rx: oneshot::Receiver<Vec<Option<TestKeys>>>,
+        server: JoinHandle<Result<()>>,
+    }
+
+    /// Serve one Control-Client with `shared_secret`, reading the Server Greeting.
+    async fn serve_with_secret(shared_secret: SharedSecret) -> Served {
+        serve(ServerConfig {
+            secrets: Some(Arc::new(shared_secret)),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Serve one Control-Client under `config`, reading the Server Greeting.
+    async fn serve(config: ServerConfig) -> Served {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (req_tw_tx, req_tw_rx) = mpsc::unbounded_channel();
+        let (ref_port_tx, ref_port_rx) = mpsc::unbounded_channel();
+        let (start_ack_tx, start_ack_rx) = oneshot::channel();
+        let (stop_sessions_tx, _stop_sessions_rx) = oneshot::channel();
+        let server = spawn(async move {
+            Server::new(socket)
+                .with_config(config)
+                .handle_control_client(req_tw_tx, ref_port_rx, start_ack_tx, stop_sessions_tx)
+                .await
+        });
+        let mut greeting = [0u8; 64];
+        client.read_exact(&mut greeting).await.unwrap();
+        let (_rest, greeting) = ServerGreeting::from_bytes((&greeting, 0)).unwrap();
+        Served {
+            client,
+            greeting,
+            req_tw_rx,
+            ref_port_tx,
+            start_ack_rx,
+            server,
+        }
+    }
+
+    /// Set-Up-Response choosing `mode`, with a zero Client-IV.
+    fn set_up_response(
+        mode: Mode,
+        greeting: &ServerGreeting,
+        shared_secret: &SharedSecret,
+        keys: &SessionKeys,
+    ) -> Vec<u8> {
+        let key = shared_secret.derive_key(&greeting.salt(), greeting.count());
+        let token = keys.to_token(&greeting.challenge(), &key);
+        SetUpResponse::keyed(mode, shared_secret.key_id_field(), token, [0; 16])
+            .unwrap()
+            .to_bytes()
+            .unwrap()
+    }
+
+    async fn keyed_session_shares_test_keys(mode: Mode) -> TestKeys {
+        let shared_secret = SharedSecret::new("probe", "secret").unwrap();
+        let Served {
+            mut client,
+            greeting,
+            req_tw_rx: _req_tw_rx,
+            ref_port_tx,
+            start_ack_rx,
+            server,
+        } = serve_with_secret(shared_secret.clone()).await;
+        assert!(greeting.has_mode(mode));
+        let keys = SessionKeys::random();
+        let mut send_cipher = ControlCipher::new(&keys.aes, &[0; 16]);
+        let mut segment = set_up_response(mode, &greeting, &shared_secret, &keys);
+        let mut request_tw_session = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            2,
+            None,
+            SessionTimeout::DEFAULT,
+        )
+        .to_bytes()
+        .unwrap();
+        keys.sign(&mut request_tw_session);
+        send_cipher.encrypt(&mut request_tw_session);
+        segment.extend(request_tw_session);
+        client.write_all(&segment).await.unwrap();
+        ref_port_tx.send(Ok(2)).unwrap();
+
+        let mut server_start = [0u8; 48];
+        client.read_exact(&mut server_start).await.unwrap();
+        assert_eq!(server_start[15], Accept::Ok.into());
+        let mut recv_cipher =
+            ControlCipher::new(&keys.aes, server_start[16..32].try_into().unwrap());
+        recv_cipher.decrypt(&mut server_start[32..]);
+        assert_eq!(server_start[40..], [0; 8]);
+        let mut accept_session = [0u8; 48];
+        client.read_exact(&mut accept_session).await.unwrap();
+        recv_cipher.decrypt(&mut accept_session);
+        assert!(keys.verify(&accept_session));
+        let (_rest, accept_session) = AcceptSession::from_bytes((&accept_session, 0)).unwrap();
+
+        let mut start_sessions = StartSessions::new().to_bytes().unwrap();
+        keys.sign(&mut start_sessions);
+        send_cipher.encrypt(&mut start_sessions);
+        client.write_all(&start_sessions).await.unwrap();
+        let test_keys = start_ack_rx.await.unwrap().remove(0).unwrap();
+        let derived = TestKeys::derive(&keys.aes, &keys.hmac, &accept_session.sid);
+        let mut start_ack = [0u8; 32];
+        client.read_exact(&mut start_ack).await.unwrap();
+        recv_cipher.decrypt(&mut start_ack);
+        assert!(keys.verify(&start_ack));
+        drop(client);
+        server.await.unwrap().unwrap();
+        assert!(test_keys == derived || test_keys == derived.clone().with_encryption());
+        test_keys
+    }
+
+    #[tokio::test]
+    async fn authenticated_session_shares_test_keys() {
+        let test_keys = keyed_session_shares_test_keys(Mode::Authenticated).await;
+        let mut packet = [0u8; 48];
+        test_keys.seal(&mut packet, 32);
+        assert_eq!(packet[16..32], [0; 16]);
+    }
+
+    #[tokio::test]
+    async fn encrypted_session_shares_test_keys() {
+        let test_keys = keyed_session_shares_test_keys(Mode::Encrypted).await;
+        let mut packet = [0u8; 48];
+        test_keys.seal(&mut packet, 32);
+        assert_ne!(packet[16..32], [0; 16]);
+    }
+
+    #[tokio::test]
+    async fn session_is_released_without_start_sessions_in_time() {
+        let Served {
+            mut client,
+            req_tw_rx: _req_tw_rx,
+            ref_port_tx,
+            start_ack_rx,
+            server,
+            ..
+        } = serve(ServerConfig {
+            start_sessions_deadline: Some(Duration::from_millis(50)),
+            ..Default::default()
+        })
+        .await;
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        segment.extend(
+            RequestTwSession::new(
+                Ipv4Addr::LOCALHOST,
+                1,
+                Ipv4Addr::LOCALHOST,
+                2,
+                None,
+                SessionTimeout::DEFAULT,
+            )
+            .to_bytes()
+            .unwrap(),
+        );
+        client.write_all(&segment).await.unwrap();
+        ref_port_tx.send(Ok(2)).unwrap();
+        let mut replies = [0u8; 96];
+        client.read_exact(&mut replies).await.unwrap();
+        let (_rest, accept_session) = AcceptSession::from_bytes((&replies[48..], 0)).unwrap();
+        assert_eq!(accept_session.accept, Accept::Ok);
+
+        let error = server.await.unwrap().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ServerError>(),
+            Some(&ServerError::StartSessionsExpired)
+        );
+        assert!(start_ack_rx.await.is_err());
+        assert_eq!(client.read(&mut replies).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn idle_connection_is_closed_after_servwait() {
+        let Served {
+            mut client,
+            req_tw_rx: _req_tw_rx,
+            ref_port_tx: _ref_port_tx,
+            server,
+            ..
+        } = serve(ServerConfig {
+            servwait: Some(Servwait::new(Duration::from_millis(50)).unwrap()),
+            ..Default::default()
+        })
+        .await;
+        let set_up_response = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        client.write_all(&set_up_response).await.unwrap();
+        let mut server_start = [0u8; 48];
+        client.read_exact(&mut server_start).await.unwrap();
+
+        let error = server.await.unwrap().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ServerError>(),
+            Some(&ServerError::ServwaitExpired)
+        );
+        assert_eq!(client.read(&mut server_start).await.unwrap(), 0);
+    }
+
+    /// Accept field of the Server-Start answering a Set-Up-Response keyed with `shared_secret`, by a
+    /// Server knowing the secrets of `store`.
+    async fn server_start_accept(store: MemorySecretStore, shared_secret: &SharedSecret) -> u8 {
+        let Served {
+            mut client,
+            greeting,
+            ..
+        } = serve(ServerConfig {
+            secrets: Some(Arc::new(store)),
+            ..Default::default()
+        })
+        .await;
+        let segment = set_up_response(
+            Mode::Authenticated,
+            &greeting,
+            shared_secret,
+            &SessionKeys::random(),
+        );
+        client.write_all(&segment).await.unwrap();
+        let mut server_start = [0u8; 48];
+        client.read_exact(&mut server_start).await.unwrap();
+        // Only the part before the Server-IV is in the clear once accepted.
+        server_start[15]
+    }
+
+    #[tokio::test]
+    async fn secret_is_looked_up_by_key_id() {
+        let tenant_a = SharedSecret::new("tenant-a", "a").unwrap();
+        let tenant_b = SharedSecret::new("tenant-b", "b").unwrap();
+        let store = MemorySecretStore::new()
+            .with_secret(tenant_a)
+            .with_secret(tenant_b.clone());
+        let accept = server_start_accept(store.clone(), &tenant_b).await;
+        assert_eq!(accept, Accept::Ok.into());
+        let unknown = SharedSecret::new("tenant-c", "b").unwrap();
+        let accept = server_start_accept(store, &unknown).await;
+        assert_eq!(accept, Accept::Failure.into());
+    }
+
+    #[tokio::test]
+    async fn token_under_other_secret_is_refused() {
+        let Served {
+            mut client,
+            greeting,
+            server,
+            ..
+        } = serve_with_secret(SharedSecret::new("probe", "secret").unwrap()).await;
+        let guessed = SharedSecret::new("probe", "guess").unwrap();
+        let segment = set_up_response(
+            Mode::Authenticated,
+            &greeting,
+            &guessed,
+            &SessionKeys::random(),
+        );
+        client.write_all(&segment).await.unwrap();
+
+        let mut server_start = [0u8; 48];
+        client.read_exact(&mut server_start).await.unwrap();
+        let (_rest, server_start) = ServerStart::from_bytes((&server_start, 0)).unwrap();
+        assert_eq!(*server_start.accept(), Accept::Failure);
+        assert!(server.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn wrong_hmac_ends_the_connection() {
+        let shared_secret = SharedSecret::new("probe", "secret").unwrap();
+        let Served {
+            mut client,
+            greeting,
+            server,
+            ..
+        } = serve_with_secret(shared_secret.clone()).await;
+        let mut segment = set_up_response(
+            Mode::Authenticated,
+            &greeting,
+            &shared_secret,
+            &SessionKeys::random(),
+        );
+        segment.extend(
+            RequestTwSession::new(
+                Ipv4Addr::LOCALHOST,
+                1,
+                Ipv4Addr::LOCALHOST,
+                2,
+                None,
+                SessionTimeout::DEFAULT,
+            )
+            .to_bytes()
+            .unwrap(),
+        );
+        client.write_all(&segment).await.unwrap();
+        assert!(server.await.unwrap().is_err());
+    }
+
+    /// Acceptor and connector trusting a fresh self-signed certificate for `localhost`.
+    #[cfg(feature = "tls")]
+    fn tls_pair() -> (
+        twamp_control::stream::tokio_rustls::TlsAcceptor,
+        twamp_control::stream::tokio_rustls::TlsConnector,
+    ) {
+        use twamp_control::stream::tokio_rustls::rustls::{
+            crypto::ring::default_provider, pki_types::PrivatePkcs8KeyDer, ClientConfig,
+            RootCertStore, ServerConfig,
+        };
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let provider = Arc::new(default_provider());
+        let server_config = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key.into())
+            .unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let client_config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        (
+            Arc::new(server_config).into(),
+            Arc::new(client_config).into(),
+        )
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn serves_control_client_over_tls() {
+        use control_client::ControlClient;
+        use twamp_control::stream::tokio_rustls::{
+            client::TlsStream, rustls::pki_types::ServerName,
+        };
+
+        let (acceptor, connector) = tls_pair();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let tcp = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        let (req_tw_tx, mut req_tw_rx) = mpsc::unbounded_channel();
+        let (ref_port_tx, ref_port_rx) = mpsc::unbounded_channel();
+        let (start_ack_tx, start_ack_rx) = oneshot::channel();
+        let (stop_sessions_tx, stop_sessions_rx) = oneshot::channel();
+        let server = spawn(async move {
+            let socket = acceptor.accept(accepted).await?;
+            Server::new(socket)
+                .handle_control_client(req_tw_tx, ref_port_rx, start_ack_tx, stop_sessions_tx)
+                .await
+        });
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let stream = connector.connect(server_name, tcp).await.unwrap();
+
+        let mut control_client = ControlClient::<TlsStream<TcpStream>>::default();
+        control_client.set_up(stream).await.unwrap();
+        let (accept_session, request_tw_session) = tokio::join!(
+            control_client.request_session(2, 1, SessionTimeout::DEFAULT),
+            async {
+                let request_tw_session = req_tw_rx.recv().await.unwrap();
+                ref_port_tx.send(Ok(2)).unwrap();
+                request_tw_session
+            }
+        );
+        assert_eq!(accept_session.unwrap().accept, Accept::Ok);
+        assert_eq!(request_tw_session.sender_port, 1);
+        control_client.start_sessions().await.unwrap();
+        assert_eq!(start_ack_rx.await.unwrap(), vec![None]);
+        control_client.send_stop_sessions().await.unwrap();
+        stop_sessions_rx.await.unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
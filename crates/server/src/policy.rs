@@ -0,0 +1,178 @@
+use std::{
+    fmt::Debug,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    pin::Pin,
+    ptr,
+};
+
+use tracing::*;
+use twamp_control::{
+    accept::Accept, constants::TRACING_TARGET, request_tw_session::RequestTwSession,
+};
+
+/// A Request-TW-Session the [`Server`](crate::Server) is about to accept, with what it knows
+/// about the TWAMP-Control connection it arrived on.
+#[derive(Clone, Copy, Debug)]
+pub struct SessionRequest<'a> {
+    pub request: &'a RequestTwSession,
+
+    /// Address of the Control-Client.
+    pub peer: SocketAddr,
+
+    /// Sessions already accepted on this connection.
+    pub sessions: usize,
+}
+
+/// Decides whether the [`Server`](crate::Server) accepts a Request-TW-Session, after the checks
+/// TWAMP itself requires. Anything but [`Accept::Ok`] is sent back in Accept-Session and the
+/// session is not set up. Shared by every connection of a Server, so it may keep state across
+/// them, e.g. to consult a database or rate limit tenants.
+pub trait SessionPolicy: Debug + Send + Sync {
+    fn decide<'a>(
+        &'a self,
+        request: SessionRequest<'a>,
+    ) -> Pin<Box<dyn Future<Output = Accept> + Send + 'a>>;
+}
+
+/// Policies are only equal to themselves, so configs holding one can still be compared.
+impl PartialEq for dyn SessionPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::addr_eq(self, other)
+    }
+}
+
+/// [`SessionPolicy`] refusing sessions outside fixed limits with
+/// [`Accept::NotSupported`]. Limits left `None` are not checked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionLimits {
+    /// Receiver Ports the Session-Reflector may be asked to use. The Server may still grant
+    /// another port if the one asked for is taken.
+    pub receiver_ports: Option<RangeInclusive<u16>>,
+
+    /// Sender Addresses test packets may come from.
+    pub sender_addresses: Option<Vec<IpAddr>>,
+
+    /// DSCPs test packets may be marked with.
+    pub dscps: Option<Vec<u8>>,
+
+    /// Largest Padding Length of test packets.
+    pub max_padding_length: Option<u32>,
+
+    /// Most sessions accepted on one TWAMP-Control connection.
+    pub max_sessions: Option<usize>,
+}
+
+impl SessionLimits {
+    /// Why `request` is outside the limits, `None` if it is within them.
+    pub fn violation(&self, request: &SessionRequest) -> Option<&'static str> {
+        let session = request.request;
+        if self
+            .receiver_ports
+            .as_ref()
+            .is_some_and(|ports| !ports.contains(&session.receiver_port))
+        {
+            Some("Receiver Port outside allowed range")
+        } else if self
+            .sender_addresses
+            .as_ref()
+            .is_some_and(|addresses| !addresses.contains(&session.sender_address()))
+        {
+            Some("Sender Address not allowed")
+        } else if self
+            .dscps
+            .as_ref()
+            .is_some_and(|dscps| !dscps.contains(&session.dscp()))
+        {
+            Some("DSCP not allowed")
+        } else if self
+            .max_padding_length
+            .is_some_and(|max| session.padding_length > max)
+        {
+            Some("Padding Length above limit")
+        } else if self.max_sessions.is_some_and(|max| request.sessions >= max) {
+            Some("Too many sessions on this connection")
+        } else {
+            None
+        }
+    }
+}
+
+impl SessionPolicy for SessionLimits {
+    fn decide<'a>(
+        &'a self,
+        request: SessionRequest<'a>,
+    ) -> Pin<Box<dyn Future<Output = Accept> + Send + 'a>> {
+        let accept = match self.violation(&request) {
+            Some(reason) => {
+                debug!(
+                    target: TRACING_TARGET,
+                    reason,
+                    "Session outside limits"
+                );
+                Accept::NotSupported
+            }
+            None => Accept::Ok,
+        };
+        Box::pin(async move { accept })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use twamp_control::timers::SessionTimeout;
+
+    fn request(session: &RequestTwSession, sessions: usize) -> SessionRequest<'_> {
+        SessionRequest {
+            request: session,
+            peer: (Ipv4Addr::LOCALHOST, 1).into(),
+            sessions,
+        }
+    }
+
+    #[tokio::test]
+    async fn limits_refuse_sessions_outside_them() {
+        let session = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            5000,
+            None,
+            SessionTimeout::DEFAULT,
+        );
+        let limits = SessionLimits {
+            receiver_ports: Some(5000..=5010),
+            sender_addresses: Some(vec![Ipv4Addr::LOCALHOST.into()]),
+            max_sessions: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(limits.decide(request(&session, 1)).await, Accept::Ok);
+        assert_eq!(
+            limits.decide(request(&session, 2)).await,
+            Accept::NotSupported
+        );
+        let elsewhere = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            6000,
+            None,
+            SessionTimeout::DEFAULT,
+        );
+        assert_eq!(
+            limits.violation(&request(&elsewhere, 0)),
+            Some("Receiver Port outside allowed range")
+        );
+        let limits = SessionLimits {
+            dscps: Some(vec![46]),
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.violation(&request(&session, 0)),
+            Some("DSCP not allowed")
+        );
+    }
+}
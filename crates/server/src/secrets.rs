@@ -0,0 +1,106 @@
+use std::{collections::HashMap, fmt::Debug, ptr};
+
+use twamp_control::auth::{SharedSecret, KEY_ID_LENGTH};
+
+/// Shared secrets a [`Server`](crate::Server) accepts keyed modes with, looked up by the KeyID
+/// the Control-Client sends in Set-Up-Response. Implement it to keep secrets per tenant in a
+/// database or vault rather than in memory.
+pub trait SecretStore: Debug + Send + Sync {
+    /// Secret known by `key_id`, the KeyID field of Set-Up-Response, if there is one.
+    fn lookup(&self, key_id: &[u8; KEY_ID_LENGTH]) -> Option<SharedSecret>;
+}
+
+/// Stores are only equal to themselves, so configs holding one can still be compared.
+impl PartialEq for dyn SecretStore {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::addr_eq(self, other)
+    }
+}
+
+/// A single secret is a store of its own KeyID.
+impl SecretStore for SharedSecret {
+    fn lookup(&self, key_id: &[u8; KEY_ID_LENGTH]) -> Option<SharedSecret> {
+        self.matches(key_id).then(|| self.clone())
+    }
+}
+
+/// [`SecretStore`] keeping every secret in memory.
+#[derive(Clone, Debug, Default)]
+pub struct MemorySecretStore {
+    secrets: HashMap<[u8; KEY_ID_LENGTH], SharedSecret>,
+}
+
+impl MemorySecretStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `shared_secret`, replacing any secret with the same KeyID.
+    pub fn with_secret(mut self, shared_secret: SharedSecret) -> Self {
+        self.insert(shared_secret);
+        self
+    }
+
+    /// Add `shared_secret`, returning the secret it replaces with the same KeyID, if any.
+    pub fn insert(&mut self, shared_secret: SharedSecret) -> Option<SharedSecret> {
+        self.secrets
+            .insert(shared_secret.key_id_field(), shared_secret)
+    }
+
+    /// Remove the secret known by `key_id`, returning it if there was one.
+    pub fn remove(&mut self, key_id: &str) -> Option<SharedSecret> {
+        let key_id = SharedSecret::new(key_id, Vec::new()).ok()?.key_id_field();
+        self.secrets.remove(&key_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.secrets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+}
+
+impl FromIterator<SharedSecret> for MemorySecretStore {
+    fn from_iter<T: IntoIterator<Item = SharedSecret>>(iter: T) -> Self {
+        let mut store = MemorySecretStore::new();
+        for shared_secret in iter {
+            store.insert(shared_secret);
+        }
+        store
+    }
+}
+
+impl SecretStore for MemorySecretStore {
+    fn lookup(&self, key_id: &[u8; KEY_ID_LENGTH]) -> Option<SharedSecret> {
+        self.secrets.get(key_id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_secret_by_key_id_field() {
+        let tenant_a = SharedSecret::new("tenant-a", "a").unwrap();
+        let tenant_b = SharedSecret::new("tenant-b", "b").unwrap();
+        let mut store: MemorySecretStore =
+            [tenant_a.clone(), tenant_b.clone()].into_iter().collect();
+        assert_eq!(store.lookup(&tenant_a.key_id_field()), Some(tenant_a));
+        assert_eq!(store.remove("tenant-b"), Some(tenant_b.clone()));
+        assert_eq!(store.lookup(&tenant_b.key_id_field()), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn single_secret_only_knows_its_key_id() {
+        let shared_secret = SharedSecret::new("probe", "secret").unwrap();
+        let other = SharedSecret::new("other", "secret").unwrap();
+        assert!(shared_secret
+            .lookup(&shared_secret.key_id_field())
+            .is_some());
+        assert!(shared_secret.lookup(&other.key_id_field()).is_none());
+    }
+}
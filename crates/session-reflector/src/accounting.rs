@@ -0,0 +1,463 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use twamp_test::ecn::{Ecn, EcnCounts};
+
+/// Number of test packets received of each size, keyed by size in bytes.
+pub type PacketSizes = BTreeMap<usize, u64>;
+
+/// Resources held on behalf of a single TWAMP-Control connection, and totals of the test packets
+/// it has handled.
+///
+/// Shared between the tasks serving the connection, and can be read at any time by whoever holds
+/// a clone of the `Arc`.
+#[derive(Debug, Default)]
+pub struct Accounting {
+    tasks: AtomicUsize,
+    queued_packets: AtomicUsize,
+    buffered_bytes: AtomicUsize,
+    reflected: AtomicU64,
+    dropped: AtomicU64,
+    received_ecn: Mutex<EcnCounts>,
+    sessions: Mutex<Vec<Arc<SessionAccounting>>>,
+}
+
+impl Accounting {
+    /// Number of tasks currently running for the connection.
+    pub fn tasks(&self) -> usize {
+        self.tasks.load(Ordering::Relaxed)
+    }
+
+    /// Number of test packets waiting to be reflected.
+    pub fn queued_packets(&self) -> usize {
+        self.queued_packets.load(Ordering::Relaxed)
+    }
+
+    /// Total size of test packets waiting to be reflected.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Number of test packets reflected so far.
+    pub fn packets_reflected(&self) -> u64 {
+        self.reflected.load(Ordering::Relaxed)
+    }
+
+    /// Number of test packets dropped without being reflected so far.
+    pub fn packets_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// ECN codepoints of the test packets received so far.
+    pub fn received_ecn(&self) -> EcnCounts {
+        *self.received_ecn.lock().unwrap()
+    }
+
+    /// Test sessions reflected so far, in the order they started.
+    pub fn sessions(&self) -> Vec<Arc<SessionAccounting>> {
+        self.sessions.lock().unwrap().clone()
+    }
+
+    /// Reflection rate and counters of every test session, taken at once.
+    pub fn runtime_stats(&self) -> RuntimeStats {
+        let now = Instant::now();
+        let sessions: Vec<SessionStats> = self
+            .sessions()
+            .iter()
+            .map(|session| session.stats(now))
+            .collect();
+        RuntimeStats {
+            reflected_per_second: sessions
+                .iter()
+                .filter(|session| session.active)
+                .map(|session| session.reflected_per_second)
+                .fold(0.0, |total, rate| total + rate),
+            sessions,
+        }
+    }
+
+    /// Start accounting for a test session reflecting from `local` towards `peer`.
+    pub(crate) fn track_session(
+        &self,
+        local: SocketAddr,
+        peer: SocketAddr,
+    ) -> Arc<SessionAccounting> {
+        let session = Arc::new(SessionAccounting {
+            local,
+            peer,
+            ..Default::default()
+        });
+        self.sessions.lock().unwrap().push(Arc::clone(&session));
+        session
+    }
+
+    pub(crate) fn record_ecn(&self, ecn: Option<Ecn>) {
+        self.received_ecn.lock().unwrap().record(ecn);
+    }
+
+    pub(crate) fn record_reflected(&self) {
+        self.reflected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a task as running until the returned guard is dropped.
+    pub fn track_task(self: &Arc<Self>) -> TaskGuard {
+        self.tasks.fetch_add(1, Ordering::Relaxed);
+        TaskGuard(Arc::clone(self))
+    }
+
+    /// Count a packet of `len` bytes as queued until the returned guard is dropped.
+    pub(crate) fn track_packet(self: &Arc<Self>, len: usize) -> PacketGuard {
+        self.queued_packets.fetch_add(1, Ordering::Relaxed);
+        self.buffered_bytes.fetch_add(len, Ordering::Relaxed);
+        PacketGuard {
+            accounting: Arc::clone(self),
+            len,
+        }
+    }
+}
+
+/// Resources held by a single test session of a connection, so a session sending at a high rate
+/// cannot hold on to every queued packet of the connection, and how long its test packets waited
+/// to be reflected.
+#[derive(Debug)]
+pub struct SessionAccounting {
+    local: SocketAddr,
+    peer: SocketAddr,
+    started_at: Instant,
+    ended_at: Mutex<Option<Instant>>,
+    reflected: AtomicU64,
+    dropped: AtomicU64,
+    queued_packets: AtomicUsize,
+    delayed: AtomicU64,
+    total_delay_nanos: AtomicU64,
+    max_delay_nanos: AtomicU64,
+    packet_sizes: Mutex<PacketSizes>,
+}
+
+impl Default for SessionAccounting {
+    fn default() -> Self {
+        SessionAccounting {
+            local: SocketAddr::from(([0, 0, 0, 0], 0)),
+            peer: SocketAddr::from(([0, 0, 0, 0], 0)),
+            started_at: Instant::now(),
+            ended_at: Mutex::default(),
+            reflected: AtomicU64::default(),
+            dropped: AtomicU64::default(),
+            queued_packets: AtomicUsize::default(),
+            delayed: AtomicU64::default(),
+            total_delay_nanos: AtomicU64::default(),
+            max_delay_nanos: AtomicU64::default(),
+            packet_sizes: Mutex::default(),
+        }
+    }
+}
+
+impl SessionAccounting {
+    /// Address the test packets are reflected from.
+    pub fn local(&self) -> SocketAddr {
+        self.local
+    }
+
+    /// Session-Sender the test packets are reflected to.
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Number of test packets of this session reflected so far.
+    pub fn packets_reflected(&self) -> u64 {
+        self.reflected.load(Ordering::Relaxed)
+    }
+
+    /// Number of test packets of this session dropped without being reflected so far.
+    pub fn packets_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sizes of the test packets of this session received so far, dropped ones included, to
+    /// spot fragmentation or padding changed on the way.
+    pub fn packet_sizes(&self) -> PacketSizes {
+        self.packet_sizes.lock().unwrap().clone()
+    }
+
+    /// Whether the session is still reflecting.
+    pub fn is_active(&self) -> bool {
+        self.ended_at.lock().unwrap().is_none()
+    }
+
+    /// Snapshot of the session as of `now`.
+    fn stats(&self, now: Instant) -> SessionStats {
+        let ended_at = *self.ended_at.lock().unwrap();
+        let elapsed = ended_at
+            .unwrap_or(now)
+            .saturating_duration_since(self.started_at);
+        let reflected = self.packets_reflected();
+        SessionStats {
+            local: self.local,
+            peer: self.peer,
+            reflected,
+            dropped: self.packets_dropped(),
+            reflected_per_second: if elapsed.is_zero() {
+                0.0
+            } else {
+                reflected as f64 / elapsed.as_secs_f64()
+            },
+            queued_packets: self.queued_packets(),
+            active: ended_at.is_none(),
+            packet_sizes: self.packet_sizes(),
+        }
+    }
+
+    pub(crate) fn record_packet_size(&self, len: usize) {
+        *self.packet_sizes.lock().unwrap().entry(len).or_default() += 1;
+    }
+
+    pub(crate) fn record_reflected(&self) {
+        self.reflected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Keep the session [active](Self::is_active) until the returned guard is dropped.
+    pub(crate) fn track_reflecting(self: &Arc<Self>) -> ReflectingGuard {
+        ReflectingGuard(Arc::clone(self))
+    }
+
+    /// Number of test packets of this session waiting to be reflected.
+    pub fn queued_packets(&self) -> usize {
+        self.queued_packets.load(Ordering::Relaxed)
+    }
+
+    /// Mean time reflected packets were sent after they were due, `None` until one is sent.
+    pub fn mean_queue_delay(&self) -> Option<Duration> {
+        let delayed = self.delayed.load(Ordering::Relaxed);
+        (delayed > 0)
+            .then(|| Duration::from_nanos(self.total_delay_nanos.load(Ordering::Relaxed) / delayed))
+    }
+
+    /// Longest time a reflected packet was sent after it was due.
+    pub fn max_queue_delay(&self) -> Duration {
+        Duration::from_nanos(self.max_delay_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Record that a reflected packet was sent `delay` after it was due, according to the
+    /// [`Pacing`](crate::config::Pacing).
+    pub(crate) fn record_queue_delay(&self, delay: Duration) {
+        let nanos = u64::try_from(delay.as_nanos()).unwrap_or(u64::MAX);
+        self.delayed.fetch_add(1, Ordering::Relaxed);
+        self.total_delay_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_delay_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Count a packet as queued until the returned guard is dropped.
+    pub(crate) fn track_packet(self: &Arc<Self>) -> SessionPacketGuard {
+        self.queued_packets.fetch_add(1, Ordering::Relaxed);
+        SessionPacketGuard(Arc::clone(self))
+    }
+}
+
+/// Reflection rate and counters of the test sessions of a connection, see
+/// [`Accounting::runtime_stats`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuntimeStats {
+    /// Test packets reflected per second, summed over the sessions still reflecting.
+    pub reflected_per_second: f64,
+
+    /// Every test session, in the order they started.
+    pub sessions: Vec<SessionStats>,
+}
+
+/// Counters of a single test session, see [`Accounting::runtime_stats`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionStats {
+    /// Address the test packets are reflected from.
+    pub local: SocketAddr,
+
+    /// Session-Sender the test packets are reflected to.
+    pub peer: SocketAddr,
+
+    pub reflected: u64,
+    pub dropped: u64,
+
+    /// Test packets reflected per second, averaged over the time the session was reflecting.
+    pub reflected_per_second: f64,
+
+    /// Test packets waiting to be reflected.
+    pub queued_packets: usize,
+
+    /// Whether the session is still reflecting.
+    pub active: bool,
+
+    /// Sizes of the test packets received, see [`SessionAccounting::packet_sizes`].
+    pub packet_sizes: PacketSizes,
+}
+
+/// Keeps a task counted in [`Accounting::tasks`] while alive.
+#[derive(Debug)]
+pub struct TaskGuard(Arc<Accounting>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.tasks.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Keeps a packet counted in [`Accounting::queued_packets`] and [`Accounting::buffered_bytes`]
+/// while alive.
+#[derive(Debug)]
+pub(crate) struct PacketGuard {
+    accounting: Arc<Accounting>,
+    len: usize,
+}
+
+impl Drop for PacketGuard {
+    fn drop(&mut self) {
+        self.accounting
+            .queued_packets
+            .fetch_sub(1, Ordering::Relaxed);
+        self.accounting
+            .buffered_bytes
+            .fetch_sub(self.len, Ordering::Relaxed);
+    }
+}
+
+/// Keeps a session [active](SessionAccounting::is_active) while alive.
+#[derive(Debug)]
+pub(crate) struct ReflectingGuard(Arc<SessionAccounting>);
+
+impl Drop for ReflectingGuard {
+    fn drop(&mut self) {
+        *self.0.ended_at.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+/// Keeps a packet counted in [`SessionAccounting::queued_packets`] while alive.
+#[derive(Debug)]
+pub(crate) struct SessionPacketGuard(Arc<SessionAccounting>);
+
+impl Drop for SessionPacketGuard {
+    fn drop(&mut self) {
+        self.0.queued_packets.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn task_is_counted_until_guard_drops() {
+        let accounting = Arc::new(Accounting::default());
+        let guard = accounting.track_task();
+        assert_eq!(accounting.tasks(), 1);
+        drop(guard);
+        assert_eq!(accounting.tasks(), 0);
+    }
+
+    #[test]
+    fn packet_is_counted_until_guard_drops() {
+        let accounting = Arc::new(Accounting::default());
+        let first = accounting.track_packet(41);
+        let second = accounting.track_packet(100);
+        assert_eq!(accounting.queued_packets(), 2);
+        assert_eq!(accounting.buffered_bytes(), 141);
+        drop(first);
+        assert_eq!(accounting.queued_packets(), 1);
+        assert_eq!(accounting.buffered_bytes(), 100);
+        drop(second);
+        assert_eq!(accounting.queued_packets(), 0);
+        assert_eq!(accounting.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn sessions_queue_separately() {
+        let accounting = Arc::new(Accounting::default());
+        let local = SocketAddr::from(([127, 0, 0, 1], 862));
+        let busy = accounting.track_session(local, SocketAddr::from(([127, 0, 0, 1], 1)));
+        let quiet = accounting.track_session(local, SocketAddr::from(([127, 0, 0, 1], 2)));
+        let queued = busy.track_packet();
+        assert_eq!((busy.queued_packets(), quiet.queued_packets()), (1, 0));
+        drop(queued);
+        assert_eq!(busy.queued_packets(), 0);
+        assert_eq!(accounting.sessions().len(), 2);
+        assert_eq!(accounting.sessions()[1].peer().port(), 2);
+    }
+
+    #[test]
+    fn queue_delay_is_summarised() {
+        let session = SessionAccounting::default();
+        assert_eq!(session.mean_queue_delay(), None);
+        session.record_queue_delay(Duration::from_micros(10));
+        session.record_queue_delay(Duration::from_micros(30));
+        assert_eq!(session.mean_queue_delay(), Some(Duration::from_micros(20)));
+        assert_eq!(session.max_queue_delay(), Duration::from_micros(30));
+    }
+
+    #[test]
+    fn packet_sizes_are_counted_per_session() {
+        let accounting = Arc::new(Accounting::default());
+        let local = SocketAddr::from(([127, 0, 0, 1], 862));
+        let first = accounting.track_session(local, SocketAddr::from(([127, 0, 0, 1], 1)));
+        let second = accounting.track_session(local, SocketAddr::from(([127, 0, 0, 1], 2)));
+        first.record_packet_size(41);
+        first.record_packet_size(41);
+        first.record_packet_size(1400);
+        second.record_packet_size(41);
+        assert_eq!(
+            first.packet_sizes(),
+            PacketSizes::from([(41, 2), (1400, 1)])
+        );
+        assert_eq!(
+            accounting.runtime_stats().sessions[1].packet_sizes,
+            PacketSizes::from([(41, 1)])
+        );
+    }
+
+    #[test]
+    fn runtime_stats_sum_active_sessions() {
+        let accounting = Arc::new(Accounting::default());
+        let ended = accounting.track_session(
+            SocketAddr::from(([127, 0, 0, 1], 4001)),
+            SocketAddr::from(([127, 0, 0, 1], 1)),
+        );
+        let active = accounting.track_session(
+            SocketAddr::from(([127, 0, 0, 1], 4002)),
+            SocketAddr::from(([127, 0, 0, 1], 2)),
+        );
+        let reflecting = (ended.track_reflecting(), active.track_reflecting());
+        for _ in 0..10 {
+            ended.record_reflected();
+            active.record_reflected();
+        }
+        active.record_dropped();
+        std::thread::sleep(Duration::from_millis(10));
+        drop(reflecting.0);
+
+        let stats = accounting.runtime_stats();
+        assert_eq!(stats.sessions.len(), 2);
+        assert!(!stats.sessions[0].active);
+        assert!(stats.sessions[1].active);
+        assert_eq!(stats.sessions[1].local.port(), 4002);
+        assert_eq!(
+            (stats.sessions[1].reflected, stats.sessions[1].dropped),
+            (10, 1)
+        );
+        assert!(stats.sessions[0].reflected_per_second > 0.0);
+        assert_eq!(
+            stats.reflected_per_second,
+            stats.sessions[1].reflected_per_second
+        );
+    }
+}
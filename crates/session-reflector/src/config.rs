@@ -0,0 +1,334 @@
+use std::time::Duration;
+
+use timestamp::timestamp::TimestampFormat;
+use twamp_control::compliance::Compliance;
+use twamp_runtime::time::Instant;
+use twamp_test::{
+    ecn::Ecn, twamp_test_auth::TwampTestPacketAuth,
+    twamp_test_auth_reflected::TwampTestPacketAuthReflected,
+    twamp_test_unauth::TwampTestPacketUnauth,
+    twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
+};
+
+/// Behaviour of a [`SessionReflector`](crate::SessionReflector) that is not negotiated over
+/// TWAMP-Control.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectorConfig {
+    /// Test packets shorter than this many bytes are dropped without being reflected.
+    pub min_request_size: usize,
+
+    /// Never send a reflected packet larger than the test packet that caused it, so the reflector
+    /// cannot be used to amplify UDP traffic towards a spoofed source. Test packets too short to
+    /// be reflected within that limit are dropped.
+    pub cap_to_request_size: bool,
+
+    /// Test packets arriving while this many of the same session are still waiting to be
+    /// reflected are dropped, bounding the memory and tasks a single session can hold on to
+    /// without starving the other sessions of its connection.
+    pub max_queued_packets: usize,
+
+    /// When reflected packets are sent relative to the arrival of test packets.
+    pub pacing: Pacing,
+
+    /// How the Sequence Number of reflected packets is chosen.
+    pub sequence: ReflectorSequence,
+
+    /// Embed the reflector's count of processed test packets in the padding of reflected
+    /// packets, so restarts and drops inside the reflector host can be spotted. Skipped for test
+    /// packets too short to carry it under [`cap_to_request_size`](Self::cap_to_request_size),
+    /// and when the session [reflects octets](crate::SessionReflector::with_reflect_octets).
+    pub echo_counter: bool,
+
+    /// Mark every reflected packet with this DSCP (0-63), whatever the test packets carried,
+    /// e.g. to measure a specific reverse class of service.
+    pub reflected_dscp: Option<u8>,
+
+    /// Mark every reflected packet with this ECN codepoint, so the reverse path can be checked
+    /// for congestion marking too.
+    pub reflected_ecn: Ecn,
+
+    /// How strictly test packets are parsed. Test packets failing to parse are dropped.
+    pub compliance: Compliance,
+
+    /// Format of the Timestamp and Receive Timestamp of reflected packets. Test packets are
+    /// reflected whatever format their own timestamp is in.
+    pub timestamp_format: TimestampFormat,
+}
+
+/// Controls when a Session-Reflector sends each reflected packet.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Pacing {
+    /// Reflect as soon as the test packet has been read.
+    #[default]
+    Immediate,
+
+    /// Keep the same spacing between reflected packets as between the test packets that caused
+    /// them, absorbing variation in the reflector's own processing time.
+    MirrorArrival,
+
+    /// Hold every test packet for a fixed duration before reflecting it.
+    FixedDwell(Duration),
+}
+
+/// Numbering of reflected packets by a Session-Reflector. RFC 5357 counts reflected packets from
+/// 0, other implementations differ; these reproduce them when testing interoperability.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReflectorSequence {
+    /// Count reflected packets of the session from `start`, wrapping around after `u32::MAX`.
+    Count { start: u32 },
+
+    /// Reuse the Sequence Number of the test packet that caused each reflected packet.
+    MirrorSender,
+}
+
+impl Default for ReflectorSequence {
+    fn default() -> Self {
+        ReflectorSequence::Count { start: 0 }
+    }
+}
+
+/// Works out the Sequence Number of each reflected packet according to a [`ReflectorSequence`].
+#[derive(Debug)]
+pub(crate) struct Sequencer {
+    sequence: ReflectorSequence,
+    next: u32,
+}
+
+impl Sequencer {
+    pub(crate) fn new(sequence: ReflectorSequence) -> Self {
+        let next = match sequence {
+            ReflectorSequence::Count { start } => start,
+            ReflectorSequence::MirrorSender => 0,
+        };
+        Sequencer { sequence, next }
+    }
+
+    /// Sequence Number of the reflection of a test packet numbered `sender_seq`.
+    pub(crate) fn next(&mut self, sender_seq: u32) -> u32 {
+        match self.sequence {
+            ReflectorSequence::Count { .. } => {
+                let seq = self.next;
+                self.next = seq.wrapping_add(1);
+                seq
+            }
+            ReflectorSequence::MirrorSender => sender_seq,
+        }
+    }
+}
+
+/// Works out when each reflected packet should be sent according to a [`Pacing`].
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    pacing: Pacing,
+    last: Option<(Instant, Instant)>,
+}
+
+impl Pacer {
+    pub(crate) fn new(pacing: Pacing) -> Self {
+        Pacer { pacing, last: None }
+    }
+
+    /// Instant at which to reflect a test packet that arrived at `arrival`.
+    pub(crate) fn send_at(&mut self, arrival: Instant) -> Instant {
+        match self.pacing {
+            Pacing::Immediate => arrival,
+            Pacing::FixedDwell(dwell) => arrival + dwell,
+            Pacing::MirrorArrival => {
+                let send_at = match self.last {
+                    Some((last_arrival, last_send_at)) => {
+                        (last_send_at + (arrival - last_arrival)).max(arrival)
+                    }
+                    None => arrival,
+                };
+                self.last = Some((arrival, send_at));
+                send_at
+            }
+        }
+    }
+}
+
+impl Default for ReflectorConfig {
+    fn default() -> Self {
+        ReflectorConfig {
+            min_request_size: TwampTestPacketUnauth::MIN_LENGTH,
+            cap_to_request_size: true,
+            max_queued_packets: 1024,
+            pacing: Pacing::default(),
+            sequence: ReflectorSequence::default(),
+            echo_counter: false,
+            reflected_dscp: None,
+            reflected_ecn: Ecn::NotEct,
+            compliance: Compliance::Strict,
+            timestamp_format: TimestampFormat::Ntp,
+        }
+    }
+}
+
+impl ReflectorConfig {
+    /// Whether a test packet of `request_size` bytes should be reflected.
+    pub fn should_reflect(&self, request_size: usize) -> bool {
+        if request_size < self.min_request_size {
+            return false;
+        }
+        !(self.cap_to_request_size && request_size < TwampTestPacketUnauthReflected::MIN_LENGTH)
+    }
+
+    /// Whether an authenticated test packet of `request_size` bytes should be reflected.
+    pub fn should_reflect_authenticated(&self, request_size: usize) -> bool {
+        if request_size < self.min_request_size.max(TwampTestPacketAuth::MIN_LENGTH) {
+            return false;
+        }
+        !(self.cap_to_request_size && request_size < TwampTestPacketAuthReflected::MIN_LENGTH)
+    }
+
+    /// IP TOS byte to set on the reflecting socket for [`reflected_dscp`](Self::reflected_dscp)
+    /// and [`reflected_ecn`](Self::reflected_ecn), `None` to leave the socket's default.
+    pub fn reflected_tos(&self) -> Option<u32> {
+        if self.reflected_dscp.is_none() && self.reflected_ecn == Ecn::NotEct {
+            return None;
+        }
+        let dscp = self.reflected_dscp.unwrap_or_default() & 0x3f;
+        Some(u32::from(dscp) << 2 | u32::from(self.reflected_ecn.bits()))
+    }
+
+    /// Largest padding, in octets, of the reflection of a test packet of `request_size` bytes.
+    pub fn reflected_padding_room(&self, request_size: usize) -> usize {
+        if self.cap_to_request_size {
+            request_size.saturating_sub(TwampTestPacketUnauthReflected::MIN_LENGTH)
+        } else {
+            usize::MAX
+        }
+    }
+
+    /// Whether the reflector counter should be embedded in the reflection of a test packet of
+    /// `request_size` bytes.
+    pub fn should_echo_counter(&self, request_size: usize) -> bool {
+        self.echo_counter
+            && !(self.cap_to_request_size
+                && request_size
+                    < TwampTestPacketUnauthReflected::MIN_LENGTH
+                        + TwampTestPacketUnauthReflected::REFLECTOR_COUNTER_LENGTH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_drops_packets_smaller_than_reflected_packet() {
+        let config = ReflectorConfig::default();
+        assert!(!config.should_reflect(TwampTestPacketUnauth::MIN_LENGTH));
+        assert!(config.should_reflect(TwampTestPacketUnauthReflected::MIN_LENGTH));
+    }
+
+    #[test]
+    fn reflect_small_packets_without_cap() {
+        let config = ReflectorConfig {
+            cap_to_request_size: false,
+            ..Default::default()
+        };
+        assert!(config.should_reflect(TwampTestPacketUnauth::MIN_LENGTH));
+        assert!(!config.should_reflect(TwampTestPacketUnauth::MIN_LENGTH - 1));
+    }
+
+    #[test]
+    fn authenticated_packets_must_cover_authenticated_reflection() {
+        let config = ReflectorConfig::default();
+        assert!(!config.should_reflect_authenticated(TwampTestPacketAuth::MIN_LENGTH));
+        assert!(config.should_reflect_authenticated(TwampTestPacketAuthReflected::MIN_LENGTH));
+        let config = ReflectorConfig {
+            cap_to_request_size: false,
+            ..Default::default()
+        };
+        assert!(config.should_reflect_authenticated(TwampTestPacketAuth::MIN_LENGTH));
+        assert!(!config.should_reflect_authenticated(TwampTestPacketAuth::MIN_LENGTH - 1));
+    }
+
+    #[test]
+    fn immediate_pacing_sends_on_arrival() {
+        let mut pacer = Pacer::new(Pacing::Immediate);
+        let arrival = Instant::now();
+        assert_eq!(pacer.send_at(arrival), arrival);
+    }
+
+    #[test]
+    fn fixed_dwell_delays_every_packet() {
+        let dwell = Duration::from_millis(5);
+        let mut pacer = Pacer::new(Pacing::FixedDwell(dwell));
+        let arrival = Instant::now();
+        assert_eq!(pacer.send_at(arrival), arrival + dwell);
+        assert_eq!(pacer.send_at(arrival + dwell), arrival + dwell * 2);
+    }
+
+    #[test]
+    fn mirror_arrival_keeps_spacing() {
+        let mut pacer = Pacer::new(Pacing::MirrorArrival);
+        let first = Instant::now();
+        let first_send_at = pacer.send_at(first);
+        let second_send_at = pacer.send_at(first + Duration::from_millis(10));
+        assert_eq!(second_send_at - first_send_at, Duration::from_millis(10));
+    }
+
+    #[test]
+    fn mirror_arrival_never_sends_before_arrival() {
+        let mut pacer = Pacer::new(Pacing::MirrorArrival);
+        let first = Instant::now();
+        pacer.send_at(first);
+        let late = first + Duration::from_millis(10);
+        assert_eq!(pacer.send_at(late), late);
+    }
+
+    #[test]
+    fn count_sequence_starts_at_start_and_wraps() {
+        let mut sequencer = Sequencer::new(ReflectorSequence::default());
+        assert_eq!(sequencer.next(7), 0);
+        assert_eq!(sequencer.next(9), 1);
+        let mut sequencer = Sequencer::new(ReflectorSequence::Count { start: u32::MAX });
+        assert_eq!(sequencer.next(0), u32::MAX);
+        assert_eq!(sequencer.next(1), 0);
+    }
+
+    #[test]
+    fn mirror_sender_sequence_reuses_sender_numbering() {
+        let mut sequencer = Sequencer::new(ReflectorSequence::MirrorSender);
+        assert_eq!(sequencer.next(7), 7);
+        assert_eq!(sequencer.next(3), 3);
+    }
+
+    #[test]
+    fn echo_counter_only_when_it_fits_under_cap() {
+        let config = ReflectorConfig {
+            echo_counter: true,
+            ..Default::default()
+        };
+        assert!(!config.should_echo_counter(TwampTestPacketUnauthReflected::MIN_LENGTH));
+        assert!(config.should_echo_counter(TwampTestPacketUnauthReflected::MIN_LENGTH + 4));
+        assert!(!ReflectorConfig::default().should_echo_counter(1472));
+    }
+
+    #[test]
+    fn reflected_dscp_sets_upper_tos_bits() {
+        let config = ReflectorConfig {
+            reflected_dscp: Some(46),
+            ..Default::default()
+        };
+        assert_eq!(config.reflected_tos(), Some(0xb8));
+        assert_eq!(ReflectorConfig::default().reflected_tos(), None);
+        let config = ReflectorConfig {
+            reflected_ecn: Ecn::Ect0,
+            ..config
+        };
+        assert_eq!(config.reflected_tos(), Some(0xba));
+    }
+
+    #[test]
+    fn drop_packets_below_min_request_size() {
+        let config = ReflectorConfig {
+            min_request_size: 100,
+            ..Default::default()
+        };
+        assert!(!config.should_reflect(99));
+        assert!(config.should_reflect(100));
+    }
+}
@@ -0,0 +1,228 @@
+//! Fair queuing of reflected packets across test sessions.
+//!
+//! Session-Reflectors sharing a [`FairQueue`] hand it the reflected packets they have to send,
+//! and its workers take them one session at a time, round-robin. A session sending at a high
+//! rate then delays the reflected packets of the others by one of its own at most, rather than
+//! by all those it has queued.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use tokio::{pin, select, sync::Notify};
+use twamp_runtime::{
+    task::spawn,
+    time::{sleep_until, Instant},
+};
+
+/// Sending of a reflected packet.
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Reflected packets of several sessions waiting to be sent, served round-robin by a pool of
+/// worker tasks.
+///
+/// Workers stop once the queue is dropped and every packet queued by then is sent.
+pub struct FairQueue {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    queues: Mutex<Queues>,
+    /// Notified when a job is queued or the queue is dropped.
+    ready: Notify,
+    closed: AtomicBool,
+    next_session: AtomicU64,
+}
+
+#[derive(Default)]
+struct Queues {
+    /// Jobs of each session with any queued, with when each is due, in the order they are.
+    sessions: HashMap<u64, VecDeque<(Instant, Job)>>,
+    /// Sessions with queued jobs, the one to serve next first.
+    turns: VecDeque<u64>,
+}
+
+impl Queues {
+    /// First job of the next session in turn whose first job is due at `now`, or else when the
+    /// earliest first job is due, if any is queued.
+    fn take(&mut self, now: Instant) -> Result<Job, Option<Instant>> {
+        let due = |session: &u64| self.sessions[session].front().unwrap().0;
+        let Some(turn) = self.turns.iter().position(|session| due(session) <= now) else {
+            return Err(self.turns.iter().map(due).min());
+        };
+        let session = self.turns.remove(turn).unwrap();
+        let jobs = self.sessions.get_mut(&session).unwrap();
+        let (_due, job) = jobs.pop_front().unwrap();
+        if jobs.is_empty() {
+            self.sessions.remove(&session);
+        } else {
+            self.turns.push_back(session);
+        }
+        Ok(job)
+    }
+}
+
+impl FairQueue {
+    /// Serve the queue with `workers` tasks, or one per CPU if 0.
+    pub fn new(workers: usize) -> Self {
+        let workers = match workers {
+            0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            workers => workers,
+        };
+        let shared = Arc::new(Shared {
+            queues: Mutex::default(),
+            ready: Notify::new(),
+            closed: AtomicBool::new(false),
+            next_session: AtomicU64::new(0),
+        });
+        for _ in 0..workers {
+            spawn(work(Arc::clone(&shared)));
+        }
+        FairQueue { shared }
+    }
+
+    /// Queue of a session of its own, taking its turn with the others.
+    pub(crate) fn session(self: &Arc<Self>) -> SessionQueue {
+        SessionQueue {
+            id: self.shared.next_session.fetch_add(1, Ordering::Relaxed),
+            queue: Arc::clone(self),
+        }
+    }
+}
+
+impl fmt::Debug for FairQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let queues = self.shared.queues.lock().unwrap();
+        f.debug_struct("FairQueue")
+            .field("sessions", &queues.turns.len())
+            .finish()
+    }
+}
+
+impl Drop for FairQueue {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.ready.notify_waiters();
+    }
+}
+
+/// The jobs of one session in a [`FairQueue`], kept alive while the session can queue more.
+#[derive(Debug)]
+pub(crate) struct SessionQueue {
+    id: u64,
+    queue: Arc<FairQueue>,
+}
+
+impl SessionQueue {
+    /// Run `job` no earlier than `due`, after the jobs this session queued before it.
+    pub(crate) fn push(&self, due: Instant, job: impl Future<Output = ()> + Send + 'static) {
+        let shared = &self.queue.shared;
+        let mut queues = shared.queues.lock().unwrap();
+        let jobs = queues.sessions.entry(self.id).or_default();
+        jobs.push_back((due, Box::pin(job)));
+        if jobs.len() == 1 {
+            queues.turns.push_back(self.id);
+        }
+        drop(queues);
+        shared.ready.notify_one();
+    }
+}
+
+/// Run the jobs of `shared` as they are due until it is dropped and none are left.
+async fn work(shared: Arc<Shared>) {
+    loop {
+        let ready = shared.ready.notified();
+        pin!(ready);
+        // Registered before looking at the queues, so no job queued meanwhile is missed.
+        ready.as_mut().enable();
+        let taken = shared.queues.lock().unwrap().take(Instant::now());
+        match taken {
+            Ok(job) => job.await,
+            Err(Some(due)) => select! {
+                _ = &mut ready => {}
+                _ = sleep_until(due) => {}
+            },
+            Err(None) if shared.closed.load(Ordering::Acquire) => return,
+            Err(None) => ready.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn sessions_take_turns() {
+        let queue = Arc::new(FairQueue::new(1));
+        let (sent_tx, mut sent_rx) = mpsc::unbounded_channel();
+        let busy = queue.session();
+        let quiet = queue.session();
+        // Due once both sessions queued, as the worker may run on a thread of its own.
+        let due = Instant::now() + Duration::from_millis(50);
+        for seq in 0..100 {
+            let sent_tx = sent_tx.clone();
+            busy.push(due, async move { sent_tx.send(("busy", seq)).unwrap() });
+        }
+        for seq in 0..2 {
+            let sent_tx = sent_tx.clone();
+            quiet.push(due, async move { sent_tx.send(("quiet", seq)).unwrap() });
+        }
+        let mut sent = Vec::new();
+        for _ in 0..5 {
+            sent.push(sent_rx.recv().await.unwrap());
+        }
+        assert_eq!(
+            sent,
+            [
+                ("busy", 0),
+                ("quiet", 0),
+                ("busy", 1),
+                ("quiet", 1),
+                ("busy", 2)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn jobs_wait_until_due() {
+        let queue = Arc::new(FairQueue::new(1));
+        let (sent_tx, mut sent_rx) = mpsc::unbounded_channel();
+        let paced = queue.session();
+        let immediate = queue.session();
+        let due = Instant::now() + Duration::from_millis(50);
+        let paced_tx = sent_tx.clone();
+        paced.push(due, async move { paced_tx.send("paced").unwrap() });
+        immediate.push(
+            Instant::now(),
+            async move { sent_tx.send("immediate").unwrap() },
+        );
+        assert_eq!(sent_rx.recv().await, Some("immediate"));
+        assert_eq!(sent_rx.recv().await, Some("paced"));
+        assert!(Instant::now() >= due);
+    }
+
+    #[tokio::test]
+    async fn queued_jobs_run_after_the_queue_is_dropped() {
+        let queue = Arc::new(FairQueue::new(2));
+        let (sent_tx, mut sent_rx) = mpsc::unbounded_channel();
+        let session = queue.session();
+        session.push(Instant::now() + Duration::from_millis(10), async move {
+            sent_tx.send(()).unwrap()
+        });
+        drop(session);
+        drop(queue);
+        assert_eq!(sent_rx.recv().await, Some(()));
+    }
+}
@@ -0,0 +1,283 @@
+//! Session-Reflector of TWAMP Light ([RFC 5357 Appendix I](https://datatracker.ietf.org/doc/html/rfc5357#appendix-I)).
+//!
+//! TWAMP Light has no TWAMP-Control for the Session-Reflector: sessions are set up out of band,
+//! typically by provisioning a port that reflects whatever TWAMP-Test packets reach it. A
+//! [`LightReflector`] binds such a port and keeps a session per Session-Sender address.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+};
+
+use anyhow::Result;
+use deku::prelude::*;
+use timestamp::timestamp::TimeStamp;
+use tracing::*;
+use twamp_control::timers::Refwait;
+use twamp_runtime::{net::UdpSocket, time::Instant};
+use twamp_test::{
+    constants::{MAX_UDP_PAYLOAD, TRACING_TARGET},
+    ecn::set_tos,
+    twamp_test_unauth::TwampTestPacketUnauth,
+    twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
+};
+
+use crate::{
+    accounting::Accounting,
+    config::{ReflectorConfig, Sequencer},
+    decode, PACKETS_PROCESSED,
+};
+
+/// Sessions a [`LightReflector`] keeps at once. Test packets starting further ones are dropped
+/// until others expire, so spoofed sources cannot grow its state without bound.
+pub const MAX_LIGHT_SESSIONS: usize = 4096;
+
+/// Reflects unauthenticated TWAMP-Test packets from any Session-Sender, back to the address each
+/// came from, with no Request-TW-Session.
+///
+/// Every Session-Sender address is a session of its own, numbered by its own [`Sequencer`], that
+/// ends once no test packet arrived from it for REFWAIT. Its next test packet starts a new one.
+///
+/// Of the [`ReflectorConfig`], the allowed request sizes, sequence numbering, reflector counter,
+/// reflected DSCP and ECN, compliance and timestamp format apply. Reflected packets are sent as soon as test
+/// packets are read.
+#[derive(Debug)]
+pub struct LightReflector {
+    socket: UdpSocket,
+    refwait: Refwait,
+    config: ReflectorConfig,
+    accounting: Arc<Accounting>,
+}
+
+/// A session of a [`LightReflector`].
+#[derive(Debug)]
+struct LightSession {
+    sequencer: Sequencer,
+    last_seen: Instant,
+}
+
+impl LightReflector {
+    /// `socket` should not be `connect`ed, so every Session-Sender can be reflected to.
+    pub fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            refwait: Refwait::DEFAULT,
+            config: ReflectorConfig::default(),
+            accounting: Arc::default(),
+        }
+    }
+
+    /// End sessions once no test packet arrived for `refwait` rather than [`Refwait::DEFAULT`].
+    pub fn with_refwait(mut self, refwait: Refwait) -> Self {
+        self.refwait = refwait;
+        self
+    }
+
+    /// Use the provided config instead of [`ReflectorConfig::default`].
+    pub fn with_config(mut self, config: ReflectorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Record reflected and dropped packets in the provided [`Accounting`] rather than a private
+    /// one.
+    pub fn with_accounting(mut self, accounting: Arc<Accounting>) -> Self {
+        self.accounting = accounting;
+        self
+    }
+
+    /// Reflects TWAMP-Test packets until reading or sending fails.
+    pub async fn do_reflect(self) -> Result<()> {
+        if let Some(tos) = self.config.reflected_tos() {
+            set_tos(&self.socket, tos)?;
+        }
+        debug!(
+            target: TRACING_TARGET,
+            local = %self.socket.local_addr()?,
+            "Reflecting TWAMP Light test packets"
+        );
+        let refwait = self.refwait.as_duration();
+        let mut sessions: HashMap<SocketAddr, LightSession> = HashMap::new();
+        // No padding is negotiated, so a test packet may be as long as any UDP datagram.
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+        loop {
+            let (bytes_read, sender) = self.socket.recv_from(&mut buf).await?;
+            let recv_timestamp = TimeStamp::default();
+            let arrival = Instant::now();
+            let counter = PACKETS_PROCESSED
+                .fetch_add(1, Ordering::Relaxed)
+                .wrapping_add(1);
+            if !self.config.should_reflect(bytes_read) {
+                debug!(
+                    target: TRACING_TARGET,
+                    %sender,
+                    bytes = bytes_read,
+                    "Dropping test packet, size not allowed"
+                );
+                self.accounting.record_dropped();
+                continue;
+            }
+            let pkt: TwampTestPacketUnauth =
+                match decode(self.config.compliance, &buf[..bytes_read]) {
+                    Ok(pkt) => pkt,
+                    Err(e) => {
+                        debug!(
+                            target: TRACING_TARGET,
+                            %sender,
+                            bytes = bytes_read,
+                            "Dropping test packet, cannot parse: {}",
+                            e
+                        );
+                        self.accounting.record_dropped();
+                        continue;
+                    }
+                };
+            if !sessions.contains_key(&sender) && sessions.len() >= MAX_LIGHT_SESSIONS {
+                sessions.retain(|_, session| arrival - session.last_seen < refwait);
+                if sessions.len() >= MAX_LIGHT_SESSIONS {
+                    debug!(
+                        target: TRACING_TARGET,
+                        %sender,
+                        "Dropping test packet, too many sessions"
+                    );
+                    self.accounting.record_dropped();
+                    continue;
+                }
+            }
+            let session = sessions.entry(sender).or_insert_with(|| {
+                debug!(target: TRACING_TARGET, %sender, "Starting TWAMP Light session");
+                LightSession {
+                    sequencer: Sequencer::new(self.config.sequence),
+                    last_seen: arrival,
+                }
+            });
+            if arrival - session.last_seen >= refwait {
+                debug!(target: TRACING_TARGET, %sender, "REFWAIT expired, starting a new session");
+                session.sequencer = Sequencer::new(self.config.sequence);
+            }
+            session.last_seen = arrival;
+            let seq = session.sequencer.next(pkt.sequence_number);
+            let mut reflected = TwampTestPacketUnauthReflected::new(seq, pkt, recv_timestamp)
+                .with_timestamp_format(self.config.timestamp_format);
+            if self.config.should_echo_counter(bytes_read) {
+                reflected = reflected.with_reflector_counter(counter);
+            }
+            let encoded = reflected.to_bytes().unwrap();
+            if let Err(e) = self.socket.send_to(&encoded, sender).await {
+                debug!(target: TRACING_TARGET, %sender, seq, "Cannot send reflected packet: {}", e);
+                self.accounting.record_dropped();
+                continue;
+            }
+            self.accounting.record_reflected();
+            trace!(
+                target: TRACING_TARGET,
+                %sender,
+                seq,
+                bytes = encoded.len(),
+                "Sent reflected packet"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReflectorSequence;
+    use std::time::Duration;
+    use twamp_runtime::{task::spawn, time::sleep};
+
+    async fn reflect(sender: &UdpSocket, seq: u32) -> TwampTestPacketUnauthReflected {
+        reflect_padded(sender, seq, 27).await
+    }
+
+    async fn reflect_padded(
+        sender: &UdpSocket,
+        seq: u32,
+        padding_length: usize,
+    ) -> TwampTestPacketUnauthReflected {
+        let pkt = TwampTestPacketUnauth::new(seq, padding_length, true)
+            .to_bytes()
+            .unwrap();
+        sender.send(&pkt).await.unwrap();
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+        let len = sender.recv(&mut buf).await.unwrap();
+        assert_eq!(len, pkt.len());
+        TwampTestPacketUnauthReflected::from_bytes((&buf[..len], 0))
+            .unwrap()
+            .1
+    }
+
+    #[tokio::test]
+    async fn numbers_each_session_on_its_own() {
+        let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let reflector_addr = reflector.local_addr().unwrap();
+        let accounting = Arc::new(Accounting::default());
+        spawn(
+            LightReflector::new(reflector)
+                .with_accounting(Arc::clone(&accounting))
+                .do_reflect(),
+        );
+        let mut senders = Vec::new();
+        for _ in 0..2 {
+            let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            sender.connect(reflector_addr).await.unwrap();
+            senders.push(sender);
+        }
+
+        let reflected = reflect(&senders[0], 7).await;
+        assert_eq!(reflected.sequence_number, 0);
+        assert_eq!(reflected.sender_sequence_number, 7);
+        assert_eq!(reflect(&senders[0], 8).await.sequence_number, 1);
+        assert_eq!(reflect(&senders[1], 0).await.sequence_number, 0);
+
+        // Too short to be reflected within its own size.
+        senders[1]
+            .send(&TwampTestPacketUnauth::new(1, 0, true).to_bytes().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(reflect(&senders[1], 2).await.sequence_number, 1);
+        // Recorded after sending, so maybe after the sender read the reflected packet.
+        while accounting.packets_reflected() < 4 {
+            sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(accounting.packets_dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn sessions_end_after_refwait() {
+        let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .connect(reflector.local_addr().unwrap())
+            .await
+            .unwrap();
+        spawn(
+            LightReflector::new(reflector)
+                .with_refwait(Refwait::new(1).unwrap())
+                .with_config(ReflectorConfig {
+                    sequence: ReflectorSequence::Count { start: 1 },
+                    ..Default::default()
+                })
+                .do_reflect(),
+        );
+        assert_eq!(reflect(&sender, 0).await.sequence_number, 1);
+        assert_eq!(reflect(&sender, 1).await.sequence_number, 2);
+        sleep(Duration::from_millis(1100)).await;
+        assert_eq!(reflect(&sender, 0).await.sequence_number, 1);
+    }
+
+    #[tokio::test]
+    async fn reflects_test_packets_padded_beyond_ethernet_mtu() {
+        let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .connect(reflector.local_addr().unwrap())
+            .await
+            .unwrap();
+        spawn(LightReflector::new(reflector).do_reflect());
+        let reflected = reflect_padded(&sender, 3, 4000).await;
+        assert_eq!(reflected.sender_sequence_number, 3);
+    }
+}
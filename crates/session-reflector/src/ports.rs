@@ -0,0 +1,151 @@
+use std::{
+    fmt::Debug,
+    ops::RangeInclusive,
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use twamp_control::request_tw_session::RequestTwSession;
+
+/// Chooses the ports a Session-Reflector may be bound to when a Request-TW-Session is
+/// accepted. The Responder tries binding them in order and refuses the session if none can be,
+/// so the Receiver Port sent back in Accept-Session is always one of them. Shared by every
+/// connection of a Responder, so it may keep state across them.
+pub trait PortAllocator: Debug + Send + Sync {
+    /// Ports to try for the Session-Reflector of `request`, in order. 0 lets the OS pick any.
+    fn candidates(&self, request: &RequestTwSession) -> Vec<u16>;
+}
+
+/// Allocators are only equal to themselves, so configs holding one can still be compared.
+impl PartialEq for dyn PortAllocator {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::addr_eq(self, other)
+    }
+}
+
+/// The Receiver Port asked for, or any port the OS picks if it is taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RequestedOrAny;
+
+impl PortAllocator for RequestedOrAny {
+    fn candidates(&self, request: &RequestTwSession) -> Vec<u16> {
+        match request.receiver_port {
+            0 => vec![0],
+            port => vec![port, 0],
+        }
+    }
+}
+
+/// Only the Receiver Port asked for. Sessions are refused rather than moved to another port,
+/// e.g. when firewalls only let the requested ports through.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RequestedOnly;
+
+impl PortAllocator for RequestedOnly {
+    fn candidates(&self, request: &RequestTwSession) -> Vec<u16> {
+        vec![request.receiver_port]
+    }
+}
+
+/// Ports of a range: the Receiver Port asked for if it is within it, then the range from its
+/// lowest port.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortRange(pub RangeInclusive<u16>);
+
+impl PortAllocator for PortRange {
+    fn candidates(&self, request: &RequestTwSession) -> Vec<u16> {
+        within(&self.0, request, self.0.clone().collect())
+    }
+}
+
+/// Ports of a range handed out in turn: the Receiver Port asked for if it is within it, then
+/// the range starting one past where the previous session started, wrapping around. Spreads
+/// sessions over the range rather than reusing its first free ports.
+#[derive(Debug)]
+pub struct Sequential {
+    range: RangeInclusive<u16>,
+    next: AtomicU32,
+}
+
+impl Sequential {
+    pub fn new(range: RangeInclusive<u16>) -> Self {
+        Sequential {
+            range,
+            next: AtomicU32::new(0),
+        }
+    }
+}
+
+impl PortAllocator for Sequential {
+    fn candidates(&self, request: &RequestTwSession) -> Vec<u16> {
+        let ports: Vec<u16> = self.range.clone().collect();
+        if ports.is_empty() {
+            return ports;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed) as usize % ports.len();
+        let rotated = ports[start..]
+            .iter()
+            .chain(&ports[..start])
+            .copied()
+            .collect();
+        within(&self.range, request, rotated)
+    }
+}
+
+/// `ports` preceded by the Receiver Port of `request` if it is a port of `range`.
+fn within(range: &RangeInclusive<u16>, request: &RequestTwSession, ports: Vec<u16>) -> Vec<u16> {
+    let requested = request.receiver_port;
+    if !range.contains(&requested) {
+        return ports;
+    }
+    let mut candidates = Vec::with_capacity(ports.len());
+    candidates.push(requested);
+    candidates.extend(ports.into_iter().filter(|port| *port != requested));
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use twamp_control::timers::SessionTimeout;
+
+    fn request(receiver_port: u16) -> RequestTwSession {
+        RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            receiver_port,
+            None,
+            SessionTimeout::DEFAULT,
+        )
+    }
+
+    #[test]
+    fn requested_port_comes_first() {
+        assert_eq!(RequestedOrAny.candidates(&request(5000)), vec![5000, 0]);
+        assert_eq!(RequestedOrAny.candidates(&request(0)), vec![0]);
+        assert_eq!(RequestedOnly.candidates(&request(5000)), vec![5000]);
+        let range = PortRange(5000..=5003);
+        assert_eq!(
+            range.candidates(&request(5002)),
+            vec![5002, 5000, 5001, 5003]
+        );
+        assert_eq!(
+            range.candidates(&request(6000)),
+            vec![5000, 5001, 5002, 5003]
+        );
+    }
+
+    #[test]
+    fn sequential_starts_one_further_every_time() {
+        let sequential = Sequential::new(5000..=5002);
+        assert_eq!(sequential.candidates(&request(0)), vec![5000, 5001, 5002]);
+        assert_eq!(sequential.candidates(&request(0)), vec![5001, 5002, 5000]);
+        assert_eq!(
+            sequential.candidates(&request(5000)),
+            vec![5000, 5002, 5001]
+        );
+        assert_eq!(sequential.candidates(&request(0)), vec![5000, 5001, 5002]);
+    }
+}
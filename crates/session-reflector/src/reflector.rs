@@ -0,0 +1,536 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use anyhow::Result;
+use deku::prelude::*;
+use timestamp::timestamp::TimeStamp;
+use tracing::*;
+use twamp_control::{compliance::Compliance, timers::Refwait};
+use twamp_runtime::{
+    net::{read_with, UdpSocket},
+    time::{timeout, Instant},
+};
+use twamp_test::{
+    constants::{ip_udp_header_length, ETHERNET_MTU, IPV4_UDP_HEADER_LENGTH, TRACING_TARGET},
+    ecn::{enable_recv_ecn, recv_with_ecn, set_tos},
+    keys::TestKeys,
+    twamp_test_auth::TwampTestPacketAuth,
+    twamp_test_auth_reflected::TwampTestPacketAuthReflected,
+    twamp_test_unauth::TwampTestPacketUnauth,
+    twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
+};
+
+use crate::{
+    accounting::Accounting,
+    config::{Pacer, ReflectorConfig, Sequencer},
+    fair_queue::FairQueue,
+};
+
+/// Test packets read by every Session-Reflector in this process.
+pub(crate) static PACKETS_PROCESSED: AtomicU32 = AtomicU32::new(0);
+
+/// Decode a test packet according to `compliance`, logging the MBZ fields it ignores.
+pub(crate) fn decode<'a, T: DekuRead<'a, bool>>(
+    compliance: Compliance,
+    bytes: &'a [u8],
+) -> Result<T, DekuError> {
+    compliance.read(
+        bytes,
+        |e| debug!(target: TRACING_TARGET, "Ignoring non-zero MBZ: {}", e),
+    )
+}
+
+/// Number of test packets read by every Session-Reflector in this process, see
+/// [`ReflectorConfig::echo_counter`].
+pub fn packets_processed() -> u32 {
+    PACKETS_PROCESSED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug)]
+pub struct SessionReflector {
+    socket: UdpSocket,
+    refwait: Refwait,
+    config: ReflectorConfig,
+    accounting: Arc<Accounting>,
+    queue: Arc<FairQueue>,
+    test_keys: Option<TestKeys>,
+    start_at: Option<Instant>,
+    reflect_octets: u16,
+    padding_length: u32,
+}
+
+impl SessionReflector {
+    /// socket should already be `connect`ed to the dest.
+    pub async fn new(socket: UdpSocket, refwait: Refwait) -> Self {
+        Self {
+            socket,
+            refwait,
+            config: ReflectorConfig::default(),
+            accounting: Arc::default(),
+            queue: Arc::new(FairQueue::new(1)),
+            test_keys: None,
+            start_at: None,
+            reflect_octets: 0,
+            padding_length: 0,
+        }
+    }
+
+    /// Expect and send authenticated test packets protected by `test_keys`. Test packets with a
+    /// wrong HMAC are dropped.
+    pub fn with_test_keys(mut self, test_keys: TestKeys) -> Self {
+        self.test_keys = Some(test_keys);
+        self
+    }
+
+    /// Only reflect test packets arriving from `start_at`, the Start-Time of the session.
+    /// Earlier ones are dropped, and REFWAIT only starts counting then.
+    pub fn with_start_at(mut self, start_at: Instant) -> Self {
+        self.start_at = Some(start_at);
+        self
+    }
+
+    /// Copy the first `length` octets of the padding of unauthenticated test packets into the
+    /// padding of reflected packets, as asked for by the Length of Padding to Reflect of
+    /// [RFC 6038](https://datatracker.ietf.org/doc/html/rfc6038#section-4.2). Fewer are copied
+    /// if the test packet is shorter or the reflected packet would not fit within
+    /// [`ReflectorConfig::cap_to_request_size`].
+    pub fn with_reflect_octets(mut self, length: u16) -> Self {
+        self.reflect_octets = length;
+        self
+    }
+
+    /// Expect test packets with `padding_length` octets of padding, the Padding Length of
+    /// Request-TW-Session. Test packets up to what fits in an Ethernet MTU are read whatever
+    /// the padding length, longer ones only if it asks for them.
+    pub fn with_padding_length(mut self, padding_length: u32) -> Self {
+        self.padding_length = padding_length;
+        self
+    }
+
+    /// Largest test packet read.
+    fn buffer_size(&self) -> usize {
+        let header_length = self
+            .socket
+            .local_addr()
+            .map_or(IPV4_UDP_HEADER_LENGTH, |addr| {
+                ip_udp_header_length(addr.ip())
+            });
+        (TwampTestPacketAuth::MIN_LENGTH + self.padding_length as usize)
+            .max(ETHERNET_MTU - header_length)
+    }
+
+    /// Use the provided config instead of [`ReflectorConfig::default`].
+    pub fn with_config(mut self, config: ReflectorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Record resource usage in the provided [`Accounting`] rather than a private one.
+    pub fn with_accounting(mut self, accounting: Arc<Accounting>) -> Self {
+        self.accounting = accounting;
+        self
+    }
+
+    /// Send reflected packets through `queue`, taking turns with the sessions of the other
+    /// Session-Reflectors sharing it rather than through a queue of its own.
+    pub fn with_fair_queue(mut self, queue: Arc<FairQueue>) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// Reflects TWAMP-Test packets until no test packet arrives for REFWAIT seconds, which is
+    /// not an error. Reflected and dropped packets are counted in the [`Accounting`], and the
+    /// session's queue in one of its [`sessions`](Accounting::sessions).
+    pub async fn do_reflect(self) -> Result<()> {
+        let l = self.socket.local_addr().unwrap();
+        let p = self.socket.peer_addr().unwrap();
+        if let Some(tos) = self.config.reflected_tos() {
+            set_tos(&self.socket, tos)?;
+        }
+        if let Err(e) = enable_recv_ecn(&self.socket) {
+            warn!(target: TRACING_TARGET, "Cannot read ECN of test packets: {}", e);
+        }
+        let session = self.accounting.track_session(l, p);
+        let _reflecting = session.track_reflecting();
+        let session_queue = self.queue.session();
+        let buf_len = self.buffer_size();
+        let sock = Arc::new(self.socket);
+        debug!(target: TRACING_TARGET, peer = %p, local = %l, "Reflecting test packets");
+        let mut sequencer = Sequencer::new(self.config.sequence);
+        let mut pacer = Pacer::new(self.config.pacing);
+        let mut buf = vec![0u8; buf_len];
+        loop {
+            let sock_clone = Arc::clone(&sock);
+            let not_started = self
+                .start_at
+                .map(|start_at| start_at.saturating_duration_since(Instant::now()))
+                .unwrap_or_default();
+            let bytes_read = timeout(
+                self.refwait.as_duration() + not_started,
+                read_with(&sock_clone, || recv_with_ecn(&*sock_clone, &mut buf)),
+            )
+            .await;
+            let Ok(bytes_read) = bytes_read else {
+                debug!(target: TRACING_TARGET, "REFWAIT expired");
+                return Ok(());
+            };
+            let recv_timestamp = TimeStamp::default();
+            let arrival = Instant::now();
+            let (bytes_read, ecn) = bytes_read?;
+            self.accounting.record_ecn(ecn);
+            session.record_packet_size(bytes_read);
+            let counter = PACKETS_PROCESSED
+                .fetch_add(1, Ordering::Relaxed)
+                .wrapping_add(1);
+            if self.start_at.is_some_and(|start_at| arrival < start_at) {
+                debug!(
+                    target: TRACING_TARGET,
+                    bytes = bytes_read,
+                    "Dropping test packet, before Start-Time"
+                );
+                self.accounting.record_dropped();
+                session.record_dropped();
+                continue;
+            }
+            let should_reflect = match self.test_keys {
+                Some(_) => self.config.should_reflect_authenticated(bytes_read),
+                None => self.config.should_reflect(bytes_read),
+            };
+            if !should_reflect {
+                debug!(
+                    target: TRACING_TARGET,
+                    bytes = bytes_read,
+                    "Dropping test packet, size not allowed"
+                );
+                self.accounting.record_dropped();
+                session.record_dropped();
+                continue;
+            }
+            let decoded = match &self.test_keys {
+                Some(keys) => {
+                    let Some(pkt) = TwampTestPacketAuth::unseal(&buf[..bytes_read], keys) else {
+                        debug!(
+                            target: TRACING_TARGET,
+                            bytes = bytes_read,
+                            "Dropping test packet, wrong HMAC"
+                        );
+                        self.accounting.record_dropped();
+                        session.record_dropped();
+                        continue;
+                    };
+                    decode::<TwampTestPacketAuth>(self.config.compliance, &pkt)
+                        .map(TwampTestPacketUnauth::from)
+                }
+                None => decode(self.config.compliance, &buf[..bytes_read]),
+            };
+            let twamp_test_unauth = match decoded {
+                Ok(pkt) => pkt,
+                Err(e) => {
+                    debug!(
+                        target: TRACING_TARGET,
+                        bytes = bytes_read,
+                        "Dropping test packet, cannot parse: {}",
+                        e
+                    );
+                    self.accounting.record_dropped();
+                    session.record_dropped();
+                    continue;
+                }
+            };
+            trace!(
+                target: TRACING_TARGET,
+                seq = twamp_test_unauth.sequence_number,
+                bytes = bytes_read,
+                content = ?twamp_test_unauth,
+                "Received test packet"
+            );
+            if session.queued_packets() >= self.config.max_queued_packets {
+                debug!(
+                    target: TRACING_TARGET,
+                    seq = twamp_test_unauth.sequence_number,
+                    "Dropping test packet, too many queued"
+                );
+                self.accounting.record_dropped();
+                session.record_dropped();
+                continue;
+            }
+            let task = self.accounting.track_task();
+            let queued = (
+                self.accounting.track_packet(bytes_read),
+                session.track_packet(),
+            );
+            let send_at = pacer.send_at(arrival);
+            let accounting = Arc::clone(&self.accounting);
+            let session = Arc::clone(&session);
+            let echo_counter = self.config.should_echo_counter(bytes_read);
+            let reflect_octets = match self.test_keys {
+                Some(_) => 0,
+                None => usize::from(self.reflect_octets)
+                    .min(bytes_read.saturating_sub(TwampTestPacketUnauth::MIN_LENGTH))
+                    .min(self.config.reflected_padding_room(bytes_read)),
+            };
+            let test_keys = self.test_keys.clone();
+            let timestamp_format = self.config.timestamp_format;
+            let seq = sequencer.next(twamp_test_unauth.sequence_number);
+            // Sent by the workers of the fair queue, so we still read.
+            session_queue.push(send_at, async move {
+                let _accounted = (task, queued);
+                let pkt = twamp_test_unauth;
+                let reflected_octets = pkt.packet_padding[..reflect_octets].to_vec();
+                let mut pkt_reflected =
+                    TwampTestPacketUnauthReflected::new(seq, pkt, recv_timestamp)
+                        .with_timestamp_format(timestamp_format);
+                if !reflected_octets.is_empty() {
+                    pkt_reflected = pkt_reflected.with_reflected_octets(&reflected_octets);
+                } else if echo_counter {
+                    pkt_reflected = pkt_reflected.with_reflector_counter(counter);
+                }
+                let encoded = match &test_keys {
+                    Some(keys) => TwampTestPacketAuthReflected::from(pkt_reflected).seal(keys),
+                    None => pkt_reflected.to_bytes().unwrap(),
+                };
+                session.record_queue_delay(Instant::now().saturating_duration_since(send_at));
+                match sock_clone.send(&encoded[..]).await {
+                    Ok(len) => {
+                        accounting.record_reflected();
+                        session.record_reflected();
+                        trace!(target: TRACING_TARGET, seq, bytes = len, "Sent reflected packet");
+                    }
+                    Err(e) => {
+                        debug!(target: TRACING_TARGET, seq, "Cannot send reflected packet: {}", e);
+                        accounting.record_dropped();
+                        session.record_dropped();
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReflectorSequence;
+    use std::time::Duration;
+    use timestamp::timestamp::TimestampFormat;
+    use twamp_runtime::{task::spawn, time::sleep_until};
+    use twamp_test::padding::PaddingPattern;
+
+    /// Reflector and sender sockets on loopback, connected to each other.
+    async fn socket_pair() -> (UdpSocket, UdpSocket) {
+        let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        reflector
+            .connect(sender.local_addr().unwrap())
+            .await
+            .unwrap();
+        sender
+            .connect(reflector.local_addr().unwrap())
+            .await
+            .unwrap();
+        (reflector, sender)
+    }
+
+    #[tokio::test]
+    async fn drops_test_packets_before_start_time() {
+        let (reflector, sender) = socket_pair().await;
+        let accounting = Arc::new(Accounting::default());
+        let reflecting = spawn(
+            SessionReflector::new(reflector, Refwait::new(1).unwrap())
+                .await
+                .with_accounting(Arc::clone(&accounting))
+                .with_start_at(Instant::now() + Duration::from_millis(200))
+                .do_reflect(),
+        );
+
+        for seq in [1, 2] {
+            let pkt = TwampTestPacketUnauth::new(seq, 27, true)
+                .to_bytes()
+                .unwrap();
+            sender.send(&pkt).await.unwrap();
+            sleep_until(Instant::now() + Duration::from_millis(300)).await;
+        }
+        let mut buf = [0u8; 1472];
+        sender.recv(&mut buf).await.unwrap();
+        let (_rest, reflected) = TwampTestPacketUnauthReflected::from_bytes((&buf, 0)).unwrap();
+        assert_eq!(reflected.sender_sequence_number, 2);
+        reflecting.await.unwrap().unwrap();
+        assert_eq!(accounting.packets_dropped(), 1);
+        assert_eq!(accounting.packets_reflected(), 1);
+    }
+
+    #[tokio::test]
+    async fn numbers_reflected_packets_from_configured_start() {
+        let (reflector, sender) = socket_pair().await;
+        let reflecting = spawn(
+            SessionReflector::new(reflector, Refwait::new(1).unwrap())
+                .await
+                .with_config(ReflectorConfig {
+                    sequence: ReflectorSequence::Count { start: 1 },
+                    ..Default::default()
+                })
+                .do_reflect(),
+        );
+
+        let mut buf = [0u8; 1472];
+        for (sender_seq, seq) in [(5, 1), (6, 2)] {
+            let pkt = TwampTestPacketUnauth::new(sender_seq, 27, true)
+                .to_bytes()
+                .unwrap();
+            sender.send(&pkt).await.unwrap();
+            sender.recv(&mut buf).await.unwrap();
+            let (_rest, reflected) = TwampTestPacketUnauthReflected::from_bytes((&buf, 0)).unwrap();
+            assert_eq!(reflected.sequence_number, seq);
+            assert_eq!(reflected.sender_sequence_number, sender_seq);
+        }
+        reflecting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reflects_octets_of_padding() {
+        let (reflector, sender) = socket_pair().await;
+        let reflecting = spawn(
+            SessionReflector::new(reflector, Refwait::new(1).unwrap())
+                .await
+                .with_config(ReflectorConfig {
+                    cap_to_request_size: false,
+                    ..Default::default()
+                })
+                .with_reflect_octets(4)
+                .do_reflect(),
+        );
+
+        let pkt = TwampTestPacketUnauth::new(0, 27, true)
+            .with_padding_octets(&[1, 2, 3, 4, 5])
+            .to_bytes()
+            .unwrap();
+        sender.send(&pkt).await.unwrap();
+        let mut buf = [0u8; 1472];
+        let len = sender.recv(&mut buf).await.unwrap();
+        assert_eq!(len, TwampTestPacketUnauthReflected::MIN_LENGTH + 4);
+        let (_rest, reflected) = TwampTestPacketUnauthReflected::from_bytes((&buf, 0)).unwrap();
+        assert_eq!(reflected.packet_padding[..5], [1, 2, 3, 4, 0]);
+        reflecting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reflected_packets_are_the_size_of_test_packets() {
+        let (reflector, sender) = socket_pair().await;
+        let reflecting = spawn(
+            SessionReflector::new(reflector, Refwait::new(1).unwrap())
+                .await
+                .do_reflect(),
+        );
+
+        let pkt =
+            TwampTestPacketUnauth::new(0, 512, true).with_padding_pattern(PaddingPattern::Random);
+        let encoded = pkt.to_bytes().unwrap();
+        sender.send(&encoded).await.unwrap();
+        let mut buf = [0u8; 1472];
+        let len = sender.recv(&mut buf).await.unwrap();
+        assert_eq!(len, encoded.len());
+        let (_rest, reflected) =
+            TwampTestPacketUnauthReflected::from_bytes((&buf[..len], 0)).unwrap();
+        assert_eq!(
+            reflected.packet_padding,
+            pkt.packet_padding[..512 - TwampTestPacketUnauthReflected::PADDING_TRUNCATION]
+        );
+        reflecting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_test_packets_padded_beyond_ethernet_mtu() {
+        let (reflector, sender) = socket_pair().await;
+        let reflecting = spawn(
+            SessionReflector::new(reflector, Refwait::new(1).unwrap())
+                .await
+                .with_config(ReflectorConfig {
+                    cap_to_request_size: false,
+                    ..Default::default()
+                })
+                .with_padding_length(3000)
+                .with_reflect_octets(3000)
+                .do_reflect(),
+        );
+
+        let mut padding = vec![0; 3000];
+        padding[2999] = 7;
+        let pkt = TwampTestPacketUnauth::new(0, 3000, true)
+            .with_padding_octets(&padding)
+            .to_bytes()
+            .unwrap();
+        sender.send(&pkt).await.unwrap();
+        let mut buf = [0u8; 4096];
+        let len = sender.recv(&mut buf).await.unwrap();
+        assert_eq!(len, TwampTestPacketUnauthReflected::MIN_LENGTH + 3000);
+        let (_rest, reflected) =
+            TwampTestPacketUnauthReflected::from_bytes((&buf[..len], 0)).unwrap();
+        assert_eq!(reflected.packet_padding, padding);
+        reflecting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn lenient_reflects_test_packets_with_non_zero_mbz() {
+        for (compliance, reflected) in [(Compliance::Strict, 0), (Compliance::Lenient, 1)] {
+            let (reflector, sender) = socket_pair().await;
+            let accounting = Arc::new(Accounting::default());
+            let keys = TestKeys::derive(&[1; 16], &[2; 32], &[3; 16]);
+            let reflecting = spawn(
+                SessionReflector::new(reflector, Refwait::new(1).unwrap())
+                    .await
+                    .with_accounting(Arc::clone(&accounting))
+                    .with_test_keys(keys.clone())
+                    .with_config(ReflectorConfig {
+                        compliance,
+                        ..Default::default()
+                    })
+                    .do_reflect(),
+            );
+
+            let mut pkt = TwampTestPacketAuth::new(0, true).to_bytes().unwrap();
+            // First MBZ octets, after the Sequence Number.
+            pkt[4] = 1;
+            // HMAC covers the 32 octets before it.
+            keys.seal(&mut pkt, 32);
+            pkt.resize(TwampTestPacketAuthReflected::MIN_LENGTH, 0);
+            sender.send(&pkt).await.unwrap();
+            reflecting.await.unwrap().unwrap();
+            assert_eq!(accounting.packets_reflected(), reflected);
+            assert_eq!(accounting.packets_dropped(), 1 - reflected);
+        }
+    }
+
+    #[tokio::test]
+    async fn reflects_in_configured_timestamp_format() {
+        let (reflector, sender) = socket_pair().await;
+        let reflecting = spawn(
+            SessionReflector::new(reflector, Refwait::new(1).unwrap())
+                .await
+                .with_config(ReflectorConfig {
+                    timestamp_format: TimestampFormat::Ptp,
+                    ..Default::default()
+                })
+                .do_reflect(),
+        );
+
+        // NTP test packets, with the Z bit that was MBZ before RFC 8186 unset.
+        let pkt = TwampTestPacketUnauth::new(0, 27, true);
+        let sent = pkt.timestamp;
+        sender.send(&pkt.to_bytes().unwrap()).await.unwrap();
+        let mut buf = [0u8; 1472];
+        sender.recv(&mut buf).await.unwrap();
+        let (_rest, reflected) = TwampTestPacketUnauthReflected::from_bytes((&buf, 0)).unwrap();
+        assert_eq!(
+            reflected.error_estimate.timestamp_format(),
+            TimestampFormat::Ptp
+        );
+        assert_eq!(
+            reflected.error_estimate_sender.timestamp_format(),
+            TimestampFormat::Ntp
+        );
+        assert_eq!(reflected.ntp_timestamps().0, sent);
+        reflecting.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,453 @@
+//! Session-Reflector of STAMP ([RFC 8762](https://datatracker.ietf.org/doc/html/rfc8762)).
+//!
+//! STAMP has no control protocol: Session-Senders send unauthenticated test packets straight to
+//! the reflector's port, by default the well-known port 862 that TWAMP-Test packets never go to.
+//! A Responder can run a [`StampReflector`] next to its TWAMP-Control listener to serve both.
+//!
+//! TLVs of [RFC 8972](https://datatracker.ietf.org/doc/html/rfc8972) in test packets are
+//! returned, those of [`twamp_test::stamp_tlv::Tlv`] populated and the others flagged.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use timestamp::timestamp::TimeStamp;
+use tracing::*;
+use twamp_runtime::net::{read_with, UdpSocket};
+use twamp_test::{
+    ancillary::{enable_recv_ancillary, recv_from_with_ancillary, Ancillary},
+    constants::{MAX_UDP_PAYLOAD, TRACING_TARGET},
+    ecn::set_tos,
+    stamp::{StampTestPacket, StampTestPacketReflected},
+    stamp_tlv::{LocationSubTlv, Tlv, FLAG_MALFORMED, FLAG_UNRECOGNIZED},
+};
+
+use crate::{
+    accounting::Accounting,
+    config::{ReflectorConfig, ReflectorSequence, Sequencer},
+    decode,
+};
+
+/// Session-Senders a stateful [`StampReflector`] keeps counting for. Test packets of further
+/// ones are dropped, so spoofed sources cannot grow its state without bound.
+pub const MAX_STAMP_SENDERS: usize = 4096;
+
+/// Reflects STAMP test packets from any Session-Sender, back to the address each came from.
+///
+/// Of the [`ReflectorConfig`], the minimum request size, sequence numbering, reflected DSCP and
+/// ECN and compliance apply. Reflected packets always have the length of the test packet that
+/// caused them, as STAMP asks, so test packets shorter than the [`StampTestPacketReflected`]
+/// layout are dropped, and reflected packets are sent as soon as test packets are read.
+///
+/// Numbered [`ReflectorSequence::MirrorSender`] this is a stateless Session-Reflector. Otherwise
+/// it is stateful, counting reflected packets per Session-Sender address.
+///
+/// Of the TLVs, Location ones get the ports and addresses of the test packet, Class of Service
+/// ones the DSCP and ECN it was received with, and the reflected packet is sent with the DSCP
+/// they ask for. Direct Measurement ones get the packets received from and sent to the
+/// Session-Sender, or to all of them when stateless.
+#[derive(Debug)]
+pub struct StampReflector {
+    socket: UdpSocket,
+    config: ReflectorConfig,
+    accounting: Arc<Accounting>,
+}
+
+impl StampReflector {
+    /// `socket` should not be `connect`ed, so every Session-Sender can be reflected to.
+    pub fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            config: ReflectorConfig::default(),
+            accounting: Arc::default(),
+        }
+    }
+
+    /// Use the provided config instead of [`ReflectorConfig::default`].
+    pub fn with_config(mut self, config: ReflectorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Record reflected and dropped packets in the provided [`Accounting`] rather than a private
+    /// one.
+    pub fn with_accounting(mut self, accounting: Arc<Accounting>) -> Self {
+        self.accounting = accounting;
+        self
+    }
+
+    /// Reflects STAMP test packets until reading or sending fails.
+    pub async fn do_reflect(self) -> Result<()> {
+        let reflected_tos = self.config.reflected_tos();
+        if let Some(tos) = reflected_tos {
+            set_tos(&self.socket, tos)?;
+        }
+        if let Err(e) = enable_recv_ancillary(&self.socket) {
+            debug!(
+                target: TRACING_TARGET,
+                "Cannot read the TOS or destination of STAMP test packets: {}", e
+            );
+        }
+        let local = self.socket.local_addr()?;
+        debug!(target: TRACING_TARGET, %local, "Reflecting STAMP test packets");
+        let mut senders: HashMap<SocketAddr, SenderState> = HashMap::new();
+        let mut stateless = SenderState::new(&self.config);
+        // STAMP has no control protocol to bound the length of test packets.
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+        loop {
+            let (bytes_read, sender, ancillary) = read_with(&self.socket, || {
+                recv_from_with_ancillary(&self.socket, &mut buf)
+            })
+            .await?;
+            let recv_timestamp = TimeStamp::default();
+            self.accounting.record_ecn(ancillary.ecn());
+            if bytes_read < StampTestPacket::LENGTH || bytes_read < self.config.min_request_size {
+                debug!(
+                    target: TRACING_TARGET,
+                    %sender,
+                    bytes = bytes_read,
+                    "Dropping STAMP test packet, size not allowed"
+                );
+                self.accounting.record_dropped();
+                continue;
+            }
+            let pkt: StampTestPacket = match decode(self.config.compliance, &buf[..bytes_read]) {
+                Ok(pkt) => pkt,
+                Err(e) => {
+                    debug!(
+                        target: TRACING_TARGET,
+                        %sender,
+                        bytes = bytes_read,
+                        "Dropping STAMP test packet, cannot parse: {}",
+                        e
+                    );
+                    self.accounting.record_dropped();
+                    continue;
+                }
+            };
+            let state = match self.config.sequence {
+                ReflectorSequence::MirrorSender => &mut stateless,
+                ReflectorSequence::Count { .. } => {
+                    if !senders.contains_key(&sender) && senders.len() >= MAX_STAMP_SENDERS {
+                        debug!(
+                            target: TRACING_TARGET,
+                            %sender,
+                            "Dropping STAMP test packet, too many Session-Senders"
+                        );
+                        self.accounting.record_dropped();
+                        continue;
+                    }
+                    senders
+                        .entry(sender)
+                        .or_insert_with(|| SenderState::new(&self.config))
+                }
+            };
+            state.received = state.received.wrapping_add(1);
+            state.transmitted = state.transmitted.wrapping_add(1);
+            let seq = state.sequencer.next(pkt.sequence_number);
+            let mut reflected = StampTestPacketReflected::new(seq, &pkt, recv_timestamp);
+            let arrival = Arrival {
+                sender,
+                destination: SocketAddr::new(
+                    ancillary.destination.unwrap_or(local.ip()),
+                    local.port(),
+                ),
+                ancillary,
+                received: state.received,
+                transmitted: state.transmitted,
+            };
+            let mut requested_dscp = None;
+            for tlv in &mut reflected.tlvs {
+                if let Some(dscp) = arrival.populate(tlv) {
+                    requested_dscp = Some(dscp);
+                }
+            }
+            let marked = match requested_dscp {
+                Some(dscp) => {
+                    let ecn = reflected_tos.unwrap_or_default() & 0x3;
+                    let marked = set_tos(&self.socket, u32::from(dscp) << 2 | ecn);
+                    if let Err(e) = &marked {
+                        debug!(target: TRACING_TARGET, %sender, dscp, "Cannot mark reflected STAMP packet: {}", e);
+                        for tlv in &mut reflected.tlvs {
+                            if let Tlv::ClassOfService(cos) = tlv {
+                                cos.rp = 1;
+                            }
+                        }
+                    }
+                    marked.is_ok()
+                }
+                None => false,
+            };
+            let encoded = reflected.to_bytes_of_length(bytes_read);
+            let sent = self.socket.send_to(&encoded, sender).await;
+            if marked {
+                set_tos(&self.socket, reflected_tos.unwrap_or_default())?;
+            }
+            if let Err(e) = sent {
+                debug!(target: TRACING_TARGET, %sender, seq, "Cannot send reflected STAMP packet: {}", e);
+                self.accounting.record_dropped();
+                continue;
+            }
+            self.accounting.record_reflected();
+            trace!(
+                target: TRACING_TARGET,
+                %sender,
+                seq,
+                bytes = encoded.len(),
+                tlvs = reflected.tlvs.len(),
+                "Sent reflected STAMP packet"
+            );
+        }
+    }
+}
+
+/// What a [`StampReflector`] keeps of a Session-Sender, or of all of them when stateless.
+#[derive(Debug)]
+struct SenderState {
+    sequencer: Sequencer,
+    received: u32,
+    transmitted: u32,
+}
+
+impl SenderState {
+    fn new(config: &ReflectorConfig) -> Self {
+        Self {
+            sequencer: Sequencer::new(config.sequence),
+            received: 0,
+            transmitted: 0,
+        }
+    }
+}
+
+/// A test packet as read, to populate the TLVs of its reflection with.
+struct Arrival {
+    sender: SocketAddr,
+    destination: SocketAddr,
+    ancillary: Ancillary,
+    received: u32,
+    transmitted: u32,
+}
+
+impl Arrival {
+    /// Populate `tlv`, returning the DSCP a Class of Service TLV asks the reflected packet to
+    /// be sent with.
+    fn populate(&self, tlv: &mut Tlv) -> Option<u8> {
+        match tlv {
+            Tlv::ExtraPadding(_) => (),
+            Tlv::Location(location) => {
+                location.destination_port = self.destination.port();
+                location.source_port = self.sender.port();
+                let destination = canonical(self.destination.ip());
+                let source = canonical(self.sender.ip());
+                for sub_tlv in &mut location.sub_tlvs {
+                    match (sub_tlv, destination, source) {
+                        (LocationSubTlv::DestinationIpv4(ip), IpAddr::V4(addr), _)
+                        | (LocationSubTlv::SourceIpv4(ip), _, IpAddr::V4(addr)) => *ip = addr,
+                        (LocationSubTlv::DestinationIpv6(ip), IpAddr::V6(addr), _)
+                        | (LocationSubTlv::SourceIpv6(ip), _, IpAddr::V6(addr)) => *ip = addr,
+                        (LocationSubTlv::Other(raw), _, _) if raw.flags & FLAG_MALFORMED == 0 => {
+                            raw.flags |= FLAG_UNRECOGNIZED
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            Tlv::ClassOfService(cos) => {
+                let tos = self.ancillary.tos.unwrap_or_default();
+                cos.dscp2 = tos >> 2;
+                cos.ecn = tos & 0x3;
+                cos.rp = 0;
+                return Some(cos.dscp1);
+            }
+            Tlv::DirectMeasurement(dm) => {
+                dm.reflector_rx = self.received;
+                dm.reflector_tx = self.transmitted;
+            }
+            Tlv::Other(raw) => {
+                if raw.flags & FLAG_MALFORMED == 0 {
+                    raw.flags |= FLAG_UNRECOGNIZED;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// `ip`, or the IPv4 address it maps, as seen by dual-stack sockets.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deku::prelude::*;
+    use std::net::Ipv4Addr;
+    use twamp_runtime::task::spawn;
+    use twamp_test::{
+        ancillary::recv_with_ancillary,
+        stamp_tlv::{ClassOfService, DirectMeasurement, Location, RawTlv},
+    };
+
+    async fn reflect(sender: &UdpSocket, pkt: &[u8]) -> StampTestPacketReflected {
+        sender.send(pkt).await.unwrap();
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+        let len = sender.recv(&mut buf).await.unwrap();
+        assert_eq!(
+            len,
+            pkt.len(),
+            "reflected packets are as long as test packets"
+        );
+        StampTestPacketReflected::from_bytes((&buf[..len], 0))
+            .unwrap()
+            .1
+    }
+
+    #[tokio::test]
+    async fn counts_reflected_packets_per_session_sender() {
+        let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let reflector_addr = reflector.local_addr().unwrap();
+        let accounting = Arc::new(Accounting::default());
+        spawn(
+            StampReflector::new(reflector)
+                .with_accounting(Arc::clone(&accounting))
+                .do_reflect(),
+        );
+        let mut senders = Vec::new();
+        for _ in 0..2 {
+            let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            sender.connect(reflector_addr).await.unwrap();
+            senders.push(sender);
+        }
+
+        let pkt = StampTestPacket::new(7, true).to_bytes().unwrap();
+        let reflected = reflect(&senders[0], &pkt).await;
+        assert_eq!(reflected.sequence_number, 0);
+        assert_eq!(reflected.sender_sequence_number, 7);
+        assert_eq!(reflect(&senders[0], &pkt).await.sequence_number, 1);
+        assert_eq!(reflect(&senders[1], &pkt).await.sequence_number, 0);
+
+        let mut padded = pkt.clone();
+        padded.resize(100, 0);
+        assert_eq!(reflect(&senders[1], &padded).await.sequence_number, 1);
+        padded.resize(4000, 0);
+        assert_eq!(reflect(&senders[1], &padded).await.sequence_number, 2);
+
+        // A TWAMP-Test packet with the usual 27 octets of padding is too short.
+        senders[0]
+            .send(&pkt[..StampTestPacket::LENGTH - 3])
+            .await
+            .unwrap();
+        assert_eq!(reflect(&senders[0], &pkt).await.sequence_number, 2);
+        assert_eq!(accounting.packets_reflected(), 6);
+        assert_eq!(accounting.packets_dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn stateless_reflector_mirrors_sender_sequence() {
+        let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .connect(reflector.local_addr().unwrap())
+            .await
+            .unwrap();
+        spawn(
+            StampReflector::new(reflector)
+                .with_config(ReflectorConfig {
+                    sequence: ReflectorSequence::MirrorSender,
+                    ..Default::default()
+                })
+                .do_reflect(),
+        );
+        for seq in [7, 3] {
+            let pkt = StampTestPacket::new(seq, true).to_bytes().unwrap();
+            assert_eq!(reflect(&sender, &pkt).await.sequence_number, seq);
+        }
+    }
+
+    #[tokio::test]
+    async fn populates_tlvs() {
+        let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let reflector_addr = reflector.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.connect(reflector_addr).await.unwrap();
+        set_tos(&sender, 10 << 2 | 1).unwrap();
+        enable_recv_ancillary(&sender).unwrap();
+        spawn(StampReflector::new(reflector).do_reflect());
+
+        let unknown = RawTlv {
+            flags: 0,
+            tlv_type: 100,
+            value: vec![1, 2, 3],
+        };
+        let tlvs = vec![
+            Tlv::ExtraPadding(vec![0; 8]),
+            Tlv::Location(Location::request(reflector_addr.ip())),
+            Tlv::ClassOfService(ClassOfService::request(46)),
+            Tlv::DirectMeasurement(DirectMeasurement {
+                sender_tx: 1,
+                ..Default::default()
+            }),
+            Tlv::Other(unknown.clone()),
+        ];
+        let pkt = StampTestPacket::new(0, true)
+            .with_ssid(7)
+            .with_tlvs(tlvs)
+            .to_bytes()
+            .unwrap();
+        for received in 1..=2 {
+            sender.send(&pkt).await.unwrap();
+            let mut buf = [0u8; 1472];
+            let (len, ancillary) = read_with(&sender, || recv_with_ancillary(&sender, &mut buf))
+                .await
+                .unwrap();
+            assert_eq!(len, pkt.len());
+            assert_eq!(ancillary.tos.map(|tos| tos >> 2), Some(46));
+            let (_rest, reflected) =
+                StampTestPacketReflected::from_bytes((&buf[..len], 0)).unwrap();
+            assert_eq!(reflected.ssid, 7);
+            let Tlv::Location(location) = &reflected.tlvs[1] else {
+                panic!("Location TLV not returned");
+            };
+            assert_eq!(location.destination_port, reflector_addr.port());
+            assert_eq!(location.source_port, sender.local_addr().unwrap().port());
+            assert_eq!(
+                location.sub_tlvs,
+                [
+                    LocationSubTlv::DestinationIpv4(Ipv4Addr::LOCALHOST),
+                    LocationSubTlv::SourceIpv4(Ipv4Addr::LOCALHOST),
+                ]
+            );
+            assert_eq!(
+                reflected.tlvs[2],
+                Tlv::ClassOfService(ClassOfService {
+                    dscp1: 46,
+                    dscp2: 10,
+                    ecn: 1,
+                    ..Default::default()
+                })
+            );
+            assert_eq!(
+                reflected.tlvs[3],
+                Tlv::DirectMeasurement(DirectMeasurement {
+                    sender_tx: 1,
+                    reflector_rx: received,
+                    reflector_tx: received,
+                })
+            );
+            assert_eq!(
+                reflected.tlvs[4],
+                Tlv::Other(RawTlv {
+                    flags: FLAG_UNRECOGNIZED,
+                    ..unknown.clone()
+                })
+            );
+        }
+    }
+}
@@ -1,74 +1,717 @@
-use anyhow::Result;
+pub mod measurement;
+pub mod pacing;
+pub mod sequence;
+
+use anyhow::{anyhow, Result};
 use deku::prelude::*;
+use measurement::{Measurement, MeasurementCallback, RunningStats};
+use pacing::{Calibration, PacingStats};
 use std::{
-    net::{SocketAddr, SocketAddrV4},
-    sync::Arc,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
 };
-use timestamp::timestamp::TimeStamp;
-use tokio::{net::UdpSocket, spawn, sync::Mutex};
+use timestamp::timestamp::{TimeStamp, TimestampFormat};
+use tokio::sync::Mutex;
 use tracing::*;
+use twamp_control::compliance::Compliance;
+use twamp_runtime::{
+    net::{read_with, UdpSocket},
+    task::{spawn, JoinHandle},
+    time::{sleep, timeout, Instant},
+};
 use twamp_test::{
+    ancillary::{enable_recv_ancillary, recv_with_ancillary},
+    constants::{ETHERNET_MTU, MAX_UDP_PAYLOAD, TRACING_TARGET},
+    ecn::{set_tos, Ecn, EcnCounts},
+    keys::TestKeys,
+    padding::PaddingPattern,
+    reflect_octets::ReflectOctets,
+    send_queue::queued_bytes,
+    stamp::StampTestPacket,
+    stamp_tlv::Tlv,
+    twamp_test_auth::TwampTestPacketAuth,
+    twamp_test_auth_reflected::TwampTestPacketAuthReflected,
     twamp_test_unauth::TwampTestPacketUnauth,
     twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
 };
 
+/// Number of padding octets appended to each TWAMP-Test packet.
+///
+/// Pads up to the size of the reflected packet (RFC 5357 section 4.1.2) so that reflectors
+/// guarding against amplification don't drop it.
+pub const PADDING_LENGTH: u32 = 27;
+
+/// Number of padding octets appended to each authenticated TWAMP-Test packet, up to the size of
+/// the authenticated reflected packet.
+pub const AUTH_PADDING_LENGTH: usize =
+    TwampTestPacketAuthReflected::MIN_LENGTH - TwampTestPacketAuth::MIN_LENGTH;
+
+/// Sends test packets in trains: `packets` back-to-back, then waits for `gap` before the next
+/// train.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Train {
+    pub packets: u32,
+    pub gap: Duration,
+}
+
+/// Shape of a run of test packets, so a session can cycle through several back-to-back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PacketProfile {
+    /// Number of test packets to send.
+    pub packets: u32,
+
+    /// Padding octets appended to each test packet, truncated to what fits in the
+    /// [MTU](Self::mtu).
+    pub padding_length: u32,
+
+    /// MTU of the path to the Session-Reflector, in bytes. Test packets are kept within it so
+    /// they are not fragmented.
+    pub mtu: usize,
+
+    /// Time to wait between test packets, zero to send them back-to-back.
+    pub interval: Duration,
+
+    /// DSCP to mark test packets with.
+    pub dscp: u8,
+}
+
+impl PacketProfile {
+    /// `packets` test packets with the same shape as [`SessionSender::send_it`] sends.
+    pub fn new(packets: u32) -> Self {
+        PacketProfile {
+            packets,
+            padding_length: PADDING_LENGTH,
+            mtu: ETHERNET_MTU,
+            interval: Duration::ZERO,
+            dscp: 0,
+        }
+    }
+
+    /// Padding octets actually appended to each test packet sent to `reflector`, whose address
+    /// family sets the size of the headers sharing the MTU.
+    pub fn sent_padding_length(&self, reflector: IpAddr) -> u32 {
+        self.padding_length
+            .min(TwampTestPacketUnauth::max_padding_length(self.mtu, reflector) as u32)
+    }
+
+    /// Size in bytes of each test packet sent to `reflector`.
+    pub fn packet_length(&self, reflector: IpAddr) -> usize {
+        TwampTestPacketUnauth::MIN_LENGTH + self.sent_padding_length(reflector) as usize
+    }
+}
+
+/// Decode a reflected packet according to `compliance`, logging the MBZ fields it ignores.
+fn decode<'a, T: DekuRead<'a, bool>>(
+    compliance: Compliance,
+    bytes: &'a [u8],
+) -> Result<T, DekuError> {
+    compliance.read(
+        bytes,
+        |e| debug!(target: TRACING_TARGET, "Ignoring non-zero MBZ: {}", e),
+    )
+}
+
 #[derive(Debug)]
 pub struct SessionSender {
     pub socket: Arc<UdpSocket>,
     pub dest: SocketAddr,
+    train: Option<Train>,
+    on_measurement: Option<MeasurementCallback>,
+    ecn_marking: Ecn,
+    received_ecn: Arc<StdMutex<EcnCounts>>,
+    test_keys: Option<TestKeys>,
+    max_records: Option<usize>,
+    running_stats: Arc<StdMutex<RunningStats>>,
+    reflect_octets: Option<ReflectOctets>,
+    reflected_octets_mismatched: Arc<AtomicU32>,
+    packets_sent: Arc<AtomicU32>,
+    first_sequence_number: u32,
+    compliance: Compliance,
+    calibration: Calibration,
+    pacing: Arc<StdMutex<Vec<PacingStats>>>,
+    stamp: bool,
+    stamp_tlvs: Vec<Tlv>,
+    timestamp_format: TimestampFormat,
+    padding_pattern: PaddingPattern,
+    reflector_counter: bool,
 }
 
 impl SessionSender {
-    pub async fn new(socket: Arc<UdpSocket>, dest: SocketAddrV4) -> Self {
+    pub async fn new(socket: Arc<UdpSocket>, dest: impl Into<SocketAddr>) -> Self {
         Self {
             socket,
-            dest: SocketAddr::V4(dest),
+            dest: dest.into(),
+            train: None,
+            on_measurement: None,
+            ecn_marking: Ecn::NotEct,
+            received_ecn: Arc::default(),
+            test_keys: None,
+            max_records: None,
+            running_stats: Arc::default(),
+            reflect_octets: None,
+            reflected_octets_mismatched: Arc::default(),
+            packets_sent: Arc::default(),
+            first_sequence_number: 0,
+            compliance: Compliance::Strict,
+            calibration: Calibration::default(),
+            pacing: Arc::default(),
+            stamp: false,
+            stamp_tlvs: Vec::new(),
+            timestamp_format: TimestampFormat::Ntp,
+            padding_pattern: PaddingPattern::Zeros,
+            reflector_counter: false,
         }
     }
 
+    /// A Session-Sender for the Session-Reflector at `reflector`, from an ephemeral port of the
+    /// same address family. For TWAMP Light, see [`Self::run_light`].
+    pub async fn connect(reflector: SocketAddr) -> Result<Self> {
+        let local = match reflector {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(reflector).await?;
+        Ok(Self::new(Arc::new(socket), reflector).await)
+    }
+
+    /// Send and expect authenticated test packets protected by `test_keys`. They are always
+    /// padded with [`AUTH_PADDING_LENGTH`] octets, whatever the padding of the
+    /// [`PacketProfile`].
+    pub fn with_test_keys(mut self, test_keys: TestKeys) -> Self {
+        self.test_keys = Some(test_keys);
+        self
+    }
+
+    /// Mark test packets with an ECN codepoint, e.g. ECT(0) to see whether the path marks
+    /// congestion rather than dropping.
+    pub fn with_ecn_marking(mut self, ecn: Ecn) -> Self {
+        self.ecn_marking = ecn;
+        self
+    }
+
+    /// ECN codepoints of the reflected packets received so far.
+    pub fn received_ecn(&self) -> EcnCounts {
+        *self.received_ecn.lock().unwrap()
+    }
+
+    /// Send test packets in trains rather than all back-to-back.
+    pub fn with_train(mut self, train: Train) -> Self {
+        self.train = Some(train);
+        self
+    }
+
+    /// Invoke `callback` for every reflected packet received.
+    pub fn with_measurement_callback(mut self, callback: MeasurementCallback) -> Self {
+        self.on_measurement = Some(callback);
+        self
+    }
+
+    /// Keep at most `max_records` reflected packets in the vector passed to
+    /// [`recv`](Self::recv). Packets beyond that are only folded into
+    /// [`running_stats`](Self::running_stats).
+    pub fn with_max_records(mut self, max_records: usize) -> Self {
+        self.max_records = Some(max_records);
+        self
+    }
+
+    /// Aggregates of every reflected packet received so far, if
+    /// [records are capped](Self::with_max_records).
+    pub fn running_stats(&self) -> Option<RunningStats> {
+        self.max_records
+            .map(|_| self.running_stats.lock().unwrap().clone())
+    }
+
+    /// Start the padding of unauthenticated test packets with the pattern of `reflect_octets`,
+    /// and check reflected packets carry it back.
+    pub fn with_reflect_octets(mut self, reflect_octets: ReflectOctets) -> Self {
+        self.reflect_octets = Some(reflect_octets);
+        self
+    }
+
+    /// Number of reflected packets received so far without the
+    /// [octets to reflect](Self::with_reflect_octets), `None` unless they are checked.
+    pub fn reflected_octets_mismatched(&self) -> Option<u32> {
+        self.reflect_octets
+            .filter(|_| self.test_keys.is_none())
+            .map(|_| self.reflected_octets_mismatched.load(Ordering::Relaxed))
+    }
+
+    /// Number test packets from `first_sequence_number` rather than zero, wrapping. Profiles
+    /// and trains still count their packets from the start of the session.
+    pub fn with_first_sequence_number(mut self, first_sequence_number: u32) -> Self {
+        self.first_sequence_number = first_sequence_number;
+        self
+    }
+
+    /// Parse reflected packets according to `compliance`. Reflected packets failing to parse
+    /// are ignored.
+    pub fn with_compliance(mut self, compliance: Compliance) -> Self {
+        self.compliance = compliance;
+        self
+    }
+
+    /// Shorten the waits between test packets by the timer lateness `calibration` measured.
+    pub fn with_pacing_calibration(mut self, calibration: Calibration) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    /// Send STAMP ([RFC 8762](https://datatracker.ietf.org/doc/html/rfc8762)) test packets
+    /// rather than TWAMP-Test ones, for STAMP Session-Reflectors set up without TWAMP-Control.
+    /// They are always [`StampTestPacket::LENGTH`] bytes and their
+    /// [TLVs](Self::with_stamp_tlvs), whatever the padding of the [`PacketProfile`], and
+    /// unauthenticated. Reflected STAMP packets are parsed as
+    /// [`TwampTestPacketUnauthReflected`], their trailing MBZ octets as padding.
+    pub fn with_stamp(mut self) -> Self {
+        self.stamp = true;
+        self
+    }
+
+    /// Follow [STAMP](Self::with_stamp) test packets with `tlvs`, making them longer by their
+    /// length. Direct Measurement TLVs get the number of test packets sent, this one included.
+    pub fn with_stamp_tlvs(mut self, tlvs: Vec<Tlv>) -> Self {
+        self.stamp_tlvs = tlvs;
+        self
+    }
+
+    /// Timestamp TWAMP-Test packets in `format` rather than NTP, see
+    /// [RFC 8186](https://datatracker.ietf.org/doc/html/rfc8186). Only for Session-Reflectors
+    /// supporting it, as others may drop test packets with the Z bit set. Reflected packets are
+    /// read in whatever format they were sent in.
+    pub fn with_timestamp_format(mut self, format: TimestampFormat) -> Self {
+        self.timestamp_format = format;
+        self
+    }
+
+    /// Fill the padding of unauthenticated test packets with `pattern` rather than zeros. The
+    /// [octets to reflect](Self::with_reflect_octets) still come first.
+    pub fn with_padding_pattern(mut self, pattern: PaddingPattern) -> Self {
+        self.padding_pattern = pattern;
+        self
+    }
+
+    /// Read the Session-Reflector's count of processed test packets into the
+    /// [`Measurement::reflector_counter`] of reflected packets. Only for Session-Reflectors
+    /// agreed to embed one, e.g. a Responder echoing its counter, since otherwise the padding
+    /// copied back from test packets would be read as a counter.
+    pub fn with_reflector_counter(mut self) -> Self {
+        self.reflector_counter = true;
+        self
+    }
+
+    /// Intervals achieved between test packets of each profile sent so far with a non-zero
+    /// interval, in the order they were sent. Gaps between trains are left out.
+    pub fn pacing(&self) -> Vec<PacingStats> {
+        self.pacing.lock().unwrap().clone()
+    }
+
+    /// Sequence Number of the first test packet of the session.
+    pub fn first_sequence_number(&self) -> u32 {
+        self.first_sequence_number
+    }
+
+    /// Sequence Number the next test packet would be sent with.
+    pub fn next_sequence_number(&self) -> u32 {
+        self.first_sequence_number.wrapping_add(self.packets_sent())
+    }
+
+    /// Number of test packets sent so far, over every profile.
+    pub fn packets_sent(&self) -> u32 {
+        self.packets_sent.load(Ordering::Relaxed)
+    }
+
+    /// The STAMP TLVs of the test packet sent `sent`th.
+    fn stamp_tlvs(&self, sent: u32) -> Vec<Tlv> {
+        let mut tlvs = self.stamp_tlvs.clone();
+        for tlv in &mut tlvs {
+            if let Tlv::DirectMeasurement(dm) = tlv {
+                dm.sender_tx = sent;
+            }
+        }
+        tlvs
+    }
+
     pub async fn send_it(&self, number_of_packets: u32) -> Result<()> {
-        info!("Sending Twamp-Test packets to {}", self.dest);
-        for i in 0..number_of_packets {
-            let twamp_test = TwampTestPacketUnauth::new(i, 0, true);
-            trace!("Twamp-Test: {:?}", twamp_test);
-            let encoded = twamp_test.to_bytes().unwrap();
-            let l = self.socket.local_addr().unwrap();
-            let p = self.socket.peer_addr().unwrap();
-            trace!("Sending pkt from {} to {}", l, p);
+        self.send_profile(0, &PacketProfile::new(number_of_packets))
+            .await
+    }
+
+    /// Send the test packets of `profile`, numbered from `first_seq` past the
+    /// [first Sequence Number](Self::with_first_sequence_number), so several profiles can follow
+    /// each other in one session.
+    pub async fn send_profile(&self, first_seq: u32, profile: &PacketProfile) -> Result<()> {
+        info!(
+            target: TRACING_TARGET,
+            dest = %self.dest,
+            number_of_packets = profile.packets,
+            first_seq,
+            "Sending test packets"
+        );
+        if self.stamp && self.test_keys.is_some() {
+            return Err(anyhow!("STAMP test packets cannot be authenticated"));
+        }
+        set_tos(
+            &*self.socket,
+            u32::from(profile.dscp) << 2 | u32::from(self.ecn_marking.bits()),
+        )?;
+        let reflected_pattern = self.reflect_octets.as_ref().map(ReflectOctets::pattern);
+        let mut pacing = PacingStats::new(profile.interval, self.calibration);
+        let mut last_sent: Option<Instant> = None;
+        for i in first_seq..first_seq + profile.packets {
+            if let Some(train) = self.train {
+                if train.packets > 0 && i > 0 && i % train.packets == 0 {
+                    sleep(train.gap).await;
+                    last_sent = None;
+                }
+            }
+            if i > first_seq && !profile.interval.is_zero() {
+                sleep(self.calibration.corrected(profile.interval)).await;
+            }
+            let seq = self.first_sequence_number.wrapping_add(i);
+            let mut twamp_test = TwampTestPacketUnauth::new(
+                seq,
+                profile.sent_padding_length(self.dest.ip()) as usize,
+                true,
+            )
+            .with_timestamp_format(self.timestamp_format)
+            .with_padding_pattern(self.padding_pattern);
+            if let (Some(pattern), None) = (&reflected_pattern, &self.test_keys) {
+                twamp_test = twamp_test.with_padding_octets(pattern);
+            }
+            let encoded = match &self.test_keys {
+                Some(keys) => {
+                    TwampTestPacketAuth::from(twamp_test.clone()).seal(keys, AUTH_PADDING_LENGTH)
+                }
+                None if self.stamp => StampTestPacket::new(seq, true)
+                    .with_tlvs(self.stamp_tlvs(self.packets_sent().wrapping_add(1)))
+                    .to_bytes()
+                    .unwrap(),
+                None => twamp_test.to_bytes().unwrap(),
+            };
             let len = self.socket.send(&encoded[..]).await?;
-            trace!("Twamp-Test sent of bytes: {}", len);
+            if len != encoded.len() {
+                return Err(anyhow!(
+                    "Sent {} of the {} bytes of test packet {}",
+                    len,
+                    encoded.len(),
+                    seq
+                ));
+            }
+            self.packets_sent.fetch_add(1, Ordering::Relaxed);
+            let sent_at = Instant::now();
+            if let Some(last_sent) = last_sent {
+                pacing.record(sent_at - last_sent);
+            }
+            last_sent = Some(sent_at);
+            trace!(
+                target: TRACING_TARGET,
+                seq,
+                bytes = len,
+                content = ?twamp_test,
+                "Sent test packet"
+            );
+        }
+        if !profile.interval.is_zero() {
+            debug!(
+                target: TRACING_TARGET,
+                requested = pacing.requested,
+                mean = pacing.mean,
+                std_dev = pacing.std_dev,
+                "Paced test packets"
+            );
+            self.pacing.lock().unwrap().push(pacing);
         }
         Ok(())
     }
 
+    /// Wait until the test packets sent so far have left the host, rather than being queued in
+    /// the kernel, for at most `max_wait`. Errors if some are still queued by then.
+    pub async fn flush(&self, max_wait: Duration) -> Result<()> {
+        let deadline = Instant::now() + max_wait;
+        loop {
+            let queued = queued_bytes(&*self.socket)?;
+            if queued == 0 {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!("{} bytes of test packets still queued", queued));
+            }
+            trace!(target: TRACING_TARGET, queued, "Waiting for test packets to leave");
+            sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    /// Run a TWAMP Light session ([RFC 5357 Appendix I](https://datatracker.ietf.org/doc/html/rfc5357#appendix-I)):
+    /// send the test packets of `profile` to a Session-Reflector set up out of band, with no
+    /// TWAMP-Control, and collect those reflected until all are or `wait` passes after the last
+    /// one was sent. Returns the reflected packets with the time each was received.
+    pub async fn run_light(
+        &self,
+        profile: &PacketProfile,
+        wait: Duration,
+    ) -> Result<Vec<(TwampTestPacketUnauthReflected, TimeStamp)>> {
+        info!(
+            target: TRACING_TARGET,
+            dest = %self.dest,
+            packets = profile.packets,
+            "Running TWAMP Light session"
+        );
+        let reflected_pkts: Arc<Mutex<Vec<_>>> = Arc::default();
+        if profile.packets == 0 {
+            return Ok(Vec::new());
+        }
+        let mut recv_task = self.spawn_recv(profile.packets, Arc::clone(&reflected_pkts));
+        if let Err(e) = self.send_profile(0, profile).await {
+            recv_task.abort();
+            return Err(e);
+        }
+        if timeout(wait, &mut recv_task).await.is_err() {
+            debug!(
+                target: TRACING_TARGET,
+                ?wait,
+                "Stopped waiting for reflected packets"
+            );
+            recv_task.abort();
+        }
+        let mut reflected_pkts = reflected_pkts.lock().await;
+        Ok(std::mem::take(&mut *reflected_pkts))
+    }
+
     pub async fn recv(
         &self,
         number_of_packets: u32,
         reflected_pkts_shared: Arc<Mutex<Vec<(TwampTestPacketUnauthReflected, TimeStamp)>>>,
     ) {
+        self.spawn_recv(number_of_packets, reflected_pkts_shared)
+            .await
+            .unwrap()
+    }
+
+    /// Receive reflected packets in a task of their own, until `number_of_packets` are.
+    fn spawn_recv(
+        &self,
+        number_of_packets: u32,
+        reflected_pkts_shared: Arc<Mutex<Vec<(TwampTestPacketUnauthReflected, TimeStamp)>>>,
+    ) -> JoinHandle<()> {
         let sock_clone = Arc::clone(&self.socket);
-        let reflect_task = spawn(async move {
+        let on_measurement = self.on_measurement.clone();
+        let received_ecn = Arc::clone(&self.received_ecn);
+        let test_keys = self.test_keys.clone();
+        let max_records = self.max_records;
+        let running_stats = Arc::clone(&self.running_stats);
+        let reflect_octets = self.reflect_octets.filter(|_| self.test_keys.is_none());
+        let reflected_octets_mismatched = Arc::clone(&self.reflected_octets_mismatched);
+        let compliance = self.compliance;
+        let reflector_counter = self.reflector_counter;
+        if let Err(e) = enable_recv_ancillary(&*sock_clone) {
+            warn!(
+                target: TRACING_TARGET,
+                "Cannot read ECN or ancillary data of reflected packets: {}", e
+            );
+        }
+        spawn(async move {
             let mut count: u32 = 1;
+            // Reflected packets may carry back padding as long as test packets can have.
+            let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
             loop {
-                let mut buf = [0u8; 1024]; // Buffer to hold incoming packets
-                let bytes_read = sock_clone.recv(&mut buf).await.unwrap();
-                trace!("Bytes read: {}", bytes_read);
-                let (_rest, reflected_pkt) =
-                    TwampTestPacketUnauthReflected::from_bytes((&buf, 0)).unwrap();
-                trace!("Received reflected pkt: {:?}", reflected_pkt);
-                //debug!("Adding reflector pkt to vec");
+                let (bytes_read, ancillary) =
+                    read_with(&sock_clone, || recv_with_ancillary(&*sock_clone, &mut buf))
+                        .await
+                        .unwrap();
+                // Take T4 before parsing or waiting on the lock, so neither inflates the RTT.
+                let received_at = TimeStamp::default();
+                let decoded = match &test_keys {
+                    Some(keys) => {
+                        let Some(pkt) =
+                            TwampTestPacketAuthReflected::unseal(&buf[..bytes_read], keys)
+                        else {
+                            warn!(
+                                target: TRACING_TARGET,
+                                bytes = bytes_read,
+                                "Ignoring reflected packet with wrong HMAC"
+                            );
+                            continue;
+                        };
+                        decode::<TwampTestPacketAuthReflected>(compliance, &pkt)
+                            .map(TwampTestPacketUnauthReflected::from)
+                    }
+                    None => decode(compliance, &buf[..bytes_read]),
+                };
+                let reflected_pkt = match decoded {
+                    Ok(pkt) => pkt,
+                    Err(e) => {
+                        warn!(
+                            target: TRACING_TARGET,
+                            bytes = bytes_read,
+                            "Ignoring reflected packet that cannot be parsed: {}",
+                            e
+                        );
+                        continue;
+                    }
+                };
+                trace!(
+                    target: TRACING_TARGET,
+                    seq = reflected_pkt.sender_sequence_number,
+                    bytes = bytes_read,
+                    content = ?reflected_pkt,
+                    ?ancillary,
+                    "Received reflected packet"
+                );
+                received_ecn.lock().unwrap().record(ancillary.ecn());
+                if let Some(reflect_octets) =
+                    reflect_octets.filter(|r| !r.matches(&reflected_pkt.packet_padding))
+                {
+                    debug!(
+                        target: TRACING_TARGET,
+                        seq = reflected_pkt.sender_sequence_number,
+                        expected = ?reflect_octets.pattern(),
+                        "Reflected packet without the octets to reflect"
+                    );
+                    reflected_octets_mismatched.fetch_add(1, Ordering::Relaxed);
+                }
+                let measurement = (on_measurement.is_some() || max_records.is_some()).then(|| {
+                    let measurement =
+                        Measurement::new(&reflected_pkt, received_at).with_ancillary(ancillary);
+                    match reflector_counter {
+                        true => measurement.with_reflector_counter(&reflected_pkt),
+                        false => measurement,
+                    }
+                });
+                if let (Some(_), Some(measurement)) = (max_records, &measurement) {
+                    running_stats.lock().unwrap().record(measurement);
+                }
                 let mut acquired_vec = reflected_pkts_shared.lock().await;
-                //debug!("Added reflector pkt to vec");
-                acquired_vec.push((reflected_pkt, TimeStamp::default()));
+                if max_records.is_none_or(|max| acquired_vec.len() < max) {
+                    acquired_vec.push((reflected_pkt, received_at));
+                }
+                drop(acquired_vec);
+                if let (Some(callback), Some(measurement)) = (&on_measurement, measurement) {
+                    callback.call(measurement).await;
+                }
                 if count == number_of_packets {
                     break;
                 }
                 count += 1;
             }
-        });
-        reflect_task.await.unwrap()
+        })
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    /// Reflects the test packets it reads, but the ones numbered `lost`.
+    async fn reflector(lost: u32) -> SocketAddr {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        spawn(async move {
+            let mut buf = [0u8; 1472];
+            for seq in 0.. {
+                let (len, sender) = socket.recv_from(&mut buf).await.unwrap();
+                let recv_ts = TimeStamp::default();
+                let (_rest, pkt) = TwampTestPacketUnauth::from_bytes((&buf[..len], 0)).unwrap();
+                if pkt.sequence_number == lost {
+                    continue;
+                }
+                let reflected = TwampTestPacketUnauthReflected::new(seq, pkt, recv_ts);
+                socket
+                    .send_to(&reflected.to_bytes().unwrap(), sender)
+                    .await
+                    .unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn light_session_needs_no_control_connection() {
+        let session_sender = SessionSender::connect(reflector(u32::MAX).await)
+            .await
+            .unwrap();
+        let profile = PacketProfile {
+            interval: Duration::from_millis(1),
+            dscp: 10,
+            ..PacketProfile::new(5)
+        };
+        let reflected = session_sender
+            .run_light(&profile, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let seqs: Vec<_> = reflected
+            .iter()
+            .map(|(pkt, _)| pkt.sender_sequence_number)
+            .collect();
+        assert_eq!(seqs, [0, 1, 2, 3, 4]);
+        assert_eq!(session_sender.packets_sent(), 5);
+    }
+
+    #[test]
+    fn padding_fits_the_mtu_of_the_address_family() {
+        let profile = PacketProfile {
+            padding_length: 1500,
+            ..PacketProfile::new(1)
+        };
+        assert_eq!(profile.packet_length(Ipv4Addr::LOCALHOST.into()), 1472);
+        assert_eq!(profile.packet_length(Ipv6Addr::LOCALHOST.into()), 1452);
+    }
+
+    #[tokio::test]
+    async fn padding_pattern_fills_test_packets() {
+        let reflector = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let session_sender = SessionSender::connect(reflector.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_padding_pattern(PaddingPattern::Fixed(0xa5));
+        session_sender.send_it(1).await.unwrap();
+        let mut buf = [0u8; 1472];
+        let len = reflector.recv(&mut buf).await.unwrap();
+        let (_rest, pkt) = TwampTestPacketUnauth::from_bytes((&buf[..len], 0)).unwrap();
+        assert_eq!(pkt.packet_padding, [0xa5; PADDING_LENGTH as usize]);
+    }
+
+    #[tokio::test]
+    async fn reflector_counter_is_only_read_when_asked_for() {
+        let reflector = reflector(u32::MAX).await;
+        for (read_counter, counter) in [(false, None), (true, Some(0xa5a5_a5a5))] {
+            let counters = Arc::new(StdMutex::new(Vec::new()));
+            let recorded = Arc::clone(&counters);
+            let mut session_sender = SessionSender::connect(reflector)
+                .await
+                .unwrap()
+                // Copied back by the reflector, which embeds no counter.
+                .with_padding_pattern(PaddingPattern::Fixed(0xa5))
+                .with_measurement_callback(MeasurementCallback::new(move |measurement| {
+                    recorded.lock().unwrap().push(measurement.reflector_counter);
+                    async {}
+                }));
+            if read_counter {
+                session_sender = session_sender.with_reflector_counter();
+            }
+            let profile = PacketProfile {
+                padding_length: 100,
+                ..PacketProfile::new(2)
+            };
+            session_sender
+                .run_light(&profile, Duration::from_secs(5))
+                .await
+                .unwrap();
+            assert_eq!(*counters.lock().unwrap(), [counter; 2]);
+        }
+    }
+
+    #[tokio::test]
+    async fn light_session_stops_waiting_for_lost_packets() {
+        let session_sender = SessionSender::connect(reflector(2).await).await.unwrap();
+        let reflected = session_sender
+            .run_light(&PacketProfile::new(5), Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!(reflected.len(), 4);
+    }
+}
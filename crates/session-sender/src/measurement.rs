@@ -0,0 +1,209 @@
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use timestamp::timestamp::TimeStamp;
+use twamp_test::{
+    ancillary::Ancillary, ecn::Ecn, twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
+};
+
+/// Timestamps of a single reflected TWAMP-Test packet, named after RFC 5357 notation. They are
+/// in NTP format whatever format they were sent in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement {
+    /// Sequence number of the test packet sent by the Session-Sender.
+    pub sequence_number: u32,
+
+    /// T1: when the Session-Sender sent the test packet.
+    pub t1: TimeStamp,
+
+    /// T2: when the Session-Reflector received the test packet.
+    pub t2: TimeStamp,
+
+    /// T3: when the Session-Reflector sent the reflected packet.
+    pub t3: TimeStamp,
+
+    /// T4: when the Session-Sender received the reflected packet.
+    pub t4: TimeStamp,
+
+    /// Round-trip time in seconds, excluding time spent in the Session-Reflector.
+    pub rtt: f64,
+
+    /// Session-Reflector's count of processed test packets, if it
+    /// [embedded one](TwampTestPacketUnauthReflected::with_reflector_counter) and the
+    /// Session-Sender [reads it](crate::SessionSender::with_reflector_counter).
+    pub reflector_counter: Option<u32>,
+
+    /// ECN codepoint the reflected packet arrived with, if it could be read.
+    pub ecn: Option<Ecn>,
+
+    /// Interface, TOS and hop limit the reflected packet arrived with, where the OS passes
+    /// them along.
+    pub ancillary: Ancillary,
+}
+
+impl Measurement {
+    /// Measure `pkt`, received at `received_at`.
+    pub fn new(pkt: &TwampTestPacketUnauthReflected, received_at: TimeStamp) -> Self {
+        let (ntp_t1, ntp_t2, ntp_t3) = pkt.ntp_timestamps();
+        let (t1, t2, t3, t4): (f64, f64, f64, f64) = (
+            ntp_t1.into(),
+            ntp_t2.into(),
+            ntp_t3.into(),
+            received_at.into(),
+        );
+        Measurement {
+            sequence_number: pkt.sender_sequence_number,
+            t1: ntp_t1,
+            t2: ntp_t2,
+            t3: ntp_t3,
+            t4: received_at,
+            rtt: (t4 - t1) - (t3 - t2),
+            reflector_counter: None,
+            ecn: None,
+            ancillary: Ancillary::default(),
+        }
+    }
+
+    /// Read the Session-Reflector's count of processed test packets from the padding of `pkt`.
+    /// Only where the Session-Reflector is known to embed one, as any other padding copied
+    /// back from the test packet reads as a counter too.
+    pub fn with_reflector_counter(mut self, pkt: &TwampTestPacketUnauthReflected) -> Self {
+        self.reflector_counter = pkt.reflector_counter();
+        self
+    }
+
+    /// Record the ECN codepoint the reflected packet arrived with.
+    pub fn with_ecn(mut self, ecn: Option<Ecn>) -> Self {
+        self.ecn = ecn;
+        self
+    }
+
+    /// Record the ancillary data the reflected packet arrived with, ECN codepoint included.
+    pub fn with_ancillary(mut self, ancillary: Ancillary) -> Self {
+        self.ecn = ancillary.ecn();
+        self.ancillary = ancillary;
+        self
+    }
+}
+
+/// Aggregates of reflected packets, folded in one [`Measurement`] at a time so they can be
+/// kept without storing every packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunningStats {
+    received: u32,
+    rtt_min: f64,
+    rtt_max: f64,
+    rtt_sum: f64,
+    owd_forward_sum: f64,
+    owd_reverse_sum: f64,
+    jitter: f64,
+    last_rtt: Option<f64>,
+}
+
+impl Default for RunningStats {
+    fn default() -> Self {
+        RunningStats {
+            received: 0,
+            rtt_min: f64::INFINITY,
+            rtt_max: f64::NEG_INFINITY,
+            rtt_sum: 0.0,
+            owd_forward_sum: 0.0,
+            owd_reverse_sum: 0.0,
+            jitter: 0.0,
+            last_rtt: None,
+        }
+    }
+}
+
+impl RunningStats {
+    /// Fold `measurement` in, in the order reflected packets were received.
+    pub fn record(&mut self, measurement: &Measurement) {
+        let (t1, t2, t3, t4): (f64, f64, f64, f64) = (
+            measurement.t1.into(),
+            measurement.t2.into(),
+            measurement.t3.into(),
+            measurement.t4.into(),
+        );
+        let rtt = measurement.rtt;
+        self.received += 1;
+        self.rtt_min = self.rtt_min.min(rtt);
+        self.rtt_max = self.rtt_max.max(rtt);
+        self.rtt_sum += rtt;
+        self.owd_forward_sum += t2 - t1;
+        self.owd_reverse_sum += t4 - t3;
+        if let Some(last_rtt) = self.last_rtt {
+            self.jitter += ((rtt - last_rtt).abs() - self.jitter) / 16.0;
+        }
+        self.last_rtt = Some(rtt);
+    }
+
+    /// Number of reflected packets folded in.
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    pub fn rtt_min(&self) -> f64 {
+        self.rtt_min
+    }
+
+    pub fn rtt_max(&self) -> f64 {
+        self.rtt_max
+    }
+
+    pub fn rtt_avg(&self) -> f64 {
+        self.rtt_sum / self.received as f64
+    }
+
+    /// Average one-way delay from Session-Sender to Session-Reflector.
+    pub fn owd_forward_avg(&self) -> f64 {
+        self.owd_forward_sum / self.received as f64
+    }
+
+    /// Average one-way delay from Session-Reflector to Session-Sender.
+    pub fn owd_reverse_avg(&self) -> f64 {
+        self.owd_reverse_sum / self.received as f64
+    }
+
+    /// Smoothed RTT variation, as in [RFC 3550](https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.8).
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+}
+
+type BoxedCallback = dyn Fn(Measurement) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync;
+
+/// Async callback invoked by the Session-Sender for each reflected packet it receives.
+///
+/// The Session-Sender awaits the callback before reading the next reflected packet, so slow
+/// callbacks should hand work off rather than block.
+///
+/// # Example
+///
+/// ```
+/// use session_sender::measurement::MeasurementCallback;
+///
+/// let callback = MeasurementCallback::new(|measurement| async move {
+///     println!("seq {}: {:.3}ms", measurement.sequence_number, measurement.rtt * 1e3);
+/// });
+/// ```
+#[derive(Clone)]
+pub struct MeasurementCallback(Arc<BoxedCallback>);
+
+impl MeasurementCallback {
+    pub fn new<F, Fut>(callback: F) -> Self
+    where
+        F: Fn(Measurement) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        MeasurementCallback(Arc::new(move |measurement| Box::pin(callback(measurement))))
+    }
+
+    pub async fn call(&self, measurement: Measurement) {
+        (self.0)(measurement).await
+    }
+}
+
+impl fmt::Debug for MeasurementCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MeasurementCallback")
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use twamp_runtime::time::{sleep, Instant};
+
+/// How late the runtime's timer wakes up, measured by sleeping for a probe interval several
+/// times. Waits between test packets are shortened by that much, so the achieved interval is
+/// closer to the one asked for.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Calibration {
+    /// Mean time past the probe interval the timer woke up at.
+    pub lateness: Duration,
+}
+
+impl Calibration {
+    /// Sleep for `probe` `samples` times and take the mean lateness.
+    pub async fn measure(probe: Duration, samples: u32) -> Self {
+        let mut lateness = Duration::ZERO;
+        for _ in 0..samples {
+            let started_at = Instant::now();
+            sleep(probe).await;
+            lateness += started_at.elapsed().saturating_sub(probe);
+        }
+        Calibration {
+            lateness: lateness / samples.max(1),
+        }
+    }
+
+    /// Time to sleep for the timer to wake up `interval` later.
+    pub fn corrected(&self, interval: Duration) -> Duration {
+        interval.saturating_sub(self.lateness)
+    }
+}
+
+/// Intervals achieved between consecutive test packets against the one asked for, in seconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PacingStats {
+    /// Interval asked for.
+    pub requested: f64,
+
+    /// Time [subtracted](Calibration::corrected) from each wait, zero without calibration.
+    pub correction: f64,
+
+    /// Number of intervals measured.
+    pub intervals: u32,
+
+    /// Mean interval achieved.
+    pub mean: f64,
+
+    /// Standard deviation of the intervals achieved.
+    pub std_dev: f64,
+
+    /// Sum of squared differences from the mean, updated as in Welford's algorithm.
+    m2: f64,
+}
+
+impl PacingStats {
+    pub fn new(requested: Duration, calibration: Calibration) -> Self {
+        PacingStats {
+            requested: requested.as_secs_f64(),
+            correction: calibration.lateness.as_secs_f64(),
+            ..Default::default()
+        }
+    }
+
+    /// Account for one more interval achieved.
+    pub fn record(&mut self, interval: Duration) {
+        let interval = interval.as_secs_f64();
+        self.intervals += 1;
+        let delta = interval - self.mean;
+        self.mean += delta / f64::from(self.intervals);
+        self.m2 += delta * (interval - self.mean);
+        self.std_dev = (self.m2 / f64::from(self.intervals)).sqrt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_are_summarized() {
+        let mut stats = PacingStats::new(Duration::from_millis(10), Calibration::default());
+        for ms in [9, 10, 11, 10] {
+            stats.record(Duration::from_millis(ms));
+        }
+        assert_eq!(stats.intervals, 4);
+        assert!((stats.mean - 0.010).abs() < 1e-9);
+        assert!((stats.std_dev - 0.000_707).abs() < 1e-6);
+        assert_eq!(stats.requested, 0.010);
+
+        let calibration = Calibration {
+            lateness: Duration::from_millis(2),
+        };
+        assert_eq!(
+            calibration.corrected(Duration::from_millis(10)),
+            Duration::from_millis(8)
+        );
+        assert_eq!(
+            calibration.corrected(Duration::from_millis(1)),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+/// Where the Sequence Number of the first test packet of a session comes from. Starting every
+/// session from zero makes sessions run one after the other reuse the same numbers, which
+/// confuses Session-Reflectors and analyzers correlating test packets across sessions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum SequenceStart {
+    /// Start from zero.
+    #[default]
+    Zero,
+
+    /// Start from milliseconds since the Unix epoch, wrapping, so sessions sending at most a
+    /// packet per millisecond never overlap, without keeping any state.
+    WallClock,
+
+    /// Start from the number saved in this file, or zero if there is none, and save the number
+    /// following the last test packet sent once the session ends.
+    Persisted(PathBuf),
+}
+
+impl SequenceStart {
+    /// Sequence Number of the first test packet of the next session.
+    pub fn first_sequence_number(&self) -> Result<u32> {
+        match self {
+            SequenceStart::Zero => Ok(0),
+            SequenceStart::WallClock => {
+                let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                Ok(since_epoch.as_millis() as u32)
+            }
+            SequenceStart::Persisted(path) => match fs::read_to_string(path) {
+                Ok(saved) => saved
+                    .trim()
+                    .parse()
+                    .map_err(|e| anyhow!("Bad sequence number in {}: {}", path.display(), e)),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+                Err(e) => Err(e).with_context(|| format!("Reading {}", path.display())),
+            },
+        }
+    }
+
+    /// Remember `next` as the first Sequence Number of the next session, if persisted.
+    pub fn save(&self, next: u32) -> Result<()> {
+        if let SequenceStart::Persisted(path) = self {
+            fs::write(path, format!("{next}\n"))
+                .with_context(|| format!("Writing {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persisted_sequence_continues_where_it_stopped() {
+        let path = std::env::temp_dir().join(format!("twamp-seq-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let start = SequenceStart::Persisted(path.clone());
+        assert_eq!(start.first_sequence_number().unwrap(), 0);
+        start.save(1000).unwrap();
+        assert_eq!(start.first_sequence_number().unwrap(), 1000);
+        fs::write(&path, "not a number").unwrap();
+        assert!(start.first_sequence_number().is_err());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(SequenceStart::Zero.first_sequence_number().unwrap(), 0);
+        assert!(SequenceStart::Zero.save(1).is_ok());
+    }
+}
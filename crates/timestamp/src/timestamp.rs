@@ -5,7 +5,6 @@ use std::{
     iter::Sum,
     ops::{Add, Sub},
     time::{Duration, SystemTime, UNIX_EPOCH},
-    u32,
 };
 
 /// See [RFC 1305](https://datatracker.ietf.org/doc/html/rfc1305) for the format.
@@ -90,6 +89,15 @@ impl TryFrom<Duration> for TimeStamp {
     }
 }
 
+impl From<TimeStamp> for SystemTime {
+    /// Inverse of `TryFrom<Duration>`. Timestamps before [`UNIX_EPOCH`] are clamped to it.
+    fn from(value: TimeStamp) -> Self {
+        let secs = u64::from(value.integer_part_of_seconds).saturating_sub(NTP_EPOCH);
+        let nanos = value.fractional_part_of_seconds.min(999_999_999);
+        UNIX_EPOCH + Duration::new(secs, nanos)
+    }
+}
+
 impl Display for TimeStamp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -118,6 +126,110 @@ impl TimeStamp {
     }
 }
 
+/// Truncated IEEE 1588v2 (PTP) timestamp of
+/// [RFC 8186](https://datatracker.ietf.org/doc/html/rfc8186#section-2.1): the low 32 bits of the
+/// seconds and the nanoseconds since [`UNIX_EPOCH`].
+#[derive(Clone, Copy, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub struct PtpTimeStamp {
+    seconds: u32,
+    nanoseconds: u32,
+}
+
+impl From<Duration> for PtpTimeStamp {
+    /// Convert from a Duration since [`UNIX_EPOCH`].
+    fn from(value: Duration) -> Self {
+        PtpTimeStamp {
+            seconds: (value.as_secs() % 4_294_967_296u64) as u32,
+            nanoseconds: value.subsec_nanos(),
+        }
+    }
+}
+
+impl From<PtpTimeStamp> for SystemTime {
+    fn from(value: PtpTimeStamp) -> Self {
+        UNIX_EPOCH + Duration::new(value.seconds.into(), value.nanoseconds.min(999_999_999))
+    }
+}
+
+impl From<PtpTimeStamp> for TimeStamp {
+    /// The same instant in NTP format.
+    fn from(value: PtpTimeStamp) -> Self {
+        let since_unix_epoch =
+            Duration::new(value.seconds.into(), value.nanoseconds.min(999_999_999));
+        TimeStamp::try_from(since_unix_epoch).unwrap()
+    }
+}
+
+impl From<TimeStamp> for PtpTimeStamp {
+    /// The same instant in PTP format. Timestamps before [`UNIX_EPOCH`] are clamped to it.
+    fn from(value: TimeStamp) -> Self {
+        let since_unix_epoch = SystemTime::from(value).duration_since(UNIX_EPOCH).unwrap();
+        PtpTimeStamp::from(since_unix_epoch)
+    }
+}
+
+impl Default for PtpTimeStamp {
+    fn default() -> Self {
+        PtpTimeStamp::from(SystemTime::now().duration_since(UNIX_EPOCH).unwrap())
+    }
+}
+
+impl PtpTimeStamp {
+    pub fn seconds(&self) -> u32 {
+        self.seconds
+    }
+
+    pub fn nanoseconds(&self) -> u32 {
+        self.nanoseconds
+    }
+}
+
+/// Format of the timestamps of a test packet, told apart by the Z bit of the Error Estimate
+/// following them, see [RFC 8186](https://datatracker.ietf.org/doc/html/rfc8186#section-2.3).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// [`TimeStamp`], Z of 0.
+    #[default]
+    Ntp,
+
+    /// [`PtpTimeStamp`], Z of 1.
+    Ptp,
+}
+
+impl TimestampFormat {
+    /// The current time in this format, laid out in a [`TimeStamp`] as test packets carry it.
+    pub fn now(self) -> TimeStamp {
+        self.encode(TimeStamp::default())
+    }
+
+    /// `ntp` laid out in a [`TimeStamp`] in this format.
+    pub fn encode(self, ntp: TimeStamp) -> TimeStamp {
+        match self {
+            TimestampFormat::Ntp => ntp,
+            TimestampFormat::Ptp => {
+                let ptp = PtpTimeStamp::from(ntp);
+                TimeStamp {
+                    integer_part_of_seconds: ptp.seconds,
+                    fractional_part_of_seconds: ptp.nanoseconds,
+                }
+            }
+        }
+    }
+
+    /// The NTP timestamp of the same instant as `encoded`, a timestamp in this format as test
+    /// packets carry it.
+    pub fn to_ntp(self, encoded: TimeStamp) -> TimeStamp {
+        match self {
+            TimestampFormat::Ntp => encoded,
+            TimestampFormat::Ptp => TimeStamp::from(PtpTimeStamp {
+                seconds: encoded.integer_part_of_seconds,
+                nanoseconds: encoded.fractional_part_of_seconds,
+            }),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -136,6 +248,40 @@ mod tests {
         assert_eq!(timestamp.fractional_part_of_seconds, fractional_part);
     }
 
+    #[test]
+    fn timestamp_to_system_time() {
+        let duration = Duration::from_nanos(1713088089243932687);
+        let timestamp = TimeStamp::try_from(duration).unwrap();
+        assert_eq!(SystemTime::from(timestamp), UNIX_EPOCH + duration);
+    }
+
+    #[test]
+    fn ptp_timestamp_counts_from_unix_epoch() {
+        let duration = Duration::from_nanos(1713088089243932687);
+        let ptp = PtpTimeStamp::from(duration);
+        assert_eq!(ptp.seconds(), 1713088089);
+        assert_eq!(ptp.nanoseconds(), 243932687);
+        assert_eq!(SystemTime::from(ptp), UNIX_EPOCH + duration);
+    }
+
+    #[test]
+    fn ptp_and_ntp_timestamps_convert_to_each_other() {
+        let duration = Duration::from_nanos(1713088089243932687);
+        let ntp = TimeStamp::try_from(duration).unwrap();
+        let ptp = PtpTimeStamp::from(duration);
+        assert_eq!(PtpTimeStamp::from(ntp), ptp);
+        assert_eq!(TimeStamp::from(ptp), ntp);
+    }
+
+    #[test]
+    fn timestamp_formats_round_trip_through_encoding() {
+        let ntp = TimeStamp::try_from(Duration::from_secs(1_700_000_000)).unwrap();
+        assert_eq!(TimestampFormat::Ntp.encode(ntp), ntp);
+        let encoded = TimestampFormat::Ptp.encode(ntp);
+        assert_eq!(encoded.integer_part_of_seconds(), 1_700_000_000);
+        assert_eq!(TimestampFormat::Ptp.to_ntp(encoded), ntp);
+    }
+
     #[test]
     fn subtraction_from_bigger_to_smaller() {
         let t1 = TimeStamp {
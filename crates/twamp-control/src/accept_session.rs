@@ -3,13 +3,13 @@ use deku::prelude::*;
 
 /// Response for a Request-TW-Session command.
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
 pub struct AcceptSession {
     /// Represents Server's willingness to continue or reject.
     pub accept: Accept,
 
     /// MBZ (Must Be Zero).
-    #[deku(assert_eq = "0u8")]
+    #[deku(assert = "lenient || *mbz_first == 0u8")]
     mbz_first: u8,
 
     /// Either the port that was present in Request-TW-Session or an alternative port in case the
@@ -31,7 +31,7 @@ pub struct AcceptSession {
     pub server_octets: u16,
 
     /// MBZ (Must Be Zero).
-    #[deku(assert_eq = "[0u8; 8]")]
+    #[deku(assert = "lenient || *mbz_second == [0u8; 8]")]
     mbz_second: [u8; 8],
 
     pub hmac: [u8; 16],
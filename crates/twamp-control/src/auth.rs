@@ -0,0 +1,288 @@
+//! Keys, HMACs and encryption of the keyed modes,
+//! [authenticated](crate::security_mode::Mode::Authenticated) and
+//! [encrypted](crate::security_mode::Mode::Encrypted).
+//!
+//! See details in [RFC 4656](https://datatracker.ietf.org/doc/html/rfc4656#section-3.1).
+
+use std::fmt;
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::rng::{OsRngSource, RngSource};
+
+/// Length in bytes of the HMAC field closing authenticated control messages.
+pub const HMAC_LENGTH: usize = 16;
+
+/// Length in bytes of the KeyID field of Set-Up-Response.
+pub const KEY_ID_LENGTH: usize = 80;
+
+/// A shared secret and the KeyID both ends know it by.
+#[derive(Clone, PartialEq)]
+pub struct SharedSecret {
+    key_id: String,
+    secret: Vec<u8>,
+}
+
+impl fmt::Debug for SharedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedSecret")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SharedSecret {
+    /// Errors if `key_id` does not fit in the 80 bytes of the KeyID field.
+    pub fn new(key_id: impl Into<String>, secret: impl Into<Vec<u8>>) -> Result<Self, String> {
+        let key_id = key_id.into();
+        if key_id.len() > KEY_ID_LENGTH {
+            return Err(format!(
+                "KeyID is {} bytes, at most {} fit in Set-Up-Response",
+                key_id.len(),
+                KEY_ID_LENGTH
+            ));
+        }
+        Ok(SharedSecret {
+            key_id,
+            secret: secret.into(),
+        })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// KeyID as sent in Set-Up-Response, padded with zeros.
+    pub fn key_id_field(&self) -> [u8; KEY_ID_LENGTH] {
+        let mut field = [0; KEY_ID_LENGTH];
+        field[..self.key_id.len()].copy_from_slice(self.key_id.as_bytes());
+        field
+    }
+
+    /// Whether `field`, a KeyID as sent in Set-Up-Response, names this secret.
+    pub fn matches(&self, field: &[u8; KEY_ID_LENGTH]) -> bool {
+        *field == self.key_id_field()
+    }
+
+    /// Key encrypting the Token, derived with PBKDF2-HMAC-SHA1 from the secret and the Salt and
+    /// Count of the Server Greeting.
+    pub fn derive_key(&self, salt: &[u8; 16], count: u32) -> [u8; 16] {
+        let mut key = [0; 16];
+        pbkdf2::pbkdf2_hmac::<Sha1>(&self.secret, salt, count, &mut key);
+        key
+    }
+}
+
+/// Session keys chosen by the Control-Client and sent to the Server in the Token.
+#[derive(Clone, PartialEq)]
+pub struct SessionKeys {
+    pub aes: [u8; 16],
+    pub hmac: [u8; 32],
+}
+
+impl fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionKeys").finish_non_exhaustive()
+    }
+}
+
+impl SessionKeys {
+    /// Fresh keys from the OS CSPRNG.
+    pub fn random() -> Self {
+        Self::random_from(&OsRngSource)
+    }
+
+    /// Fresh keys from `rng`.
+    pub fn random_from(rng: &dyn RngSource) -> Self {
+        let mut keys = SessionKeys {
+            aes: [0; 16],
+            hmac: [0; 32],
+        };
+        rng.fill_bytes(&mut keys.aes);
+        rng.fill_bytes(&mut keys.hmac);
+        keys
+    }
+
+    /// Token of Set-Up-Response: the `challenge` and these keys, encrypted in AES-CBC with a zero
+    /// IV under `key` from [`SharedSecret::derive_key`].
+    pub fn to_token(&self, challenge: &[u8; 16], key: &[u8; 16]) -> [u8; 64] {
+        let mut token = [0; 64];
+        token[..16].copy_from_slice(challenge);
+        token[16..32].copy_from_slice(&self.aes);
+        token[32..].copy_from_slice(&self.hmac);
+        let cipher = Aes128::new(key.into());
+        let mut previous = [0; 16];
+        for block in token.chunks_exact_mut(16) {
+            block.iter_mut().zip(previous).for_each(|(b, p)| *b ^= p);
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+            previous.copy_from_slice(block);
+        }
+        token
+    }
+
+    /// Decrypt a Token made by [`to_token`](Self::to_token), returning the challenge it
+    /// carries along with the keys.
+    pub fn from_token(token: &[u8; 64], key: &[u8; 16]) -> ([u8; 16], Self) {
+        let cipher = Aes128::new(key.into());
+        let mut plain = *token;
+        let mut previous = [0; 16];
+        for block in plain.chunks_exact_mut(16) {
+            let encrypted: [u8; 16] = block.try_into().unwrap();
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+            block.iter_mut().zip(previous).for_each(|(b, p)| *b ^= p);
+            previous = encrypted;
+        }
+        let keys = SessionKeys {
+            aes: plain[16..32].try_into().unwrap(),
+            hmac: plain[32..].try_into().unwrap(),
+        };
+        (plain[..16].try_into().unwrap(), keys)
+    }
+
+    fn mac(&self) -> Hmac<Sha1> {
+        <Hmac<Sha1> as Mac>::new_from_slice(&self.hmac).unwrap()
+    }
+
+    /// HMAC-SHA1 of `bytes` under the HMAC session key, truncated to 16 bytes.
+    pub fn hmac(&self, bytes: &[u8]) -> [u8; HMAC_LENGTH] {
+        self.mac().chain_update(bytes).finalize().into_bytes()[..HMAC_LENGTH]
+            .try_into()
+            .unwrap()
+    }
+
+    /// Fill the HMAC field ending an encoded control message.
+    pub fn sign(&self, message: &mut [u8]) {
+        let (covered, hmac) = message.split_at_mut(message.len() - HMAC_LENGTH);
+        hmac.copy_from_slice(&self.hmac(covered));
+    }
+
+    /// Whether the HMAC field ending an encoded control message is correct.
+    pub fn verify(&self, message: &[u8]) -> bool {
+        let (covered, hmac) = message.split_at(message.len() - HMAC_LENGTH);
+        self.mac()
+            .chain_update(covered)
+            .verify_truncated_left(hmac)
+            .is_ok()
+    }
+}
+
+/// AES-CBC over one direction of TWAMP-Control, chained from one message to the next.
+///
+/// In keyed modes everything the Control-Client sends after Set-Up-Response is encrypted,
+/// starting from its Client-IV, and everything the Server sends after the Server-IV of
+/// Server-Start, starting from that IV.
+#[derive(Clone)]
+pub struct ControlCipher {
+    cipher: Aes128,
+    previous: [u8; 16],
+}
+
+impl fmt::Debug for ControlCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ControlCipher").finish_non_exhaustive()
+    }
+}
+
+impl ControlCipher {
+    /// Cipher under the AES session key, starting from `iv`.
+    pub fn new(aes_session_key: &[u8; 16], iv: &[u8; 16]) -> Self {
+        ControlCipher {
+            cipher: Aes128::new(aes_session_key.into()),
+            previous: *iv,
+        }
+    }
+
+    /// Encrypt `bytes` in place. Their length must be a multiple of 16.
+    pub fn encrypt(&mut self, bytes: &mut [u8]) {
+        for block in bytes.chunks_exact_mut(16) {
+            block
+                .iter_mut()
+                .zip(self.previous)
+                .for_each(|(b, p)| *b ^= p);
+            self.cipher
+                .encrypt_block(GenericArray::from_mut_slice(block));
+            self.previous.copy_from_slice(block);
+        }
+    }
+
+    /// Decrypt `bytes` in place. Their length must be a multiple of 16.
+    pub fn decrypt(&mut self, bytes: &mut [u8]) {
+        for block in bytes.chunks_exact_mut(16) {
+            let encrypted: [u8; 16] = block.try_into().unwrap();
+            self.cipher
+                .decrypt_block(GenericArray::from_mut_slice(block));
+            block
+                .iter_mut()
+                .zip(self.previous)
+                .for_each(|(b, p)| *b ^= p);
+            self.previous = encrypted;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_id_longer_than_field_is_refused() {
+        assert!(SharedSecret::new("k".repeat(KEY_ID_LENGTH), "secret").is_ok());
+        assert!(SharedSecret::new("k".repeat(KEY_ID_LENGTH + 1), "secret").is_err());
+    }
+
+    #[test]
+    fn key_depends_on_secret_salt_and_count() {
+        let secret = SharedSecret::new("probe", "password").unwrap();
+        let key = secret.derive_key(&[1; 16], 1024);
+        assert_eq!(key, secret.derive_key(&[1; 16], 1024));
+        assert_ne!(key, secret.derive_key(&[2; 16], 1024));
+        assert_ne!(key, secret.derive_key(&[1; 16], 1025));
+        let other = SharedSecret::new("probe", "drowssap").unwrap();
+        assert_ne!(key, other.derive_key(&[1; 16], 1024));
+    }
+
+    #[test]
+    fn token_round_trips() {
+        let keys = SessionKeys::random();
+        let key = [7; 16];
+        let token = keys.to_token(&[1; 16], &key);
+        assert_ne!(token[16..32], keys.aes);
+        let (challenge, decrypted) = SessionKeys::from_token(&token, &key);
+        assert_eq!(challenge, [1; 16]);
+        assert_eq!(decrypted, keys);
+        let (challenge, _) = SessionKeys::from_token(&token, &[8; 16]);
+        assert_ne!(challenge, [1; 16]);
+    }
+
+    #[test]
+    fn control_cipher_chains_across_messages() {
+        let mut sender = ControlCipher::new(&[1; 16], &[2; 16]);
+        let mut receiver = ControlCipher::new(&[1; 16], &[2; 16]);
+        let mut first = [3u8; 48];
+        let mut second = [3u8; 32];
+        sender.encrypt(&mut first);
+        sender.encrypt(&mut second);
+        assert_ne!(first[..16], first[16..32]);
+        assert_ne!(first[..32], second);
+        receiver.decrypt(&mut first);
+        receiver.decrypt(&mut second);
+        assert_eq!(first, [3; 48]);
+        assert_eq!(second, [3; 32]);
+    }
+
+    #[test]
+    fn signed_message_verifies_until_tampered() {
+        let keys = SessionKeys::random();
+        let mut message = [5u8; 48];
+        keys.sign(&mut message);
+        assert!(keys.verify(&message));
+        message[0] ^= 1;
+        assert!(!keys.verify(&message));
+        assert!(!SessionKeys::random().verify(&[5u8; 48]));
+    }
+}
@@ -0,0 +1,311 @@
+//! Framing of TWAMP-Control messages on a byte stream, with the `tokio` feature.
+//!
+//! [`ControlCodec`] splits the bytes read into whole messages, however TCP segmented or
+//! coalesced them. Commands are told apart by their Command Number, other messages by which
+//! one is [expected](ControlCodec::expect) next. In keyed modes it also decrypts the messages
+//! read and checks their HMAC, and signs and encrypts those written.
+
+use std::{fmt, io};
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::*;
+
+use crate::{
+    auth::{ControlCipher, SessionKeys},
+    command_number::CommandNumber,
+    constants::{Messages, TRACING_TARGET},
+};
+
+/// A whole message read by [`ControlCodec`], decrypted in keyed modes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub message: Messages,
+    pub bytes: BytesMut,
+}
+
+/// Why [`ControlCodec`] could not read or write a message.
+#[derive(Debug)]
+pub enum CodecError {
+    Io(io::Error),
+
+    /// The Command Number is not defined.
+    UnknownCommand(u8),
+
+    /// The Command Number is defined, but not for a TWAMP command, e.g. OWAMP's Fetch-Session.
+    Unsupported(CommandNumber),
+
+    /// The HMAC of the message does not match its content, in keyed modes.
+    WrongHmac(Messages),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Io(e) => write!(f, "{}", e),
+            CodecError::UnknownCommand(command) => write!(f, "Unknown command {}", command),
+            CodecError::Unsupported(command) => write!(f, "Unsupported {:?}", command),
+            CodecError::WrongHmac(message) => {
+                write!(f, "{} has a wrong HMAC", message.msg_type())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CodecError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+
+/// [`Decoder`] and [`Encoder`] of TWAMP-Control messages, for
+/// [`Framed`](tokio_util::codec::Framed) over a [`ControlStream`](crate::stream::ControlStream).
+///
+/// Messages are written already encoded, e.g. by `to_bytes`, and read as [`Frame`]s to decode.
+#[derive(Debug, Default)]
+pub struct ControlCodec {
+    /// Message to read next, if it can't be told by its Command Number.
+    expected: Option<Messages>,
+    /// Keys and cipher of the messages written, in keyed modes.
+    sealed: Option<(SessionKeys, ControlCipher)>,
+    /// Keys and cipher of the messages read, in keyed modes.
+    opened: Option<(SessionKeys, ControlCipher)>,
+}
+
+impl ControlCodec {
+    /// Codec in the clear, telling the messages read apart by their Command Number.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `message` next, whatever its first byte. Messages without a Command Number, e.g. the
+    /// Server Greeting, can only be read this way.
+    pub fn expect(&mut self, message: Messages) {
+        self.expected = Some(message);
+    }
+
+    /// Sign and encrypt every message written from now on with `cipher`, as keyed modes do after
+    /// Set-Up-Response from the Control-Client and after the Server-IV from the Server.
+    pub fn seal_with(&mut self, keys: SessionKeys, cipher: ControlCipher) {
+        self.sealed = Some((keys, cipher));
+    }
+
+    /// Decrypt every message read from now on with `cipher` and check its HMAC, the other end of
+    /// [`seal_with`](Self::seal_with).
+    pub fn open_with(&mut self, keys: SessionKeys, cipher: ControlCipher) {
+        self.opened = Some((keys, cipher));
+    }
+
+    /// Command starting `src`, once its first block has arrived. The block is decrypted from a
+    /// copy of the cipher, which only moves on once the whole message is read.
+    fn peek_command(&self, src: &BytesMut) -> Result<Option<Messages>, CodecError> {
+        let Some(block) = src.get(..16) else {
+            return Ok(None);
+        };
+        let mut block: [u8; 16] = block.try_into().unwrap();
+        if let Some((_keys, cipher)) = &self.opened {
+            cipher.clone().decrypt(&mut block);
+        }
+        let command =
+            CommandNumber::try_from(block[0]).map_err(|_| CodecError::UnknownCommand(block[0]))?;
+        Messages::from_command(command)
+            .map(Some)
+            .ok_or(CodecError::Unsupported(command))
+    }
+}
+
+impl Decoder for ControlCodec {
+    type Item = Frame;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, CodecError> {
+        let message = match self.expected {
+            Some(message) => message,
+            None => match self.peek_command(src)? {
+                Some(message) => message,
+                None => return Ok(None),
+            },
+        };
+        if src.len() < message.length() {
+            src.reserve(message.length() - src.len());
+            return Ok(None);
+        }
+        let mut bytes = src.split_to(message.length());
+        self.expected = None;
+        if let Some((keys, cipher)) = &mut self.opened {
+            cipher.decrypt(&mut bytes);
+            if !keys.verify(&bytes) {
+                warn!(target: TRACING_TARGET, msg_type = message.msg_type(), "Wrong HMAC");
+                return Err(CodecError::WrongHmac(message));
+            }
+        }
+        Ok(Some(Frame { message, bytes }))
+    }
+}
+
+impl Encoder<Vec<u8>> for ControlCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, mut message: Vec<u8>, dst: &mut BytesMut) -> Result<(), CodecError> {
+        if let Some((keys, cipher)) = &mut self.sealed {
+            keys.sign(&mut message);
+            cipher.encrypt(&mut message);
+        }
+        dst.extend_from_slice(&message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accept::Accept, request_tw_session::RequestTwSession, security_mode::Mode,
+        server_greeting::ServerGreeting, set_up_response::SetUpResponse,
+        start_sessions::StartSessions, stop_sessions::StopSessions, timers::SessionTimeout,
+    };
+    use deku::DekuContainerWrite;
+    use std::net::Ipv4Addr;
+
+    /// Frames read from `bytes` arriving in chunks of `chunk` bytes.
+    fn decode_in_chunks(codec: &mut ControlCodec, bytes: &[u8], chunk: usize) -> Vec<Frame> {
+        let mut src = BytesMut::new();
+        let mut frames = Vec::new();
+        for chunk in bytes.chunks(chunk) {
+            src.extend_from_slice(chunk);
+            while let Some(frame) = codec.decode(&mut src).unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert!(src.is_empty());
+        frames
+    }
+
+    fn commands() -> Vec<u8> {
+        let mut bytes = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            2,
+            None,
+            SessionTimeout::DEFAULT,
+        )
+        .to_bytes()
+        .unwrap();
+        bytes.extend(StartSessions::new().to_bytes().unwrap());
+        bytes.extend(StopSessions::new(Accept::Ok).to_bytes().unwrap());
+        bytes
+    }
+
+    #[test]
+    fn reads_messages_however_they_are_segmented() {
+        let mut bytes = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        bytes.extend(commands());
+        for chunk in [1, 7, 16, 100, bytes.len()] {
+            let mut codec = ControlCodec::new();
+            codec.expect(Messages::SetUpResponse);
+            let frames = decode_in_chunks(&mut codec, &bytes, chunk);
+            let messages: Vec<_> = frames.iter().map(|frame| frame.message).collect();
+            assert_eq!(
+                messages,
+                [
+                    Messages::SetUpResponse,
+                    Messages::RequestTwSession,
+                    Messages::StartSessions,
+                    Messages::StopSessions
+                ],
+                "in chunks of {}",
+                chunk
+            );
+            assert_eq!(frames[3].bytes[..], commands()[144..]);
+        }
+    }
+
+    #[test]
+    fn reads_expected_message_without_command_number() {
+        let greeting = ServerGreeting::new(&[Mode::Unauthenticated])
+            .to_bytes()
+            .unwrap();
+        let mut codec = ControlCodec::new();
+        codec.expect(Messages::ServerGreeting);
+        let frames = decode_in_chunks(&mut codec, &greeting, 10);
+        assert_eq!(frames[0].message, Messages::ServerGreeting);
+        assert_eq!(frames[0].bytes[..], greeting[..]);
+    }
+
+    #[test]
+    fn refuses_commands_twamp_does_not_use() {
+        let mut codec = ControlCodec::new();
+        let mut src = BytesMut::from(&[0xffu8; 32][..]);
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(CodecError::UnknownCommand(0xff))
+        ));
+        let mut src = BytesMut::from(&[4u8; 32][..]);
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(CodecError::Unsupported(CommandNumber::FetchSession))
+        ));
+    }
+
+    #[test]
+    fn sealed_messages_are_opened_across_segments() {
+        let keys = SessionKeys::random();
+        let iv = [7; 16];
+        let mut sender = ControlCodec::new();
+        sender.seal_with(keys.clone(), ControlCipher::new(&keys.aes, &iv));
+        let mut bytes = BytesMut::new();
+        let commands = commands();
+        for message in [&commands[..112], &commands[112..144], &commands[144..]] {
+            sender.encode(message.to_vec(), &mut bytes).unwrap();
+        }
+        assert_ne!(bytes[..], commands[..]);
+
+        let mut receiver = ControlCodec::new();
+        receiver.open_with(keys.clone(), ControlCipher::new(&keys.aes, &iv));
+        let frames = decode_in_chunks(&mut receiver, &bytes, 5);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].message, Messages::StartSessions);
+        let mut signed = StartSessions::new().to_bytes().unwrap();
+        keys.sign(&mut signed);
+        assert_eq!(frames[1].bytes[..], signed[..]);
+    }
+
+    #[test]
+    fn wrong_hmac_is_refused() {
+        let iv = [7; 16];
+        let mut sender = ControlCodec::new();
+        let sender_keys = SessionKeys::random();
+        sender.seal_with(
+            sender_keys.clone(),
+            ControlCipher::new(&sender_keys.aes, &iv),
+        );
+        let mut bytes = BytesMut::new();
+        sender
+            .encode(StartSessions::new().to_bytes().unwrap(), &mut bytes)
+            .unwrap();
+
+        let mut receiver = ControlCodec::new();
+        let other_keys = SessionKeys {
+            hmac: [1; 32],
+            ..sender_keys
+        };
+        receiver.open_with(other_keys.clone(), ControlCipher::new(&other_keys.aes, &iv));
+        assert!(matches!(
+            receiver.decode(&mut bytes),
+            Err(CodecError::WrongHmac(Messages::StartSessions))
+        ));
+    }
+}
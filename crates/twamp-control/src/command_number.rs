@@ -1,16 +1,18 @@
 use deku::prelude::*;
-use num_enum::IntoPrimitive;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 /// Values of Command Number.
 ///
 /// Defined in [RFC 5357](https://datatracker.ietf.org/doc/html/rfc5357/#section-8.4).
-#[derive(Clone, Copy, Debug, PartialEq, IntoPrimitive, DekuRead, DekuWrite)]
+#[derive(Clone, Copy, Debug, PartialEq, IntoPrimitive, TryFromPrimitive, DekuRead, DekuWrite)]
 #[repr(u8)]
 #[deku(type = "u8", endian = "endian", ctx = "endian: deku::ctx::Endian")]
 pub enum CommandNumber {
     Forbidden = 1,
     StartSessions = 2,
     StopSessions = 3,
+    /// OWAMP only, not used by TWAMP.
+    FetchSession = 4,
     RequestTwSession = 5,
     Experimentation = 6,
 }
@@ -24,12 +26,21 @@ mod tests {
         let forbidden: u8 = CommandNumber::Forbidden.into();
         let start_session: u8 = CommandNumber::StartSessions.into();
         let stop_session: u8 = CommandNumber::StopSessions.into();
+        let fetch_session: u8 = CommandNumber::FetchSession.into();
         let request_tw_session: u8 = CommandNumber::RequestTwSession.into();
         let experimentation: u8 = CommandNumber::Experimentation.into();
         assert_eq!(forbidden, 1u8);
         assert_eq!(start_session, 2u8);
         assert_eq!(stop_session, 3u8);
+        assert_eq!(fetch_session, 4u8);
         assert_eq!(request_tw_session, 5u8);
         assert_eq!(experimentation, 6u8);
     }
+
+    #[test]
+    fn unknown_values_are_refused() {
+        assert_eq!(CommandNumber::try_from(4), Ok(CommandNumber::FetchSession));
+        assert!(CommandNumber::try_from(0).is_err());
+        assert!(CommandNumber::try_from(7).is_err());
+    }
 }
@@ -0,0 +1,64 @@
+use deku::{bitvec::BitView, DekuError, DekuRead};
+
+/// How strictly TWAMP-Control messages and TWAMP-Test packets are checked when parsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compliance {
+    /// Reject messages and packets whose MBZ fields are not zero.
+    #[default]
+    Strict,
+
+    /// Ignore the content of MBZ fields, as RFC 4656 asks receivers to, for implementations
+    /// that fill them with garbage.
+    Lenient,
+}
+
+impl Compliance {
+    /// Decode `T` from the start of `bytes`. If only lenient decoding succeeds, `ignored` is
+    /// given the error strict decoding failed with, so non-zero MBZ fields can be logged.
+    pub fn read<'a, T>(
+        self,
+        bytes: &'a [u8],
+        ignored: impl FnOnce(&DekuError),
+    ) -> Result<T, DekuError>
+    where
+        T: DekuRead<'a, bool>,
+    {
+        let strict = T::read(bytes.view_bits(), false);
+        let (_rest, value) = match (self, strict) {
+            (Compliance::Lenient, Err(e @ DekuError::Assertion(_))) => {
+                let lenient = T::read(bytes.view_bits(), true)?;
+                ignored(&e);
+                lenient
+            }
+            (_, strict) => strict?,
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{accept::Accept, start_ack::StartAck};
+    use deku::DekuContainerWrite;
+
+    #[test]
+    fn lenient_ignores_non_zero_mbz() {
+        let mut start_ack = StartAck::new(Accept::Ok).to_bytes().unwrap();
+        start_ack[1] = 0xff;
+        let mut ignored = None;
+        assert!(Compliance::Strict
+            .read::<StartAck>(&start_ack, |_| unreachable!())
+            .is_err());
+        let parsed: StartAck = Compliance::Lenient
+            .read(&start_ack, |e| ignored = Some(e.to_string()))
+            .unwrap();
+        assert_eq!(parsed.accept, Accept::Ok);
+        assert!(ignored.unwrap().contains("mbz"));
+
+        let compliant = StartAck::new(Accept::Ok).to_bytes().unwrap();
+        Compliance::Lenient
+            .read::<StartAck>(&compliant, |_| panic!("nothing to ignore"))
+            .unwrap();
+    }
+}
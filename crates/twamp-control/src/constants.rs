@@ -1,9 +1,136 @@
+use crate::command_number::CommandNumber;
+
 pub const TWAMP_CONTROL_WELL_KNOWN_PORT: u16 = 862;
 
-#[derive(PartialEq)]
+/// Target of tracing events about TWAMP-Control.
+pub const TRACING_TARGET: &str = "twamp::control";
+
+/// TWAMP-Control messages, as far as telling them apart on the wire goes.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Messages {
+    ServerGreeting,
     SetUpResponse,
+    ServerStart,
     RequestTwSession,
+    AcceptSession,
     StartSessions,
+    StartAck,
     StopSessions,
 }
+
+impl Messages {
+    /// Length in bytes of the message on the wire.
+    pub fn length(&self) -> usize {
+        match self {
+            Messages::ServerGreeting => 64,
+            Messages::SetUpResponse => 164,
+            Messages::ServerStart => 48,
+            Messages::RequestTwSession => 112,
+            Messages::AcceptSession => 48,
+            Messages::StartSessions => 32,
+            Messages::StartAck => 32,
+            Messages::StopSessions => 32,
+        }
+    }
+
+    /// Command the Command Number stands for, `None` if TWAMP does not use it.
+    pub fn from_command(command: CommandNumber) -> Option<Self> {
+        match command {
+            CommandNumber::RequestTwSession => Some(Messages::RequestTwSession),
+            CommandNumber::StartSessions => Some(Messages::StartSessions),
+            CommandNumber::StopSessions => Some(Messages::StopSessions),
+            _ => None,
+        }
+    }
+
+    /// Command Number of the command, `None` for messages that are not commands.
+    pub fn command_number(&self) -> Option<CommandNumber> {
+        match self {
+            Messages::RequestTwSession => Some(CommandNumber::RequestTwSession),
+            Messages::StartSessions => Some(CommandNumber::StartSessions),
+            Messages::StopSessions => Some(CommandNumber::StopSessions),
+            _ => None,
+        }
+    }
+
+    /// Name of the message as used in tracing events.
+    pub fn msg_type(&self) -> &'static str {
+        match self {
+            Messages::ServerGreeting => "Server Greeting",
+            Messages::SetUpResponse => "Set-Up-Response",
+            Messages::ServerStart => "Server-Start",
+            Messages::RequestTwSession => "Request-TW-Session",
+            Messages::AcceptSession => "Accept-Session",
+            Messages::StartSessions => "Start-Sessions",
+            Messages::StartAck => "Start-Ack",
+            Messages::StopSessions => "Stop-Sessions",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accept::Accept, accept_session::AcceptSession, request_tw_session::RequestTwSession,
+        security_mode::Mode, server_greeting::ServerGreeting, server_start::ServerStart,
+        set_up_response::SetUpResponse, start_ack::StartAck, start_sessions::StartSessions,
+        stop_sessions::StopSessions, timers::SessionTimeout,
+    };
+    use deku::DekuContainerWrite;
+    use std::{net::Ipv4Addr, time::Duration};
+
+    #[test]
+    fn lengths_match_serialized_messages() {
+        let set_up_response = SetUpResponse::new(Mode::Unauthenticated).unwrap();
+        let request_tw_session = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            0,
+            Ipv4Addr::LOCALHOST,
+            0,
+            None,
+            SessionTimeout::DEFAULT,
+        );
+        assert_eq!(
+            Messages::SetUpResponse.length(),
+            set_up_response.to_bytes().unwrap().len()
+        );
+        assert_eq!(
+            Messages::RequestTwSession.length(),
+            request_tw_session.to_bytes().unwrap().len()
+        );
+        assert_eq!(
+            Messages::StartSessions.length(),
+            StartSessions::new().to_bytes().unwrap().len()
+        );
+        assert_eq!(
+            Messages::StopSessions.length(),
+            StopSessions::new(Accept::Ok).to_bytes().unwrap().len()
+        );
+        assert_eq!(
+            Messages::ServerGreeting.length(),
+            ServerGreeting::new(&[Mode::Unauthenticated])
+                .to_bytes()
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            Messages::ServerStart.length(),
+            ServerStart::new(Accept::Ok, Duration::ZERO)
+                .to_bytes()
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            Messages::AcceptSession.length(),
+            AcceptSession::new(Accept::Ok, 0, 0, 0)
+                .to_bytes()
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            Messages::StartAck.length(),
+            StartAck::new(Accept::Ok).to_bytes().unwrap().len()
+        );
+    }
+}
@@ -1,8 +1,17 @@
+// `DekuRead` derive expands to a manual `div_ceil`, which we have no control over.
+#![allow(clippy::manual_div_ceil)]
+
 pub mod accept;
 pub mod accept_session;
+pub mod auth;
+#[cfg(feature = "tokio")]
+pub mod codec;
 pub mod command_number;
+pub mod compliance;
 pub mod constants;
+pub mod message;
 pub mod request_tw_session;
+pub mod rng;
 pub mod security_mode;
 pub mod server_greeting;
 pub mod server_start;
@@ -10,3 +19,6 @@ pub mod set_up_response;
 pub mod start_ack;
 pub mod start_sessions;
 pub mod stop_sessions;
+#[cfg(feature = "tokio")]
+pub mod stream;
+pub mod timers;
@@ -0,0 +1,177 @@
+use std::fmt;
+
+use deku::DekuContainerRead;
+
+use crate::{
+    command_number::CommandNumber, compliance::Compliance, constants::Messages,
+    request_tw_session::RequestTwSession, start_sessions::StartSessions,
+    stop_sessions::StopSessions,
+};
+
+/// TWAMP-Control command parsed from bytes, told apart from the others by its Command Number.
+/// Messages without one, e.g. Server Greeting or Accept-Session, can only be told apart by their
+/// place in the exchange. Not to be confused with the `ControlMessage` of `control-client`, which
+/// names the messages a Control-Client waits for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParsedMessage {
+    RequestTwSession(RequestTwSession),
+    StartSessions(StartSessions),
+    StopSessions(StopSessions),
+}
+
+/// Why bytes could not be [parsed](ParsedMessage::parse) as a command.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    /// The bytes hold only part of the message, which is `needed` bytes long.
+    Incomplete { needed: usize },
+
+    /// The Command Number is not defined.
+    UnknownCommand(u8),
+
+    /// The Command Number is defined, but not for a TWAMP command, e.g. OWAMP's Fetch-Session.
+    Unsupported(CommandNumber),
+
+    /// The message does not decode, e.g. because fields that must be zero are not.
+    Malformed(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Incomplete { needed } => write!(f, "Incomplete, {} bytes needed", needed),
+            ParseError::UnknownCommand(command) => write!(f, "Unknown command {}", command),
+            ParseError::Unsupported(command) => write!(f, "Unsupported {:?}", command),
+            ParseError::Malformed(reason) => write!(f, "Malformed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ParsedMessage {
+    /// Parse the command at the start of `bytes`, rejecting non-zero MBZ fields. Bytes past the
+    /// command are ignored, see [`length`](Self::length).
+    ///
+    /// Request-TW-Session is parsed whatever its MBZ fields, as Servers answer it with
+    /// Accept-Session rather than dropping it. See [`RequestTwSession::mbz_is_zero`].
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(bytes, Compliance::Strict)
+    }
+
+    /// Like [`parse`](Self::parse), with MBZ fields checked according to `compliance`.
+    pub fn parse_with(bytes: &[u8], compliance: Compliance) -> Result<Self, ParseError> {
+        let command = *bytes.first().ok_or(ParseError::Incomplete { needed: 1 })?;
+        let command =
+            CommandNumber::try_from(command).map_err(|_| ParseError::UnknownCommand(command))?;
+        let message = Messages::from_command(command).ok_or(ParseError::Unsupported(command))?;
+        if bytes.len() < message.length() {
+            return Err(ParseError::Incomplete {
+                needed: message.length(),
+            });
+        }
+        let malformed = |e: deku::DekuError| ParseError::Malformed(e.to_string());
+        Ok(match message {
+            Messages::RequestTwSession => {
+                let (_rest, request_tw_session) =
+                    RequestTwSession::from_bytes((bytes, 0)).map_err(malformed)?;
+                ParsedMessage::RequestTwSession(request_tw_session)
+            }
+            Messages::StartSessions => {
+                ParsedMessage::StartSessions(compliance.read(bytes, |_| ()).map_err(malformed)?)
+            }
+            Messages::StopSessions => {
+                ParsedMessage::StopSessions(compliance.read(bytes, |_| ()).map_err(malformed)?)
+            }
+            _ => unreachable!("Only commands have a Command Number"),
+        })
+    }
+
+    pub fn command_number(&self) -> CommandNumber {
+        match self {
+            ParsedMessage::RequestTwSession(_) => CommandNumber::RequestTwSession,
+            ParsedMessage::StartSessions(_) => CommandNumber::StartSessions,
+            ParsedMessage::StopSessions(_) => CommandNumber::StopSessions,
+        }
+    }
+
+    /// Length in bytes of the message on the wire, so the next one can be parsed after it.
+    pub fn length(&self) -> usize {
+        match self {
+            ParsedMessage::RequestTwSession(_) => Messages::RequestTwSession.length(),
+            ParsedMessage::StartSessions(_) => Messages::StartSessions.length(),
+            ParsedMessage::StopSessions(_) => Messages::StopSessions.length(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{accept::Accept, timers::SessionTimeout};
+    use deku::DekuContainerWrite;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn dispatches_on_command_number() {
+        let request_tw_session = RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            2,
+            None,
+            SessionTimeout::DEFAULT,
+        );
+        let stop_sessions = StopSessions::new(Accept::Ok).with_number_of_sessions(2);
+        let mut bytes = request_tw_session.to_bytes().unwrap();
+        bytes.extend(StartSessions::new().to_bytes().unwrap());
+        bytes.extend(stop_sessions.to_bytes().unwrap());
+
+        let mut parsed = Vec::new();
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            let message = ParsedMessage::parse(rest).unwrap();
+            rest = &rest[message.length()..];
+            parsed.push(message);
+        }
+        assert_eq!(
+            parsed,
+            vec![
+                ParsedMessage::RequestTwSession(request_tw_session),
+                ParsedMessage::StartSessions(StartSessions::new()),
+                ParsedMessage::StopSessions(stop_sessions),
+            ]
+        );
+        assert_eq!(parsed[1].command_number(), CommandNumber::StartSessions);
+    }
+
+    #[test]
+    fn reports_why_bytes_are_not_a_command() {
+        assert_eq!(
+            ParsedMessage::parse(&[]),
+            Err(ParseError::Incomplete { needed: 1 })
+        );
+        assert_eq!(
+            ParsedMessage::parse(&[2; 16]),
+            Err(ParseError::Incomplete { needed: 32 })
+        );
+        assert_eq!(
+            ParsedMessage::parse(&[9; 32]),
+            Err(ParseError::UnknownCommand(9))
+        );
+        assert_eq!(
+            ParsedMessage::parse(&[4; 32]),
+            Err(ParseError::Unsupported(CommandNumber::FetchSession))
+        );
+
+        let mut start_sessions = StartSessions::new().to_bytes().unwrap();
+        start_sessions[1] = 0xff;
+        assert!(matches!(
+            ParsedMessage::parse(&start_sessions),
+            Err(ParseError::Malformed(_))
+        ));
+        assert!(matches!(
+            ParsedMessage::parse_with(&start_sessions, Compliance::Lenient),
+            Ok(ParsedMessage::StartSessions(_))
+        ));
+    }
+}
@@ -0,0 +1,35 @@
+//! Where the random bytes of TWAMP-Control come from: Challenge, Salt, Server-IV, Client-IV,
+//! session keys and SIDs.
+
+use std::fmt;
+
+use rand::{rngs::OsRng, RngCore};
+
+/// Source of random bytes for protocol nonces and keys. [`OsRngSource`] is used unless another
+/// is provided, e.g. a certified generator where FIPS requires one, or a seeded one in tests.
+pub trait RngSource: fmt::Debug + Send + Sync {
+    /// Fill `dest` with random bytes.
+    fn fill_bytes(&self, dest: &mut [u8]);
+}
+
+/// The OS CSPRNG.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OsRngSource;
+
+impl RngSource for OsRngSource {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn os_rng_fills_bytes() {
+        let mut bytes = [0u8; 32];
+        OsRngSource.fill_bytes(&mut bytes);
+        assert_ne!(bytes, [0; 32]);
+    }
+}
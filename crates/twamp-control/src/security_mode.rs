@@ -27,6 +27,44 @@ pub enum Mode {
     EncryptedControlUnauthTest = 8,
 }
 
+impl Mode {
+    /// Whether the mode needs a shared secret, and so a Token in Set-Up-Response.
+    pub fn is_keyed(self) -> bool {
+        matches!(
+            self,
+            Mode::Authenticated | Mode::Encrypted | Mode::EncryptedControlUnauthTest
+        )
+    }
+}
+
+/// Features advertised next to the security modes in the Modes field of the Server Greeting.
+#[derive(Clone, Copy, Debug, PartialEq, IntoPrimitive)]
+#[repr(u32)]
+pub enum ModeExtension {
+    /// [Individual Session Control](https://datatracker.ietf.org/doc/html/rfc5938).
+    IndividualSessionControl = 16,
+
+    /// [Reflect Octets](https://datatracker.ietf.org/doc/html/rfc6038).
+    ReflectOctets = 32,
+
+    /// [Symmetrical Size](https://datatracker.ietf.org/doc/html/rfc6038) of test packets.
+    SymmetricalSize = 64,
+
+    /// [Truncated PTP timestamps](https://datatracker.ietf.org/doc/html/rfc8186) in test
+    /// packets, told apart from NTP ones by the Z bit of their Error Estimate.
+    PtpTimestamp = 256,
+}
+
+impl ModeExtension {
+    /// Every extension twamp-rs knows the bit of.
+    pub const ALL: [ModeExtension; 4] = [
+        ModeExtension::IndividualSessionControl,
+        ModeExtension::ReflectOctets,
+        ModeExtension::SymmetricalSize,
+        ModeExtension::PtpTimestamp,
+    ];
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
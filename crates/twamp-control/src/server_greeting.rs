@@ -1,18 +1,18 @@
 use std::fmt;
 
-use crate::security_mode::Mode;
+use crate::rng::{OsRngSource, RngSource};
+use crate::security_mode::{Mode, ModeExtension};
 use deku::prelude::*;
-use rand::random;
 
 /// Server Greeting sent by `Server` to `Control-Client` after `Control-Client` opens up a TCP
 /// connection.
 ///
 /// See details in [RFC 4656](https://datatracker.ietf.org/doc/html/rfc4656#section-3.1).
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
 pub struct ServerGreeting {
     /// Same semantics as MBZ (Must Be Zero).
-    #[deku(assert_eq = "[0u8; 12]")]
+    #[deku(assert = "lenient || *unused == [0u8; 12]")]
     unused: [u8; 12],
 
     /// Security mode(s) that the Server supports.
@@ -29,10 +29,24 @@ pub struct ServerGreeting {
     count: u32,
 
     /// Must Be Zero.
-    #[deku(assert_eq = "[0u8; 12]")]
+    #[deku(assert = "lenient || *mbz == [0u8; 12]")]
     mbz: [u8; 12],
 }
 
+/// How the Challenge and Salt of a [`ServerGreeting`] are filled in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChallengePolicy {
+    /// Random only if an authenticated or encrypted mode is advertised, zero otherwise.
+    #[default]
+    Auto,
+
+    /// Always random.
+    Random,
+
+    /// Always zero.
+    Zero,
+}
+
 impl fmt::Display for ServerGreeting {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -55,27 +69,77 @@ impl ServerGreeting {
     /// let supported_modes = &[Mode::Unauthenticated, Mode::Authenticated];
     /// let server_greeting = ServerGreeting::new(supported_modes);
     /// ```
+    ///
+    /// Challenge and Salt are filled in according to [`ChallengePolicy::Auto`].
     pub fn new(modes: &[Mode]) -> Self {
         ServerGreeting {
             unused: [0; 12],
             mode: modes
                 .iter()
                 .fold(0u32, |acc, mode| acc | <Mode as Into<u32>>::into(*mode)),
-            challenge: Vec::from([0; 16])
-                .iter()
-                .map(|_| random())
-                .collect::<Vec<u8>>()
-                .try_into()
-                .unwrap(),
-            salt: Vec::from([0; 16])
-                .iter()
-                .map(|_| random())
-                .collect::<Vec<u8>>()
-                .try_into()
-                .unwrap(),
+            challenge: [0; 16],
+            salt: [0; 16],
             count: 1024,
             mbz: [0; 12],
         }
+        .with_challenge_policy(ChallengePolicy::Auto)
+    }
+
+    /// Also advertise `extensions` in the `Modes` field.
+    ///
+    /// ```
+    /// use twamp_control::security_mode::{Mode, ModeExtension};
+    /// use twamp_control::server_greeting::ServerGreeting;
+    ///
+    /// let server_greeting = ServerGreeting::new(&[Mode::Unauthenticated])
+    ///     .with_extensions(&[ModeExtension::PtpTimestamp]);
+    /// assert_eq!(server_greeting.modes(), vec![Mode::Unauthenticated]);
+    /// assert_eq!(server_greeting.extensions(), vec![ModeExtension::PtpTimestamp]);
+    /// ```
+    pub fn with_extensions(mut self, extensions: &[ModeExtension]) -> Self {
+        self.mode = extensions
+            .iter()
+            .fold(self.mode, |acc, extension| acc | u32::from(*extension));
+        self
+    }
+
+    /// Fill in Challenge and Salt according to `policy`. Random bytes come from the OS CSPRNG,
+    /// see [`with_challenge_policy_from`](Self::with_challenge_policy_from) for another source.
+    ///
+    /// ```
+    /// use twamp_control::security_mode::Mode;
+    /// use twamp_control::server_greeting::{ChallengePolicy, ServerGreeting};
+    ///
+    /// let server_greeting = ServerGreeting::new(&[Mode::Authenticated])
+    ///     .with_challenge_policy(ChallengePolicy::Zero);
+    /// assert_eq!(server_greeting.challenge(), [0; 16]);
+    /// ```
+    pub fn with_challenge_policy(self, policy: ChallengePolicy) -> Self {
+        self.with_challenge_policy_from(policy, &OsRngSource)
+    }
+
+    /// Like [`with_challenge_policy`](Self::with_challenge_policy), with random bytes from `rng`.
+    pub fn with_challenge_policy_from(
+        mut self,
+        policy: ChallengePolicy,
+        rng: &dyn RngSource,
+    ) -> Self {
+        let random = match policy {
+            ChallengePolicy::Auto => self
+                .modes()
+                .iter()
+                .any(|mode| *mode != Mode::Unauthenticated),
+            ChallengePolicy::Random => true,
+            ChallengePolicy::Zero => false,
+        };
+        if random {
+            rng.fill_bytes(&mut self.challenge);
+            rng.fill_bytes(&mut self.salt);
+        } else {
+            self.challenge = [0; 16];
+            self.salt = [0; 16];
+        }
+        self
     }
 
     /// Use the provided count value in the greeting.
@@ -100,6 +164,59 @@ impl ServerGreeting {
         self.count
     }
 
+    /// Get the value of challenge field.
+    pub fn challenge(&self) -> [u8; 16] {
+        self.challenge
+    }
+
+    /// Get the value of salt field.
+    pub fn salt(&self) -> [u8; 16] {
+        self.salt
+    }
+
+    /// Security modes advertised in the greeting's `Mode` field. Empty if the Server sent
+    /// [`Mode::Reserved`].
+    ///
+    /// ```
+    /// use twamp_control::security_mode::Mode;
+    /// use twamp_control::server_greeting::ServerGreeting;
+    ///
+    /// let server_greeting = ServerGreeting::new(&[Mode::Unauthenticated, Mode::Encrypted]);
+    /// assert_eq!(server_greeting.modes(), vec![Mode::Unauthenticated, Mode::Encrypted]);
+    /// ```
+    pub fn modes(&self) -> Vec<Mode> {
+        [
+            Mode::Unauthenticated,
+            Mode::Authenticated,
+            Mode::Encrypted,
+            Mode::EncryptedControlUnauthTest,
+        ]
+        .into_iter()
+        .filter(|mode| self.has_mode(*mode))
+        .collect()
+    }
+
+    /// Raw `Mode` field, including bits twamp-rs does not know.
+    pub fn mode_bits(&self) -> u32 {
+        self.mode
+    }
+
+    /// Extensions advertised in the greeting's `Mode` field.
+    ///
+    /// ```
+    /// use twamp_control::security_mode::{Mode, ModeExtension};
+    /// use twamp_control::server_greeting::ServerGreeting;
+    ///
+    /// let server_greeting = ServerGreeting::new(&[Mode::Unauthenticated]);
+    /// assert!(server_greeting.extensions().is_empty());
+    /// ```
+    pub fn extensions(&self) -> Vec<ModeExtension> {
+        ModeExtension::ALL
+            .into_iter()
+            .filter(|extension| self.mode & u32::from(*extension) != 0)
+            .collect()
+    }
+
     /// Checks if the provided mode exists in greeting's `Mode` field.
     ///
     /// ```
@@ -250,18 +367,58 @@ mod tests {
 
     #[test]
     fn challenge_bytes_are_random() {
-        let server_greeting = ServerGreeting::new(&[Mode::Reserved]);
+        let server_greeting = ServerGreeting::new(&[Mode::Authenticated]);
         let challenge_bytes_unique = server_greeting.challenge.iter().collect::<HashSet<_>>();
         assert!(challenge_bytes_unique.len() > 1);
     }
 
     #[test]
     fn salt_bytes_are_random() {
-        let server_greeting = ServerGreeting::new(&[Mode::Reserved]);
+        let server_greeting = ServerGreeting::new(&[Mode::Authenticated]);
         let challenge_bytes_unique = server_greeting.salt.iter().collect::<HashSet<_>>();
         assert!(challenge_bytes_unique.len() > 1);
     }
 
+    /// Fills every byte with the same value.
+    #[derive(Debug)]
+    struct Repeat(u8);
+
+    impl RngSource for Repeat {
+        fn fill_bytes(&self, dest: &mut [u8]) {
+            dest.fill(self.0);
+        }
+    }
+
+    #[test]
+    fn challenge_and_salt_come_from_provided_rng() {
+        let server_greeting = ServerGreeting::new(&[Mode::Authenticated])
+            .with_challenge_policy_from(ChallengePolicy::Auto, &Repeat(7));
+        assert_eq!(server_greeting.challenge, [7; 16]);
+        assert_eq!(server_greeting.salt, [7; 16]);
+    }
+
+    #[test]
+    fn challenge_and_salt_are_zero_in_unauthenticated_only_greeting() {
+        let server_greeting = ServerGreeting::new(&[Mode::Unauthenticated]);
+        assert_eq!(server_greeting.challenge, [0; 16]);
+        assert_eq!(server_greeting.salt, [0; 16]);
+    }
+
+    #[test]
+    fn challenge_and_salt_are_random_when_any_secure_mode_is_advertised() {
+        let server_greeting = ServerGreeting::new(&[Mode::Unauthenticated, Mode::Encrypted]);
+        assert_ne!(server_greeting.challenge, [0; 16]);
+        assert_ne!(server_greeting.salt, [0; 16]);
+    }
+
+    #[test]
+    fn random_policy_overrides_unauthenticated_only_greeting() {
+        let server_greeting = ServerGreeting::new(&[Mode::Unauthenticated])
+            .with_challenge_policy(ChallengePolicy::Random);
+        assert_ne!(server_greeting.challenge, [0; 16]);
+        assert_ne!(server_greeting.salt, [0; 16]);
+    }
+
     #[test]
     fn default_count_is_under_a_valid_range() {
         let server_greeting = ServerGreeting::new(&[Mode::Reserved]);
@@ -275,6 +432,19 @@ mod tests {
         assert_eq!(server_greeting.count, count_value);
     }
 
+    #[test]
+    fn no_modes_in_reserved_greeting() {
+        let server_greeting = ServerGreeting::new(&[Mode::Reserved]);
+        assert!(server_greeting.modes().is_empty());
+    }
+
+    #[test]
+    fn accessors_return_greeting_fields() {
+        let server_greeting = ServerGreeting::new(&[Mode::Unauthenticated]);
+        assert_eq!(server_greeting.challenge(), server_greeting.challenge);
+        assert_eq!(server_greeting.salt(), server_greeting.salt);
+    }
+
     #[test]
     fn mbz_are_zeros() {
         let server_greeting = ServerGreeting::new(&[Mode::Reserved]);
@@ -6,10 +6,10 @@ use timestamp::timestamp::TimeStamp;
 
 /// Sent by Server to Control-Client after receiving a [Set-Up-Response](crate::set_up_response::SetUpResponse) command.
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
 pub struct ServerStart {
     /// MBZ (Must Be Zero).
-    #[deku(assert_eq = "[0u8; 15]")]
+    #[deku(assert = "lenient || *mbz_start == [0u8; 15]")]
     mbz_start: [u8; 15],
 
     /// Indicates Server's willingness to continue. See [list of possible values](Accept).
@@ -23,7 +23,7 @@ pub struct ServerStart {
     start_time: TimeStamp,
 
     /// MBZ (Must Be Zero).
-    #[deku(assert_eq = "[0u8; 8]")]
+    #[deku(assert = "lenient || *mbz_end == [0u8; 8]")]
     mbz_end: [u8; 8],
 }
 
@@ -45,11 +45,22 @@ impl ServerStart {
         }
     }
 
+    /// Use `server_iv` rather than the random one [`new`](Self::new) generates.
+    pub fn with_server_iv(mut self, server_iv: [u8; 16]) -> Self {
+        self.server_iv = server_iv;
+        self
+    }
+
     /// Returns the value of Accept field.
     pub fn accept(&self) -> &Accept {
         &self.accept
     }
 
+    /// Returns the value of Server-IV field.
+    pub fn server_iv(&self) -> &[u8; 16] {
+        &self.server_iv
+    }
+
     /// Returns the value of Start-Time field.
     pub fn start_time(&self) -> &TimeStamp {
         &self.start_time
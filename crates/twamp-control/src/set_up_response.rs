@@ -34,7 +34,8 @@ pub struct SetUpResponse {
 impl SetUpResponse {
     /// Attempt to create Set-Up-Response with provided mode.
     ///
-    /// Errors if the provided mode is not supported by `twamp-rs`.
+    /// Errors if the provided mode is not supported by `twamp-rs`, or needs a Token, see
+    /// [`Self::keyed`].
     pub fn new(mode: Mode) -> Result<Self, String> {
         match mode {
             Mode::Reserved | Mode::Unauthenticated => Ok(SetUpResponse {
@@ -50,6 +51,48 @@ impl SetUpResponse {
             .to_string()),
         }
     }
+
+    /// Create Set-Up-Response choosing a [keyed](Mode::is_keyed) mode, with a `token` from
+    /// [`SessionKeys::to_token`](crate::auth::SessionKeys::to_token) under the shared secret
+    /// named by `key_id`.
+    ///
+    /// Errors if `mode` is not keyed.
+    pub fn keyed(
+        mode: Mode,
+        key_id: [u8; 80],
+        token: [u8; 64],
+        client_iv: [u8; 16],
+    ) -> Result<Self, String> {
+        if !mode.is_keyed() {
+            return Err(format!("{:?} mode does not use a Token", mode));
+        }
+        Ok(SetUpResponse {
+            mode,
+            key_id,
+            token,
+            client_iv,
+        })
+    }
+
+    /// Returns the value of Mode field.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns the value of KeyID field.
+    pub fn key_id(&self) -> &[u8; 80] {
+        &self.key_id
+    }
+
+    /// Returns the value of Token field.
+    pub fn token(&self) -> &[u8; 64] {
+        &self.token
+    }
+
+    /// Returns the value of Client-IV field.
+    pub fn client_iv(&self) -> &[u8; 16] {
+        &self.client_iv
+    }
 }
 
 #[cfg(test)]
@@ -62,48 +105,42 @@ mod tests {
     fn unused_key_id_in_unauth_mode() {
         let set_up_response = SetUpResponse::new(Mode::Unauthenticated)
             .expect("should have created set_up_response.");
-        assert_eq!(set_up_response.key_id.iter().fold(0, |acc, v| acc + v), 0);
+        assert_eq!(set_up_response.key_id.iter().sum::<u8>(), 0);
     }
 
     #[test]
     fn unused_token_in_unauth_mode() {
         let set_up_response = SetUpResponse::new(Mode::Unauthenticated)
             .expect("should have created set_up_response.");
-        assert_eq!(set_up_response.token.iter().fold(0, |acc, v| acc + v), 0);
+        assert_eq!(set_up_response.token.iter().sum::<u8>(), 0);
     }
 
     #[test]
     fn unused_client_iv_in_unauth_mode() {
         let set_up_response = SetUpResponse::new(Mode::Unauthenticated)
             .expect("should have created set_up_response.");
-        assert_eq!(
-            set_up_response.client_iv.iter().fold(0, |acc, v| acc + v),
-            0
-        );
+        assert_eq!(set_up_response.client_iv.iter().sum::<u8>(), 0);
     }
 
     #[test]
     fn unused_key_id_in_reserved_mode() {
         let set_up_response =
             SetUpResponse::new(Mode::Reserved).expect("should have created set_up_response.");
-        assert_eq!(set_up_response.key_id.iter().fold(0, |acc, v| acc + v), 0);
+        assert_eq!(set_up_response.key_id.iter().sum::<u8>(), 0);
     }
 
     #[test]
     fn unused_token_in_reserved_mode() {
         let set_up_response =
             SetUpResponse::new(Mode::Reserved).expect("should have created set_up_response.");
-        assert_eq!(set_up_response.token.iter().fold(0, |acc, v| acc + v), 0);
+        assert_eq!(set_up_response.token.iter().sum::<u8>(), 0);
     }
 
     #[test]
     fn unused_client_iv_in_reserved_mode() {
         let set_up_response =
             SetUpResponse::new(Mode::Reserved).expect("should have created set_up_response.");
-        assert_eq!(
-            set_up_response.client_iv.iter().fold(0, |acc, v| acc + v),
-            0
-        );
+        assert_eq!(set_up_response.client_iv.iter().sum::<u8>(), 0);
     }
 
     /// Unsupported mode by twamp-rs.
@@ -128,6 +165,20 @@ mod tests {
             .expect("should have created set_up_response.");
     }
 
+    #[test]
+    fn keyed_carries_key_id_and_token() {
+        assert!(SetUpResponse::keyed(Mode::Unauthenticated, [1; 80], [2; 64], [3; 16]).is_err());
+        let set_up_response =
+            SetUpResponse::keyed(Mode::Authenticated, [1; 80], [2; 64], [3; 16]).unwrap();
+        let encoded = set_up_response.to_bytes().unwrap();
+        assert_eq!(encoded.len(), SET_UP_RESPONSE_LENGTH_IN_BYTES);
+        let (_rest, val) = SetUpResponse::from_bytes((&encoded, 0)).unwrap();
+        assert_eq!(val.mode(), Mode::Authenticated);
+        assert_eq!(val.key_id(), &[1; 80]);
+        assert_eq!(val.token(), &[2; 64]);
+        assert_eq!(val.client_iv(), &[3; 16]);
+    }
+
     #[test]
     fn serialize_to_correct_length_of_bytes() {
         let set_up_response = SetUpResponse::new(Mode::Unauthenticated)
@@ -6,10 +6,10 @@ use deku::prelude::*;
 ///
 /// See details in [RFC 4656](https://datatracker.ietf.org/doc/html/rfc4656#section-3.1).
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
 pub struct StartAck {
     pub accept: Accept,
-    #[deku(assert_eq = "[0u8; 15]")]
+    #[deku(assert = "lenient || *mbz == [0u8; 15]")]
     mbz: [u8; 15],
     hmac: [u8; 16],
 }
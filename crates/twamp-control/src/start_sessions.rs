@@ -6,11 +6,11 @@ use deku::prelude::*;
 ///
 /// See details in [RFC 4656](https://datatracker.ietf.org/doc/html/rfc4656#section-3.1).
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
 pub struct StartSessions {
     #[deku(assert_eq = "CommandNumber::StartSessions")]
     command_number: CommandNumber,
-    #[deku(assert_eq = "[0u8; 15]")]
+    #[deku(assert = "lenient || *mbz == [0u8; 15]")]
     mbz: [u8; 15],
     hmac: [u8; 16],
 }
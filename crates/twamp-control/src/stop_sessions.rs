@@ -1,30 +1,53 @@
 use crate::{accept::Accept, command_number::CommandNumber};
 use deku::prelude::*;
 
-/// Server Greeting sent by `Server` to `Control-Client` after `Control-Client` opens up a TCP
-/// connection.
+/// Stop-Sessions sent by `Control-Client` to `Server`, or the other way around, to stop every
+/// test session of the TWAMP-Control connection.
 ///
-/// See details in [RFC 4656](https://datatracker.ietf.org/doc/html/rfc4656#section-3.1).
+/// See details in [RFC 5357](https://datatracker.ietf.org/doc/html/rfc5357#section-3.8).
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
 pub struct StopSessions {
     #[deku(assert_eq = "CommandNumber::StopSessions")]
     command_number: CommandNumber,
     accept: Accept,
-    #[deku(assert_eq = "0u16")]
+    #[deku(assert = "lenient || *mbz == 0u16")]
     mbz: u16,
+    /// Number of sessions being stopped.
+    number_of_sessions: u32,
+    #[deku(assert = "lenient || *mbz_second == [0u8; 8]")]
+    mbz_second: [u8; 8],
     hmac: [u8; 16],
 }
 
 impl StopSessions {
+    /// Stop-Sessions for the single session of a TWAMP-Control connection.
     pub fn new(accept: Accept) -> Self {
         StopSessions {
             command_number: CommandNumber::StopSessions,
             accept,
             mbz: 0,
+            number_of_sessions: 1,
+            mbz_second: [0; 8],
             hmac: [0; 16],
         }
     }
+
+    /// Stop-Sessions for the `number_of_sessions` sessions of a TWAMP-Control connection.
+    pub fn with_number_of_sessions(mut self, number_of_sessions: u32) -> Self {
+        self.number_of_sessions = number_of_sessions;
+        self
+    }
+
+    /// Whether the sessions ended normally, `Ok`, or abnormally otherwise.
+    pub fn accept(&self) -> Accept {
+        self.accept
+    }
+
+    /// Returns the value of Number of Sessions field.
+    pub fn number_of_sessions(&self) -> u32 {
+        self.number_of_sessions
+    }
 }
 
 #[cfg(test)]
@@ -32,7 +55,7 @@ mod tests {
     use super::*;
     use crate::{accept::Accept, command_number::CommandNumber};
 
-    const STOP_SESSIONS_LENGTH_IN_BYTES: usize = 20;
+    const STOP_SESSIONS_LENGTH_IN_BYTES: usize = 32;
 
     #[test]
     fn command_number_is_correct() {
@@ -53,10 +76,9 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
     fn deserialize_to_struct() {
         let stop_sessions_as_bytes = [
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00, 0x00, 0x00, 0x00,
         ];
@@ -65,6 +87,16 @@ mod tests {
         assert_eq!(stop_sessions.command_number, CommandNumber::StopSessions);
         assert_eq!(stop_sessions.accept, Accept::Ok);
         assert_eq!(stop_sessions.mbz, 0u16);
+        assert_eq!(stop_sessions.number_of_sessions(), 2);
         assert_eq!(stop_sessions.hmac, [0u8; 16]);
     }
+
+    #[test]
+    fn number_of_sessions_is_at_offset_4() {
+        let encoded = StopSessions::new(Accept::Ok)
+            .with_number_of_sessions(3)
+            .to_bytes()
+            .unwrap();
+        assert_eq!(encoded[4..8], 3u32.to_be_bytes());
+    }
 }
@@ -0,0 +1,55 @@
+//! Byte streams TWAMP-Control runs over, with the `tokio` feature. The TCP stream is that of the
+//! twamp-runtime backend, tokio or smol. The `tls` feature adds TLS streams from
+//! [`tokio_rustls`], re-exported so their version matches.
+
+use std::{fmt::Debug, io, net::SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "tls")]
+pub use tokio_rustls;
+use twamp_runtime::net::TcpStream;
+
+/// Byte stream TWAMP-Control runs over. Implemented for TCP, the stream RFC 5357 specifies;
+/// streams wrapping TCP, e.g. TLS for management networks that forbid cleartext TCP, can
+/// implement it too by forwarding to the TCP stream they wrap.
+pub trait ControlStream: AsyncRead + AsyncWrite + Debug + Send + Unpin {
+    /// Local address of the underlying TCP connection.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Remote address of the underlying TCP connection.
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl ControlStream for TcpStream {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::local_addr(self)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+}
+
+/// TLS stream of a Control-Client, from [`TlsConnector::connect`](tokio_rustls::TlsConnector::connect).
+#[cfg(feature = "tls")]
+impl<S: ControlStream> ControlStream for tokio_rustls::client::TlsStream<S> {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().0.local_addr()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().0.peer_addr()
+    }
+}
+
+/// TLS stream of a Server, from [`TlsAcceptor::accept`](tokio_rustls::TlsAcceptor::accept).
+#[cfg(feature = "tls")]
+impl<S: ControlStream> ControlStream for tokio_rustls::server::TlsStream<S> {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().0.local_addr()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().0.peer_addr()
+    }
+}
@@ -0,0 +1,310 @@
+//! Timers of TWAMP, as types of their own so one can't be passed where another is expected,
+//! nor a value outside the bounds this crate supports.
+//!
+//! With the `serde` feature, timers serialize as whole seconds, or as a [`Duration`] for
+//! [`Servwait`], and deserializing checks the same bounds as their `new`.
+
+use std::{fmt, str::FromStr, time::Duration};
+
+/// A timer value outside the bounds of its type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimerError {
+    /// Name of the timer, as in the RFCs.
+    pub timer: &'static str,
+    pub value: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for TimerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {:?} is outside {:?} to {:?}",
+            self.timer, self.value, self.min, self.max
+        )
+    }
+}
+
+impl std::error::Error for TimerError {}
+
+/// Check `value` is within `min` and `max`, naming `timer` otherwise.
+fn bounded(
+    timer: &'static str,
+    value: Duration,
+    min: Duration,
+    max: Duration,
+) -> Result<Duration, TimerError> {
+    if value < min || value > max {
+        return Err(TimerError {
+            timer,
+            value,
+            min,
+            max,
+        });
+    }
+    Ok(value)
+}
+
+/// Parse whole seconds, as given on command lines and in config files.
+fn parse_secs(s: &str) -> Result<u64, String> {
+    s.trim()
+        .parse()
+        .map_err(|e| format!("{:?} is not a number of seconds: {}", s, e))
+}
+
+/// REFWAIT of [RFC 5357](https://datatracker.ietf.org/doc/html/rfc5357#section-4.2): how long
+/// a Session-Reflector waits for the next test packet before ending the session, in whole
+/// seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u16", into = "u16")
+)]
+pub struct Refwait(u16);
+
+impl Refwait {
+    /// The RFC's 900 seconds.
+    pub const DEFAULT: Refwait = Refwait(900);
+
+    /// Fails for 0 seconds, which would end sessions before their first test packet.
+    pub fn new(secs: u16) -> Result<Self, TimerError> {
+        bounded(
+            "REFWAIT",
+            Duration::from_secs(secs.into()),
+            Duration::from_secs(1),
+            Duration::from_secs(u16::MAX.into()),
+        )?;
+        Ok(Refwait(secs))
+    }
+
+    pub fn secs(self) -> u16 {
+        self.0
+    }
+
+    pub fn as_duration(self) -> Duration {
+        Duration::from_secs(self.0.into())
+    }
+}
+
+impl Default for Refwait {
+    fn default() -> Self {
+        Refwait::DEFAULT
+    }
+}
+
+impl FromStr for Refwait {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let secs = parse_secs(s)?;
+        let secs = u16::try_from(secs)
+            .map_err(|_| format!("REFWAIT of {}s is above {}s", secs, u16::MAX))?;
+        Refwait::new(secs).map_err(|e| e.to_string())
+    }
+}
+
+impl From<Refwait> for u16 {
+    fn from(refwait: Refwait) -> Self {
+        refwait.0
+    }
+}
+
+/// Refused outside the bounds of [`Refwait::new`].
+impl TryFrom<u16> for Refwait {
+    type Error = TimerError;
+
+    fn try_from(secs: u16) -> Result<Self, Self::Error> {
+        Refwait::new(secs)
+    }
+}
+
+/// SERVWAIT of [RFC 5357](https://datatracker.ietf.org/doc/html/rfc5357#section-3.1): how long
+/// a Server waits for the next TWAMP-Control message until the sessions start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "Duration", into = "Duration")
+)]
+pub struct Servwait(Duration);
+
+impl Servwait {
+    /// The RFC's 900 seconds.
+    pub const DEFAULT: Servwait = Servwait(Duration::from_secs(900));
+
+    /// Fails for no time at all, which would close connections before Set-Up-Response.
+    pub fn new(duration: Duration) -> Result<Self, TimerError> {
+        Ok(Servwait(bounded(
+            "SERVWAIT",
+            duration,
+            Duration::from_millis(1),
+            Duration::MAX,
+        )?))
+    }
+
+    pub fn as_duration(self) -> Duration {
+        self.0
+    }
+}
+
+impl Default for Servwait {
+    fn default() -> Self {
+        Servwait::DEFAULT
+    }
+}
+
+impl FromStr for Servwait {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Servwait::new(Duration::from_secs(parse_secs(s)?)).map_err(|e| e.to_string())
+    }
+}
+
+impl From<Servwait> for Duration {
+    fn from(servwait: Servwait) -> Self {
+        servwait.0
+    }
+}
+
+/// Refused outside the bounds of [`Servwait::new`].
+impl TryFrom<Duration> for Servwait {
+    type Error = TimerError;
+
+    fn try_from(duration: Duration) -> Result<Self, Self::Error> {
+        Servwait::new(duration)
+    }
+}
+
+/// Timeout of Request-TW-Session: how long the Session-Reflector keeps reflecting test packets
+/// still in flight after Stop-Sessions, in whole seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u64", into = "u64")
+)]
+pub struct SessionTimeout(u64);
+
+impl SessionTimeout {
+    /// Largest Timeout asked for. The Session-Reflector holds on to the session that long after
+    /// Stop-Sessions.
+    pub const MAX: SessionTimeout = SessionTimeout(3600);
+
+    /// As long as the RFC's REFWAIT, since Session-Reflectors may end sessions then anyway.
+    pub const DEFAULT: SessionTimeout = SessionTimeout(900);
+
+    /// Fails for 0, which has packets still in flight at Stop-Sessions dropped, and anything
+    /// above [`MAX`](Self::MAX).
+    pub fn new(secs: u64) -> Result<Self, TimerError> {
+        bounded(
+            "Timeout",
+            Duration::from_secs(secs),
+            Duration::from_secs(1),
+            SessionTimeout::MAX.as_duration(),
+        )?;
+        Ok(SessionTimeout(secs))
+    }
+
+    pub fn secs(self) -> u64 {
+        self.0
+    }
+
+    pub fn as_duration(self) -> Duration {
+        Duration::from_secs(self.0)
+    }
+}
+
+impl Default for SessionTimeout {
+    fn default() -> Self {
+        SessionTimeout::DEFAULT
+    }
+}
+
+impl FromStr for SessionTimeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SessionTimeout::new(parse_secs(s)?).map_err(|e| e.to_string())
+    }
+}
+
+/// Timeout field of Request-TW-Session.
+impl From<SessionTimeout> for u64 {
+    fn from(timeout: SessionTimeout) -> Self {
+        timeout.0
+    }
+}
+
+/// Timeout field of Request-TW-Session, refused outside the bounds of [`SessionTimeout::new`].
+impl TryFrom<u64> for SessionTimeout {
+    type Error = TimerError;
+
+    fn try_from(secs: u64) -> Result<Self, Self::Error> {
+        SessionTimeout::new(secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timers_are_bounded() {
+        assert_eq!(Refwait::default().secs(), 900);
+        assert!(Refwait::new(0).is_err());
+        assert_eq!("30".parse(), Ok(Refwait::new(30).unwrap()));
+        assert!("70000".parse::<Refwait>().is_err());
+
+        assert_eq!(Servwait::default().as_duration(), Duration::from_secs(900));
+        assert!(Servwait::new(Duration::ZERO).is_err());
+        assert!("0".parse::<Servwait>().is_err());
+
+        assert!(SessionTimeout::new(0).is_err());
+        assert_eq!(
+            SessionTimeout::try_from(3600),
+            Ok(SessionTimeout::MAX),
+            "the maximum itself is allowed"
+        );
+        let error = SessionTimeout::new(3601).unwrap_err();
+        assert_eq!(error.timer, "Timeout");
+        assert_eq!(error.max, Duration::from_secs(3600));
+        assert_eq!(u64::from(SessionTimeout::new(5).unwrap()), 5);
+        assert!("five".parse::<SessionTimeout>().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserializing_checks_bounds() {
+        use serde_test::{assert_de_tokens_error, assert_tokens, Token};
+
+        assert_tokens(&Refwait::new(30).unwrap(), &[Token::U16(30)]);
+        assert_de_tokens_error::<Refwait>(
+            &[Token::U16(0)],
+            "REFWAIT of 0ns is outside 1s to 65535s",
+        );
+
+        assert_de_tokens_error::<Servwait>(
+            &[
+                Token::Struct {
+                    name: "Duration",
+                    len: 2,
+                },
+                Token::Str("secs"),
+                Token::U64(0),
+                Token::Str("nanos"),
+                Token::U32(0),
+                Token::StructEnd,
+            ],
+            "SERVWAIT of 0ns is outside 1ms to 18446744073709551615.999999999s",
+        );
+
+        assert_tokens(&SessionTimeout::MAX, &[Token::U64(3600)]);
+        assert_de_tokens_error::<SessionTimeout>(
+            &[Token::U64(3601)],
+            "Timeout of 3601s is outside 1s to 3600s",
+        );
+    }
+}
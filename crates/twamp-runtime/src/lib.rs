@@ -0,0 +1,137 @@
+//! Sockets, timers and tasks the I/O crates of twamp-rs run on, from the async runtime chosen
+//! with a feature.
+//!
+//! `control-client`, `server`, `session-sender`, `session-reflector` and `twamp-rs` itself only
+//! reach the runtime through [`net`], [`time`] and [`task`], so they run on whichever backend is
+//! enabled:
+//!
+//! -   `tokio`, the default, re-exports tokio's own types, for callers running a tokio runtime.
+//! -   `smol` provides them from smol, whose reactor and executor run on threads of their own,
+//!     so the protocol layers run on async-std, smol, or a custom executor e.g. in embedded
+//!     probes. It takes precedence over `tokio` if both are enabled.
+//!
+//! Both backends have the same API, that of tokio. Runtime independent parts of tokio, e.g.
+//! `tokio::sync`, `tokio::select!` and the `AsyncRead` and `AsyncWrite` traits TWAMP-Control
+//! streams implement, are used directly.
+
+#[cfg(feature = "smol")]
+#[path = "smol.rs"]
+mod backend;
+#[cfg(all(feature = "tokio", not(feature = "smol")))]
+#[path = "tokio.rs"]
+mod backend;
+
+#[cfg(not(any(feature = "tokio", feature = "smol")))]
+compile_error!("twamp-runtime needs the `tokio` or `smol` feature");
+
+pub use backend::{net, task, time};
+
+#[cfg(test)]
+mod tests {
+    use std::{os::fd::AsFd, time::Duration};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{net, task, time};
+
+    // `#[tokio::test]` only drives the futures, which run on either backend.
+
+    #[tokio::test]
+    async fn udp_round_trip() {
+        let a = net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        a.send_to(b"ping", b.local_addr().unwrap()).await.unwrap();
+        let mut buf = [0; 8];
+        let (len, from) = b.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(from, a.local_addr().unwrap());
+    }
+
+    #[tokio::test]
+    async fn read_with_waits_until_readable() {
+        let a = net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let to = b.local_addr().unwrap();
+        let send = task::spawn(async move {
+            time::sleep(Duration::from_millis(20)).await;
+            a.send_to(b"late", to).await.unwrap();
+        });
+        // A duplicate of `b`'s descriptor, as the ancillary reads of the I/O crates use.
+        let dup = std::net::UdpSocket::from(b.as_fd().try_clone_to_owned().unwrap());
+        let mut buf = [0; 8];
+        let len = net::read_with(&b, || dup.recv(&mut buf)).await.unwrap();
+        assert_eq!(&buf[..len], b"late");
+        send.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tcp_round_trip() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let echo = task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+        let mut stream = net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"echo").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"echo");
+        echo.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn timeout_elapses() {
+        let slow = time::sleep(Duration::from_secs(10));
+        assert!(time::timeout(Duration::from_millis(10), slow)
+            .await
+            .is_err());
+        let deadline = time::Instant::now() + Duration::from_secs(10);
+        assert_eq!(time::timeout_at(deadline, async { 1 }).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn interval_ticks_first_at_once() {
+        let start = time::Instant::now();
+        let mut ticks = time::interval(Duration::from_millis(20));
+        ticks.tick().await;
+        assert!(start.elapsed() < Duration::from_millis(20));
+        ticks.tick().await;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn aborted_task_is_cancelled() {
+        let handle = task::spawn(time::sleep(Duration::from_secs(10)));
+        handle.abort();
+        assert!(handle.await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn abort_handle_cancels_task() {
+        let handle = task::spawn(time::sleep(Duration::from_secs(10)));
+        handle.abort_handle().abort();
+        assert!(handle.await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn join_set_joins_in_order_of_ending() {
+        let mut tasks = task::JoinSet::new();
+        tasks.spawn(async {
+            time::sleep(Duration::from_millis(50)).await;
+            "slow"
+        });
+        tasks.spawn(async { "fast" });
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks.join_next().await.unwrap().unwrap(), "fast");
+        assert_eq!(tasks.join_next().await.unwrap().unwrap(), "slow");
+        assert!(tasks.join_next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn blocking_task_returns() {
+        assert_eq!(task::spawn_blocking(|| 1).await.unwrap(), 1);
+    }
+}
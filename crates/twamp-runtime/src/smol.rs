@@ -0,0 +1,470 @@
+//! Backend of the `smol` feature, with the API of tokio.
+
+/// First of the addresses `addr` resolves to that `f` succeeds with.
+fn each_addr<A: std::net::ToSocketAddrs, T>(
+    addr: A,
+    mut f: impl FnMut(std::net::SocketAddr) -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match f(addr) {
+            Ok(t) => return Ok(t),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "could not resolve to any address",
+        )
+    }))
+}
+
+pub mod net {
+    use std::{
+        io,
+        net::{SocketAddr, ToSocketAddrs},
+        os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+        pin::Pin,
+        task::{ready, Context, Poll},
+    };
+
+    use smol::{
+        io::{AsyncRead as _, AsyncWrite as _},
+        Async,
+    };
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::each_addr;
+
+    /// UDP socket registered with smol's reactor.
+    #[derive(Debug)]
+    pub struct UdpSocket(Async<std::net::UdpSocket>);
+
+    impl UdpSocket {
+        pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+            each_addr(addr, Async::<std::net::UdpSocket>::bind).map(UdpSocket)
+        }
+
+        /// `socket` must be non-blocking, as for tokio.
+        pub fn from_std(socket: std::net::UdpSocket) -> io::Result<Self> {
+            Async::new_nonblocking(socket).map(UdpSocket)
+        }
+
+        pub async fn connect(&self, addr: impl ToSocketAddrs) -> io::Result<()> {
+            each_addr(addr, |addr| self.0.get_ref().connect(addr))
+        }
+
+        pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+            self.0.send(buf).await
+        }
+
+        pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.recv(buf).await
+        }
+
+        pub async fn send_to(&self, buf: &[u8], target: impl ToSocketAddrs) -> io::Result<usize> {
+            let target = each_addr(target, Ok)?;
+            self.0.send_to(buf, target).await
+        }
+
+        pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            self.0.recv_from(buf).await
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.get_ref().local_addr()
+        }
+
+        pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+            self.0.get_ref().peer_addr()
+        }
+    }
+
+    impl AsRawFd for UdpSocket {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.as_raw_fd()
+        }
+    }
+
+    impl AsFd for UdpSocket {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            self.0.as_fd()
+        }
+    }
+
+    /// Run `read` once `socket` is readable, until it no longer fails with
+    /// [`WouldBlock`](io::ErrorKind::WouldBlock). For reads `UdpSocket` has no method for, e.g.
+    /// `recvmsg` with ancillary data.
+    pub async fn read_with<R>(
+        socket: &UdpSocket,
+        mut read: impl FnMut() -> io::Result<R>,
+    ) -> io::Result<R> {
+        socket.0.read_with(|_| read()).await
+    }
+
+    /// TCP stream registered with smol's reactor. It implements tokio's `AsyncRead` and
+    /// `AsyncWrite`, which don't depend on the tokio runtime, so TWAMP-Control runs over it.
+    #[derive(Debug)]
+    pub struct TcpStream(Async<std::net::TcpStream>);
+
+    impl TcpStream {
+        pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+            let mut last_error = None;
+            for addr in addr.to_socket_addrs()? {
+                match Async::<std::net::TcpStream>::connect(addr).await {
+                    Ok(stream) => return Ok(TcpStream(stream)),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            Err(last_error.unwrap_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "could not resolve to any address",
+                )
+            }))
+        }
+
+        /// `stream` must be non-blocking, as for tokio.
+        pub fn from_std(stream: std::net::TcpStream) -> io::Result<Self> {
+            Async::new_nonblocking(stream).map(TcpStream)
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.get_ref().local_addr()
+        }
+
+        pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+            self.0.get_ref().peer_addr()
+        }
+
+        pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+            self.0.get_ref().set_nodelay(nodelay)
+        }
+    }
+
+    impl AsyncRead for TcpStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let read = ready!(Pin::new(&mut self.0).poll_read(cx, buf.initialize_unfilled()))?;
+            buf.advance(read);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for TcpStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_close(cx)
+        }
+    }
+
+    impl AsRawFd for TcpStream {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.as_raw_fd()
+        }
+    }
+
+    /// TCP listener registered with smol's reactor.
+    #[derive(Debug)]
+    pub struct TcpListener(Async<std::net::TcpListener>);
+
+    impl TcpListener {
+        pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+            each_addr(addr, Async::<std::net::TcpListener>::bind).map(TcpListener)
+        }
+
+        pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+            let (stream, addr) = self.0.accept().await?;
+            Ok((TcpStream(stream), addr))
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.get_ref().local_addr()
+        }
+    }
+}
+
+pub mod time {
+    use std::{fmt, future::IntoFuture, time::Duration};
+
+    use smol::{future, Timer};
+    pub use std::time::Instant;
+
+    /// Error of [`timeout`] and [`timeout_at`] when the deadline passes first.
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct Elapsed(());
+
+    impl fmt::Display for Elapsed {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("deadline has elapsed")
+        }
+    }
+
+    impl std::error::Error for Elapsed {}
+
+    pub async fn sleep(duration: Duration) {
+        Timer::after(duration).await;
+    }
+
+    pub async fn sleep_until(deadline: Instant) {
+        Timer::at(deadline).await;
+    }
+
+    pub async fn timeout<F: IntoFuture>(
+        duration: Duration,
+        future: F,
+    ) -> Result<F::Output, Elapsed> {
+        within(Timer::after(duration), future).await
+    }
+
+    pub async fn timeout_at<F: IntoFuture>(
+        deadline: Instant,
+        future: F,
+    ) -> Result<F::Output, Elapsed> {
+        within(Timer::at(deadline), future).await
+    }
+
+    /// Ticks every `period`, the first one right away, see [`interval`]. Missed ticks fire at
+    /// once, as with tokio's default.
+    #[derive(Debug)]
+    pub struct Interval {
+        next: Instant,
+        period: Duration,
+    }
+
+    impl Interval {
+        /// Wait for the next tick and return when it was due.
+        pub async fn tick(&mut self) -> Instant {
+            let due = self.next;
+            sleep_until(due).await;
+            self.next = due + self.period;
+            due
+        }
+    }
+
+    pub fn interval(period: Duration) -> Interval {
+        assert!(!period.is_zero(), "`period` must be non-zero");
+        Interval {
+            next: Instant::now(),
+            period,
+        }
+    }
+
+    /// Output of `future`, unless `timer` fires first.
+    async fn within<F: IntoFuture>(timer: Timer, future: F) -> Result<F::Output, Elapsed> {
+        let future = future.into_future();
+        future::or(async { Ok(future.await) }, async {
+            timer.await;
+            Err(Elapsed(()))
+        })
+        .await
+    }
+}
+
+pub mod task {
+    use std::{
+        fmt,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use smol::{channel, future, unblock, Task};
+
+    /// Error of a [`JoinHandle`] whose task was aborted.
+    #[derive(Debug)]
+    pub struct JoinError(());
+
+    impl JoinError {
+        pub fn is_cancelled(&self) -> bool {
+            true
+        }
+    }
+
+    impl fmt::Display for JoinError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("task was cancelled")
+        }
+    }
+
+    impl std::error::Error for JoinError {}
+
+    /// Task spawned on smol's global executor. As with tokio, dropping it detaches the task
+    /// rather than cancelling it, and [`abort`](Self::abort) cancels it.
+    pub struct JoinHandle<T> {
+        /// `None` once dropped.
+        task: Option<Task<Option<T>>>,
+        /// Closed to abort the task.
+        abort: channel::Sender<()>,
+    }
+
+    impl<T> JoinHandle<T> {
+        pub fn abort(&self) {
+            self.abort.close();
+        }
+
+        pub fn is_finished(&self) -> bool {
+            self.task.as_ref().is_none_or(Task::is_finished)
+        }
+
+        /// Handle aborting the task without awaiting it.
+        pub fn abort_handle(&self) -> AbortHandle {
+            AbortHandle {
+                abort: self.abort.clone(),
+            }
+        }
+    }
+
+    /// Aborts the task of a [`JoinHandle`], see [`JoinHandle::abort_handle`].
+    #[derive(Clone, Debug)]
+    pub struct AbortHandle {
+        abort: channel::Sender<()>,
+    }
+
+    impl AbortHandle {
+        pub fn abort(&self) {
+            self.abort.close();
+        }
+    }
+
+    impl<T> fmt::Debug for JoinHandle<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("JoinHandle")
+                .field("finished", &self.is_finished())
+                .finish()
+        }
+    }
+
+    impl<T> Future for JoinHandle<T> {
+        type Output = Result<T, JoinError>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let task = self.task.as_mut().expect("JoinHandle polled after drop");
+            Pin::new(task)
+                .poll(cx)
+                .map(|output| output.ok_or(JoinError(())))
+        }
+    }
+
+    impl<T> Drop for JoinHandle<T> {
+        fn drop(&mut self) {
+            if let Some(task) = self.task.take() {
+                task.detach();
+            }
+        }
+    }
+
+    /// Run the blocking `f` on smol's thread pool.
+    pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        spawn(unblock(f))
+    }
+
+    /// Tasks spawned together, joined in the order they end. As with tokio, dropping the set
+    /// aborts the tasks still running.
+    #[derive(Debug)]
+    pub struct JoinSet<T> {
+        tasks: Vec<JoinHandle<T>>,
+    }
+
+    impl<T> Default for JoinSet<T> {
+        fn default() -> Self {
+            JoinSet { tasks: Vec::new() }
+        }
+    }
+
+    impl<T: Send + 'static> JoinSet<T> {
+        pub fn new() -> Self {
+            JoinSet::default()
+        }
+
+        pub fn spawn<F>(&mut self, future: F) -> AbortHandle
+        where
+            F: Future<Output = T> + Send + 'static,
+        {
+            let task = spawn(future);
+            let abort = task.abort_handle();
+            self.tasks.push(task);
+            abort
+        }
+
+        /// Output of the next task to end, `None` once all have been joined.
+        pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+            if self.tasks.is_empty() {
+                return None;
+            }
+            future::poll_fn(|cx| {
+                for i in 0..self.tasks.len() {
+                    if let Poll::Ready(output) = Pin::new(&mut self.tasks[i]).poll(cx) {
+                        self.tasks.swap_remove(i);
+                        return Poll::Ready(Some(output));
+                    }
+                }
+                Poll::Pending
+            })
+            .await
+        }
+    }
+
+    impl<T> JoinSet<T> {
+        pub fn len(&self) -> usize {
+            self.tasks.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.tasks.is_empty()
+        }
+
+        pub fn abort_all(&mut self) {
+            for task in &self.tasks {
+                task.abort();
+            }
+        }
+    }
+
+    impl<T> Drop for JoinSet<T> {
+        fn drop(&mut self) {
+            self.abort_all();
+        }
+    }
+
+    pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (abort, aborted) = channel::bounded(1);
+        // Held by the task, so dropping the `JoinHandle` does not close the channel.
+        let open = abort.clone();
+        let task = smol::spawn(async move {
+            let _open = open;
+            future::or(async { Some(future.await) }, async {
+                let _ = aborted.recv().await;
+                None
+            })
+            .await
+        });
+        JoinHandle {
+            task: Some(task),
+            abort,
+        }
+    }
+}
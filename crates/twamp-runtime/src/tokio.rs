@@ -0,0 +1,28 @@
+//! Backend of the `tokio` feature, re-exporting tokio.
+
+pub mod net {
+    use std::io;
+
+    use tokio::io::Interest;
+    pub use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+    /// Run `read` once `socket` is readable, until it no longer fails with
+    /// [`WouldBlock`](io::ErrorKind::WouldBlock). For reads `UdpSocket` has no method for, e.g.
+    /// `recvmsg` with ancillary data.
+    pub async fn read_with<R>(
+        socket: &UdpSocket,
+        read: impl FnMut() -> io::Result<R>,
+    ) -> io::Result<R> {
+        socket.async_io(Interest::READABLE, read).await
+    }
+}
+
+pub mod time {
+    pub use tokio::time::{
+        error::Elapsed, interval, sleep, sleep_until, timeout, timeout_at, Instant, Interval,
+    };
+}
+
+pub mod task {
+    pub use tokio::task::{spawn, spawn_blocking, AbortHandle, JoinError, JoinHandle, JoinSet};
+}
@@ -0,0 +1,261 @@
+//! Ancillary data the kernel passes along with a received test packet, for troubleshooting
+//! which interface packets arrive on, how they were marked and how many hops they crossed.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    os::fd::AsRawFd,
+};
+
+use crate::ecn::{enable_recv_ecn, Ecn};
+
+/// Network information of a received packet. Fields the platform or socket could not provide
+/// are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Ancillary {
+    /// Index of the interface the packet was received on.
+    pub interface: Option<u32>,
+
+    /// TOS byte, or Traffic Class on IPv6.
+    pub tos: Option<u8>,
+
+    /// TTL, or Hop Limit on IPv6, left when the packet arrived.
+    pub hop_limit: Option<u8>,
+
+    /// Address the packet was sent to, which tells local addresses apart on sockets bound to an
+    /// unspecified one.
+    pub destination: Option<IpAddr>,
+}
+
+impl Ancillary {
+    /// ECN codepoint of the packet, if its TOS byte was read.
+    pub fn ecn(&self) -> Option<Ecn> {
+        self.tos.map(Ecn::from_tos)
+    }
+}
+
+/// Ask the kernel to pass every field of [`Ancillary`] with received packets to
+/// [`recv_with_ancillary`]. A no-op where that is not supported.
+pub fn enable_recv_ancillary(socket: &impl AsRawFd) -> io::Result<()> {
+    enable_recv_ecn(socket)?;
+    #[cfg(target_os = "linux")]
+    {
+        use crate::ecn::{is_ipv6, set_int_option};
+
+        let enable_v4 = |socket: &_| -> io::Result<()> {
+            set_int_option(socket, libc::IPPROTO_IP, libc::IP_PKTINFO, 1)?;
+            set_int_option(socket, libc::IPPROTO_IP, libc::IP_RECVTTL, 1)
+        };
+        if is_ipv6(socket)? {
+            set_int_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, 1)?;
+            set_int_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+            // IPv4 packets reach dual-stack sockets too. IPv6-only sockets may refuse this.
+            let _ = enable_v4(socket);
+        } else {
+            enable_v4(socket)?;
+        }
+    }
+    Ok(())
+}
+
+/// Receive a datagram like `recv`, along with the ancillary data enabled on the socket by
+/// [`enable_recv_ancillary`] or [`enable_recv_ecn`]. Returns `WouldBlock` on a non-blocking
+/// socket with nothing to read.
+#[cfg(target_os = "linux")]
+pub fn recv_with_ancillary(
+    socket: &impl AsRawFd,
+    buf: &mut [u8],
+) -> io::Result<(usize, Ancillary)> {
+    let (len, _source, ancillary) = recvmsg(socket, buf)?;
+    Ok((len, ancillary))
+}
+
+/// Receive a datagram like `recv_from`, along with the ancillary data enabled on the socket, see
+/// [`recv_with_ancillary`].
+#[cfg(target_os = "linux")]
+pub fn recv_from_with_ancillary(
+    socket: &impl AsRawFd,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Ancillary)> {
+    let (len, source, ancillary) = recvmsg(socket, buf)?;
+    let source =
+        source.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No source address"))?;
+    Ok((len, source, ancillary))
+}
+
+#[cfg(target_os = "linux")]
+fn recvmsg(
+    socket: &impl AsRawFd,
+    buf: &mut [u8],
+) -> io::Result<(usize, Option<SocketAddr>, Ancillary)> {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV6};
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // Room for a packet info, a TOS and a TTL cmsg, whichever the address family.
+    let mut control = [0u8; 256];
+    // SAFETY: `msghdr` and `sockaddr_storage` are plain data, the pointers set below outlive
+    // `recvmsg`, cmsgs are only read within the `msg_controllen` the kernel reports, and the
+    // source address is only read as the family the kernel says it is.
+    unsafe {
+        let mut name: libc::sockaddr_storage = std::mem::zeroed();
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_name = &mut name as *mut libc::sockaddr_storage as *mut libc::c_void;
+        msg.msg_namelen = size_of::<libc::sockaddr_storage>() as _;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        let len = libc::recvmsg(socket.as_raw_fd(), &mut msg, 0);
+        if len == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut ancillary = Ancillary::default();
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_TOS) => ancillary.tos = Some(*data),
+                // The TTL, Traffic Class and Hop Limit are passed as ints rather than bytes.
+                (libc::IPPROTO_IP, libc::IP_TTL) => {
+                    let ttl = (data as *const libc::c_int).read_unaligned();
+                    ancillary.hop_limit = Some(ttl as u8);
+                }
+                (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                    let info = (data as *const libc::in_pktinfo).read_unaligned();
+                    ancillary.interface = Some(info.ipi_ifindex as u32);
+                    ancillary.destination =
+                        Some(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr)).into());
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    let tclass = (data as *const libc::c_int).read_unaligned();
+                    ancillary.tos = Some(tclass as u8);
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                    let hop_limit = (data as *const libc::c_int).read_unaligned();
+                    ancillary.hop_limit = Some(hop_limit as u8);
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                    let info = (data as *const libc::in6_pktinfo).read_unaligned();
+                    ancillary.interface = Some(info.ipi6_ifindex);
+                    ancillary.destination = Some(Ipv6Addr::from(info.ipi6_addr.s6_addr).into());
+                }
+                _ => (),
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        let source = match name.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let addr = *(&name as *const libc::sockaddr_storage as *const libc::sockaddr_in);
+                Some(SocketAddr::new(
+                    Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)).into(),
+                    u16::from_be(addr.sin_port),
+                ))
+            }
+            libc::AF_INET6 => {
+                let addr = *(&name as *const libc::sockaddr_storage as *const libc::sockaddr_in6);
+                Some(
+                    SocketAddrV6::new(
+                        Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                        u16::from_be(addr.sin6_port),
+                        addr.sin6_flowinfo,
+                        addr.sin6_scope_id,
+                    )
+                    .into(),
+                )
+            }
+            _ => None,
+        };
+        Ok((len as usize, source, ancillary))
+    }
+}
+
+/// Receive a datagram like `recv`. Ancillary data can't be read on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn recv_with_ancillary(
+    socket: &impl AsRawFd,
+    buf: &mut [u8],
+) -> io::Result<(usize, Ancillary)> {
+    // SAFETY: `buf` outlives the call and its length is passed along.
+    let len = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            0,
+        )
+    };
+    if len == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((len as usize, Ancillary::default()))
+}
+
+/// Receive a datagram like `recv_from`. Ancillary data can't be read on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn recv_from_with_ancillary(
+    socket: &impl AsRawFd,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Ancillary)> {
+    use std::os::fd::{FromRawFd, IntoRawFd};
+
+    // SAFETY: the descriptor is borrowed for the call and handed back rather than closed.
+    let socket = unsafe { std::net::UdpSocket::from_raw_fd(socket.as_raw_fd()) };
+    let received = socket.recv_from(buf);
+    let _ = socket.into_raw_fd();
+    let (len, source) = received?;
+    Ok((len, source, Ancillary::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecn::set_tos;
+    use std::net::UdpSocket;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn recv_reads_ancillary_data() {
+        for addr in ["127.0.0.1:0", "[::1]:0"] {
+            let Ok(receiver) = UdpSocket::bind(addr) else {
+                // No IPv6 on this host.
+                continue;
+            };
+            let sender = UdpSocket::bind(addr).unwrap();
+            enable_recv_ancillary(&receiver).unwrap();
+            set_tos(&sender, Ecn::Ect1.bits().into()).unwrap();
+            sender
+                .send_to(b"twamp", receiver.local_addr().unwrap())
+                .unwrap();
+            let mut buf = [0u8; 16];
+            let (len, ancillary) = recv_with_ancillary(&receiver, &mut buf).unwrap();
+            assert_eq!(&buf[..len], b"twamp");
+            assert_eq!(
+                ancillary.destination,
+                Some(receiver.local_addr().unwrap().ip()),
+                "over {}",
+                addr
+            );
+            assert_eq!(ancillary.ecn(), Some(Ecn::Ect1), "over {}", addr);
+            assert!(ancillary.hop_limit.is_some_and(|h| h > 0), "over {}", addr);
+            // The loopback interface.
+            assert!(ancillary.interface.is_some_and(|i| i > 0), "over {}", addr);
+        }
+    }
+
+    #[test]
+    fn recv_from_reads_source_address() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        enable_recv_ancillary(&receiver).unwrap();
+        sender
+            .send_to(b"stamp", receiver.local_addr().unwrap())
+            .unwrap();
+        let mut buf = [0u8; 16];
+        let (len, source, _ancillary) = recv_from_with_ancillary(&receiver, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"stamp");
+        assert_eq!(source, sender.local_addr().unwrap());
+    }
+}
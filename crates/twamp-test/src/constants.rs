@@ -1 +1,27 @@
+use std::net::IpAddr;
+
 pub const TWAMP_TEST_WELL_KNOWN_PORT: u16 = 862;
+
+/// Target of tracing events about TWAMP-Test.
+pub const TRACING_TARGET: &str = "twamp::test";
+
+/// Size in bytes of the IPv4 and UDP headers in front of each test packet.
+pub const IPV4_UDP_HEADER_LENGTH: usize = 28;
+
+/// Size in bytes of the IPv6 and UDP headers in front of each test packet, without extension
+/// headers.
+pub const IPV6_UDP_HEADER_LENGTH: usize = 48;
+
+/// Size in bytes of the IP and UDP headers in front of test packets exchanged with `addr`.
+pub fn ip_udp_header_length(addr: IpAddr) -> usize {
+    match addr.to_canonical() {
+        IpAddr::V4(_) => IPV4_UDP_HEADER_LENGTH,
+        IpAddr::V6(_) => IPV6_UDP_HEADER_LENGTH,
+    }
+}
+
+/// Largest UDP payload an IPv4 packet carries.
+pub const MAX_UDP_PAYLOAD: usize = u16::MAX as usize - IPV4_UDP_HEADER_LENGTH;
+
+/// MTU of Ethernet, which test packets fit in without fragmentation unless told otherwise.
+pub const ETHERNET_MTU: usize = 1500;
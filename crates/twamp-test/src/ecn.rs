@@ -0,0 +1,174 @@
+//! ECN codepoints ([RFC 3168](https://datatracker.ietf.org/doc/html/rfc3168#section-5)) of test
+//! packets, and std-only helpers to set and read the TOS byte, or the Traffic Class on IPv6,
+//! through a UDP socket's file descriptor.
+
+use std::{io, os::fd::AsRawFd};
+
+use crate::ancillary::recv_with_ancillary;
+
+/// ECN field of an IP packet.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Ecn {
+    #[default]
+    NotEct,
+    Ect1,
+    Ect0,
+    /// Congestion Experienced, set by a router instead of dropping the packet.
+    Ce,
+}
+
+impl Ecn {
+    /// ECN field of an IPv4 TOS byte.
+    pub fn from_tos(tos: u8) -> Self {
+        match tos & 0b11 {
+            0b00 => Ecn::NotEct,
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+
+    /// Bits to OR into a TOS byte to mark packets with this codepoint.
+    pub fn bits(self) -> u8 {
+        match self {
+            Ecn::NotEct => 0b00,
+            Ecn::Ect1 => 0b01,
+            Ecn::Ect0 => 0b10,
+            Ecn::Ce => 0b11,
+        }
+    }
+}
+
+/// Number of packets seen with each [`Ecn`] codepoint.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EcnCounts {
+    pub not_ect: u64,
+    pub ect1: u64,
+    pub ect0: u64,
+    pub ce: u64,
+    /// Packets whose codepoint could not be read.
+    pub unknown: u64,
+}
+
+impl EcnCounts {
+    pub fn record(&mut self, ecn: Option<Ecn>) {
+        match ecn {
+            Some(Ecn::NotEct) => self.not_ect += 1,
+            Some(Ecn::Ect1) => self.ect1 += 1,
+            Some(Ecn::Ect0) => self.ect0 += 1,
+            Some(Ecn::Ce) => self.ce += 1,
+            None => self.unknown += 1,
+        }
+    }
+}
+
+pub(crate) fn set_int_option(
+    socket: &impl AsRawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    // SAFETY: `value` outlives the call and its size is passed along.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether the socket is bound to an IPv6 address, including dual-stack sockets.
+pub(crate) fn is_ipv6(socket: &impl AsRawFd) -> io::Result<bool> {
+    // SAFETY: `sockaddr_storage` is plain data, large enough for any address, and its size is
+    // passed along.
+    unsafe {
+        let mut addr: libc::sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of_val(&addr) as libc::socklen_t;
+        if libc::getsockname(
+            socket.as_raw_fd(),
+            &mut addr as *mut _ as *mut libc::sockaddr,
+            &mut len,
+        ) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(libc::c_int::from(addr.ss_family) == libc::AF_INET6)
+    }
+}
+
+/// Set the TOS byte of packets sent from the socket, or their Traffic Class if it is IPv6.
+pub fn set_tos(socket: &impl AsRawFd, tos: u32) -> io::Result<()> {
+    let tos = tos as libc::c_int;
+    if is_ipv6(socket)? {
+        set_int_option(socket, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos)
+    } else {
+        set_int_option(socket, libc::IPPROTO_IP, libc::IP_TOS, tos)
+    }
+}
+
+/// Ask the kernel to pass the TOS byte, or the Traffic Class on IPv6, of received packets to
+/// [`recv_with_ecn`]. A no-op where that is not supported.
+pub fn enable_recv_ecn(socket: &impl AsRawFd) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        if is_ipv6(socket)? {
+            set_int_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1)?;
+            // IPv4 packets reach dual-stack sockets too. IPv6-only sockets may refuse this.
+            let _ = set_int_option(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, 1);
+        } else {
+            set_int_option(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, 1)?;
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = socket;
+    Ok(())
+}
+
+/// Receive a datagram like `recv`, along with its ECN codepoint if [`enable_recv_ecn`] was
+/// called on the socket and the platform supports it. Returns `WouldBlock` on a non-blocking
+/// socket with nothing to read.
+pub fn recv_with_ecn(socket: &impl AsRawFd, buf: &mut [u8]) -> io::Result<(usize, Option<Ecn>)> {
+    let (len, ancillary) = recv_with_ancillary(socket, buf)?;
+    Ok((len, ancillary.ecn()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn ecn_round_trips_through_tos() {
+        for ecn in [Ecn::NotEct, Ecn::Ect1, Ecn::Ect0, Ecn::Ce] {
+            assert_eq!(Ecn::from_tos(0xb8 | ecn.bits()), ecn);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn recv_reads_ecn_of_marked_packet() {
+        for addr in ["127.0.0.1:0", "[::1]:0"] {
+            let Ok(receiver) = UdpSocket::bind(addr) else {
+                // No IPv6 on this host.
+                continue;
+            };
+            let sender = UdpSocket::bind(addr).unwrap();
+            enable_recv_ecn(&receiver).unwrap();
+            set_tos(&sender, Ecn::Ect0.bits().into()).unwrap();
+            sender
+                .send_to(b"twamp", receiver.local_addr().unwrap())
+                .unwrap();
+            let mut buf = [0u8; 16];
+            let (len, ecn) = recv_with_ecn(&receiver, &mut buf).unwrap();
+            assert_eq!(&buf[..len], b"twamp");
+            assert_eq!(ecn, Some(Ecn::Ect0), "over {}", addr);
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use deku::prelude::*;
+use timestamp::timestamp::TimestampFormat;
 
+/// Error Estimate following a timestamp, see
+/// [RFC 4656](https://datatracker.ietf.org/doc/html/rfc4656#section-4.1.2).
+///
+/// Read in the same compliance context as the test packets carrying it, though it has no MBZ
+/// field since [RFC 8186](https://datatracker.ietf.org/doc/html/rfc8186#section-2.3) made its
+/// only one the Z bit.
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian, _lenient: bool")]
 pub struct ErrorEstimate {
     /// SHOULD be set if the party generating the timestamp has a clock that is synchronized to UTC
     /// using an external source (e.g., the bit should be set if GPS hardware is used and it
@@ -12,10 +19,11 @@ pub struct ErrorEstimate {
     #[deku(bits = "1")]
     s: u8,
 
-    /// Same semantics as MBZ fields elsewhere: it MUST be set to zero by the sender and ignored
-    /// by everyone else.
-    #[deku(bits = "1", assert_eq = "0u8")]
-    mbz: u8,
+    /// Z: format of the timestamp, NTP if unset and truncated PTP if set. MBZ before
+    /// [RFC 8186](https://datatracker.ietf.org/doc/html/rfc8186#section-2.3), so peers without
+    /// its support always send NTP.
+    #[deku(bits = "1")]
+    z: u8,
 
     /// An unsigned integer.
     #[deku(bits = "6")]
@@ -34,11 +42,28 @@ impl ErrorEstimate {
     pub fn new(ntp_synchronized: bool) -> ErrorEstimate {
         ErrorEstimate {
             s: if ntp_synchronized { 1 } else { 0 },
-            mbz: 0,
+            z: 0,
             scale: if ntp_synchronized { 0 } else { 63 },
             multiplier: if ntp_synchronized { 1 } else { 255 },
         }
     }
+
+    /// The same estimate for a timestamp in `format`.
+    pub fn with_timestamp_format(self, format: TimestampFormat) -> ErrorEstimate {
+        let z = match format {
+            TimestampFormat::Ntp => 0,
+            TimestampFormat::Ptp => 1,
+        };
+        ErrorEstimate { z, ..self }
+    }
+
+    /// Format of the timestamp this estimate is for.
+    pub fn timestamp_format(&self) -> TimestampFormat {
+        match self.z {
+            0 => TimestampFormat::Ntp,
+            _ => TimestampFormat::Ptp,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -49,7 +74,7 @@ mod tests {
     fn create_error_estimate_with_ntp_synchronized() {
         let error_estimate = ErrorEstimate::new(true);
         assert_eq!(error_estimate.s, 1);
-        assert_eq!(error_estimate.mbz, 0);
+        assert_eq!(error_estimate.z, 0);
         assert_eq!(error_estimate.scale, 0);
         assert_eq!(error_estimate.multiplier, 1);
     }
@@ -58,8 +83,17 @@ mod tests {
     fn create_error_estimate_with_ntp_not_synchronized() {
         let error_estimate = ErrorEstimate::new(false);
         assert_eq!(error_estimate.s, 0);
-        assert_eq!(error_estimate.mbz, 0);
+        assert_eq!(error_estimate.z, 0);
         assert_eq!(error_estimate.scale, 63);
         assert_eq!(error_estimate.multiplier, 255);
     }
+
+    #[test]
+    fn z_bit_tells_timestamp_format() {
+        let error_estimate = ErrorEstimate::new(true).with_timestamp_format(TimestampFormat::Ptp);
+        assert_eq!(error_estimate.z, 1);
+        assert_eq!(error_estimate.timestamp_format(), TimestampFormat::Ptp);
+        let error_estimate = error_estimate.with_timestamp_format(TimestampFormat::Ntp);
+        assert_eq!(error_estimate.timestamp_format(), TimestampFormat::Ntp);
+    }
 }
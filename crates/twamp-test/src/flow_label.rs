@@ -0,0 +1,199 @@
+//! IPv6 flow labels ([RFC 6437](https://datatracker.ietf.org/doc/html/rfc6437)) of test
+//! packets. Routers hashing the flow label to pick among equal-cost paths send packets of
+//! different labels down different paths, so sessions can explore them deliberately.
+
+use std::{
+    io,
+    net::{Ipv6Addr, SocketAddrV6},
+    os::fd::AsRawFd,
+};
+
+/// Largest flow label, which is 20 bits wide.
+pub const MAX_FLOW_LABEL: u32 = 0xf_ffff;
+
+/// Flow label to send the test packets of a session with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlowLabel {
+    /// This label, at most [`MAX_FLOW_LABEL`].
+    Fixed(u32),
+
+    /// A label picked by the kernel among those no other socket holds, fresh for every session.
+    Random,
+}
+
+/// `addr` carrying `label`, to `connect` a socket to once it leased the label so the packets it
+/// sends carry it.
+pub fn with_flow_label(addr: SocketAddrV6, label: u32) -> SocketAddrV6 {
+    // The flow information is passed in network byte order.
+    SocketAddrV6::new(*addr.ip(), addr.port(), label.to_be(), addr.scope_id())
+}
+
+/// Lease the flow label for packets sent from the IPv6 `socket` to `peer`, and return it. The
+/// socket must then be connected to the peer [carrying the label](with_flow_label).
+#[cfg(target_os = "linux")]
+pub fn lease_flow_label(
+    socket: &impl AsRawFd,
+    peer: Ipv6Addr,
+    flow_label: FlowLabel,
+) -> io::Result<u32> {
+    use crate::ecn::set_int_option;
+
+    // From linux/in6.h, which the libc crate leaves out.
+    const IPV6_FLOWLABEL_MGR: libc::c_int = 32;
+    const IPV6_FLOWINFO_SEND: libc::c_int = 33;
+    const IPV6_FL_A_GET: u8 = 0;
+    const IPV6_FL_S_EXCL: u8 = 1;
+    const IPV6_FL_F_CREATE: u16 = 1;
+    /// Seconds the label stays reserved once the socket closes.
+    const LINGER: u16 = 6;
+
+    #[repr(C)]
+    struct In6FlowlabelReq {
+        flr_dst: libc::in6_addr,
+        flr_label: u32,
+        flr_action: u8,
+        flr_share: u8,
+        flr_flags: u16,
+        flr_expires: u16,
+        flr_linger: u16,
+        flr_pad: u32,
+    }
+
+    let label = match flow_label {
+        FlowLabel::Fixed(label) if label > MAX_FLOW_LABEL => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Flow label {:#x} is wider than 20 bits", label),
+            ))
+        }
+        FlowLabel::Fixed(label) => label,
+        // The kernel picks one for a label of 0.
+        FlowLabel::Random => 0,
+    };
+    let mut request = In6FlowlabelReq {
+        flr_dst: libc::in6_addr {
+            s6_addr: peer.octets(),
+        },
+        flr_label: label.to_be(),
+        flr_action: IPV6_FL_A_GET,
+        flr_share: IPV6_FL_S_EXCL,
+        flr_flags: IPV6_FL_F_CREATE,
+        flr_expires: 0,
+        flr_linger: LINGER,
+        flr_pad: 0,
+    };
+    // SAFETY: `request` outlives the call and its size is passed along. The kernel writes the
+    // label it picked back into it.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            IPV6_FLOWLABEL_MGR,
+            &mut request as *mut _ as *const libc::c_void,
+            size_of::<In6FlowlabelReq>() as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    set_int_option(socket, libc::IPPROTO_IPV6, IPV6_FLOWINFO_SEND, 1)?;
+    Ok(u32::from_be(request.flr_label))
+}
+
+/// Flow labels can't be set on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn lease_flow_label(
+    _socket: &impl AsRawFd,
+    _peer: Ipv6Addr,
+    _flow_label: FlowLabel,
+) -> io::Result<u32> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Flow labels are only supported on Linux",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddr, UdpSocket};
+
+    fn socket_pair() -> Option<(UdpSocket, UdpSocket)> {
+        // No IPv6 on this host otherwise.
+        let receiver = UdpSocket::bind("[::1]:0").ok()?;
+        let sender = UdpSocket::bind("[::1]:0").unwrap();
+        Some((sender, receiver))
+    }
+
+    fn v6_addr(socket: &UdpSocket) -> SocketAddrV6 {
+        match socket.local_addr().unwrap() {
+            SocketAddr::V6(addr) => addr,
+            SocketAddr::V4(_) => unreachable!(),
+        }
+    }
+
+    /// Flow label of the next packet `receiver` gets.
+    fn recv_flow_label(receiver: &UdpSocket) -> u32 {
+        const IPV6_FLOWINFO: libc::c_int = 11;
+        crate::ecn::set_int_option(receiver, libc::IPPROTO_IPV6, IPV6_FLOWINFO, 1).unwrap();
+        let mut buf = [0u8; 16];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let mut control = [0u8; 64];
+        // SAFETY: as in `ancillary::recvmsg`.
+        unsafe {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = control.len() as _;
+            assert!(libc::recvmsg(receiver.as_raw_fd(), &mut msg, 0) > 0);
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            assert!(!cmsg.is_null());
+            assert_eq!((*cmsg).cmsg_type, IPV6_FLOWINFO);
+            let flowinfo = (libc::CMSG_DATA(cmsg) as *const u32).read_unaligned();
+            u32::from_be(flowinfo) & MAX_FLOW_LABEL
+        }
+    }
+
+    #[test]
+    fn packets_carry_fixed_flow_label() {
+        let Some((sender, receiver)) = socket_pair() else {
+            return;
+        };
+        let peer = v6_addr(&receiver);
+        let label = lease_flow_label(&sender, *peer.ip(), FlowLabel::Fixed(0x1_2345)).unwrap();
+        assert_eq!(label, 0x1_2345);
+        sender.connect(with_flow_label(peer, label)).unwrap();
+        sender.send(b"twamp").unwrap();
+        assert_eq!(recv_flow_label(&receiver), 0x1_2345);
+    }
+
+    #[test]
+    fn random_flow_labels_differ_between_sockets() {
+        let Some((sender, receiver)) = socket_pair() else {
+            return;
+        };
+        let other = UdpSocket::bind("[::1]:0").unwrap();
+        let peer = v6_addr(&receiver);
+        let label = lease_flow_label(&sender, *peer.ip(), FlowLabel::Random).unwrap();
+        let other_label = lease_flow_label(&other, *peer.ip(), FlowLabel::Random).unwrap();
+        assert_ne!(label, 0);
+        assert_ne!(label, other_label);
+        sender.connect(with_flow_label(peer, label)).unwrap();
+        sender.send(b"twamp").unwrap();
+        assert_eq!(recv_flow_label(&receiver), label);
+    }
+
+    #[test]
+    fn rejects_flow_label_wider_than_20_bits() {
+        let Some((sender, receiver)) = socket_pair() else {
+            return;
+        };
+        let peer = *v6_addr(&receiver).ip();
+        let err = lease_flow_label(&sender, peer, FlowLabel::Fixed(0x10_0000));
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,143 @@
+//! Keys protecting TWAMP-Test packets in
+//! [authenticated and encrypted modes](https://datatracker.ietf.org/doc/html/rfc5357#section-4.1.2).
+
+use std::fmt;
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+/// Length in bytes of the HMAC field of authenticated test packets.
+pub const HMAC_LENGTH: usize = 16;
+
+/// Keys of a single test session, derived from the TWAMP-Control session keys and the SID.
+#[derive(Clone, PartialEq)]
+pub struct TestKeys {
+    aes: [u8; 16],
+    hmac: [u8; 32],
+    encrypt_all: bool,
+}
+
+impl fmt::Debug for TestKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestKeys").finish_non_exhaustive()
+    }
+}
+
+impl TestKeys {
+    /// The AES key is the SID encrypted in ECB mode under the AES session key. The HMAC key is
+    /// the HMAC session key encrypted in CBC mode under the AES session key, with the SID as IV.
+    pub fn derive(aes_session_key: &[u8; 16], hmac_session_key: &[u8; 32], sid: &[u8; 16]) -> Self {
+        let cipher = Aes128::new(aes_session_key.into());
+        let mut aes = *sid;
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut aes));
+        let mut hmac = *hmac_session_key;
+        let mut previous = *sid;
+        for block in hmac.chunks_exact_mut(16) {
+            block.iter_mut().zip(previous).for_each(|(b, p)| *b ^= p);
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+            previous.copy_from_slice(block);
+        }
+        TestKeys {
+            aes,
+            hmac,
+            encrypt_all: false,
+        }
+    }
+
+    /// Encrypt every block covered by the HMAC in CBC mode with a zero IV, as in encrypted mode,
+    /// rather than only the first block.
+    pub fn with_encryption(mut self) -> Self {
+        self.encrypt_all = true;
+        self
+    }
+
+    /// Number of leading bytes encrypted when `covered` bytes are covered by the HMAC.
+    fn encrypted_length(&self, covered: usize) -> usize {
+        if self.encrypt_all {
+            covered
+        } else {
+            16
+        }
+    }
+
+    fn mac(&self) -> Hmac<Sha1> {
+        <Hmac<Sha1> as Mac>::new_from_slice(&self.hmac).unwrap()
+    }
+
+    /// Write the HMAC of the first `covered` bytes of an encoded packet right after them, then
+    /// encrypt its first block.
+    pub fn seal(&self, packet: &mut [u8], covered: usize) {
+        let hmac = self.mac().chain_update(&packet[..covered]).finalize();
+        packet[covered..covered + HMAC_LENGTH].copy_from_slice(&hmac.into_bytes()[..HMAC_LENGTH]);
+        let cipher = Aes128::new((&self.aes).into());
+        let mut previous = [0; 16];
+        for block in packet[..self.encrypted_length(covered)].chunks_exact_mut(16) {
+            block.iter_mut().zip(previous).for_each(|(b, p)| *b ^= p);
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+            previous.copy_from_slice(block);
+        }
+    }
+
+    /// Undo [`seal`](Self::seal), returning whether the HMAC is correct.
+    pub fn open(&self, packet: &mut [u8], covered: usize) -> bool {
+        if packet.len() < covered + HMAC_LENGTH {
+            return false;
+        }
+        let cipher = Aes128::new((&self.aes).into());
+        let mut previous = [0; 16];
+        for block in packet[..self.encrypted_length(covered)].chunks_exact_mut(16) {
+            let encrypted: [u8; 16] = block.try_into().unwrap();
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+            block.iter_mut().zip(previous).for_each(|(b, p)| *b ^= p);
+            previous = encrypted;
+        }
+        self.mac()
+            .chain_update(&packet[..covered])
+            .verify_truncated_left(&packet[covered..covered + HMAC_LENGTH])
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_differ_per_session() {
+        let keys = TestKeys::derive(&[1; 16], &[2; 32], &[3; 16]);
+        assert_eq!(keys, TestKeys::derive(&[1; 16], &[2; 32], &[3; 16]));
+        assert_ne!(keys, TestKeys::derive(&[1; 16], &[2; 32], &[4; 16]));
+    }
+
+    #[test]
+    fn sealed_packet_opens_until_tampered() {
+        let keys = TestKeys::derive(&[1; 16], &[2; 32], &[3; 16]);
+        let mut packet = [9u8; 64];
+        keys.seal(&mut packet, 32);
+        assert_ne!(packet[..16], [9; 16]);
+        let mut opened = packet;
+        assert!(keys.open(&mut opened, 32));
+        assert_eq!(opened[..32], [9; 32]);
+        packet[20] ^= 1;
+        assert!(!keys.open(&mut packet, 32));
+        assert!(!keys.open(&mut [0; 40], 32));
+    }
+
+    #[test]
+    fn encryption_covers_every_block_before_hmac() {
+        let keys = TestKeys::derive(&[1; 16], &[2; 32], &[3; 16]);
+        let mut authenticated = [9u8; 64];
+        keys.seal(&mut authenticated, 32);
+        assert_eq!(authenticated[16..32], [9; 16]);
+        let keys = keys.with_encryption();
+        let mut encrypted = [9u8; 64];
+        keys.seal(&mut encrypted, 32);
+        assert_ne!(encrypted[16..32], [9; 16]);
+        assert!(keys.open(&mut encrypted, 32));
+        assert_eq!(encrypted[..32], [9; 32]);
+    }
+}
@@ -1,4 +1,18 @@
+// `DekuRead` derive expands to a manual `div_ceil`, which we have no control over.
+#![allow(clippy::manual_div_ceil)]
+
+pub mod ancillary;
 pub mod constants;
+pub mod ecn;
 pub mod error_estimate;
+pub mod flow_label;
+pub mod keys;
+pub mod padding;
+pub mod reflect_octets;
+pub mod send_queue;
+pub mod stamp;
+pub mod stamp_tlv;
+pub mod twamp_test_auth;
+pub mod twamp_test_auth_reflected;
 pub mod twamp_test_unauth;
 pub mod twamp_test_unauth_reflected;
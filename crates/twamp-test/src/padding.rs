@@ -0,0 +1,100 @@
+//! Padding closing TWAMP-Test packets, as long as the rest of the datagram.
+
+use deku::{
+    bitvec::{BitSlice, Msb0},
+    prelude::*,
+};
+use rand::RngCore;
+
+/// What the padding of test packets is filled with.
+///
+/// [RFC 4656](https://datatracker.ietf.org/doc/html/rfc4656#section-4.1.2) recommends
+/// pseudo-random padding: links and middleboxes compressing payloads shrink zero padding, which
+/// skews delay and throughput results.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PaddingPattern {
+    /// Zero octets.
+    #[default]
+    Zeros,
+
+    /// Random octets, drawn afresh for each packet.
+    Random,
+
+    /// The PRBS31 sequence of ITU-T O.150 from its all-ones seed, the same in each packet so
+    /// it can be checked on arrival.
+    Prbs31,
+
+    /// The same octet repeated.
+    Fixed(u8),
+}
+
+impl PaddingPattern {
+    /// Overwrite `padding` with the pattern.
+    pub fn fill(&self, padding: &mut [u8]) {
+        match self {
+            PaddingPattern::Zeros => padding.fill(0),
+            PaddingPattern::Random => rand::thread_rng().fill_bytes(padding),
+            PaddingPattern::Prbs31 => {
+                let mut state: u32 = 0x7fff_ffff;
+                for octet in padding {
+                    let mut bits = 0;
+                    for _ in 0..8 {
+                        let bit = ((state >> 30) ^ (state >> 27)) & 1;
+                        state = ((state << 1) | bit) & 0x7fff_ffff;
+                        bits = (bits << 1) | bit as u8;
+                    }
+                    *octet = bits;
+                }
+            }
+            PaddingPattern::Fixed(octet) => padding.fill(*octet),
+        }
+    }
+}
+
+/// Read the rest of a packet as padding, for `#[deku(reader)]`.
+pub(crate) fn read_padding(
+    rest: &BitSlice<u8, Msb0>,
+) -> Result<(&BitSlice<u8, Msb0>, Vec<u8>), DekuError> {
+    let mut padding = Vec::with_capacity(rest.len() / 8);
+    let mut rest = rest;
+    while rest.len() >= 8 {
+        let (next, byte) = u8::read(rest, ())?;
+        padding.push(byte);
+        rest = next;
+    }
+    Ok((rest, padding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(pattern: PaddingPattern) -> Vec<u8> {
+        let mut padding = vec![0x55; 64];
+        pattern.fill(&mut padding);
+        padding
+    }
+
+    #[test]
+    fn zeros_and_fixed_repeat_one_octet() {
+        assert_eq!(filled(PaddingPattern::Zeros), [0; 64]);
+        assert_eq!(filled(PaddingPattern::Fixed(0xa5)), [0xa5; 64]);
+    }
+
+    #[test]
+    fn random_differs_between_packets() {
+        assert_ne!(
+            filled(PaddingPattern::Random),
+            filled(PaddingPattern::Random)
+        );
+    }
+
+    #[test]
+    fn prbs31_is_the_same_in_every_packet() {
+        let padding = filled(PaddingPattern::Prbs31);
+        assert_eq!(padding, filled(PaddingPattern::Prbs31));
+        assert!(padding.iter().any(|&octet| octet != padding[0]));
+        // From the all-ones seed, the sequence starts with 28 zeros.
+        assert_eq!(padding[..4], [0, 0, 0, 0x0e]);
+    }
+}
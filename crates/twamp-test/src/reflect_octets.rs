@@ -0,0 +1,61 @@
+use crate::twamp_test_unauth::TwampTestPacketUnauth;
+
+/// Octets the Session-Sender places at the start of the padding of test packets for the
+/// Session-Reflector to copy back, see
+/// [RFC 6038](https://datatracker.ietf.org/doc/html/rfc6038#section-4.2).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReflectOctets {
+    /// Octets to be Reflected of Request-TW-Session, echoed by the Server in Accept-Session and
+    /// repeated to fill the padding to reflect.
+    pub octets: u16,
+
+    /// Number of padding octets the Session-Reflector is asked to reflect, at most
+    /// [`TwampTestPacketUnauth::MAX_PADDING_LENGTH`].
+    pub length: u16,
+}
+
+impl ReflectOctets {
+    pub fn new(octets: u16, length: u16) -> Self {
+        ReflectOctets {
+            octets,
+            length: length.min(TwampTestPacketUnauth::MAX_PADDING_LENGTH as u16),
+        }
+    }
+
+    /// Padding octets to send and expect back.
+    pub fn pattern(&self) -> Vec<u8> {
+        self.octets
+            .to_be_bytes()
+            .into_iter()
+            .cycle()
+            .take(self.length.into())
+            .collect()
+    }
+
+    /// Whether the padding of a reflected packet starts with the [pattern](Self::pattern).
+    pub fn matches(&self, padding: &[u8]) -> bool {
+        padding.starts_with(&self.pattern())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_repeats_octets_over_length() {
+        let reflect_octets = ReflectOctets::new(0xabcd, 5);
+        assert_eq!(reflect_octets.pattern(), [0xab, 0xcd, 0xab, 0xcd, 0xab]);
+        assert!(reflect_octets.matches(&[0xab, 0xcd, 0xab, 0xcd, 0xab, 0, 0]));
+        assert!(!reflect_octets.matches(&[0xab, 0xcd, 0xab, 0xcd]));
+    }
+
+    #[test]
+    fn length_is_capped_to_padding() {
+        assert_eq!(ReflectOctets::new(1, 1400).length, 1400);
+        assert_eq!(
+            usize::from(ReflectOctets::new(1, u16::MAX).length),
+            TwampTestPacketUnauth::MAX_PADDING_LENGTH
+        );
+    }
+}
@@ -0,0 +1,46 @@
+//! std-only helper telling whether datagrams sent through a UDP socket's file descriptor have
+//! actually left the host.
+
+use std::{io, os::fd::AsRawFd};
+
+/// Bytes of datagrams sent from the socket that the kernel still holds, whether queued or
+/// awaiting transmit completion from the network device. Always 0 where that cannot be read.
+pub fn queued_bytes(socket: &impl AsRawFd) -> io::Result<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut queued: libc::c_int = 0;
+        // SAFETY: SIOCOUTQ writes a single `c_int` through the pointer, which outlives the call.
+        let ret = unsafe { libc::ioctl(socket.as_raw_fd(), libc::TIOCOUTQ, &mut queued) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(queued.max(0) as usize)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = socket;
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::UdpSocket, thread::sleep, time::Duration};
+
+    #[test]
+    fn send_queue_drains_after_send() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .send_to(b"twamp", receiver.local_addr().unwrap())
+            .unwrap();
+        for _ in 0..100 {
+            if queued_bytes(&sender).unwrap() == 0 {
+                return;
+            }
+            sleep(Duration::from_millis(1));
+        }
+        panic!("Send queue did not drain");
+    }
+}
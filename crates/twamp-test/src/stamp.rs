@@ -0,0 +1,231 @@
+//! Unauthenticated test packets of STAMP ([RFC 8762](https://datatracker.ietf.org/doc/html/rfc8762)).
+//!
+//! A STAMP Session-Sender packet is a TWAMP-Test one with 30 octets of MBZ padding, so
+//! [`TwampTestPacketUnauth`](crate::twamp_test_unauth::TwampTestPacketUnauth) parses it too.
+//! Reflected packets differ: the STAMP layout ends with 3 more MBZ octets, and is sent padded to
+//! the length of the test packet. TWAMP Session-Senders read those octets as padding.
+//!
+//! Either packet may be followed by [TLVs](crate::stamp_tlv) of RFC 8972, which also gives the
+//! first MBZ octets of both a Session-ID (SSID).
+
+use std::fmt::Display;
+
+use crate::{
+    error_estimate::ErrorEstimate,
+    stamp_tlv::{read_tlvs, write_tlvs, Tlv},
+};
+use deku::prelude::*;
+use timestamp::timestamp::TimeStamp;
+
+/// The packet sent by a STAMP Session-Sender in unauthenticated mode.
+///
+/// See details in [RFC 8762](https://datatracker.ietf.org/doc/html/rfc8762#section-4.2.1).
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
+pub struct StampTestPacket {
+    pub sequence_number: u32,
+    pub timestamp: TimeStamp,
+    #[deku(ctx = "lenient")]
+    pub error_estimate: ErrorEstimate,
+    /// Session-ID picked by the Session-Sender, 0 where RFC 8972 is not in use.
+    pub ssid: u16,
+    #[deku(assert = "lenient || *mbz == [0u8; 28]")]
+    mbz: [u8; 28],
+    /// TLVs after the [`LENGTH`](Self::LENGTH) octets of the packet.
+    #[deku(
+        reader = "read_tlvs(deku::rest)",
+        writer = "write_tlvs(deku::output, tlvs)"
+    )]
+    pub tlvs: Vec<Tlv>,
+}
+
+impl Display for StampTestPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "STAMP Session-Sender packet with sequence: {}",
+            self.sequence_number
+        )
+    }
+}
+
+impl StampTestPacket {
+    /// Length in bytes of the packet without TLVs, the same as of the reflected packet.
+    pub const LENGTH: usize = 44;
+
+    pub fn new(sequence_number: u32, is_ntp_synchronized: bool) -> Self {
+        StampTestPacket {
+            sequence_number,
+            timestamp: TimeStamp::default(),
+            error_estimate: ErrorEstimate::new(is_ntp_synchronized),
+            ssid: 0,
+            mbz: [0; 28],
+            tlvs: Vec::new(),
+        }
+    }
+
+    /// Send with the Session-ID `ssid`.
+    pub fn with_ssid(mut self, ssid: u16) -> Self {
+        self.ssid = ssid;
+        self
+    }
+
+    /// Follow the packet with `tlvs`.
+    pub fn with_tlvs(mut self, tlvs: Vec<Tlv>) -> Self {
+        self.tlvs = tlvs;
+        self
+    }
+}
+
+/// The packet sent by a STAMP Session-Reflector in unauthenticated mode.
+///
+/// See details in [RFC 8762](https://datatracker.ietf.org/doc/html/rfc8762#section-4.3.1).
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
+pub struct StampTestPacketReflected {
+    /// Counted by the Session-Reflector in stateful mode, a copy of the Session-Sender's in
+    /// stateless mode.
+    pub sequence_number: u32,
+    /// Timestamp when the reflected packet was sent from the Session-Reflector.
+    pub timestamp: TimeStamp,
+    #[deku(ctx = "lenient")]
+    pub error_estimate: ErrorEstimate,
+    /// Session-ID of the test packet.
+    pub ssid: u16,
+    /// Time the test packet was received by the Session-Reflector.
+    pub receive_timestamp: TimeStamp,
+    pub sender_sequence_number: u32,
+    pub sender_timestamp: TimeStamp,
+    #[deku(ctx = "lenient")]
+    pub error_estimate_sender: ErrorEstimate,
+    #[deku(assert = "lenient || *mbz_second == 0u16")]
+    pub mbz_second: u16,
+    pub sender_ttl: u8,
+    #[deku(assert = "lenient || *mbz_third == [0u8; 3]")]
+    pub mbz_third: [u8; 3],
+    /// TLVs of the test packet, populated by the Session-Reflector.
+    #[deku(
+        reader = "read_tlvs(deku::rest)",
+        writer = "write_tlvs(deku::output, tlvs)"
+    )]
+    pub tlvs: Vec<Tlv>,
+}
+
+impl Display for StampTestPacketReflected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "STAMP Session-Reflector packet with sequence: {}",
+            self.sequence_number
+        )
+    }
+}
+
+impl StampTestPacketReflected {
+    /// Length in bytes of the packet without TLVs or padding.
+    pub const LENGTH: usize = 44;
+
+    /// Reflection of `sender_pkt`, the first [`LENGTH`](Self::LENGTH) octets of which are
+    /// those of a STAMP Session-Sender packet. Its TLVs are returned as they are.
+    pub fn new(seq: u32, sender_pkt: &StampTestPacket, recv_ts: TimeStamp) -> Self {
+        StampTestPacketReflected {
+            sequence_number: seq,
+            timestamp: TimeStamp::default(),
+            error_estimate: ErrorEstimate::new(true),
+            ssid: sender_pkt.ssid,
+            receive_timestamp: recv_ts,
+            sender_sequence_number: sender_pkt.sequence_number,
+            sender_timestamp: sender_pkt.timestamp,
+            error_estimate_sender: sender_pkt.error_estimate.clone(),
+            mbz_second: 0,
+            sender_ttl: 255,
+            mbz_third: [0; 3],
+            tlvs: sender_pkt.tlvs.clone(),
+        }
+    }
+
+    /// Encode followed by zero octets up to `length`, that of the test packet, so both
+    /// directions carry packets of the same size. Never shorter than [`LENGTH`](Self::LENGTH)
+    /// and the TLVs.
+    pub fn to_bytes_of_length(&self, length: usize) -> Vec<u8> {
+        let mut encoded = self.to_bytes().unwrap();
+        encoded.resize(length.max(Self::LENGTH), 0);
+        encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        twamp_test_unauth::TwampTestPacketUnauth,
+        twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
+    };
+    use deku::bitvec::BitView;
+
+    #[test]
+    fn serialize_to_rfc_lengths() {
+        let sender_pkt = StampTestPacket::new(7, true);
+        let encoded = sender_pkt.to_bytes().unwrap();
+        assert_eq!(encoded.len(), StampTestPacket::LENGTH);
+        let reflected = StampTestPacketReflected::new(0, &sender_pkt, TimeStamp::default());
+        assert_eq!(
+            reflected.to_bytes().unwrap().len(),
+            StampTestPacketReflected::LENGTH
+        );
+        assert_eq!(reflected.to_bytes_of_length(100).len(), 100);
+        assert_eq!(
+            reflected.to_bytes_of_length(0).len(),
+            StampTestPacketReflected::LENGTH
+        );
+    }
+
+    #[test]
+    fn stamp_and_twamp_read_each_others_packets() {
+        let sender_pkt = StampTestPacket::new(7, true);
+        let encoded = sender_pkt.to_bytes().unwrap();
+        let (_rest, twamp) = TwampTestPacketUnauth::from_bytes((&encoded, 0)).unwrap();
+        assert_eq!(twamp.sequence_number, 7);
+        assert_eq!(twamp.timestamp, sender_pkt.timestamp);
+
+        let reflected = StampTestPacketReflected::new(3, &sender_pkt, TimeStamp::default());
+        let encoded = reflected.to_bytes_of_length(StampTestPacket::LENGTH);
+        let (_rest, twamp) = TwampTestPacketUnauthReflected::from_bytes((&encoded, 0)).unwrap();
+        assert_eq!(twamp.sequence_number, 3);
+        assert_eq!(twamp.sender_sequence_number, 7);
+        assert_eq!(twamp.sender_timestamp, sender_pkt.timestamp);
+    }
+
+    #[test]
+    fn non_zero_mbz_is_only_read_leniently() {
+        let mut encoded = StampTestPacket::new(7, true).to_bytes().unwrap();
+        encoded[StampTestPacket::LENGTH - 1] = 1;
+        assert!(StampTestPacket::from_bytes((&encoded, 0)).is_err());
+        assert!(StampTestPacket::read(encoded.view_bits(), true).is_ok());
+    }
+
+    #[test]
+    fn tlvs_follow_the_packets() {
+        let tlvs = vec![Tlv::ExtraPadding(vec![0; 20])];
+        let sender_pkt = StampTestPacket::new(7, true)
+            .with_ssid(0xbeef)
+            .with_tlvs(tlvs.clone());
+        let encoded = sender_pkt.to_bytes().unwrap();
+        assert_eq!(encoded.len(), StampTestPacket::LENGTH + 24);
+        let (_rest, decoded) = StampTestPacket::from_bytes((&encoded, 0)).unwrap();
+        assert_eq!(decoded, sender_pkt);
+
+        let reflected = StampTestPacketReflected::new(0, &decoded, TimeStamp::default());
+        let encoded = reflected.to_bytes_of_length(encoded.len());
+        assert_eq!(encoded.len(), StampTestPacketReflected::LENGTH + 24);
+        let (_rest, decoded) = StampTestPacketReflected::from_bytes((&encoded, 0)).unwrap();
+        assert_eq!(decoded.ssid, 0xbeef);
+        assert_eq!(decoded.tlvs, tlvs);
+
+        // Padding of RFC 8762 Session-Senders is no TLV.
+        let mut padded = StampTestPacket::new(7, true).to_bytes().unwrap();
+        padded.resize(100, 0);
+        let (_rest, decoded) = StampTestPacket::from_bytes((&padded, 0)).unwrap();
+        assert!(decoded.tlvs.is_empty());
+    }
+}
@@ -0,0 +1,412 @@
+//! Optional TLVs of STAMP ([RFC 8972](https://datatracker.ietf.org/doc/html/rfc8972)).
+//!
+//! TLVs follow the [`StampTestPacket`](crate::stamp::StampTestPacket) and
+//! [`StampTestPacketReflected`](crate::stamp::StampTestPacketReflected) layouts, each a Flags
+//! octet, a Type octet and a 2-octet Length of the Value that comes next. The Session-Reflector
+//! returns every TLV of the test packet, the ones it recognizes populated, the others flagged.
+//!
+//! Octets left after the last TLV that are all zero are padding rather than TLVs, so packets
+//! padded the RFC 8762 way are read without any.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use deku::{
+    bitvec::{BitSlice, BitVec, Msb0},
+    prelude::*,
+};
+
+use crate::padding::read_padding;
+
+/// Length of the Flags, Type and Length fields before the Value of a TLV.
+pub const TLV_HEADER_LENGTH: usize = 4;
+
+/// Set by the Session-Reflector on TLVs of a Type it does not recognize.
+pub const FLAG_UNRECOGNIZED: u8 = 0x80;
+
+/// Set by the Session-Reflector on TLVs it cannot parse.
+pub const FLAG_MALFORMED: u8 = 0x40;
+
+/// Set by the Session-Reflector on TLVs failing an integrity check.
+pub const FLAG_INTEGRITY: u8 = 0x20;
+
+const TYPE_EXTRA_PADDING: u8 = 1;
+const TYPE_LOCATION: u8 = 2;
+const TYPE_CLASS_OF_SERVICE: u8 = 4;
+const TYPE_DIRECT_MEASUREMENT: u8 = 5;
+
+const SUB_TYPE_DESTINATION_IPV4: u8 = 3;
+const SUB_TYPE_DESTINATION_IPV6: u8 = 4;
+const SUB_TYPE_SOURCE_IPV4: u8 = 5;
+const SUB_TYPE_SOURCE_IPV6: u8 = 6;
+
+/// A TLV as it is on the wire, Value undecoded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RawTlv {
+    pub flags: u8,
+    pub tlv_type: u8,
+    pub value: Vec<u8>,
+}
+
+impl RawTlv {
+    /// Encoded length, header included.
+    pub fn encoded_len(&self) -> usize {
+        TLV_HEADER_LENGTH + self.value.len()
+    }
+
+    fn write(&self, output: &mut Vec<u8>) {
+        output.push(self.flags);
+        output.push(self.tlv_type);
+        output.extend_from_slice(&(self.value.len() as u16).to_be_bytes());
+        output.extend_from_slice(&self.value);
+    }
+
+    /// Read the TLVs filling `bytes`, up to trailing zero padding. A TLV overrunning `bytes` is
+    /// kept, flagged [malformed](FLAG_MALFORMED), with the octets there are as its Value.
+    fn read_all(mut bytes: &[u8]) -> Vec<RawTlv> {
+        let mut tlvs = Vec::new();
+        while bytes.len() >= TLV_HEADER_LENGTH && bytes.iter().any(|&b| b != 0) {
+            let length = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+            let mut tlv = RawTlv {
+                flags: bytes[0],
+                tlv_type: bytes[1],
+                value: Vec::new(),
+            };
+            let value = &bytes[TLV_HEADER_LENGTH..];
+            if length > value.len() {
+                tlv.flags |= FLAG_MALFORMED;
+                tlv.value = value.to_vec();
+                tlvs.push(tlv);
+                break;
+            }
+            tlv.value = value[..length].to_vec();
+            tlvs.push(tlv);
+            bytes = &value[length..];
+        }
+        tlvs
+    }
+}
+
+/// A STAMP TLV, decoded where its Type is one of those supported here.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tlv {
+    /// Octets to make the packet longer, returned as they are.
+    ExtraPadding(Vec<u8>),
+    /// Ports and addresses the test packet was received with.
+    Location(Location),
+    /// DSCP the reflected packet should be sent with, and that the test packet was received
+    /// with.
+    ClassOfService(ClassOfService),
+    /// Packet counters of both ends.
+    DirectMeasurement(DirectMeasurement),
+    /// Unsupported or malformed TLVs.
+    Other(RawTlv),
+}
+
+impl Tlv {
+    /// Encoded length, header included.
+    pub fn encoded_len(&self) -> usize {
+        self.to_raw().encoded_len()
+    }
+
+    /// Decode `raw`. A supported Type with a Value of the wrong length is kept as
+    /// [`Tlv::Other`], flagged [malformed](FLAG_MALFORMED).
+    pub fn from_raw(raw: RawTlv) -> Self {
+        if raw.flags & FLAG_MALFORMED != 0 {
+            return Tlv::Other(raw);
+        }
+        let decoded = match raw.tlv_type {
+            TYPE_EXTRA_PADDING => Some(Tlv::ExtraPadding(raw.value.clone())),
+            TYPE_LOCATION => Location::from_value(&raw.value).map(Tlv::Location),
+            TYPE_CLASS_OF_SERVICE => ClassOfService::from_bytes((&raw.value, 0))
+                .ok()
+                .filter(|((rest, _), _)| rest.is_empty())
+                .map(|(_, cos)| Tlv::ClassOfService(cos)),
+            TYPE_DIRECT_MEASUREMENT => DirectMeasurement::from_bytes((&raw.value, 0))
+                .ok()
+                .filter(|((rest, _), _)| rest.is_empty())
+                .map(|(_, dm)| Tlv::DirectMeasurement(dm)),
+            _ => return Tlv::Other(raw),
+        };
+        decoded.unwrap_or(Tlv::Other(RawTlv {
+            flags: raw.flags | FLAG_MALFORMED,
+            ..raw
+        }))
+    }
+
+    pub fn to_raw(&self) -> RawTlv {
+        let (tlv_type, value) = match self {
+            Tlv::ExtraPadding(padding) => (TYPE_EXTRA_PADDING, padding.clone()),
+            Tlv::Location(location) => (TYPE_LOCATION, location.to_value()),
+            Tlv::ClassOfService(cos) => (TYPE_CLASS_OF_SERVICE, cos.to_bytes().unwrap()),
+            Tlv::DirectMeasurement(dm) => (TYPE_DIRECT_MEASUREMENT, dm.to_bytes().unwrap()),
+            Tlv::Other(raw) => return raw.clone(),
+        };
+        RawTlv {
+            flags: 0,
+            tlv_type,
+            value,
+        }
+    }
+
+    /// Decode the TLVs filling `bytes`.
+    pub fn read_all(bytes: &[u8]) -> Vec<Tlv> {
+        RawTlv::read_all(bytes)
+            .into_iter()
+            .map(Tlv::from_raw)
+            .collect()
+    }
+
+    /// Encode `tlvs` one after the other.
+    pub fn write_all(tlvs: &[Tlv]) -> Vec<u8> {
+        let mut output = Vec::new();
+        for tlv in tlvs {
+            tlv.to_raw().write(&mut output);
+        }
+        output
+    }
+}
+
+/// Read the rest of a packet as TLVs, for `#[deku(reader)]`.
+pub(crate) fn read_tlvs(
+    rest: &BitSlice<u8, Msb0>,
+) -> Result<(&BitSlice<u8, Msb0>, Vec<Tlv>), DekuError> {
+    let (rest, bytes) = read_padding(rest)?;
+    Ok((rest, Tlv::read_all(&bytes)))
+}
+
+/// Write TLVs after a packet, for `#[deku(writer)]`.
+pub(crate) fn write_tlvs(output: &mut BitVec<u8, Msb0>, tlvs: &[Tlv]) -> Result<(), DekuError> {
+    for byte in Tlv::write_all(tlvs) {
+        byte.write(output, ())?;
+    }
+    Ok(())
+}
+
+/// Value of the Location TLV. The Session-Sender includes the address Sub-TLVs it wants
+/// populated, zeroed, and the Session-Reflector fills them and the ports in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    pub destination_port: u16,
+    pub source_port: u16,
+    pub sub_tlvs: Vec<LocationSubTlv>,
+}
+
+impl Location {
+    /// A Location TLV asking for the destination and source addresses of the family of `ip`.
+    pub fn request(ip: IpAddr) -> Self {
+        let sub_tlvs = match ip {
+            IpAddr::V4(_) => vec![
+                LocationSubTlv::DestinationIpv4(Ipv4Addr::UNSPECIFIED),
+                LocationSubTlv::SourceIpv4(Ipv4Addr::UNSPECIFIED),
+            ],
+            IpAddr::V6(_) => vec![
+                LocationSubTlv::DestinationIpv6(Ipv6Addr::UNSPECIFIED),
+                LocationSubTlv::SourceIpv6(Ipv6Addr::UNSPECIFIED),
+            ],
+        };
+        Location {
+            destination_port: 0,
+            source_port: 0,
+            sub_tlvs,
+        }
+    }
+
+    fn from_value(value: &[u8]) -> Option<Self> {
+        if value.len() < 4 {
+            return None;
+        }
+        let sub_tlvs = RawTlv::read_all(&value[4..])
+            .into_iter()
+            .map(LocationSubTlv::from_raw)
+            .collect::<Option<_>>()?;
+        Some(Location {
+            destination_port: u16::from_be_bytes([value[0], value[1]]),
+            source_port: u16::from_be_bytes([value[2], value[3]]),
+            sub_tlvs,
+        })
+    }
+
+    fn to_value(&self) -> Vec<u8> {
+        let mut value = Vec::new();
+        value.extend_from_slice(&self.destination_port.to_be_bytes());
+        value.extend_from_slice(&self.source_port.to_be_bytes());
+        for sub_tlv in &self.sub_tlvs {
+            sub_tlv.to_raw().write(&mut value);
+        }
+        value
+    }
+}
+
+/// Sub-TLVs of the [`Location`] TLV.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LocationSubTlv {
+    DestinationIpv4(Ipv4Addr),
+    DestinationIpv6(Ipv6Addr),
+    SourceIpv4(Ipv4Addr),
+    SourceIpv6(Ipv6Addr),
+    /// Unsupported Sub-TLVs, such as those of MAC addresses.
+    Other(RawTlv),
+}
+
+impl LocationSubTlv {
+    /// `None` when a supported Sub-TLV has a Value of the wrong length, or overruns the TLV.
+    fn from_raw(raw: RawTlv) -> Option<Self> {
+        if raw.flags & FLAG_MALFORMED != 0 {
+            return None;
+        }
+        let ipv4 = <[u8; 4]>::try_from(raw.value.as_slice()).map(Ipv4Addr::from);
+        let ipv6 = <[u8; 16]>::try_from(raw.value.as_slice()).map(Ipv6Addr::from);
+        match raw.tlv_type {
+            SUB_TYPE_DESTINATION_IPV4 => ipv4.ok().map(LocationSubTlv::DestinationIpv4),
+            SUB_TYPE_DESTINATION_IPV6 => ipv6.ok().map(LocationSubTlv::DestinationIpv6),
+            SUB_TYPE_SOURCE_IPV4 => ipv4.ok().map(LocationSubTlv::SourceIpv4),
+            SUB_TYPE_SOURCE_IPV6 => ipv6.ok().map(LocationSubTlv::SourceIpv6),
+            _ => Some(LocationSubTlv::Other(raw)),
+        }
+    }
+
+    fn to_raw(&self) -> RawTlv {
+        let (tlv_type, value) = match self {
+            LocationSubTlv::DestinationIpv4(ip) => (SUB_TYPE_DESTINATION_IPV4, ip.octets().into()),
+            LocationSubTlv::DestinationIpv6(ip) => (SUB_TYPE_DESTINATION_IPV6, ip.octets().into()),
+            LocationSubTlv::SourceIpv4(ip) => (SUB_TYPE_SOURCE_IPV4, ip.octets().into()),
+            LocationSubTlv::SourceIpv6(ip) => (SUB_TYPE_SOURCE_IPV6, ip.octets().into()),
+            LocationSubTlv::Other(raw) => return raw.clone(),
+        };
+        RawTlv {
+            flags: 0,
+            tlv_type,
+            value,
+        }
+    }
+}
+
+/// Value of the Class of Service TLV.
+#[derive(Clone, Debug, Default, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct ClassOfService {
+    /// DSCP the Session-Sender asks the reflected packet to be sent with.
+    #[deku(bits = "6")]
+    pub dscp1: u8,
+    /// DSCP the test packet was received with.
+    #[deku(bits = "6")]
+    pub dscp2: u8,
+    /// ECN codepoint the test packet was received with.
+    #[deku(bits = "2")]
+    pub ecn: u8,
+    /// Reverse Path, set to 1 by a Session-Reflector that could not send with `dscp1`.
+    #[deku(bits = "2")]
+    pub rp: u8,
+    pub reserved: u16,
+}
+
+impl ClassOfService {
+    /// A Class of Service TLV asking for reflected packets marked `dscp`.
+    pub fn request(dscp: u8) -> Self {
+        ClassOfService {
+            dscp1: dscp & 0x3f,
+            ..Default::default()
+        }
+    }
+}
+
+/// Value of the Direct Measurement TLV.
+#[derive(Clone, Debug, Default, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct DirectMeasurement {
+    /// Test packets sent by the Session-Sender, this one included.
+    pub sender_tx: u32,
+    /// Test packets of the session received by the Session-Reflector, this one included.
+    pub reflector_rx: u32,
+    /// Packets of the session sent by the Session-Reflector, this reflection included.
+    pub reflector_tx: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tlvs_round_trip() {
+        let tlvs = vec![
+            Tlv::ExtraPadding(vec![0; 10]),
+            Tlv::Location(Location::request(Ipv6Addr::LOCALHOST.into())),
+            Tlv::ClassOfService(ClassOfService {
+                dscp1: 46,
+                dscp2: 10,
+                ecn: 2,
+                rp: 1,
+                reserved: 0,
+            }),
+            Tlv::DirectMeasurement(DirectMeasurement {
+                sender_tx: 1,
+                reflector_rx: 2,
+                reflector_tx: 3,
+            }),
+            Tlv::Other(RawTlv {
+                flags: FLAG_UNRECOGNIZED,
+                tlv_type: 200,
+                value: vec![1, 2, 3],
+            }),
+        ];
+        let encoded = Tlv::write_all(&tlvs);
+        assert_eq!(
+            encoded.len(),
+            tlvs.iter().map(Tlv::encoded_len).sum::<usize>()
+        );
+        // Location of IPv6 addresses: ports, then two Sub-TLVs of 16 octets.
+        assert_eq!(
+            tlvs[1].encoded_len(),
+            TLV_HEADER_LENGTH + 4 + 2 * (TLV_HEADER_LENGTH + 16)
+        );
+        assert_eq!(Tlv::read_all(&encoded), tlvs);
+    }
+
+    #[test]
+    fn class_of_service_bit_layout() {
+        let cos = ClassOfService {
+            dscp1: 0b101110,
+            dscp2: 0b001010,
+            ecn: 0b10,
+            rp: 0b01,
+            reserved: 0,
+        };
+        assert_eq!(cos.to_bytes().unwrap(), [0b1011_1000, 0b1010_1001, 0, 0]);
+    }
+
+    #[test]
+    fn trailing_zeros_are_padding() {
+        let mut encoded = Tlv::write_all(&[Tlv::ExtraPadding(vec![1])]);
+        encoded.resize(40, 0);
+        assert_eq!(Tlv::read_all(&encoded), [Tlv::ExtraPadding(vec![1])]);
+        assert!(Tlv::read_all(&[0; 27]).is_empty());
+    }
+
+    #[test]
+    fn malformed_tlvs_are_flagged() {
+        // Direct Measurement with a Value of 4 octets rather than 12.
+        let encoded = [0, TYPE_DIRECT_MEASUREMENT, 0, 4, 0, 0, 0, 1];
+        let Tlv::Other(raw) = &Tlv::read_all(&encoded)[0] else {
+            panic!("read as supported");
+        };
+        assert_eq!(raw.flags, FLAG_MALFORMED);
+        assert_eq!(raw.value, [0, 0, 0, 1]);
+
+        // Length beyond the packet: the Value is what there is, so the length stays the same.
+        let encoded = [0, TYPE_EXTRA_PADDING, 0, 100, 1, 2];
+        let tlvs = Tlv::read_all(&encoded);
+        assert!(matches!(&tlvs[..], [Tlv::Other(raw)] if raw.flags == FLAG_MALFORMED));
+        assert_eq!(Tlv::write_all(&tlvs).len(), encoded.len());
+
+        // Unknown Types are kept as they are, for the Session-Reflector to flag.
+        let encoded = [0, 100, 0, 1, 7];
+        assert_eq!(
+            Tlv::read_all(&encoded),
+            [Tlv::Other(RawTlv {
+                flags: 0,
+                tlv_type: 100,
+                value: vec![7],
+            })]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt::Display;
+
+use crate::{
+    error_estimate::ErrorEstimate, keys::TestKeys, twamp_test_unauth::TwampTestPacketUnauth,
+};
+use deku::prelude::*;
+use timestamp::timestamp::TimeStamp;
+
+/// The packet sent by Session-Sender to Session-Reflector in authenticated mode.
+///
+/// See details in [RFC 5357](https://datatracker.ietf.org/doc/html/rfc5357#section-4.1.2).
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
+pub struct TwampTestPacketAuth {
+    pub sequence_number: u32,
+    #[deku(assert = "lenient || *mbz_first == [0u8; 12]")]
+    mbz_first: [u8; 12],
+    pub timestamp: TimeStamp,
+    #[deku(ctx = "lenient")]
+    pub error_estimate: ErrorEstimate,
+    #[deku(assert = "lenient || *mbz_second == [0u8; 6]")]
+    mbz_second: [u8; 6],
+    hmac: [u8; 16],
+}
+
+impl Display for TwampTestPacketAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Twamp-Test authenticated sender packet with sequence: {}",
+            self.sequence_number
+        )
+    }
+}
+
+impl TwampTestPacketAuth {
+    /// Length in bytes of the packet without any padding.
+    pub const MIN_LENGTH: usize = 48;
+
+    /// Number of leading bytes covered by the HMAC.
+    const HMAC_COVERS: usize = 32;
+
+    /// Creates a new authenticated Twamp-Test packet to be sent by Session-Sender.
+    pub fn new(sequence_number: u32, is_ntp_synchronized: bool) -> Self {
+        TwampTestPacketAuth {
+            sequence_number,
+            mbz_first: [0; 12],
+            timestamp: TimeStamp::default(),
+            error_estimate: ErrorEstimate::new(is_ntp_synchronized),
+            mbz_second: [0; 6],
+            hmac: [0; 16],
+        }
+    }
+
+    /// Encode followed by `padding_length` zero octets, with HMAC and encryption applied.
+    pub fn seal(&self, keys: &TestKeys, padding_length: usize) -> Vec<u8> {
+        let mut encoded = self.to_bytes().unwrap();
+        keys.seal(&mut encoded, Self::HMAC_COVERS);
+        encoded.resize(Self::MIN_LENGTH + padding_length, 0);
+        encoded
+    }
+
+    /// Decode a packet made by [`seal`](Self::seal), `None` if it is too short or its HMAC is
+    /// wrong.
+    pub fn open(packet: &[u8], keys: &TestKeys) -> Option<Self> {
+        let packet = Self::unseal(packet, keys)?;
+        Self::from_bytes((&packet, 0)).ok().map(|(_rest, pkt)| pkt)
+    }
+
+    /// Decrypt a packet made by [`seal`](Self::seal) for decoding, `None` if it is too short or
+    /// its HMAC is wrong.
+    pub fn unseal(packet: &[u8], keys: &TestKeys) -> Option<Vec<u8>> {
+        let mut packet = packet.get(..Self::MIN_LENGTH)?.to_vec();
+        keys.open(&mut packet, Self::HMAC_COVERS).then_some(packet)
+    }
+}
+
+impl From<TwampTestPacketUnauth> for TwampTestPacketAuth {
+    fn from(packet: TwampTestPacketUnauth) -> Self {
+        TwampTestPacketAuth {
+            sequence_number: packet.sequence_number,
+            mbz_first: [0; 12],
+            timestamp: packet.timestamp,
+            error_estimate: packet.error_estimate,
+            mbz_second: [0; 6],
+            hmac: [0; 16],
+        }
+    }
+}
+
+impl From<TwampTestPacketAuth> for TwampTestPacketUnauth {
+    fn from(packet: TwampTestPacketAuth) -> Self {
+        TwampTestPacketUnauth {
+            sequence_number: packet.sequence_number,
+            timestamp: packet.timestamp,
+            error_estimate: packet.error_estimate,
+            packet_padding: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_without_padding_to_min_length() {
+        let encoded = TwampTestPacketAuth::new(1, true).to_bytes().unwrap();
+        assert_eq!(encoded.len(), TwampTestPacketAuth::MIN_LENGTH);
+    }
+
+    #[test]
+    fn sealed_packet_opens_with_same_keys_only() {
+        let keys = TestKeys::derive(&[1; 16], &[2; 32], &[3; 16]);
+        let packet = TwampTestPacketAuth::new(7, true);
+        let sealed = packet.seal(&keys, 64);
+        assert_eq!(sealed.len(), TwampTestPacketAuth::MIN_LENGTH + 64);
+        assert_ne!(sealed[..4], 7u32.to_be_bytes());
+        let opened = TwampTestPacketAuth::open(&sealed, &keys).unwrap();
+        assert_eq!(opened.sequence_number, 7);
+        assert_eq!(opened.timestamp, packet.timestamp);
+        let other_keys = TestKeys::derive(&[1; 16], &[2; 32], &[4; 16]);
+        assert_eq!(TwampTestPacketAuth::open(&sealed, &other_keys), None);
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt::Display;
+
+use crate::{
+    error_estimate::ErrorEstimate, keys::TestKeys,
+    twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
+};
+use deku::prelude::*;
+use timestamp::timestamp::TimeStamp;
+
+/// The packet sent by Session-Reflector to Session-Sender in authenticated mode. Carries the
+/// same fields as [`TwampTestPacketUnauthReflected`], each padded out to 16 byte blocks.
+///
+/// See details in [RFC 5357](https://datatracker.ietf.org/doc/html/rfc5357#section-4.2.1).
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
+pub struct TwampTestPacketAuthReflected {
+    pub sequence_number: u32,
+    #[deku(assert = "lenient || *mbz_first == [0u8; 12]")]
+    mbz_first: [u8; 12],
+    pub timestamp: TimeStamp,
+    #[deku(ctx = "lenient")]
+    pub error_estimate: ErrorEstimate,
+    #[deku(assert = "lenient || *mbz_second == [0u8; 6]")]
+    mbz_second: [u8; 6],
+    pub receive_timestamp: TimeStamp,
+    #[deku(assert = "lenient || *mbz_third == [0u8; 8]")]
+    mbz_third: [u8; 8],
+    pub sender_sequence_number: u32,
+    #[deku(assert = "lenient || *mbz_fourth == [0u8; 12]")]
+    mbz_fourth: [u8; 12],
+    pub sender_timestamp: TimeStamp,
+    #[deku(ctx = "lenient")]
+    pub error_estimate_sender: ErrorEstimate,
+    #[deku(assert = "lenient || *mbz_fifth == [0u8; 6]")]
+    mbz_fifth: [u8; 6],
+    pub sender_ttl: u8,
+    #[deku(assert = "lenient || *mbz_sixth == [0u8; 15]")]
+    mbz_sixth: [u8; 15],
+    hmac: [u8; 16],
+}
+
+impl Display for TwampTestPacketAuthReflected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Twamp-Test authenticated reflected packet with sequence: {}",
+            self.sequence_number
+        )
+    }
+}
+
+impl TwampTestPacketAuthReflected {
+    /// Length in bytes of the packet without any padding.
+    pub const MIN_LENGTH: usize = 112;
+
+    /// Number of leading bytes covered by the HMAC.
+    const HMAC_COVERS: usize = 96;
+
+    /// Encode with HMAC and encryption applied. Padding is not reflected.
+    pub fn seal(&self, keys: &TestKeys) -> Vec<u8> {
+        let mut encoded = self.to_bytes().unwrap();
+        keys.seal(&mut encoded, Self::HMAC_COVERS);
+        encoded
+    }
+
+    /// Decode a packet made by [`seal`](Self::seal), `None` if it is too short or its HMAC is
+    /// wrong.
+    pub fn open(packet: &[u8], keys: &TestKeys) -> Option<Self> {
+        let packet = Self::unseal(packet, keys)?;
+        Self::from_bytes((&packet, 0)).ok().map(|(_rest, pkt)| pkt)
+    }
+
+    /// Decrypt a packet made by [`seal`](Self::seal) for decoding, `None` if it is too short or
+    /// its HMAC is wrong.
+    pub fn unseal(packet: &[u8], keys: &TestKeys) -> Option<Vec<u8>> {
+        let mut packet = packet.get(..Self::MIN_LENGTH)?.to_vec();
+        keys.open(&mut packet, Self::HMAC_COVERS).then_some(packet)
+    }
+}
+
+impl From<TwampTestPacketUnauthReflected> for TwampTestPacketAuthReflected {
+    fn from(packet: TwampTestPacketUnauthReflected) -> Self {
+        TwampTestPacketAuthReflected {
+            sequence_number: packet.sequence_number,
+            mbz_first: [0; 12],
+            timestamp: packet.timestamp,
+            error_estimate: packet.error_estimate,
+            mbz_second: [0; 6],
+            receive_timestamp: packet.receive_timestamp,
+            mbz_third: [0; 8],
+            sender_sequence_number: packet.sender_sequence_number,
+            mbz_fourth: [0; 12],
+            sender_timestamp: packet.sender_timestamp,
+            error_estimate_sender: packet.error_estimate_sender,
+            mbz_fifth: [0; 6],
+            sender_ttl: packet.sender_ttl,
+            mbz_sixth: [0; 15],
+            hmac: [0; 16],
+        }
+    }
+}
+
+impl From<TwampTestPacketAuthReflected> for TwampTestPacketUnauthReflected {
+    fn from(packet: TwampTestPacketAuthReflected) -> Self {
+        TwampTestPacketUnauthReflected {
+            sequence_number: packet.sequence_number,
+            timestamp: packet.timestamp,
+            error_estimate: packet.error_estimate,
+            mbz_first: 0,
+            receive_timestamp: packet.receive_timestamp,
+            sender_sequence_number: packet.sender_sequence_number,
+            sender_timestamp: packet.sender_timestamp,
+            error_estimate_sender: packet.error_estimate_sender,
+            mbz_second: 0,
+            sender_ttl: packet.sender_ttl,
+            packet_padding: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twamp_test_unauth::TwampTestPacketUnauth;
+
+    #[test]
+    fn round_trips_through_seal_and_open() {
+        let keys = TestKeys::derive(&[1; 16], &[2; 32], &[3; 16]);
+        let sender_pkt = TwampTestPacketUnauth::new(5, 0, true);
+        let reflected = TwampTestPacketUnauthReflected::new(9, sender_pkt, TimeStamp::default());
+        let sealed = TwampTestPacketAuthReflected::from(reflected.clone()).seal(&keys);
+        assert_eq!(sealed.len(), TwampTestPacketAuthReflected::MIN_LENGTH);
+        let opened = TwampTestPacketAuthReflected::open(&sealed, &keys).unwrap();
+        assert_eq!(TwampTestPacketUnauthReflected::from(opened), reflected);
+        let mut tampered = sealed;
+        tampered[40] ^= 1;
+        assert_eq!(TwampTestPacketAuthReflected::open(&tampered, &keys), None);
+    }
+}
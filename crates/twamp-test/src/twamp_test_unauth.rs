@@ -1,17 +1,23 @@
-use std::fmt::Display;
+use std::{fmt::Display, net::IpAddr};
 
-use crate::error_estimate::ErrorEstimate;
+use crate::{
+    constants::{ip_udp_header_length, MAX_UDP_PAYLOAD},
+    error_estimate::ErrorEstimate,
+    padding::{read_padding, PaddingPattern},
+};
 use deku::prelude::*;
-use timestamp::timestamp::TimeStamp;
+use timestamp::timestamp::{TimeStamp, TimestampFormat};
 
 /// The packet sent by Session-Sender to Session-Reflector.
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
 pub struct TwampTestPacketUnauth {
     pub sequence_number: u32,
     pub timestamp: TimeStamp,
+    #[deku(ctx = "lenient")]
     pub error_estimate: ErrorEstimate,
-    #[deku(count = "27", assert = "packet_padding.len() <= 27")]
+    /// Padding filling the rest of the datagram.
+    #[deku(reader = "read_padding(deku::rest)")]
     pub packet_padding: Vec<u8>,
 }
 
@@ -26,34 +32,65 @@ impl Display for TwampTestPacketUnauth {
 }
 
 impl TwampTestPacketUnauth {
-    const MAX_PADDING_LENGTH: u8 = 27;
-
-    /// Creates a new Twamp-Test packet to be sent by Session-Sender.
-    ///
-    /// Note that the padding length is from `0-27`.
-    /// It will resort to `27` even if a value greater
-    /// than `27` is passed.
-    pub fn new(sequence_number: u32, padding_length: u8, is_ntp_synchronized: bool) -> Self {
+    /// Length in bytes of the packet without any padding.
+    pub const MIN_LENGTH: usize = 14;
+
+    /// Largest padding length the packet carries, filling the largest UDP payload. Longer
+    /// padding is truncated.
+    pub const MAX_PADDING_LENGTH: usize = MAX_UDP_PAYLOAD - Self::MIN_LENGTH;
+
+    /// Largest padding length of a packet exchanged with `peer` without fragmentation on a
+    /// link of `mtu` bytes, leaving room for the IP and UDP headers of its address family.
+    pub fn max_padding_length(mtu: usize, peer: IpAddr) -> usize {
+        mtu.saturating_sub(ip_udp_header_length(peer) + Self::MIN_LENGTH)
+            .min(Self::MAX_PADDING_LENGTH)
+    }
+
+    /// Creates a new Twamp-Test packet to be sent by Session-Sender, padded with
+    /// `padding_length` zero octets up to [`Self::MAX_PADDING_LENGTH`].
+    pub fn new(sequence_number: u32, padding_length: usize, is_ntp_synchronized: bool) -> Self {
         TwampTestPacketUnauth {
             sequence_number,
             timestamp: TimeStamp::default(),
             error_estimate: ErrorEstimate::new(is_ntp_synchronized),
-            // NOTE: Using 27 as the max value even if > 27 was passed in padding.
-            packet_padding: vec![
-                0;
-                if padding_length > 27 {
-                    Self::MAX_PADDING_LENGTH.into()
-                } else {
-                    padding_length.into()
-                }
-            ],
+            packet_padding: vec![0; padding_length.min(Self::MAX_PADDING_LENGTH)],
+        }
+    }
+
+    /// Carry the timestamp in `format` rather than NTP, see
+    /// [RFC 8186](https://datatracker.ietf.org/doc/html/rfc8186).
+    pub fn with_timestamp_format(mut self, format: TimestampFormat) -> Self {
+        let ntp = self
+            .error_estimate
+            .timestamp_format()
+            .to_ntp(self.timestamp);
+        self.timestamp = format.encode(ntp);
+        self.error_estimate = self.error_estimate.with_timestamp_format(format);
+        self
+    }
+
+    /// Fill the padding with `pattern` rather than zeros.
+    pub fn with_padding_pattern(mut self, pattern: PaddingPattern) -> Self {
+        pattern.fill(&mut self.packet_padding);
+        self
+    }
+
+    /// Start the padding with `octets`, lengthening it if needed up to
+    /// [`Self::MAX_PADDING_LENGTH`].
+    pub fn with_padding_octets(mut self, octets: &[u8]) -> Self {
+        let octets = &octets[..octets.len().min(Self::MAX_PADDING_LENGTH)];
+        if self.packet_padding.len() < octets.len() {
+            self.packet_padding.resize(octets.len(), 0);
         }
+        self.packet_padding[..octets.len()].copy_from_slice(octets);
+        self
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
 
     #[test]
     fn create_twamp_test_packet_with_sequence_number() {
@@ -61,33 +98,95 @@ mod tests {
         assert_eq!(test_packet_sender.sequence_number, 1);
     }
 
+    #[test]
+    fn padding_octets_lengthen_short_padding() {
+        let pkt = TwampTestPacketUnauth::new(1, 1, true).with_padding_octets(&[1, 2, 3]);
+        assert_eq!(pkt.packet_padding, [1, 2, 3]);
+        let pkt = TwampTestPacketUnauth::new(1, 5, true).with_padding_octets(&[1, 2, 3]);
+        assert_eq!(pkt.packet_padding, [1, 2, 3, 0, 0]);
+    }
+
+    #[test]
+    fn padding_pattern_keeps_padding_length() {
+        let pkt = TwampTestPacketUnauth::new(1, 5, true)
+            .with_padding_pattern(PaddingPattern::Fixed(0xff))
+            .with_padding_octets(&[1, 2]);
+        assert_eq!(pkt.packet_padding, [1, 2, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn ptp_timestamp_sets_z_bit() {
+        let pkt = TwampTestPacketUnauth::new(1, 0, true);
+        let ntp = pkt.timestamp;
+        let pkt = pkt.with_timestamp_format(TimestampFormat::Ptp);
+        assert_eq!(TimestampFormat::Ptp.to_ntp(pkt.timestamp), ntp);
+        let encoded = pkt.to_bytes().unwrap();
+        // Z bit of the Error Estimate.
+        assert_eq!(encoded[12] & 0x40, 0x40);
+    }
+
     #[test]
     fn create_twamp_test_packet_with_min_padding() {
         let padding_length = 0;
         let test_packet_sender = TwampTestPacketUnauth::new(1, padding_length, true);
-        assert_eq!(
-            test_packet_sender.packet_padding.len(),
-            padding_length.into()
-        );
+        assert_eq!(test_packet_sender.packet_padding.len(), padding_length);
     }
 
     #[test]
     fn create_twamp_test_packet_with_max_padding() {
-        let padding_length = 27;
+        let padding_length = TwampTestPacketUnauth::MAX_PADDING_LENGTH;
         let test_packet_sender = TwampTestPacketUnauth::new(1, padding_length, true);
+        assert_eq!(test_packet_sender.packet_padding.len(), padding_length);
+    }
+
+    #[test]
+    fn padding_is_read_to_the_end_of_the_datagram() {
+        for padding_length in [0, 27, 1400] {
+            let pkt = TwampTestPacketUnauth::new(1, padding_length, true);
+            let encoded = pkt.to_bytes().unwrap();
+            assert_eq!(
+                encoded.len(),
+                TwampTestPacketUnauth::MIN_LENGTH + padding_length
+            );
+            let (_rest, decoded) = TwampTestPacketUnauth::from_bytes((&encoded, 0)).unwrap();
+            assert_eq!(decoded, pkt);
+        }
+    }
+
+    #[test]
+    fn max_padding_fills_the_mtu() {
+        let ipv4 = Ipv4Addr::LOCALHOST.into();
+        let ipv6 = Ipv6Addr::LOCALHOST.into();
+        assert_eq!(TwampTestPacketUnauth::max_padding_length(1500, ipv4), 1458);
+        assert_eq!(TwampTestPacketUnauth::max_padding_length(1500, ipv6), 1438);
         assert_eq!(
-            test_packet_sender.packet_padding.len(),
-            padding_length.into()
+            TwampTestPacketUnauth::max_padding_length(
+                1500,
+                Ipv4Addr::LOCALHOST.to_ipv6_mapped().into()
+            ),
+            1458
         );
+        assert_eq!(TwampTestPacketUnauth::max_padding_length(9000, ipv4), 8958);
+        assert_eq!(TwampTestPacketUnauth::max_padding_length(20, ipv4), 0);
+        assert_eq!(
+            TwampTestPacketUnauth::max_padding_length(usize::MAX, ipv4),
+            TwampTestPacketUnauth::MAX_PADDING_LENGTH
+        );
+    }
+
+    #[test]
+    fn serialize_without_padding_to_min_length() {
+        let encoded = TwampTestPacketUnauth::new(1, 0, true).to_bytes().unwrap();
+        assert_eq!(encoded.len(), TwampTestPacketUnauth::MIN_LENGTH);
     }
 
     #[test]
     fn create_twamp_test_packet_with_overflow_padding() {
-        let padding_length = 255;
+        let padding_length = u16::MAX.into();
         let test_packet_sender = TwampTestPacketUnauth::new(1, padding_length, true);
         assert_eq!(
             test_packet_sender.packet_padding.len(),
-            TwampTestPacketUnauth::MAX_PADDING_LENGTH.into()
+            TwampTestPacketUnauth::MAX_PADDING_LENGTH
         );
     }
 }
@@ -1,12 +1,14 @@
 use std::fmt::Display;
 
-use crate::{error_estimate::ErrorEstimate, twamp_test_unauth::TwampTestPacketUnauth};
+use crate::{
+    error_estimate::ErrorEstimate, padding::read_padding, twamp_test_unauth::TwampTestPacketUnauth,
+};
 use deku::prelude::*;
-use timestamp::timestamp::TimeStamp;
+use timestamp::timestamp::{TimeStamp, TimestampFormat};
 
 /// The packet sent by Session-Reflector to Session-Sender.
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
+#[deku(endian = "big", ctx = "lenient: bool", ctx_default = "false")]
 pub struct TwampTestPacketUnauthReflected {
     ///  The sequence number of the test packet according to its transmit order. It starts with
     ///  zero and is incremented by one for each subsequent packet.  The Sequence Number generated
@@ -14,8 +16,9 @@ pub struct TwampTestPacketUnauthReflected {
     pub sequence_number: u32,
     /// Timestamp when the reflected packet was sent from Session-Reflector.
     pub timestamp: TimeStamp,
+    #[deku(ctx = "lenient")]
     pub error_estimate: ErrorEstimate,
-    #[deku(assert_eq = "0u16")]
+    #[deku(assert = "lenient || *mbz_first == 0u16")]
     pub mbz_first: u16,
     /// Receive Timestamp is the time the test packet was received by the reflector. The difference
     /// between Timestamp and Receive Timestamp is the amount of time the packet was in transition
@@ -28,11 +31,13 @@ pub struct TwampTestPacketUnauthReflected {
     /// Exact copy of `timestamp` from Session-Sender.
     pub sender_timestamp: TimeStamp,
     /// Exact copy of `ErrorEstimate` from Session-Sender.
+    #[deku(ctx = "lenient")]
     pub error_estimate_sender: ErrorEstimate,
-    #[deku(assert_eq = "0u16")]
+    #[deku(assert = "lenient || *mbz_second == 0u16")]
     pub mbz_second: u16,
     pub sender_ttl: u8,
-    #[deku(count = "27")]
+    /// Padding filling the rest of the datagram.
+    #[deku(reader = "read_padding(deku::rest)")]
     pub packet_padding: Vec<u8>,
 }
 
@@ -47,7 +52,26 @@ impl Display for TwampTestPacketUnauthReflected {
 }
 
 impl TwampTestPacketUnauthReflected {
-    pub fn new(seq: u32, twamp_test_pkt: TwampTestPacketUnauth, recv_ts: TimeStamp) -> Self {
+    /// Length in bytes of the packet without any padding.
+    pub const MIN_LENGTH: usize = 41;
+
+    /// Length in bytes of the [reflector counter](Self::with_reflector_counter) extension.
+    pub const REFLECTOR_COUNTER_LENGTH: usize = 4;
+
+    /// Octets of padding the reflected packet carries fewer than the test packet, making up for
+    /// its longer fields so both are the same size.
+    pub const PADDING_TRUNCATION: usize = Self::MIN_LENGTH - TwampTestPacketUnauth::MIN_LENGTH;
+
+    /// Reflection of `twamp_test_pkt`, numbered `seq`. Its padding is copied without the last
+    /// [`Self::PADDING_TRUNCATION`] octets, so the reflected packet is the size of the test
+    /// packet when that leaves room for its fields, as
+    /// [RFC 5357](https://datatracker.ietf.org/doc/html/rfc5357#section-4.2.1) has it.
+    pub fn new(seq: u32, mut twamp_test_pkt: TwampTestPacketUnauth, recv_ts: TimeStamp) -> Self {
+        let padding_length = twamp_test_pkt
+            .packet_padding
+            .len()
+            .saturating_sub(Self::PADDING_TRUNCATION);
+        twamp_test_pkt.packet_padding.truncate(padding_length);
         TwampTestPacketUnauthReflected {
             sequence_number: seq,
             timestamp: TimeStamp::default(),
@@ -59,7 +83,147 @@ impl TwampTestPacketUnauthReflected {
             error_estimate_sender: twamp_test_pkt.error_estimate,
             mbz_second: 0,
             sender_ttl: 255, // TODO: hard-coded
-            packet_padding: vec![0; 0],
+            packet_padding: twamp_test_pkt.packet_padding,
+        }
+    }
+
+    /// Carry Timestamp and Receive Timestamp in `format` rather than NTP, see
+    /// [RFC 8186](https://datatracker.ietf.org/doc/html/rfc8186). The Session-Sender's timestamp
+    /// stays in the format it was sent in.
+    pub fn with_timestamp_format(mut self, format: TimestampFormat) -> Self {
+        let current = self.error_estimate.timestamp_format();
+        self.timestamp = format.encode(current.to_ntp(self.timestamp));
+        self.receive_timestamp = format.encode(current.to_ntp(self.receive_timestamp));
+        self.error_estimate = self.error_estimate.with_timestamp_format(format);
+        self
+    }
+
+    /// Sender Timestamp, Receive Timestamp and Timestamp, T1 to T3 of RFC 5357, in NTP format
+    /// whatever format each was sent in.
+    pub fn ntp_timestamps(&self) -> (TimeStamp, TimeStamp, TimeStamp) {
+        let sender_format = self.error_estimate_sender.timestamp_format();
+        let reflector_format = self.error_estimate.timestamp_format();
+        (
+            sender_format.to_ntp(self.sender_timestamp),
+            reflector_format.to_ntp(self.receive_timestamp),
+            reflector_format.to_ntp(self.timestamp),
+        )
+    }
+
+    /// Embed the Session-Reflector's count of processed test packets at the start of the padding.
+    /// Not part of RFC 5357, both ends must agree to use it.
+    pub fn with_reflector_counter(mut self, counter: u32) -> Self {
+        self.start_padding_with(&counter.to_be_bytes());
+        self
+    }
+
+    /// Start the padding with `octets` copied from the padding of the test packet, see
+    /// [`ReflectOctets`](crate::reflect_octets::ReflectOctets), even where the copied padding
+    /// is too short to hold them all. Replaces any
+    /// [reflector counter](Self::with_reflector_counter).
+    pub fn with_reflected_octets(mut self, octets: &[u8]) -> Self {
+        self.start_padding_with(octets);
+        self
+    }
+
+    /// Overwrite the start of the padding with `octets`, lengthening it if needed.
+    fn start_padding_with(&mut self, octets: &[u8]) {
+        if self.packet_padding.len() < octets.len() {
+            self.packet_padding.resize(octets.len(), 0);
+        }
+        self.packet_padding[..octets.len()].copy_from_slice(octets);
+    }
+
+    /// Counter embedded by [`Self::with_reflector_counter`]. The counter starts at one, so zero
+    /// padding reads as `None`. Other padding copied from the test packet reads as a counter
+    /// too, so only rely on it where the Session-Reflector is known to embed one.
+    pub fn reflector_counter(&self) -> Option<u32> {
+        let bytes = self.packet_padding.get(..Self::REFLECTOR_COUNTER_LENGTH)?;
+        let counter = u32::from_be_bytes(bytes.try_into().unwrap());
+        (counter != 0).then_some(counter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::padding::PaddingPattern;
+
+    #[test]
+    fn serialize_without_padding_to_min_length() {
+        let sender_pkt = TwampTestPacketUnauth::new(0, 0, true);
+        let reflected = TwampTestPacketUnauthReflected::new(0, sender_pkt, TimeStamp::default());
+        let encoded = reflected.to_bytes().unwrap();
+        assert_eq!(encoded.len(), TwampTestPacketUnauthReflected::MIN_LENGTH);
+    }
+
+    #[test]
+    fn timestamps_read_back_as_ntp_in_any_format() {
+        let sender_pkt =
+            TwampTestPacketUnauth::new(0, 0, true).with_timestamp_format(TimestampFormat::Ptp);
+        let sent = TimestampFormat::Ptp.to_ntp(sender_pkt.timestamp);
+        let received = TimeStamp::default();
+        let reflected = TwampTestPacketUnauthReflected::new(0, sender_pkt, received);
+        let ntp_timestamps = reflected.ntp_timestamps();
+        let reflected = reflected.with_timestamp_format(TimestampFormat::Ptp);
+        assert_eq!(
+            reflected.error_estimate_sender.timestamp_format(),
+            TimestampFormat::Ptp
+        );
+        assert_eq!(reflected.ntp_timestamps(), ntp_timestamps);
+        assert_eq!(reflected.ntp_timestamps().0, sent);
+        assert_eq!(reflected.ntp_timestamps().1, received);
+    }
+
+    #[test]
+    fn padding_is_copied_to_the_size_of_the_test_packet() {
+        for padding_length in [0, 27, 100, 1400] {
+            let sender_pkt = TwampTestPacketUnauth::new(0, padding_length, true)
+                .with_padding_pattern(PaddingPattern::Prbs31);
+            let sent = sender_pkt.to_bytes().unwrap();
+            let reflected =
+                TwampTestPacketUnauthReflected::new(0, sender_pkt.clone(), TimeStamp::default());
+            let encoded = reflected.to_bytes().unwrap();
+            assert_eq!(
+                encoded.len(),
+                sent.len().max(TwampTestPacketUnauthReflected::MIN_LENGTH)
+            );
+            assert!(sender_pkt
+                .packet_padding
+                .starts_with(&reflected.packet_padding));
         }
     }
+
+    #[test]
+    fn reflected_octets_lengthen_truncated_padding() {
+        let sender_pkt = TwampTestPacketUnauth::new(0, 30, true)
+            .with_padding_pattern(PaddingPattern::Fixed(9))
+            .with_padding_octets(&[1, 2]);
+        let reflected = TwampTestPacketUnauthReflected::new(0, sender_pkt, TimeStamp::default());
+        assert_eq!(reflected.packet_padding, [1, 2, 9]);
+        let reflected = reflected.with_reflected_octets(&[1, 2, 9, 9, 9]);
+        assert_eq!(reflected.packet_padding, [1, 2, 9, 9, 9]);
+    }
+
+    #[test]
+    fn no_reflector_counter_by_default() {
+        let sender_pkt = TwampTestPacketUnauth::new(0, 0, true);
+        let reflected = TwampTestPacketUnauthReflected::new(0, sender_pkt, TimeStamp::default());
+        assert_eq!(reflected.reflector_counter(), None);
+    }
+
+    #[test]
+    fn reflector_counter_is_read_back_from_padding() {
+        let sender_pkt = TwampTestPacketUnauth::new(0, 0, true);
+        let reflected = TwampTestPacketUnauthReflected::new(0, sender_pkt, TimeStamp::default())
+            .with_reflector_counter(258);
+        let encoded = reflected.to_bytes().unwrap();
+        assert_eq!(
+            encoded.len(),
+            TwampTestPacketUnauthReflected::MIN_LENGTH
+                + TwampTestPacketUnauthReflected::REFLECTOR_COUNTER_LENGTH
+        );
+        let (_rest, decoded) = TwampTestPacketUnauthReflected::from_bytes((&encoded, 0)).unwrap();
+        assert_eq!(decoded.reflector_counter(), Some(258));
+    }
 }
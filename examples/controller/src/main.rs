@@ -1,21 +1,54 @@
-pub mod controller;
-
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use std::process;
+use std::time::{Duration, SystemTime};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+use session_sender::{measurement::MeasurementCallback, PacketProfile, Train};
+use timestamp::timestamp::TimestampFormat;
 use tracing::*;
 
-use controller::Controller;
+use twamp_control::auth::SharedSecret;
+use twamp_control::compliance::Compliance;
 use twamp_control::constants::TWAMP_CONTROL_WELL_KNOWN_PORT;
-use twamp_test::constants::TWAMP_TEST_WELL_KNOWN_PORT;
+use twamp_control::security_mode::Mode;
+use twamp_rs::clock::ClockPolicy;
+use twamp_rs::controller::{
+    ConnectRetry, ControlMessage, Controller, ControllerConfig, SenderPortPolicy, SequenceStart,
+    SessionTimeout, StartRetry, StopPolicy,
+};
+use twamp_rs::dissect;
+use twamp_rs::paths::{explore_paths, PathReport};
+use twamp_rs::push::PushExporter;
+use twamp_rs::report::{ControlMetadata, TestReport};
+use twamp_rs::soak::{Soak, SoakReport};
+
+use twamp_runtime::task::JoinHandle;
+use twamp_test::constants::{ETHERNET_MTU, TWAMP_TEST_WELL_KNOWN_PORT};
+use twamp_test::ecn::Ecn;
+use twamp_test::flow_label::FlowLabel;
+use twamp_test::padding::PaddingPattern;
+use twamp_test::reflect_octets::ReflectOctets;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 struct Args {
-    #[arg(long, help = "IP address of Responder.")]
-    responder_addr: Ipv4Addr,
+    #[arg(
+        long,
+        required_unless_present = "dissect",
+        help = "IP address of Responder."
+    )]
+    responder_addr: Option<IpAddr>,
+
+    #[arg(
+        long,
+        exclusive = true,
+        value_name = "HEX",
+        help = "Print the TWAMP-Control message in HEX field by field, then exit."
+    )]
+    dissect: Option<String>,
 
     #[arg(
         long,
@@ -24,7 +57,7 @@ struct Args {
     responder_port: u16,
 
     #[arg(long, help = "IP address of Controller.", default_value = "0.0.0.0")]
-    controller_addr: Ipv4Addr,
+    controller_addr: IpAddr,
 
     #[arg(
         long,
@@ -33,6 +66,15 @@ struct Args {
     )]
     controller_test_port: u16,
 
+    #[arg(
+        long,
+        value_name = "LOW-HIGH",
+        value_parser = parse_port_range,
+        conflicts_with = "controller_test_port",
+        help = "Bind Session-Sender to a random port in this range, fresh for every run."
+    )]
+    sender_port_range: Option<RangeInclusive<u16>>,
+
     #[arg(
         long,
         default_value_t = TWAMP_TEST_WELL_KNOWN_PORT,
@@ -40,6 +82,12 @@ struct Args {
     )]
     responder_reflect_port: u16,
 
+    #[arg(
+        long,
+        help = "IP address of Session-Reflector, if not that of the Responder."
+    )]
+    reflector_addr: Option<IpAddr>,
+
     #[arg(
         long,
         default_value = "10",
@@ -50,38 +98,827 @@ struct Args {
     #[arg(
         long,
         default_value = "900",
-        help = "Timeout (seconds) used in Request-TW-Session."
+        help = "Timeout (seconds) used in Request-TW-Session, from 1 up to 3600."
     )]
-    timeout: u64,
+    timeout: SessionTimeout,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = StopPolicyArg::AfterTimeout,
+        help = "When to send Stop-Sessions after test pkts are sent."
+    )]
+    stop_policy: StopPolicyArg,
 
     #[arg(
         long,
         default_value = "5",
-        help = "Duration (seconds) to wait before sending Stop-Sessions after test pkts are sent"
+        help = "Duration (seconds) to wait for reflected pkts with --stop-policy after-timeout."
     )]
     stop_session_sleep: u64,
+
+    #[arg(
+        long,
+        help = "Send test pkts in trains of this many back-to-back pkts, reporting per train."
+    )]
+    train_size: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "100",
+        help = "Gap (milliseconds) between trains when --train-size is set."
+    )]
+    train_gap_ms: u64,
+
+    #[arg(
+        long = "profile",
+        value_parser = parse_profile,
+        help = "Send PACKETS[:PADDING[:INTERVAL_MS[:DSCP]]] test pkts, repeatable to run profiles back-to-back, reporting per profile. Overrides --number-of-test-packets."
+    )]
+    profiles: Vec<PacketProfile>,
+
+    #[arg(
+        long,
+        default_value_t = ETHERNET_MTU,
+        help = "MTU of the path in bytes, --profile padding is truncated to fit test pkts in it."
+    )]
+    mtu: usize,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ClockPolicyArg::Ignore,
+        help = "What to do if the local clock is not synchronized before the test."
+    )]
+    clock_policy: ClockPolicyArg,
+
+    #[arg(
+        long,
+        help = "Mark test pkts ECT(0) to observe ECN marking on the path."
+    )]
+    ect: bool,
+
+    #[arg(
+        long,
+        value_name = "LABEL",
+        value_parser = parse_flow_label,
+        help = "Send test pkts to an IPv6 Session-Reflector with this flow label, or \"random\" for a fresh one every session."
+    )]
+    flow_label: Option<FlowLabel>,
+
+    #[arg(long, help = "Log RTT of each reflected pkt as it is received.")]
+    log_measurements: bool,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        help = "Ask for the session to start this many milliseconds from now rather than straight away."
+    )]
+    start_delay_ms: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Log every TWAMP-Control message with its contents in the report, or with the error if negotiation fails."
+    )]
+    audit_control: bool,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "Keep reflected pkts within this much memory, reporting only aggregates past it."
+    )]
+    memory_budget_bytes: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "OCTETS",
+        value_parser = parse_reflect_octets,
+        help = "Ask the responder to reflect these two octets (e.g. 0xabcd), repeated over --reflect-octets-length octets of padding, as in RFC 6038."
+    )]
+    reflect_octets: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "LENGTH",
+        default_value_t = 2,
+        help = "Number of padding octets to reflect with --reflect-octets, up to the padding of test pkts."
+    )]
+    reflect_octets_length: u16,
+
+    #[arg(
+        long,
+        help = "Send test pkts from --responder-reflect-port, failing if the responder picks another port."
+    )]
+    symmetric_ports: bool,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Retry Start-Sessions this many times if the responder is temporarily out of resources."
+    )]
+    start_retries: u32,
+
+    #[arg(
+        long,
+        default_value = "500",
+        help = "Wait (milliseconds) before the first Start-Sessions retry, doubling after each."
+    )]
+    start_retry_backoff_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "10000",
+        help = "Longest wait (milliseconds) between Start-Sessions retries."
+    )]
+    start_retry_max_delay_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "1",
+        help = "Try connecting to the responder this many times before giving up."
+    )]
+    connect_attempts: u32,
+
+    #[arg(
+        long,
+        default_value = "250",
+        help = "Wait (milliseconds) before the first connection retry, doubling after each, less random jitter."
+    )]
+    connect_retry_backoff_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "10000",
+        help = "Longest wait (milliseconds) between connection retries."
+    )]
+    connect_retry_max_delay_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "32768",
+        help = "Refuse responders whose greeting asks for a Count above this."
+    )]
+    max_count: u32,
+
+    #[arg(
+        long,
+        help = "Give up if Accept-Session takes longer than this many milliseconds."
+    )]
+    accept_session_timeout_ms: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Give up if Server Greeting, Server-Start or Start-Ack takes longer than this many milliseconds."
+    )]
+    control_read_timeout_ms: Option<u64>,
+
+    #[arg(
+        long,
+        requires = "shared_secret",
+        help = "KeyID of --shared-secret, using authenticated mode."
+    )]
+    key_id: Option<String>,
+
+    #[arg(
+        long,
+        requires = "key_id",
+        help = "Shared secret for authenticated mode."
+    )]
+    shared_secret: Option<String>,
+
+    #[arg(
+        long,
+        requires = "shared_secret",
+        help = "Use encrypted mode rather than authenticated mode with --shared-secret."
+    )]
+    encrypted: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        help = "Modes to choose from, most preferred first, e.g. encrypted,authenticated,unauthenticated."
+    )]
+    modes: Vec<ModeArg>,
+
+    #[arg(
+        long,
+        help = "Push live metrics to the Prometheus Pushgateway at this address."
+    )]
+    pushgateway: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u16).range(1..),
+        conflicts_with_all = ["controller_test_port", "symmetric_ports"],
+        help = "Run this many sessions in parallel from different ports and compare their paths."
+    )]
+    paths: u16,
+
+    #[arg(
+        long,
+        conflicts_with = "paths",
+        help = "Negotiate a session up to Accept-Session and close without sending test pkts."
+    )]
+    negotiate_only: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["negotiate_only", "soak"],
+        help = "Send STAMP (RFC 8762) test pkts straight to --responder-reflect-port of the reflector, without TWAMP-Control."
+    )]
+    stamp: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["negotiate_only", "soak", "stamp"],
+        help = "Send TWAMP Light test pkts straight to --responder-reflect-port of a reflector set up out of band, without TWAMP-Control."
+    )]
+    light: bool,
+
+    #[arg(
+        long,
+        help = "Stop the test after this many milliseconds, reporting the test pkts sent so far."
+    )]
+    max_test_duration_ms: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Continue Sequence Numbers from the last test, saved in this file."
+    )]
+    sequence_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "sequence_file",
+        help = "Start Sequence Numbers from the milliseconds since the Unix epoch."
+    )]
+    wall_clock_sequence: bool,
+
+    #[arg(
+        long,
+        help = "Ignore non-zero MBZ fields in TWAMP-Control messages and reflected packets."
+    )]
+    lenient: bool,
+
+    #[arg(
+        long,
+        help = "Timestamp test pkts in truncated PTP rather than NTP format (RFC 8186). Refuses Servers not advertising it."
+    )]
+    ptp_timestamps: bool,
+
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        default_value = "zeros",
+        value_parser = parse_padding_pattern,
+        help = "Fill the padding of test pkts with zeros, random octets, the PRBS31 sequence (prbs31) or one octet repeated (e.g. 0xa5)."
+    )]
+    padding_pattern: PaddingPattern,
+
+    #[arg(
+        long,
+        help = "Announce the number of test pkts in Request-TW-Session, for OWAMP/TWAMP servers."
+    )]
+    announce_number_of_packets: bool,
+
+    #[arg(
+        long,
+        help = "Measure timer lateness before the test and make up for it when pacing test pkts."
+    )]
+    calibrate_pacing: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["paths", "negotiate_only"],
+        help = "Run this many tests back-to-back and report resource usage after each."
+    )]
+    soak: Option<u32>,
+
+    #[arg(long, default_value = "twamp", help = "Job to push metrics under.")]
+    push_job: String,
+
+    #[arg(
+        long,
+        default_value = "10000",
+        help = "Interval (milliseconds) between pushes to --pushgateway."
+    )]
+    push_interval_ms: u64,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ModeArg {
+    Unauthenticated,
+    Authenticated,
+    Encrypted,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum StopPolicyArg {
+    Immediate,
+    AfterAllReflected,
+    AfterTimeout,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ClockPolicyArg {
+    Ignore,
+    Annotate,
+    Warn,
+    Refuse,
+}
+
+fn parse_port_range(arg: &str) -> Result<RangeInclusive<u16>> {
+    let (low, high) = arg
+        .split_once('-')
+        .ok_or_else(|| anyhow!("expected LOW-HIGH"))?;
+    Ok(low.parse()?..=high.parse()?)
+}
+
+fn parse_profile(arg: &str) -> Result<PacketProfile> {
+    let mut fields = arg.split(':');
+    let mut profile = PacketProfile::new(fields.next().unwrap_or_default().parse()?);
+    if let Some(padding_length) = fields.next() {
+        profile.padding_length = padding_length.parse()?;
+    }
+    if let Some(interval_ms) = fields.next() {
+        profile.interval = Duration::from_millis(interval_ms.parse()?);
+    }
+    if let Some(dscp) = fields.next() {
+        profile.dscp = dscp.parse()?;
+    }
+    Ok(profile)
+}
+
+fn parse_reflect_octets(arg: &str) -> Result<u16> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => Ok(u16::from_str_radix(hex, 16)?),
+        None => Ok(arg.parse()?),
+    }
+}
+
+fn parse_flow_label(arg: &str) -> Result<FlowLabel> {
+    match arg {
+        "random" => Ok(FlowLabel::Random),
+        _ => match arg.strip_prefix("0x") {
+            Some(hex) => Ok(FlowLabel::Fixed(u32::from_str_radix(hex, 16)?)),
+            None => Ok(FlowLabel::Fixed(arg.parse()?)),
+        },
+    }
+}
+
+fn parse_padding_pattern(arg: &str) -> Result<PaddingPattern> {
+    match arg {
+        "zeros" => Ok(PaddingPattern::Zeros),
+        "random" => Ok(PaddingPattern::Random),
+        "prbs31" => Ok(PaddingPattern::Prbs31),
+        _ => match arg.strip_prefix("0x") {
+            Some(hex) => Ok(PaddingPattern::Fixed(u8::from_str_radix(hex, 16)?)),
+            None => Ok(PaddingPattern::Fixed(arg.parse()?)),
+        },
+    }
+}
+
+fn build_controller(args: &Args, exporter: Option<&PushExporter>) -> Result<Controller> {
+    let mut controller = Controller::new()
+        .with_max_count(args.max_count)
+        .with_clock_policy(match args.clock_policy {
+            ClockPolicyArg::Ignore => ClockPolicy::Ignore,
+            ClockPolicyArg::Annotate => ClockPolicy::Annotate,
+            ClockPolicyArg::Warn => ClockPolicy::Warn,
+            ClockPolicyArg::Refuse => ClockPolicy::Refuse,
+        });
+    if let Some(packets) = args.train_size {
+        controller = controller.with_train(Train {
+            packets,
+            gap: Duration::from_millis(args.train_gap_ms),
+        });
+    }
+    if args.start_retries > 0 {
+        controller = controller.with_start_retry(StartRetry {
+            attempts: args.start_retries,
+            backoff: Duration::from_millis(args.start_retry_backoff_ms),
+            max_delay: Duration::from_millis(args.start_retry_max_delay_ms),
+        });
+    }
+    if args.connect_attempts > 1 {
+        controller = controller.with_connect_retry(ConnectRetry {
+            max_attempts: args.connect_attempts,
+            backoff: Duration::from_millis(args.connect_retry_backoff_ms),
+            max_delay: Duration::from_millis(args.connect_retry_max_delay_ms),
+        });
+    }
+    if args.symmetric_ports {
+        controller = controller.with_symmetric_ports();
+    }
+    if let Some(range) = args.sender_port_range.clone() {
+        controller = controller.with_sender_port_policy(SenderPortPolicy::Random(range));
+    }
+    if args.ect {
+        controller = controller.with_ecn_marking(Ecn::Ect0);
+    }
+    if let Some(flow_label) = args.flow_label {
+        controller = controller.with_flow_label(flow_label);
+    }
+    if let Some(timeout_ms) = args.control_read_timeout_ms {
+        for message in [
+            ControlMessage::ServerGreeting,
+            ControlMessage::ServerStart,
+            ControlMessage::StartAck,
+        ] {
+            controller = controller.with_read_timeout(message, Duration::from_millis(timeout_ms));
+        }
+    }
+    if let Some(timeout_ms) = args.accept_session_timeout_ms {
+        controller = controller.with_accept_session_timeout(Duration::from_millis(timeout_ms));
+    }
+    if let (Some(key_id), Some(secret)) = (&args.key_id, &args.shared_secret) {
+        let shared_secret =
+            SharedSecret::new(key_id.as_str(), secret.as_bytes()).map_err(|e| anyhow!(e))?;
+        controller = controller.with_shared_secret(shared_secret);
+        if args.encrypted {
+            controller = controller.with_encryption();
+        }
+    }
+    if !args.modes.is_empty() {
+        let modes: Vec<Mode> = args
+            .modes
+            .iter()
+            .map(|mode| match mode {
+                ModeArg::Unauthenticated => Mode::Unauthenticated,
+                ModeArg::Authenticated => Mode::Authenticated,
+                ModeArg::Encrypted => Mode::Encrypted,
+            })
+            .collect();
+        controller = controller.with_mode_preference(&modes);
+    }
+    if !args.profiles.is_empty() {
+        let profiles = args
+            .profiles
+            .iter()
+            .map(|profile| PacketProfile {
+                mtu: args.mtu,
+                ..*profile
+            })
+            .collect();
+        controller = controller.with_profiles(profiles);
+    }
+    if let Some(delay_ms) = args.start_delay_ms {
+        controller =
+            controller.with_start_time(SystemTime::now() + Duration::from_millis(delay_ms));
+    }
+    if args.audit_control {
+        controller = controller.with_control_audit();
+    }
+    if let Some(octets) = args.reflect_octets {
+        controller =
+            controller.with_reflect_octets(ReflectOctets::new(octets, args.reflect_octets_length));
+    }
+    if let Some(duration_ms) = args.max_test_duration_ms {
+        controller = controller.with_max_test_duration(Duration::from_millis(duration_ms));
+    }
+    if args.lenient {
+        controller = controller.with_compliance(Compliance::Lenient);
+    }
+    if args.ptp_timestamps {
+        controller = controller.with_timestamp_format(TimestampFormat::Ptp);
+    }
+    controller = controller.with_padding_pattern(args.padding_pattern);
+    if args.announce_number_of_packets {
+        controller = controller.with_number_of_packets_announced();
+    }
+    if let Some(reflector_addr) = args.reflector_addr {
+        controller = controller.with_reflector_address(reflector_addr);
+    }
+    if args.calibrate_pacing {
+        controller = controller.with_pacing_calibration();
+    }
+    if let Some(path) = &args.sequence_file {
+        controller = controller.with_sequence_start(SequenceStart::Persisted(path.clone()));
+    } else if args.wall_clock_sequence {
+        controller = controller.with_sequence_start(SequenceStart::WallClock);
+    }
+    if let Some(bytes) = args.memory_budget_bytes {
+        controller = controller.with_memory_budget(bytes);
+    }
+    if args.log_measurements || exporter.is_some() {
+        let log_measurements = args.log_measurements;
+        let exporter = exporter.cloned();
+        controller = controller.on_measurement(MeasurementCallback::new(move |m| {
+            if log_measurements {
+                info!("seq {}: RTT {:.2}ms", m.sequence_number, m.rtt * 1e3);
+            }
+            if let Some(exporter) = &exporter {
+                exporter.record(&m);
+            }
+            async {}
+        }));
+    }
+    Ok(controller)
 }
 
 async fn try_main() -> Result<()> {
     let args = Args::parse();
-    let controller = Controller::new();
+    if let Some(hex) = &args.dissect {
+        let bytes = dissect::decode_hex(hex).ok_or_else(|| anyhow!("--dissect is not hex"))?;
+        print!("{}", dissect::dissect(&bytes));
+        return Ok(());
+    }
+    let exporter = args.pushgateway.map(|gateway| {
+        PushExporter::new(gateway, &args.push_job)
+            .with_interval(Duration::from_millis(args.push_interval_ms))
+    });
+    let responder_addr = args.responder_addr.expect("required unless --dissect");
+    let stop_policy = match args.stop_policy {
+        StopPolicyArg::Immediate => StopPolicy::Immediate,
+        StopPolicyArg::AfterAllReflected => StopPolicy::AfterAllReflected,
+        StopPolicyArg::AfterTimeout => {
+            StopPolicy::AfterTimeout(Duration::from_secs(args.stop_session_sleep))
+        }
+    };
+    if args.stamp || args.light {
+        let reflector_addr = SocketAddr::new(
+            args.reflector_addr.unwrap_or(responder_addr),
+            args.responder_reflect_port,
+        );
+        let pushing = exporter.as_ref().map(PushExporter::spawn);
+        let controller = build_controller(&args, exporter.as_ref())?;
+        let report = if args.stamp {
+            controller
+                .do_stamp(
+                    reflector_addr,
+                    args.controller_addr,
+                    args.controller_test_port,
+                    args.number_of_test_packets,
+                    stop_policy,
+                )
+                .await?
+        } else {
+            controller
+                .do_light(
+                    reflector_addr,
+                    args.controller_addr,
+                    args.controller_test_port,
+                    args.number_of_test_packets,
+                    stop_policy,
+                )
+                .await?
+        };
+        push_final(exporter, pushing).await;
+        log_report(&report);
+        return Ok(());
+    }
+    if args.paths > 1 {
+        let controllers = (0..args.paths)
+            .map(|_| build_controller(&args, exporter.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        let pushing = exporter.as_ref().map(PushExporter::spawn);
+        info!("Controllers initialized");
+        let report = explore_paths(
+            controllers,
+            responder_addr,
+            args.responder_port,
+            args.controller_addr,
+            args.responder_reflect_port,
+            args.number_of_test_packets,
+            args.timeout,
+            stop_policy,
+        )
+        .await?;
+        push_final(exporter, pushing).await;
+        log_path_report(&report);
+        return Ok(());
+    }
+    let controller = build_controller(&args, exporter.as_ref())?;
+    if let Some(iterations) = args.soak {
+        let mut config = ControllerConfig::new(responder_addr);
+        config.responder_port = args.responder_port;
+        config.controller_addr = args.controller_addr;
+        config.controller_port = args.controller_test_port;
+        config.responder_reflect_port = args.responder_reflect_port;
+        config.number_of_test_packets = args.number_of_test_packets;
+        config.reflector_timeout = args.timeout;
+        config.stop_policy = stop_policy;
+        let soak_args = args.clone();
+        let report = Soak::new(config, iterations)
+            .with_controller(move || {
+                build_controller(&soak_args, None).expect("built once already")
+            })
+            .run()
+            .await;
+        log_soak_report(&report);
+        return Ok(());
+    }
+    if args.negotiate_only {
+        let negotiated = controller
+            .negotiate(
+                responder_addr,
+                args.responder_port,
+                args.controller_addr,
+                args.controller_test_port,
+                args.responder_reflect_port,
+                args.timeout,
+            )
+            .await?;
+        log_control(&negotiated.control);
+        info!(
+            "Negotiated: port {} -> {}, sender port {}, padding {}, DSCP {}, timeout {}s",
+            negotiated.parameters.requested_port,
+            negotiated.parameters.granted_port,
+            negotiated.parameters.sender_port,
+            negotiated.parameters.requested_padding,
+            negotiated.parameters.requested_dscp,
+            negotiated.parameters.timeout,
+        );
+        return Ok(());
+    }
+    let pushing = exporter.as_ref().map(PushExporter::spawn);
     info!("Controller initialized");
 
-    controller
+    let report = controller
         .do_twamp(
-            args.responder_addr,
+            responder_addr,
             args.responder_port,
             args.controller_addr,
             args.controller_test_port,
             args.responder_reflect_port,
             args.number_of_test_packets,
             args.timeout,
-            args.stop_session_sleep,
+            stop_policy,
         )
         .await?;
+    push_final(exporter, pushing).await;
+    log_report(&report);
     Ok(())
 }
 
+async fn push_final(exporter: Option<PushExporter>, pushing: Option<JoinHandle<()>>) {
+    if let (Some(exporter), Some(pushing)) = (exporter, pushing) {
+        pushing.abort();
+        if let Err(e) = exporter.push().await {
+            warn!("Failed to push final metrics: {}", e);
+        }
+    }
+}
+
+fn log_soak_report(report: &SoakReport) {
+    let show = |counter: Option<u64>| counter.map_or("-".to_string(), |c| c.to_string());
+    for (index, iteration) in report.iterations.iter().enumerate() {
+        let counters = &iteration.counters;
+        info!(
+            "Cycle {}: {}/{} pkts, fds: {}, threads: {}, RSS: {} bytes{}",
+            index,
+            iteration.received,
+            iteration.sent,
+            show(counters.open_fds),
+            show(counters.threads),
+            show(counters.rss_bytes),
+            iteration
+                .error
+                .as_ref()
+                .map_or(String::new(), |e| format!(", failed: {}", e)),
+        );
+    }
+    info!(
+        "Soak: {} cycles, {} failed",
+        report.iterations.len(),
+        report.failures()
+    );
+    for leak in report.leaks() {
+        warn!("Grew over the soak: {:?}", leak);
+    }
+}
+
+fn log_path_report(report: &PathReport) {
+    for (index, path) in report.paths.iter().enumerate() {
+        info!(
+            "Path {} (sender port {}, reflector port {}): {}/{} pkts, loss: {}%, RTT (AVG): {:.2}ms, jitter: {:.2}ms",
+            index,
+            path.parameters.sender_port,
+            path.parameters.granted_port,
+            path.received,
+            path.sent,
+            path.loss_percent.trunc(),
+            path.rtt_avg * 1e3,
+            path.jitter * 1e3,
+        );
+    }
+    info!(
+        "RTT (AVG) spread: {:.2}ms, loss spread: {}%",
+        report.rtt_spread.rtt_avg * 1e3,
+        report.loss_spread.loss_percent.trunc()
+    );
+    if report.is_asymmetric() {
+        warn!("Paths differ by more than measurement noise");
+    }
+}
+
+fn log_control(control: &ControlMetadata) {
+    info!(
+        "Control: server modes {:?}, mode {:?}, count {}",
+        control.server_modes, control.mode, control.count
+    );
+    let capabilities = &control.capabilities;
+    info!(
+        "Peer: extensions {:?}, mode bits {:#x}, started {:?}, quirks {:?}",
+        capabilities.extensions,
+        capabilities.mode_bits,
+        capabilities.start_time,
+        capabilities.quirks
+    );
+    for event in &control.timeline {
+        info!("Control message: {}", event);
+    }
+}
+
+fn log_report(report: &TestReport) {
+    log_control(&report.control);
+    let parameters = &report.parameters;
+    info!(
+        "Parameters (requested -> used): port {} -> {}, padding {} -> {}, DSCP {} -> {}, timeout {}s, rate {}",
+        parameters.requested_port,
+        parameters.granted_port,
+        parameters.requested_padding,
+        parameters.sent_padding,
+        parameters.requested_dscp,
+        parameters.sent_dscp,
+        parameters.timeout,
+        parameters
+            .effective_rate
+            .map_or("n/a".to_string(), |rate| format!("{:.0}pkt/s", rate)),
+    );
+    if let Some(label) = parameters.flow_label {
+        info!("Flow label: {:#07x}", label);
+    }
+    info!("Termination: {:?}", report.termination);
+    if let Some(mismatched) = report.reflected_octets_mismatched {
+        info!(
+            "Reflected pkts without the octets to reflect: {}",
+            mismatched
+        );
+    }
+    for pacing in &report.pacing {
+        info!(
+            "Pacing: requested {:.3}ms, achieved {:.3}ms (σ {:.3}ms), correction {:.3}ms",
+            pacing.requested * 1e3,
+            pacing.mean * 1e3,
+            pacing.std_dev * 1e3,
+            pacing.correction * 1e3
+        );
+    }
+    if report.memory_budget_exceeded {
+        info!("Memory budget exceeded: aggregates only, no per-train or per-profile results");
+    }
+    if let Some(clock) = report.clock {
+        info!(
+            "Clock: synchronized {}, estimated error {:?}",
+            clock.synchronized, clock.estimated_error
+        );
+    }
+    info!("Packet loss: {}%", report.loss_percent.trunc());
+    info!("RTT (MIN): {:.2}ms", report.rtt_min * 1e3);
+    info!("RTT (MAX): {:.2}ms", report.rtt_max * 1e3);
+    info!("RTT (AVG): {:.2}ms", report.rtt_avg * 1e3);
+    info!(
+        "OWD (Sender -> Reflector) (AVG): {:.2}ms",
+        report.owd_forward_avg * 1e3
+    );
+    info!(
+        "OWD (Reflector -> Sender) (AVG): {:.2}ms",
+        report.owd_reverse_avg * 1e3
+    );
+    info!("Jitter: {:.2}ms", report.jitter * 1e3);
+    info!("ECN of reflected pkts: {:?}", report.ecn);
+    for train in &report.trains {
+        info!(
+            "Train {}: {}/{} pkts, RTT (AVG): {:.2}ms, dispersion: {}, bottleneck: {}",
+            train.index,
+            train.received,
+            train.sent,
+            train.rtt_avg * 1e3,
+            train
+                .dispersion
+                .map_or("n/a".to_string(), |d| format!("{:.3}ms", d * 1e3)),
+            train
+                .bottleneck_bandwidth
+                .map_or("n/a".to_string(), |b| format!("{:.2}Mbit/s", b / 1e6)),
+        );
+    }
+    for (index, profile) in report.profiles.iter().enumerate() {
+        info!(
+            "Profile {}: {}/{} pkts of {} bytes, loss: {}%, RTT (AVG): {:.2}ms, jitter: {:.2}ms",
+            index,
+            profile.report.received,
+            profile.report.sent,
+            profile.packet_length,
+            profile.report.loss_percent.trunc(),
+            profile.report.rtt_avg * 1e3,
+            profile.report.jitter * 1e3,
+        );
+    }
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
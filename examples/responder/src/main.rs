@@ -1,52 +1,572 @@
-pub mod responder;
-
-use anyhow::Result;
-use clap::Parser;
-use responder::Responder;
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+use server::config::{DuplicateStartSessions, ServerConfig};
+use server::policy::{SessionLimits, SessionPolicy};
+use server::secrets::{MemorySecretStore, SecretStore};
+use session_reflector::accounting::Accounting;
+use session_reflector::config::{Pacing, ReflectorConfig, ReflectorSequence};
+use session_reflector::ports::{PortAllocator, PortRange, RequestedOnly, Sequential};
+use session_reflector::{light::LightReflector, stamp::StampReflector};
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    path::PathBuf,
     process,
+    sync::Arc,
+    time::Duration,
+};
+use timestamp::timestamp::TimestampFormat;
+use tokio::select;
+use tokio::signal::{
+    ctrl_c,
+    unix::{signal, SignalKind},
 };
-use tokio::net::{TcpListener, TcpStream};
-use tokio::task;
+use tokio::sync::watch;
 use tracing::*;
+use twamp_control::auth::SharedSecret;
+use twamp_control::compliance::Compliance;
 use twamp_control::constants::TWAMP_CONTROL_WELL_KNOWN_PORT;
+use twamp_control::server_greeting::ChallengePolicy;
+use twamp_control::timers::{Refwait, Servwait};
+use twamp_rs::dissect;
+use twamp_rs::responder::{Responder, ResponderConfig};
+use twamp_rs::textfile::TextfileExporter;
+use twamp_runtime::{
+    net::{TcpStream, UdpSocket},
+    task,
+};
+use twamp_test::ecn::Ecn;
+use twamp_test::twamp_test_unauth::TwampTestPacketUnauth;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_override_self = true)]
 struct Args {
-    #[arg(short, long, default_value = "127.0.0.1")]
-    addr: Ipv4Addr,
+    #[arg(
+        short,
+        long,
+        default_value = "127.0.0.1",
+        help = "Address to listen on. With :: IPv4 Controllers are served too where the OS makes IPv6 sockets dual-stack, as Linux does by default."
+    )]
+    addr: IpAddr,
+
+    #[arg(
+        long,
+        exclusive = true,
+        value_name = "HEX",
+        help = "Print the TWAMP-Control message in HEX field by field, then exit."
+    )]
+    dissect: Option<String>,
 
     #[arg(short, long, default_value_t = TWAMP_CONTROL_WELL_KNOWN_PORT)]
     port: u16,
 
+    #[arg(
+        long,
+        help = "Listen on this port instead if listening on --port is not permitted, e.g. 862 without privileges."
+    )]
+    fallback_port: Option<u16>,
+
     #[arg(short, long, default_value = "900")]
-    refwait: u16,
+    refwait: Refwait,
+
+    #[arg(
+        long,
+        default_value_t = TwampTestPacketUnauth::MIN_LENGTH,
+        help = "Drop TWAMP-Test packets smaller than this many bytes."
+    )]
+    min_request_size: usize,
+
+    #[arg(
+        long,
+        help = "Reflect TWAMP-Test packets even if the reflected packet is larger than the received one."
+    )]
+    no_reflect_size_cap: bool,
+
+    #[arg(
+        long,
+        default_value = "1024",
+        help = "Drop TWAMP-Test packets while this many are waiting to be reflected."
+    )]
+    max_queued_packets: usize,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = PacingArg::Immediate,
+        help = "When to send reflected packets relative to the arrival of TWAMP-Test packets."
+    )]
+    reflect_pacing: PacingArg,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Dwell (milliseconds) used with --reflect-pacing fixed-dwell."
+    )]
+    reflect_dwell_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        conflicts_with = "mirror_sender_sequence",
+        help = "Sequence Number of the first reflected TWAMP-Test packet of each session."
+    )]
+    reflected_sequence_start: u32,
+
+    #[arg(
+        long,
+        help = "Number reflected TWAMP-Test packets like the TWAMP-Test packets that caused them."
+    )]
+    mirror_sender_sequence: bool,
+
+    #[arg(
+        long,
+        help = "Embed a count of processed TWAMP-Test packets in the padding of reflected packets."
+    )]
+    echo_counter: bool,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u8).range(0..64),
+        help = "Mark reflected TWAMP-Test packets with this DSCP."
+    )]
+    reflected_dscp: Option<u8>,
+
+    #[arg(long, help = "Mark reflected TWAMP-Test packets ECT(0).")]
+    reflected_ect: bool,
+
+    #[arg(
+        long,
+        help = "Ignore non-zero MBZ fields in TWAMP-Control messages and TWAMP-Test packets."
+    )]
+    lenient: bool,
+
+    #[arg(
+        long,
+        help = "Timestamp reflected packets in truncated PTP rather than NTP format (RFC 8186)."
+    )]
+    ptp_timestamps: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ChallengeArg::Auto,
+        help = "How to fill in Challenge and Salt of the Server Greeting."
+    )]
+    greeting_challenge: ChallengeArg,
+
+    #[arg(
+        long,
+        default_value_t = ServerConfig::default().max_padding_length,
+        help = "Refuse sessions requesting more padding than this many bytes."
+    )]
+    max_padding_length: u32,
+
+    #[arg(
+        long,
+        requires = "shared_secret",
+        help = "KeyID of --shared-secret, offering authenticated mode."
+    )]
+    key_id: Option<String>,
+
+    #[arg(
+        long,
+        requires = "key_id",
+        help = "Shared secret for authenticated mode."
+    )]
+    shared_secret: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "KEYID:SECRET",
+        help = "Shared secret for authenticated mode known by KEYID. Can be given several times."
+    )]
+    secret: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Close connections whose Start-Sessions does not arrive this many milliseconds after Accept-Session."
+    )]
+    start_sessions_deadline_ms: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "LOW-HIGH",
+        value_parser = parse_port_range,
+        help = "Refuse Request-TW-Sessions asking for a Receiver Port outside this range."
+    )]
+    receiver_port_range: Option<RangeInclusive<u16>>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "How to pick the port of each Session-Reflector. range and sequential pick from --reflector-port-range. Defaults to range with --reflector-port-range, requested-or-any otherwise."
+    )]
+    reflector_ports: Option<PortsArg>,
+
+    #[arg(
+        long,
+        value_name = "LOW-HIGH",
+        value_parser = parse_port_range,
+        required_if_eq_any = [("reflector_ports", "range"), ("reflector_ports", "sequential")],
+        help = "Bind Session-Reflectors to the Receiver Port asked for if within this range, or else to another port of it, refusing sessions once none is free."
+    )]
+    reflector_port_range: Option<RangeInclusive<u16>>,
+
+    #[arg(
+        long,
+        value_name = "PORT",
+        help = "Also reflect STAMP (RFC 8762) test packets arriving at this UDP port, 862 being the well-known one. Not reloaded on SIGHUP."
+    )]
+    stamp_port: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "PORT",
+        help = "Also reflect TWAMP Light test pkts from any sender arriving at this UDP port, with no TWAMP-Control. Not reloaded on SIGHUP."
+    )]
+    light_port: Option<u16>,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Tasks sending reflected packets, which every session takes turns at. 0 for one per CPU. Not reloaded on SIGHUP."
+    )]
+    reflector_workers: usize,
+
+    #[arg(
+        long,
+        help = "Refuse Request-TW-Sessions beyond this many on one TWAMP-Control connection."
+    )]
+    max_sessions_per_connection: Option<usize>,
+
+    #[arg(
+        long,
+        default_value_t = 900,
+        help = "Close connections sending no TWAMP-Control message for this many seconds before their sessions start (SERVWAIT). 0 waits forever."
+    )]
+    servwait_secs: u64,
+
+    #[arg(
+        long,
+        help = "Close connections sending Start-Sessions again after their sessions started, instead of acknowledging it."
+    )]
+    refuse_duplicate_start_sessions: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Periodically write reflector counters to this node_exporter textfile-collector file."
+    )]
+    textfile: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "15",
+        requires = "textfile",
+        help = "Seconds between writes of --textfile."
+    )]
+    textfile_interval_secs: u64,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Read more options from this file, overriding the command line. It is read again on SIGHUP, applying to new connections only. Listening address, port, fallback port and --textfile options are not reloaded."
+    )]
+    config: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum PacingArg {
+    Immediate,
+    MirrorArrival,
+    FixedDwell,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum PortsArg {
+    RequestedOrAny,
+    RequestedOnly,
+    Range,
+    Sequential,
 }
 
-async fn handle_client(socket: TcpStream, refwait: u16) {
-    let responder = Responder::new(socket);
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ChallengeArg {
+    Auto,
+    Random,
+    Zero,
+}
+
+async fn handle_client(
+    socket: TcpStream,
+    refwait: Refwait,
+    server_config: ServerConfig,
+    reflector_config: ReflectorConfig,
+    port_allocator: Option<Arc<dyn PortAllocator>>,
+    exporter: Option<TextfileExporter>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let mut responder = Responder::new(socket)
+        .with_server_config(server_config)
+        .with_reflector_config(reflector_config)
+        .with_shutdown(shutdown);
+    if let Some(port_allocator) = port_allocator {
+        responder = responder.with_port_allocator(port_allocator);
+    }
+    if let Some(exporter) = exporter {
+        exporter.track(responder.accounting());
+    }
     debug!("Responder created: {:?}", responder);
-    responder.handle_controller(refwait).await.unwrap();
+    let summary = responder.handle_controller(refwait).await.unwrap();
+    info!(
+        "Session ended ({:?}): reflected {}, dropped {}, ECN {:?}",
+        summary.reason, summary.reflected, summary.dropped, summary.ecn
+    );
 }
 
-async fn try_main() -> Result<()> {
+/// Command line options, overridden by those of `--config`. Options in the file are separated
+/// by whitespace, and `#` starts a comment running to the end of the line.
+fn parse_port_range(arg: &str) -> Result<RangeInclusive<u16>> {
+    let (low, high) = arg
+        .split_once('-')
+        .ok_or_else(|| anyhow!("expected LOW-HIGH"))?;
+    Ok(low.parse()?..=high.parse()?)
+}
+
+fn parse_args() -> Result<Args> {
     let args = Args::parse();
-    let socket_addr = SocketAddrV4::new(args.addr, args.port);
-    debug!("Attempting to bind to: {}/tcp", socket_addr);
+    let Some(path) = &args.config else {
+        return Ok(args);
+    };
+    let options = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+    let file_args: Vec<String> = options
+        .lines()
+        .filter_map(|line| line.split('#').next())
+        .flat_map(str::split_whitespace)
+        .map(String::from)
+        .collect();
+    Args::try_parse_from(std::env::args().chain(file_args))
+        .map_err(|e| anyhow!("Invalid options in {}: {}", path.display(), e))
+}
+
+fn responder_config(args: &Args) -> Result<ResponderConfig> {
+    let mut store = MemorySecretStore::new();
+    if let (Some(key_id), Some(secret)) = (&args.key_id, &args.shared_secret) {
+        store
+            .insert(SharedSecret::new(key_id.as_str(), secret.as_bytes()).map_err(|e| anyhow!(e))?);
+    }
+    for entry in &args.secret {
+        let (key_id, secret) = entry
+            .split_once(':')
+            .ok_or_else(|| anyhow!("--secret {} is not KEYID:SECRET", entry))?;
+        store.insert(SharedSecret::new(key_id, secret.as_bytes()).map_err(|e| anyhow!(e))?);
+    }
+    let secrets = (!store.is_empty()).then(|| Arc::new(store) as Arc<dyn SecretStore>);
+    let compliance = if args.lenient {
+        Compliance::Lenient
+    } else {
+        Compliance::Strict
+    };
+    let reflector = ReflectorConfig {
+        min_request_size: args.min_request_size,
+        cap_to_request_size: !args.no_reflect_size_cap,
+        max_queued_packets: args.max_queued_packets,
+        pacing: match args.reflect_pacing {
+            PacingArg::Immediate => Pacing::Immediate,
+            PacingArg::MirrorArrival => Pacing::MirrorArrival,
+            PacingArg::FixedDwell => {
+                Pacing::FixedDwell(Duration::from_millis(args.reflect_dwell_ms))
+            }
+        },
+        sequence: if args.mirror_sender_sequence {
+            ReflectorSequence::MirrorSender
+        } else {
+            ReflectorSequence::Count {
+                start: args.reflected_sequence_start,
+            }
+        },
+        echo_counter: args.echo_counter,
+        reflected_dscp: args.reflected_dscp,
+        reflected_ecn: if args.reflected_ect {
+            Ecn::Ect0
+        } else {
+            Ecn::NotEct
+        },
+        compliance,
+        timestamp_format: if args.ptp_timestamps {
+            TimestampFormat::Ptp
+        } else {
+            TimestampFormat::Ntp
+        },
+    };
+    let limits = SessionLimits {
+        receiver_ports: args.receiver_port_range.clone(),
+        max_sessions: args.max_sessions_per_connection,
+        ..Default::default()
+    };
+    let session_policy =
+        (limits != SessionLimits::default()).then(|| Arc::new(limits) as Arc<dyn SessionPolicy>);
+    let server = ServerConfig {
+        challenge_policy: match args.greeting_challenge {
+            ChallengeArg::Auto => ChallengePolicy::Auto,
+            ChallengeArg::Random => ChallengePolicy::Random,
+            ChallengeArg::Zero => ChallengePolicy::Zero,
+        },
+        max_padding_length: args.max_padding_length,
+        secrets,
+        start_sessions_deadline: args.start_sessions_deadline_ms.map(Duration::from_millis),
+        session_policy,
+        // No SERVWAIT for 0 seconds.
+        servwait: Servwait::new(Duration::from_secs(args.servwait_secs)).ok(),
+        duplicate_start_sessions: if args.refuse_duplicate_start_sessions {
+            DuplicateStartSessions::Refuse
+        } else {
+            DuplicateStartSessions::Acknowledge
+        },
+        compliance,
+    };
+    let reflector_port_range = || {
+        args.reflector_port_range.clone().ok_or_else(|| {
+            anyhow!(
+                "--reflector-ports {:?} needs --reflector-port-range",
+                args.reflector_ports
+            )
+        })
+    };
+    let reflector_ports = args
+        .reflector_ports
+        .unwrap_or(match args.reflector_port_range {
+            Some(_) => PortsArg::Range,
+            None => PortsArg::RequestedOrAny,
+        });
+    let ports: Option<Arc<dyn PortAllocator>> = match reflector_ports {
+        PortsArg::RequestedOrAny => None,
+        PortsArg::RequestedOnly => Some(Arc::new(RequestedOnly)),
+        PortsArg::Range => Some(Arc::new(PortRange(reflector_port_range()?))),
+        PortsArg::Sequential => Some(Arc::new(Sequential::new(reflector_port_range()?))),
+    };
+    Ok(ResponderConfig {
+        addr: SocketAddr::new(args.addr, args.port),
+        fallback_port: args.fallback_port,
+        refwait: args.refwait,
+        server,
+        reflector,
+        ports,
+        stamp_addr: args.stamp_port.map(|port| SocketAddr::new(args.addr, port)),
+        light_addr: args.light_port.map(|port| SocketAddr::new(args.addr, port)),
+        reflector_workers: args.reflector_workers,
+    })
+}
 
-    let listener = TcpListener::bind(socket_addr).await?;
+/// Read `--config` again on every SIGHUP, keeping the current config if it is invalid.
+async fn reload_on_sighup(config_tx: watch::Sender<ResponderConfig>) -> Result<()> {
+    let mut hangups = signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        match parse_args().and_then(|args| responder_config(&args)) {
+            Ok(config) => {
+                if config.addr != config_tx.borrow().addr {
+                    warn!("Listening address cannot be reloaded, still using the old one");
+                }
+                config_tx.send_replace(config);
+                info!("Reloaded config, applying to new connections");
+            }
+            Err(e) => warn!("Keeping current config, reloading failed: {}", e),
+        }
+    }
+    Ok(())
+}
+
+async fn try_main() -> Result<()> {
+    let args = parse_args()?;
+    if let Some(hex) = &args.dissect {
+        let bytes = dissect::decode_hex(hex).ok_or_else(|| anyhow!("--dissect is not hex"))?;
+        print!("{}", dissect::dissect(&bytes));
+        return Ok(());
+    }
+    let (config_tx, config_rx) = watch::channel(responder_config(&args)?);
+    if args.config.is_some() {
+        task::spawn(async move {
+            if let Err(e) = reload_on_sighup(config_tx).await {
+                warn!("Cannot reload config on SIGHUP: {}", e);
+            }
+        });
+    }
+    let exporter = args.textfile.as_ref().map(|path| {
+        TextfileExporter::new(path).with_interval(Duration::from_secs(args.textfile_interval_secs))
+    });
+    if let Some(exporter) = &exporter {
+        exporter.spawn();
+    }
+    let initial = config_rx.borrow().clone();
+    debug!("Attempting to bind to: {}/tcp", initial.addr);
+
+    let listener = initial.bind().await?;
     debug!("Successfully binded to: {}/tcp", listener.local_addr()?);
 
     info!("Listening TWAMP-Control on: {}/tcp", listener.local_addr()?);
+    if let Some(stamp_addr) = initial.stamp_addr {
+        let accounting = Arc::new(Accounting::default());
+        if let Some(exporter) = &exporter {
+            exporter.track(Arc::clone(&accounting));
+        }
+        let reflector = StampReflector::new(UdpSocket::bind(stamp_addr).await?)
+            .with_config(initial.reflector.clone())
+            .with_accounting(accounting);
+        info!("Reflecting STAMP on: {}/udp", stamp_addr);
+        task::spawn(async move {
+            if let Err(e) = reflector.do_reflect().await {
+                error!("STAMP reflector failed: {}", e);
+            }
+        });
+    }
+    if let Some(light_addr) = initial.light_addr {
+        let accounting = Arc::new(Accounting::default());
+        if let Some(exporter) = &exporter {
+            exporter.track(Arc::clone(&accounting));
+        }
+        let reflector = LightReflector::new(UdpSocket::bind(light_addr).await?)
+            .with_refwait(initial.refwait)
+            .with_config(initial.reflector.clone())
+            .with_accounting(accounting);
+        info!("Reflecting TWAMP Light on: {}/udp", light_addr);
+        task::spawn(async move {
+            if let Err(e) = reflector.do_reflect().await {
+                error!("TWAMP Light reflector failed: {}", e);
+            }
+        });
+    }
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut terminations = signal(SignalKind::terminate())?;
     loop {
-        let (socket, client_addr) = listener.accept().await?;
+        let (socket, client_addr) = select! {
+            accepted = listener.accept() => accepted?,
+            _ = ctrl_c() => break,
+            _ = terminations.recv() => break,
+        };
         info!("Received connection from {}/tcp", client_addr);
+        let config = config_rx.borrow().clone();
+        let exporter = exporter.clone();
+        let shutdown_rx = shutdown_rx.clone();
         task::spawn(async move {
-            handle_client(socket, args.refwait).await;
+            handle_client(
+                socket,
+                config.refwait,
+                config.server,
+                config.reflector,
+                config.ports,
+                exporter,
+                shutdown_rx,
+            )
+            .await;
         });
     }
+    // Connections still served send Stop-Sessions, and are done once they let go of `shutdown_rx`.
+    drop(shutdown_rx);
+    info!("Shutting down, stopping sessions in progress");
+    shutdown_tx.send_replace(true);
+    shutdown_tx.closed().await;
+    Ok(())
 }
 
 #[tokio::main]
@@ -0,0 +1,132 @@
+//! Both TWAMP roles in one process, for mesh agents that probe their peers while being probed
+//! by them. An [`Agent`] serves every Controller with a Responder on a background task of the
+//! current tokio runtime, and runs tests against peers with Controllers sharing its config.
+
+use std::{convert::Infallible, net::SocketAddr, ops::RangeInclusive};
+
+use anyhow::{anyhow, Result};
+use tokio::sync::watch;
+use twamp_runtime::task::{spawn, JoinHandle};
+
+use crate::{
+    controller::{Controller, ControllerConfig, SenderPortPolicy},
+    report::TestReport,
+    responder::ResponderConfig,
+};
+
+/// How an [`Agent`] serves Controllers and tests its peers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgentConfig {
+    /// Where and how the Responder serves Controllers. Its address is also the one tests are
+    /// sent from, unless a test's `controller_addr` says otherwise, and its server's compliance
+    /// applies to the Controllers too.
+    pub responder: ResponderConfig,
+
+    /// Ports Session-Senders pick from. Session-Reflectors bind the port a peer asks for, or
+    /// any port the OS picks if it is taken, so keeping senders to a range below the OS's
+    /// ephemeral ports keeps both roles from competing for the same ones.
+    pub sender_ports: RangeInclusive<u16>,
+}
+
+impl AgentConfig {
+    /// Serve on `addr` with the defaults of [`ResponderConfig::new`], sending from ports 20000
+    /// to 29999.
+    pub fn new(addr: SocketAddr) -> Self {
+        AgentConfig {
+            responder: ResponderConfig::new(addr),
+            sender_ports: 20000..=29999,
+        }
+    }
+}
+
+/// A Responder serving in the background and a source of Controllers that don't get in its
+/// way. Serving stops when the `Agent` is dropped.
+#[derive(Debug)]
+pub struct Agent {
+    config: AgentConfig,
+    responder_addr: SocketAddr,
+    serving: JoinHandle<Result<Infallible>>,
+}
+
+impl Agent {
+    /// Listen as described by `config.responder` and start serving Controllers. Fails if
+    /// listening does, or if the port listened on is one senders could pick.
+    pub async fn start(config: AgentConfig) -> Result<Agent> {
+        let listener = config.responder.bind().await?;
+        let responder_addr = listener.local_addr()?;
+        if config.sender_ports.contains(&responder_addr.port()) {
+            return Err(anyhow!(
+                "Responder port {} is within sender ports {:?}",
+                responder_addr.port(),
+                config.sender_ports
+            ));
+        }
+        let responder_config = watch::channel(config.responder.clone()).1;
+        let serving = spawn(crate::serve_listener(listener, responder_config));
+        Ok(Agent {
+            config,
+            responder_addr,
+            serving,
+        })
+    }
+
+    /// Address the Responder listens for TWAMP-Control on.
+    pub fn responder_addr(&self) -> SocketAddr {
+        self.responder_addr
+    }
+
+    /// A [`Controller`] sending from the [sender ports](AgentConfig::sender_ports), with the
+    /// Responder's compliance. Further builders can be chained before testing with it.
+    pub fn controller(&self) -> Controller {
+        Controller::new()
+            .with_sender_port_policy(SenderPortPolicy::Random(self.config.sender_ports.clone()))
+            .with_compliance(self.config.responder.server.compliance)
+    }
+
+    /// Run one test as described by `config` with a [`controller`](Self::controller), from
+    /// the Responder's address if `config.controller_addr` is unspecified.
+    pub async fn run(&self, mut config: ControllerConfig) -> Result<TestReport> {
+        if config.controller_addr.is_unspecified() {
+            config.controller_addr = self.responder_addr.ip();
+        }
+        crate::run_with(self.controller(), config).await
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        self.serving.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::SessionTimeout;
+
+    #[tokio::test]
+    async fn agents_test_each_other() {
+        let a = Agent::start(AgentConfig::new("127.0.0.1:0".parse().unwrap()))
+            .await
+            .unwrap();
+        let b = Agent::start(AgentConfig::new("127.0.0.1:0".parse().unwrap()))
+            .await
+            .unwrap();
+        for (from, to) in [(&a, &b), (&b, &a), (&a, &a)] {
+            let mut config = ControllerConfig::new(to.responder_addr().ip());
+            config.responder_port = to.responder_addr().port();
+            config.responder_reflect_port = 0;
+            config.number_of_test_packets = 3;
+            config.reflector_timeout = SessionTimeout::new(1).unwrap();
+            let report = from.run(config).await.unwrap();
+            assert_eq!(report.received, 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn responder_port_within_sender_ports_is_refused() {
+        let mut config = AgentConfig::new("127.0.0.1:0".parse().unwrap());
+        config.sender_ports = 0..=u16::MAX;
+        assert!(Agent::start(config).await.is_err());
+    }
+}
@@ -0,0 +1,428 @@
+//! Blocking variants of [`Controller`](crate::controller::Controller) and
+//! [`Responder`](crate::responder::Responder).
+//!
+//! Each type owns a private single-threaded tokio runtime and blocks the calling thread until the
+//! wrapped async call completes. They must not be used from within an async context.
+
+use std::{
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use server::config::ServerConfig;
+use session_reflector::{accounting::Accounting, config::ReflectorConfig, ports::PortAllocator};
+use session_sender::{measurement::MeasurementCallback, PacketProfile, Train};
+
+use crate::{
+    clock::ClockPolicy,
+    controller::{
+        ConnectRetry, ControlMessage, SenderPortPolicy, SequenceStart, SessionTimeout, StartRetry,
+        StopPolicy,
+    },
+    report::{NegotiationReport, TestReport},
+    responder::ReflectorSummary,
+};
+use timestamp::timestamp::TimestampFormat;
+use tokio::runtime::{Builder, Runtime};
+use twamp_control::{
+    auth::SharedSecret, compliance::Compliance, rng::RngSource, security_mode::Mode,
+    timers::Refwait,
+};
+use twamp_test::{
+    ecn::Ecn, flow_label::FlowLabel, padding::PaddingPattern, reflect_octets::ReflectOctets,
+    stamp_tlv::Tlv,
+};
+
+/// Blocking wrapper around [`Controller`](crate::controller::Controller).
+///
+/// # Example
+///
+/// ```no_run
+/// use std::net::Ipv4Addr;
+/// use twamp_rs::blocking::Controller;
+/// use twamp_rs::controller::{SessionTimeout, StopPolicy};
+///
+/// let controller = Controller::new().unwrap();
+/// controller
+///     .do_twamp(
+///         Ipv4Addr::new(127, 0, 0, 1).into(),
+///         862,
+///         Ipv4Addr::UNSPECIFIED.into(),
+///         0,
+///         862,
+///         10,
+///         SessionTimeout::DEFAULT,
+///         StopPolicy::default(),
+///     )
+///     .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Controller {
+    inner: crate::controller::Controller,
+    runtime: Runtime,
+}
+
+impl Controller {
+    /// Create a `Controller` along with the runtime it will be driven on.
+    pub fn new() -> Result<Self> {
+        Ok(Controller {
+            inner: crate::controller::Controller::new(),
+            runtime: new_runtime()?,
+        })
+    }
+
+    /// See [`Controller::with_train`](crate::controller::Controller::with_train).
+    pub fn with_train(mut self, train: Train) -> Self {
+        self.inner = self.inner.with_train(train);
+        self
+    }
+
+    /// See [`Controller::with_profiles`](crate::controller::Controller::with_profiles).
+    pub fn with_profiles(mut self, profiles: Vec<PacketProfile>) -> Self {
+        self.inner = self.inner.with_profiles(profiles);
+        self
+    }
+
+    /// See [`Controller::with_clock_policy`](crate::controller::Controller::with_clock_policy).
+    pub fn with_clock_policy(mut self, clock_policy: ClockPolicy) -> Self {
+        self.inner = self.inner.with_clock_policy(clock_policy);
+        self
+    }
+
+    /// See [`Controller::with_sender_port_policy`](crate::controller::Controller::with_sender_port_policy).
+    pub fn with_sender_port_policy(mut self, sender_port_policy: SenderPortPolicy) -> Self {
+        self.inner = self.inner.with_sender_port_policy(sender_port_policy);
+        self
+    }
+
+    /// See [`Controller::with_ecn_marking`](crate::controller::Controller::with_ecn_marking).
+    pub fn with_ecn_marking(mut self, ecn: Ecn) -> Self {
+        self.inner = self.inner.with_ecn_marking(ecn);
+        self
+    }
+
+    /// See [`Controller::with_symmetric_ports`](crate::controller::Controller::with_symmetric_ports).
+    pub fn with_symmetric_ports(mut self) -> Self {
+        self.inner = self.inner.with_symmetric_ports();
+        self
+    }
+
+    /// See [`Controller::with_start_retry`](crate::controller::Controller::with_start_retry).
+    pub fn with_start_retry(mut self, start_retry: StartRetry) -> Self {
+        self.inner = self.inner.with_start_retry(start_retry);
+        self
+    }
+
+    /// See
+    /// [`Controller::with_connect_retry`](crate::controller::Controller::with_connect_retry).
+    pub fn with_connect_retry(mut self, connect_retry: ConnectRetry) -> Self {
+        self.inner = self.inner.with_connect_retry(connect_retry);
+        self
+    }
+
+    /// See [`Controller::with_max_count`](crate::controller::Controller::with_max_count).
+    pub fn with_max_count(mut self, max_count: u32) -> Self {
+        self.inner = self.inner.with_max_count(max_count);
+        self
+    }
+
+    /// See [`Controller::with_read_timeout`](crate::controller::Controller::with_read_timeout).
+    pub fn with_read_timeout(mut self, message: ControlMessage, read_timeout: Duration) -> Self {
+        self.inner = self.inner.with_read_timeout(message, read_timeout);
+        self
+    }
+
+    /// See [`Controller::with_accept_session_timeout`](crate::controller::Controller::with_accept_session_timeout).
+    pub fn with_accept_session_timeout(mut self, accept_session_timeout: Duration) -> Self {
+        self.inner = self
+            .inner
+            .with_accept_session_timeout(accept_session_timeout);
+        self
+    }
+
+    /// See [`Controller::with_shared_secret`](crate::controller::Controller::with_shared_secret).
+    pub fn with_shared_secret(mut self, shared_secret: SharedSecret) -> Self {
+        self.inner = self.inner.with_shared_secret(shared_secret);
+        self
+    }
+
+    /// See [`Controller::with_encryption`](crate::controller::Controller::with_encryption).
+    pub fn with_encryption(mut self) -> Self {
+        self.inner = self.inner.with_encryption();
+        self
+    }
+
+    /// See [`Controller::with_mode_preference`](crate::controller::Controller::with_mode_preference).
+    pub fn with_mode_preference(mut self, modes: &[Mode]) -> Self {
+        self.inner = self.inner.with_mode_preference(modes);
+        self
+    }
+
+    /// See [`Controller::with_start_time`](crate::controller::Controller::with_start_time).
+    pub fn with_start_time(mut self, start_time: SystemTime) -> Self {
+        self.inner = self.inner.with_start_time(start_time);
+        self
+    }
+
+    /// See [`Controller::with_rng`](crate::controller::Controller::with_rng).
+    pub fn with_rng(mut self, rng: Arc<dyn RngSource>) -> Self {
+        self.inner = self.inner.with_rng(rng);
+        self
+    }
+
+    /// See [`Controller::with_control_audit`](crate::controller::Controller::with_control_audit).
+    pub fn with_control_audit(mut self) -> Self {
+        self.inner = self.inner.with_control_audit();
+        self
+    }
+
+    /// See [`Controller::with_reflect_octets`](crate::controller::Controller::with_reflect_octets).
+    pub fn with_reflect_octets(mut self, reflect_octets: ReflectOctets) -> Self {
+        self.inner = self.inner.with_reflect_octets(reflect_octets);
+        self
+    }
+
+    /// See [`Controller::with_memory_budget`](crate::controller::Controller::with_memory_budget).
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.inner = self.inner.with_memory_budget(bytes);
+        self
+    }
+
+    /// See [`Controller::with_max_test_duration`](crate::controller::Controller::with_max_test_duration).
+    pub fn with_max_test_duration(mut self, max_test_duration: Duration) -> Self {
+        self.inner = self.inner.with_max_test_duration(max_test_duration);
+        self
+    }
+
+    /// See [`Controller::with_compliance`](crate::controller::Controller::with_compliance).
+    pub fn with_compliance(mut self, compliance: Compliance) -> Self {
+        self.inner = self.inner.with_compliance(compliance);
+        self
+    }
+
+    /// See
+    /// [`Controller::with_timestamp_format`](crate::controller::Controller::with_timestamp_format).
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.inner = self.inner.with_timestamp_format(timestamp_format);
+        self
+    }
+
+    /// See [`Controller::with_padding_pattern`](crate::controller::Controller::with_padding_pattern).
+    pub fn with_padding_pattern(mut self, pattern: PaddingPattern) -> Self {
+        self.inner = self.inner.with_padding_pattern(pattern);
+        self
+    }
+
+    /// See
+    /// [`Controller::with_reflector_counter`](crate::controller::Controller::with_reflector_counter).
+    pub fn with_reflector_counter(mut self) -> Self {
+        self.inner = self.inner.with_reflector_counter();
+        self
+    }
+
+    /// See [`Controller::with_flow_label`](crate::controller::Controller::with_flow_label).
+    pub fn with_flow_label(mut self, flow_label: FlowLabel) -> Self {
+        self.inner = self.inner.with_flow_label(flow_label);
+        self
+    }
+
+    /// See
+    /// [`Controller::with_number_of_packets_announced`](crate::controller::Controller::with_number_of_packets_announced).
+    pub fn with_number_of_packets_announced(mut self) -> Self {
+        self.inner = self.inner.with_number_of_packets_announced();
+        self
+    }
+
+    /// See
+    /// [`Controller::with_reflector_address`](crate::controller::Controller::with_reflector_address).
+    pub fn with_reflector_address(mut self, reflector_address: IpAddr) -> Self {
+        self.inner = self.inner.with_reflector_address(reflector_address);
+        self
+    }
+
+    /// See
+    /// [`Controller::with_pacing_calibration`](crate::controller::Controller::with_pacing_calibration).
+    pub fn with_pacing_calibration(mut self) -> Self {
+        self.inner = self.inner.with_pacing_calibration();
+        self
+    }
+
+    /// See [`Controller::with_sequence_start`](crate::controller::Controller::with_sequence_start).
+    pub fn with_sequence_start(mut self, sequence_start: SequenceStart) -> Self {
+        self.inner = self.inner.with_sequence_start(sequence_start);
+        self
+    }
+
+    /// See [`Controller::with_stamp_tlvs`](crate::controller::Controller::with_stamp_tlvs).
+    pub fn with_stamp_tlvs(mut self, tlvs: Vec<Tlv>) -> Self {
+        self.inner = self.inner.with_stamp_tlvs(tlvs);
+        self
+    }
+
+    /// See [`Controller::on_measurement`](crate::controller::Controller::on_measurement). The
+    /// callback runs on the `Controller`'s private runtime.
+    pub fn on_measurement(mut self, callback: MeasurementCallback) -> Self {
+        self.inner = self.inner.on_measurement(callback);
+        self
+    }
+
+    /// Blocking version of [`Controller::negotiate`](crate::controller::Controller::negotiate).
+    pub fn negotiate(
+        self,
+        responder_addr: IpAddr,
+        responder_port: u16,
+        controller_addr: IpAddr,
+        controller_port: u16,
+        responder_reflect_port: u16,
+        reflector_timeout: SessionTimeout,
+    ) -> Result<NegotiationReport> {
+        self.runtime.block_on(self.inner.negotiate(
+            responder_addr,
+            responder_port,
+            controller_addr,
+            controller_port,
+            responder_reflect_port,
+            reflector_timeout,
+        ))
+    }
+
+    /// Blocking version of [`Controller::do_twamp`](crate::controller::Controller::do_twamp).
+    #[allow(clippy::too_many_arguments)]
+    pub fn do_twamp(
+        self,
+        responder_addr: IpAddr,
+        responder_port: u16,
+        controller_addr: IpAddr,
+        controller_port: u16,
+        responder_reflect_port: u16,
+        number_of_test_packets: u32,
+        reflector_timeout: SessionTimeout,
+        stop_policy: StopPolicy,
+    ) -> Result<TestReport> {
+        self.runtime.block_on(self.inner.do_twamp(
+            responder_addr,
+            responder_port,
+            controller_addr,
+            controller_port,
+            responder_reflect_port,
+            number_of_test_packets,
+            reflector_timeout,
+            stop_policy,
+        ))
+    }
+
+    /// Blocking version of [`Controller::do_stamp`](crate::controller::Controller::do_stamp).
+    pub fn do_stamp(
+        self,
+        reflector_addr: SocketAddr,
+        controller_addr: IpAddr,
+        controller_port: u16,
+        number_of_test_packets: u32,
+        stop_policy: StopPolicy,
+    ) -> Result<TestReport> {
+        self.runtime.block_on(self.inner.do_stamp(
+            reflector_addr,
+            controller_addr,
+            controller_port,
+            number_of_test_packets,
+            stop_policy,
+        ))
+    }
+
+    /// Blocking version of [`Controller::do_light`](crate::controller::Controller::do_light).
+    pub fn do_light(
+        self,
+        reflector_addr: SocketAddr,
+        controller_addr: IpAddr,
+        controller_port: u16,
+        number_of_test_packets: u32,
+        stop_policy: StopPolicy,
+    ) -> Result<TestReport> {
+        self.runtime.block_on(self.inner.do_light(
+            reflector_addr,
+            controller_addr,
+            controller_port,
+            number_of_test_packets,
+            stop_policy,
+        ))
+    }
+}
+
+/// Blocking wrapper around [`Responder`](crate::responder::Responder).
+///
+/// # Example
+///
+/// ```no_run
+/// use std::net::TcpListener;
+/// use twamp_control::timers::Refwait;
+/// use twamp_rs::blocking::Responder;
+///
+/// let listener = TcpListener::bind("127.0.0.1:862").unwrap();
+/// for stream in listener.incoming() {
+///     let responder = Responder::new(stream.unwrap()).unwrap();
+///     responder.handle_controller(Refwait::DEFAULT).unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Responder {
+    inner: crate::responder::Responder,
+    runtime: Runtime,
+}
+
+impl Responder {
+    /// Create a `Responder` for an accepted TWAMP-Control connection.
+    pub fn new(socket: TcpStream) -> Result<Self> {
+        let runtime = new_runtime()?;
+        socket.set_nonblocking(true)?;
+        let socket = {
+            // Registering the socket with the reactor requires being inside the runtime.
+            let _guard = runtime.enter();
+            twamp_runtime::net::TcpStream::from_std(socket)?
+        };
+        Ok(Responder {
+            inner: crate::responder::Responder::new(socket),
+            runtime,
+        })
+    }
+
+    /// See [`Responder::accounting`](crate::responder::Responder::accounting).
+    pub fn accounting(&self) -> Arc<Accounting> {
+        self.inner.accounting()
+    }
+
+    /// See [`Responder::with_reflector_config`](crate::responder::Responder::with_reflector_config).
+    pub fn with_reflector_config(mut self, reflector_config: ReflectorConfig) -> Self {
+        self.inner = self.inner.with_reflector_config(reflector_config);
+        self
+    }
+
+    /// See [`Responder::with_port_allocator`](crate::responder::Responder::with_port_allocator).
+    pub fn with_port_allocator(mut self, port_allocator: Arc<dyn PortAllocator>) -> Self {
+        self.inner = self.inner.with_port_allocator(port_allocator);
+        self
+    }
+
+    /// See [`Responder::with_server_config`](crate::responder::Responder::with_server_config).
+    pub fn with_server_config(mut self, server_config: ServerConfig) -> Self {
+        self.inner = self.inner.with_server_config(server_config);
+        self
+    }
+
+    /// See [`Responder::with_rng`](crate::responder::Responder::with_rng).
+    pub fn with_rng(mut self, rng: Arc<dyn RngSource>) -> Self {
+        self.inner = self.inner.with_rng(rng);
+        self
+    }
+
+    /// Blocking version of
+    /// [`Responder::handle_controller`](crate::responder::Responder::handle_controller).
+    pub fn handle_controller(self, refwait: Refwait) -> Result<ReflectorSummary> {
+        self.runtime.block_on(self.inner.handle_controller(refwait))
+    }
+}
+
+fn new_runtime() -> Result<Runtime> {
+    Ok(Builder::new_current_thread().enable_all().build()?)
+}
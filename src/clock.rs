@@ -0,0 +1,71 @@
+//! Quality of the local clock, which one-way delays depend on.
+
+use std::time::Duration;
+
+/// Synchronization state of the local clock, as would be advertised in the S bit and estimate of
+/// an [`ErrorEstimate`](twamp_test::error_estimate::ErrorEstimate).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClockStatus {
+    /// Whether the clock is synchronized to an external source, e.g. by NTP.
+    pub synchronized: bool,
+
+    /// Estimated error of the clock, if known.
+    pub estimated_error: Option<Duration>,
+}
+
+impl ClockStatus {
+    /// Ask the kernel for the clock status. `None` on platforms where it can't be queried.
+    #[cfg(target_os = "linux")]
+    pub fn query() -> Option<Self> {
+        // SAFETY: `timex` is plain data, and `adjtimex` with zero `modes` only reads.
+        let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+        let state = unsafe { libc::adjtimex(&mut timex) };
+        if state == -1 {
+            return None;
+        }
+        let synchronized = state != libc::TIME_ERROR && timex.status & libc::STA_UNSYNC == 0;
+        Some(ClockStatus {
+            synchronized,
+            estimated_error: synchronized
+                .then(|| Duration::from_micros(timex.esterror.max(0) as u64)),
+        })
+    }
+
+    /// Ask the kernel for the clock status. `None` on platforms where it can't be queried.
+    #[cfg(not(target_os = "linux"))]
+    pub fn query() -> Option<Self> {
+        None
+    }
+}
+
+/// What the [`Controller`](crate::controller::Controller) does about the local clock before
+/// starting a test.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClockPolicy {
+    /// Don't check the clock.
+    #[default]
+    Ignore,
+
+    /// Record the [`ClockStatus`] in the report.
+    Annotate,
+
+    /// Record the [`ClockStatus`] in the report, and log a warning if the clock is not
+    /// synchronized.
+    Warn,
+
+    /// Record the [`ClockStatus`] in the report, and refuse to start the test if the clock is
+    /// not known to be synchronized.
+    Refuse,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn query_clock_on_linux() {
+        let status = ClockStatus::query().unwrap();
+        assert_eq!(status.estimated_error.is_some(), status.synchronized);
+    }
+}
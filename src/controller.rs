@@ -0,0 +1,1644 @@
+use std::{
+    future::Future,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::RangeInclusive,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+use control_client::{audit::ControlTimeline, error::ControlClientError, ControlClient};
+pub use control_client::{error::ControlMessage, ConnectRetry, StartRetry};
+use rand::Rng;
+pub use session_sender::sequence::SequenceStart;
+use session_sender::{
+    measurement::MeasurementCallback, pacing::Calibration, PacketProfile, SessionSender, Train,
+    AUTH_PADDING_LENGTH,
+};
+use timestamp::timestamp::{TimeStamp, TimestampFormat};
+use tokio::{
+    sync::{oneshot, Mutex},
+    try_join,
+};
+use tracing::*;
+pub use twamp_control::timers::SessionTimeout;
+use twamp_control::{
+    accept::Accept,
+    auth::SharedSecret,
+    compliance::Compliance,
+    constants::{TRACING_TARGET as CONTROL_TARGET, TWAMP_CONTROL_WELL_KNOWN_PORT},
+    rng::RngSource,
+    security_mode::Mode,
+    timers::Refwait,
+};
+use twamp_runtime::{
+    net::UdpSocket,
+    task::{spawn, JoinHandle},
+    time::{sleep, timeout, timeout_at, Instant},
+};
+use twamp_test::{
+    constants::{TRACING_TARGET as TEST_TARGET, TWAMP_TEST_WELL_KNOWN_PORT},
+    ecn::Ecn,
+    flow_label::{lease_flow_label, with_flow_label, FlowLabel},
+    keys::TestKeys,
+    padding::PaddingPattern,
+    reflect_octets::ReflectOctets,
+    stamp::StampTestPacket,
+    stamp_tlv::Tlv,
+    twamp_test_auth::TwampTestPacketAuth,
+    twamp_test_unauth::TwampTestPacketUnauth,
+    twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
+};
+
+use crate::{
+    clock::{ClockPolicy, ClockStatus},
+    report::{ControlMetadata, NegotiationReport, TerminationReason, TestParameters, TestReport},
+};
+
+/// When the Controller has Control-Client send Stop-Sessions, counted from the moment the last
+/// test packet has been sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopPolicy {
+    /// Send Stop-Sessions right away. Packets still in flight are counted as lost.
+    Immediate,
+
+    /// Wait until every test packet has been reflected back. Never stops if any are lost.
+    AfterAllReflected,
+
+    /// Wait until every test packet has been reflected back, or until the duration has elapsed.
+    AfterTimeout(Duration),
+}
+
+impl Default for StopPolicy {
+    fn default() -> Self {
+        StopPolicy::AfterTimeout(Duration::from_secs(5))
+    }
+}
+
+impl StopPolicy {
+    /// Wait according to the policy for `recv_task` to collect reflected packets. The task is
+    /// aborted if the policy gives up on it.
+    async fn drain(self, mut recv_task: JoinHandle<()>) {
+        match self {
+            StopPolicy::Immediate => (),
+            StopPolicy::AfterAllReflected => {
+                let _ = (&mut recv_task).await;
+            }
+            StopPolicy::AfterTimeout(duration) => {
+                let _ = timeout(duration, &mut recv_task).await;
+            }
+        }
+        recv_task.abort();
+    }
+}
+
+/// How long to wait for sent test packets to leave the host before Stop-Sessions.
+const SEND_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Number of times the timer is probed when [calibrating pacing](Controller::with_pacing_calibration).
+const PACING_CALIBRATION_SAMPLES: u32 = 16;
+
+/// How long past the [maximum test duration](Controller::with_max_test_duration) TWAMP-Control
+/// is given to send Stop-Sessions.
+const STOP_SESSIONS_GRACE: Duration = Duration::from_secs(1);
+
+/// Run `future` to completion, or until `deadline` if there is one.
+async fn until<F: Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// How long the Session-Reflector will keep reflecting after Stop-Sessions when asked for
+/// `reflector_timeout`. Warns above the RFC's REFWAIT, since Session-Reflectors may end the
+/// session before then anyway.
+pub fn check_reflector_timeout(reflector_timeout: SessionTimeout) -> Duration {
+    if reflector_timeout.as_duration() > Refwait::DEFAULT.as_duration() {
+        warn!(
+            target: CONTROL_TARGET,
+            reflector_timeout = reflector_timeout.secs(),
+            refwait = Refwait::DEFAULT.secs(),
+            "Timeout is above the usual REFWAIT, Session-Reflectors may not wait that long"
+        );
+    }
+    reflector_timeout.as_duration()
+}
+
+/// `addr` carrying the leased `flow_label`, if any, to connect the Session-Sender's socket to.
+fn labeled(addr: SocketAddr, flow_label: Option<u32>) -> SocketAddr {
+    match (addr, flow_label) {
+        (SocketAddr::V6(addr), Some(label)) => with_flow_label(addr, label).into(),
+        _ => addr,
+    }
+}
+
+/// Number of random ports tried by [`SenderPortPolicy::Random`] before giving up.
+const RANDOM_PORT_ATTEMPTS: usize = 16;
+
+/// How the Session-Sender's UDP port is chosen for each test.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum SenderPortPolicy {
+    /// Use the `controller_port` passed to [`Controller::do_twamp`], where 0 lets the OS pick.
+    #[default]
+    Fixed,
+
+    /// Pick a fresh random port from the range for every test, so load balancers that cache
+    /// per flow don't pin repeated measurements to the same path.
+    Random(RangeInclusive<u16>),
+}
+
+impl SenderPortPolicy {
+    /// Bind the Session-Sender's socket on `addr` according to the policy.
+    async fn bind(&self, addr: IpAddr, port: u16) -> Result<UdpSocket> {
+        let SenderPortPolicy::Random(range) = self else {
+            return Ok(UdpSocket::bind(SocketAddr::new(addr, port)).await?);
+        };
+        if range.is_empty() {
+            return Err(anyhow!("Sender port range {:?} is empty", range));
+        }
+        for _ in 0..RANDOM_PORT_ATTEMPTS {
+            let port = rand::thread_rng().gen_range(range.clone());
+            match UdpSocket::bind(SocketAddr::new(addr, port)).await {
+                Ok(socket) => return Ok(socket),
+                Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(anyhow!("No free sender port found in {:?}", range))
+    }
+}
+
+/// Where and how long to test, as passed to [`Controller::do_twamp`], for [`crate::run`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControllerConfig {
+    pub responder_addr: IpAddr,
+
+    /// TWAMP-Control port of the Responder.
+    pub responder_port: u16,
+
+    /// Address for the Session-Sender to bind to.
+    pub controller_addr: IpAddr,
+
+    /// Port for the Session-Sender to bind to, 0 to let the OS pick.
+    pub controller_port: u16,
+
+    /// Port to ask the Session-Reflector to reflect from.
+    pub responder_reflect_port: u16,
+
+    pub number_of_test_packets: u32,
+
+    /// How long the Session-Reflector keeps reflecting after Stop-Sessions, see
+    /// [`check_reflector_timeout`].
+    pub reflector_timeout: SessionTimeout,
+
+    pub stop_policy: StopPolicy,
+}
+
+impl ControllerConfig {
+    /// Test `responder_addr` on the well-known ports with 10 test packets.
+    pub fn new(responder_addr: IpAddr) -> Self {
+        ControllerConfig {
+            responder_addr,
+            responder_port: TWAMP_CONTROL_WELL_KNOWN_PORT,
+            controller_addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            controller_port: 0,
+            responder_reflect_port: TWAMP_TEST_WELL_KNOWN_PORT,
+            number_of_test_packets: 10,
+            reflector_timeout: SessionTimeout::DEFAULT,
+            stop_policy: StopPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Controller {
+    control_client: ControlClient,
+    train: Option<Train>,
+    on_measurement: Option<MeasurementCallback>,
+    symmetric_ports: bool,
+    profiles: Vec<PacketProfile>,
+    clock_policy: ClockPolicy,
+    ecn_marking: Ecn,
+    sender_port_policy: SenderPortPolicy,
+    start_time: Option<SystemTime>,
+    memory_budget: Option<usize>,
+    reflect_octets: Option<ReflectOctets>,
+    max_test_duration: Option<Duration>,
+    sequence_start: SequenceStart,
+    compliance: Compliance,
+    announce_number_of_packets: bool,
+    calibrate_pacing: bool,
+    reflector_address: Option<IpAddr>,
+    stamp_tlvs: Vec<Tlv>,
+    timestamp_format: TimestampFormat,
+    padding_pattern: PaddingPattern,
+    reflector_counter: bool,
+    flow_label: Option<FlowLabel>,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Controller {
+            control_client: ControlClient::default(),
+            train: None,
+            on_measurement: None,
+            symmetric_ports: false,
+            profiles: Vec::new(),
+            clock_policy: ClockPolicy::default(),
+            ecn_marking: Ecn::NotEct,
+            sender_port_policy: SenderPortPolicy::default(),
+            start_time: None,
+            memory_budget: None,
+            reflect_octets: None,
+            max_test_duration: None,
+            sequence_start: SequenceStart::Zero,
+            compliance: Compliance::Strict,
+            announce_number_of_packets: false,
+            calibrate_pacing: false,
+            reflector_address: None,
+            stamp_tlvs: Vec::new(),
+            timestamp_format: TimestampFormat::Ntp,
+            padding_pattern: PaddingPattern::Zeros,
+            reflector_counter: false,
+            flow_label: None,
+        }
+    }
+
+    /// Send test packets in trains, and include [per-train results](TestReport::trains) in the
+    /// report.
+    pub fn with_train(mut self, train: Train) -> Self {
+        self.train = Some(train);
+        self
+    }
+
+    /// Send test packets under each of `profiles` in turn, back-to-back within the one session,
+    /// and include [per-profile results](TestReport::profiles) in the report. The number of
+    /// test packets passed to [`do_twamp`](Self::do_twamp) is then ignored.
+    ///
+    /// A session has a single DSCP, so all profiles must use the same one.
+    pub fn with_profiles(mut self, profiles: Vec<PacketProfile>) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    /// Check the local clock before starting the test, see [`ClockPolicy`].
+    pub fn with_clock_policy(mut self, clock_policy: ClockPolicy) -> Self {
+        self.clock_policy = clock_policy;
+        self
+    }
+
+    /// Choose the Session-Sender's port according to `sender_port_policy`. Ignored with
+    /// [symmetric ports](Self::with_symmetric_ports), which fix the port.
+    pub fn with_sender_port_policy(mut self, sender_port_policy: SenderPortPolicy) -> Self {
+        self.sender_port_policy = sender_port_policy;
+        self
+    }
+
+    /// Mark test packets with an ECN codepoint, see [`SessionSender::with_ecn_marking`].
+    pub fn with_ecn_marking(mut self, ecn: Ecn) -> Self {
+        self.ecn_marking = ecn;
+        self
+    }
+
+    /// Send test packets from the same port they are reflected to, for paths where a
+    /// middlebox only allows symmetric ports. The UDP socket is bound to
+    /// `responder_reflect_port`, and the test is aborted before Start-Sessions if the
+    /// Server does not accept that port.
+    pub fn with_symmetric_ports(mut self) -> Self {
+        self.control_client = self.control_client.with_symmetric_ports();
+        self.symmetric_ports = true;
+        self
+    }
+
+    /// Retry Start-Sessions if the Server is temporarily out of resources, see [`StartRetry`].
+    pub fn with_start_retry(mut self, start_retry: StartRetry) -> Self {
+        self.control_client = self.control_client.with_start_retry(start_retry);
+        self
+    }
+
+    /// Retry connecting to the Server if it refuses or doesn't answer, e.g. while it restarts,
+    /// see [`ConnectRetry`].
+    pub fn with_connect_retry(mut self, connect_retry: ConnectRetry) -> Self {
+        self.control_client = self.control_client.with_connect_retry(connect_retry);
+        self
+    }
+
+    /// Refuse Servers whose greeting asks for a Count above `max_count`, see
+    /// [`ControlClient::with_max_count`].
+    pub fn with_max_count(mut self, max_count: u32) -> Self {
+        self.control_client = self.control_client.with_max_count(max_count);
+        self
+    }
+
+    /// Give up if the Server takes longer than `read_timeout` to send `message`, see
+    /// [`ControlClient::with_read_timeout`].
+    pub fn with_read_timeout(mut self, message: ControlMessage, read_timeout: Duration) -> Self {
+        self.control_client = self.control_client.with_read_timeout(message, read_timeout);
+        self
+    }
+
+    /// Give up if Accept-Session takes longer than `accept_session_timeout`, see
+    /// [`ControlClient::with_accept_session_timeout`].
+    pub fn with_accept_session_timeout(mut self, accept_session_timeout: Duration) -> Self {
+        self.control_client = self
+            .control_client
+            .with_accept_session_timeout(accept_session_timeout);
+        self
+    }
+
+    /// Use authenticated mode with `shared_secret`, see [`ControlClient::with_shared_secret`].
+    /// Test packets are then authenticated too.
+    pub fn with_shared_secret(mut self, shared_secret: SharedSecret) -> Self {
+        self.control_client = self.control_client.with_shared_secret(shared_secret);
+        self
+    }
+
+    /// Use encrypted mode rather than authenticated mode with the
+    /// [shared secret](Self::with_shared_secret), see [`ControlClient::with_encryption`].
+    pub fn with_encryption(mut self) -> Self {
+        self.control_client = self.control_client.with_encryption();
+        self
+    }
+
+    /// Choose the first of `modes` the Server offers, see
+    /// [`ControlClient::with_mode_preference`].
+    pub fn with_mode_preference(mut self, modes: &[Mode]) -> Self {
+        self.control_client = self.control_client.with_mode_preference(modes);
+        self
+    }
+
+    /// Start the session at `start_time` rather than straight away. It is sent as the
+    /// Start-Time of Request-TW-Session, and test packets are only sent from then on. The test
+    /// fails if `start_time` has passed by the time sessions are started, see
+    /// [`ControlClient::with_start_time`].
+    pub fn with_start_time(mut self, start_time: SystemTime) -> Self {
+        self.control_client = self.control_client.with_start_time(start_time);
+        self.start_time = Some(start_time);
+        self
+    }
+
+    /// Draw the session keys and Client-IV from `rng`, see [`ControlClient::with_rng`].
+    pub fn with_rng(mut self, rng: Arc<dyn RngSource>) -> Self {
+        self.control_client = self.control_client.with_rng(rng);
+        self
+    }
+
+    /// Keep a timeline of every TWAMP-Control message in [`ControlMetadata::timeline`], see
+    /// [`ControlClient::with_audit`]. If negotiation fails, the timeline is attached to the
+    /// error as a [`ControlTimeline`].
+    pub fn with_control_audit(mut self) -> Self {
+        self.control_client = self.control_client.with_audit();
+        self
+    }
+
+    /// Keep reflected packets within about `bytes` of memory. Once that many are stored, the
+    /// rest are only aggregated as they arrive and the report is built from those aggregates,
+    /// flagged with [`TestReport::memory_budget_exceeded`].
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Ask the Session-Reflector to reflect octets placed in the padding of test packets, as in
+    /// [RFC 6038](https://datatracker.ietf.org/doc/html/rfc6038#section-4.2), and count those
+    /// not carried back in [`TestReport::reflected_octets_mismatched`]. Unauthenticated
+    /// TWAMP-Test only.
+    pub fn with_reflect_octets(mut self, reflect_octets: ReflectOctets) -> Self {
+        self.control_client = self.control_client.with_reflect_octets(reflect_octets);
+        self.reflect_octets = Some(reflect_octets);
+        self
+    }
+
+    /// Bound the whole of [`do_twamp`](Self::do_twamp), from connecting to the Server until the
+    /// reflected packets are drained, by `max_test_duration`. When it elapses during the test,
+    /// sending stops, Stop-Sessions is sent and the report covers the test packets sent so far,
+    /// with [`TerminationReason::Truncated`]. Failing to start the sessions in time is an error.
+    pub fn with_max_test_duration(mut self, max_test_duration: Duration) -> Self {
+        self.max_test_duration = Some(max_test_duration);
+        self
+    }
+
+    /// Parse TWAMP-Control messages and reflected packets according to `compliance`, e.g. to
+    /// test against Responders that put garbage in MBZ fields.
+    pub fn with_compliance(mut self, compliance: Compliance) -> Self {
+        self.control_client = self.control_client.with_compliance(compliance);
+        self.compliance = compliance;
+        self
+    }
+
+    /// Announce the number of test packets to be sent in Request-TW-Session rather than zero,
+    /// for servers also speaking OWAMP that refuse sessions without a count. See
+    /// [`ControlClient::with_number_of_packets`].
+    pub fn with_number_of_packets_announced(mut self) -> Self {
+        self.announce_number_of_packets = true;
+        self
+    }
+
+    /// Ask for the Session-Reflector at `reflector_address` rather than at the Responder's
+    /// address, e.g. a test interface of the Responder's host or another host, and send test
+    /// packets there. See [`ControlClient::with_receiver_address`].
+    pub fn with_reflector_address(mut self, reflector_address: IpAddr) -> Self {
+        self.control_client = self.control_client.with_receiver_address(reflector_address);
+        self.reflector_address = Some(reflector_address);
+        self
+    }
+
+    /// Measure how late the runtime's timer wakes up before the test, and shorten the waits
+    /// between test packets of [profiles](Self::with_profiles) with a non-zero interval to
+    /// make up for it. The intervals achieved are in [`TestReport::pacing`] either way.
+    pub fn with_pacing_calibration(mut self) -> Self {
+        self.calibrate_pacing = true;
+        self
+    }
+
+    /// Number the test packets of the session from `sequence_start` rather than zero. Reports
+    /// still count test packets from zero, while [measurements](Self::on_measurement) carry
+    /// the Sequence Numbers sent.
+    pub fn with_sequence_start(mut self, sequence_start: SequenceStart) -> Self {
+        self.sequence_start = sequence_start;
+        self
+    }
+
+    /// Timestamp TWAMP-Test packets in `timestamp_format`, see
+    /// [`SessionSender::with_timestamp_format`]. With TWAMP-Control, Servers not advertising
+    /// PTP timestamps are refused, see [`ControlClient::with_timestamp_format`].
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.control_client = self.control_client.with_timestamp_format(timestamp_format);
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    /// Fill the padding of TWAMP-Test packets with `pattern`, see
+    /// [`SessionSender::with_padding_pattern`].
+    pub fn with_padding_pattern(mut self, pattern: PaddingPattern) -> Self {
+        self.padding_pattern = pattern;
+        self
+    }
+
+    /// Read the Session-Reflector's count of processed test packets into
+    /// [measurements](Self::on_measurement), see [`SessionSender::with_reflector_counter`].
+    pub fn with_reflector_counter(mut self) -> Self {
+        self.reflector_counter = true;
+        self
+    }
+
+    /// Send test packets with an IPv6 flow label, leased afresh for every session so a
+    /// [random](FlowLabel::Random) one sends each down whichever equal-cost path it hashes to.
+    /// The label is recorded in the [report](TestParameters::flow_label). Tests to IPv4
+    /// Session-Reflectors fail.
+    pub fn with_flow_label(mut self, flow_label: FlowLabel) -> Self {
+        self.flow_label = Some(flow_label);
+        self
+    }
+
+    /// Follow the test packets of [`Self::do_stamp`] with `tlvs`, see
+    /// [`SessionSender::with_stamp_tlvs`].
+    pub fn with_stamp_tlvs(mut self, tlvs: Vec<Tlv>) -> Self {
+        self.stamp_tlvs = tlvs;
+        self
+    }
+
+    /// Invoke `callback` for every reflected packet as it is received, in addition to
+    /// producing the [`TestReport`] at the end of the test.
+    pub fn on_measurement(mut self, callback: MeasurementCallback) -> Self {
+        self.on_measurement = Some(callback);
+        self
+    }
+
+    /// Query the local clock according to the [`ClockPolicy`], failing if it refuses the clock.
+    fn check_clock(&self) -> Result<Option<ClockStatus>> {
+        if self.clock_policy == ClockPolicy::Ignore {
+            return Ok(None);
+        }
+        let status = ClockStatus::query();
+        let synchronized = status.is_some_and(|status| status.synchronized);
+        debug!(target: TEST_TARGET, ?status, "Checked local clock");
+        match self.clock_policy {
+            ClockPolicy::Warn if !synchronized => {
+                warn!(
+                    target: TEST_TARGET,
+                    "Local clock is not known to be synchronized, one-way delays may be off"
+                );
+            }
+            ClockPolicy::Refuse if !synchronized => {
+                return Err(anyhow!("Local clock is not known to be synchronized"));
+            }
+            _ => (),
+        }
+        Ok(status)
+    }
+
+    /// Bind the Session-Sender's socket according to the [`SenderPortPolicy`] and
+    /// [symmetric ports](Self::with_symmetric_ports). An unspecified IPv4 `controller_addr`
+    /// stands for any local address of the Responder's family.
+    async fn bind_sender(
+        &self,
+        responder_addr: IpAddr,
+        controller_addr: IpAddr,
+        mut controller_port: u16,
+        responder_reflect_port: u16,
+    ) -> Result<UdpSocket> {
+        let controller_addr = match (controller_addr, responder_addr) {
+            (IpAddr::V4(addr), IpAddr::V6(_)) if addr.is_unspecified() => {
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+            }
+            _ => controller_addr,
+        };
+        let mut sender_port_policy = self.sender_port_policy.clone();
+        if self.symmetric_ports {
+            if responder_reflect_port == 0 {
+                return Err(anyhow!("Symmetric ports need a non-zero reflect port"));
+            }
+            controller_port = responder_reflect_port;
+            sender_port_policy = SenderPortPolicy::Fixed;
+        }
+        let udp_socket = sender_port_policy
+            .bind(controller_addr, controller_port)
+            .await?;
+        debug!(
+            target: TEST_TARGET,
+            port = udp_socket.local_addr()?.port(),
+            "Bound Session-Sender"
+        );
+        Ok(udp_socket)
+    }
+
+    /// Lease the [flow label](Self::with_flow_label) of the session, if any, for the
+    /// Session-Sender's socket.
+    fn lease_flow_label(
+        &self,
+        udp_socket: &UdpSocket,
+        reflector_addr: IpAddr,
+    ) -> Result<Option<u32>> {
+        let Some(flow_label) = self.flow_label else {
+            return Ok(None);
+        };
+        let IpAddr::V6(reflector_addr) = reflector_addr else {
+            return Err(anyhow!("Flow labels need an IPv6 Session-Reflector"));
+        };
+        let label = lease_flow_label(udp_socket, reflector_addr, flow_label)?;
+        debug!(target: TEST_TARGET, label = format_args!("{:#07x}", label), "Leased flow label");
+        Ok(Some(label))
+    }
+
+    /// Timer lateness to correct the pacing of `profiles` by, if
+    /// [calibrating](Self::with_pacing_calibration) and any is paced.
+    async fn calibrate(&self, profiles: &[PacketProfile]) -> Calibration {
+        let shortest_interval = profiles
+            .iter()
+            .map(|profile| profile.interval)
+            .filter(|interval| !interval.is_zero())
+            .min();
+        match shortest_interval {
+            Some(probe) if self.calibrate_pacing => {
+                let calibration = Calibration::measure(probe, PACING_CALIBRATION_SAMPLES).await;
+                debug!(target: TEST_TARGET, ?probe, lateness = ?calibration.lateness, "Calibrated pacing");
+                calibration
+            }
+            _ => Calibration::default(),
+        }
+    }
+
+    /// Negotiate a session on TWAMP-Control from the Server Greeting through Accept-Session,
+    /// then close the connection without sending test packets. Checks the Responder is
+    /// reachable and accepts the session as [`do_twamp`](Self::do_twamp) would request it,
+    /// without the cost of a test.
+    pub async fn negotiate(
+        mut self,
+        responder_addr: IpAddr,
+        responder_port: u16,
+        controller_addr: IpAddr,
+        controller_port: u16,
+        responder_reflect_port: u16,
+        reflector_timeout: SessionTimeout,
+    ) -> Result<NegotiationReport> {
+        check_reflector_timeout(reflector_timeout);
+        let twamp_control = self
+            .control_client
+            .connect(SocketAddr::new(responder_addr, responder_port))
+            .await?;
+        // Bound so the Sender Port asked for is one nothing else holds.
+        let udp_socket = self
+            .bind_sender(
+                self.reflector_address.unwrap_or(responder_addr),
+                controller_addr,
+                controller_port,
+                responder_reflect_port,
+            )
+            .await?;
+        let sender_port = udp_socket.local_addr()?.port();
+        let result: Result<()> = async {
+            self.control_client.set_up(twamp_control).await?;
+            let accept_session = self
+                .control_client
+                .request_session(responder_reflect_port, sender_port, reflector_timeout)
+                .await?;
+            if accept_session.accept != Accept::Ok {
+                return Err(ControlClientError::SessionRejected {
+                    msg_type: "Accept-Session",
+                    accept: accept_session.accept,
+                }
+                .into());
+            }
+            Ok(())
+        }
+        .await;
+        let timeline = self.control_client.timeline();
+        match result {
+            Err(e) if !timeline.is_empty() => {
+                return Err(e.context(ControlTimeline(timeline.to_vec())))
+            }
+            Err(e) => return Err(e),
+            Ok(()) => (),
+        }
+        info!(target: CONTROL_TARGET, "Session accepted, closing without testing");
+        let mut parameters = TestParameters::from(&self.control_client);
+        parameters.sender_port = sender_port;
+        Ok(NegotiationReport {
+            control: ControlMetadata::from(&self.control_client),
+            parameters,
+        })
+    }
+
+    /// Informs `Control-Client` to establish TCP connection with provided
+    /// `server_addr` and negotiate a TWAMP session. The `Controller` does
+    /// not walk `Control-Client` through the TWAMP-Control communication.
+    /// That is up to `Control-Client` to handle.
+    ///
+    /// TWAMP-Test runs over the same IP version as `responder_addr`. An unspecified IPv4
+    /// `controller_addr` also covers IPv6 Responders.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn do_twamp(
+        mut self,
+        responder_addr: IpAddr,
+        responder_port: u16,
+        controller_addr: IpAddr,
+        mut controller_port: u16,
+        responder_reflect_port: u16,
+        mut number_of_test_packets: u32,
+        reflector_timeout: SessionTimeout,
+        stop_policy: StopPolicy,
+    ) -> Result<TestReport> {
+        let deadline = self.max_test_duration.map(|max| Instant::now() + max);
+        let train = self.train;
+        let ecn_marking = self.ecn_marking;
+        let on_measurement = self.on_measurement.take();
+        let start_time = self.start_time;
+        let reflect_octets = self.reflect_octets;
+        let compliance = self.compliance;
+        let timestamp_format = self.timestamp_format;
+        let padding_pattern = self.padding_pattern;
+        let reflector_counter = self.reflector_counter;
+        let sequence_start = std::mem::take(&mut self.sequence_start);
+        let first_sequence_number = sequence_start.first_sequence_number()?;
+        let max_records = self
+            .memory_budget
+            .map(|bytes| bytes / size_of::<(TwampTestPacketUnauthReflected, TimeStamp)>());
+        let profiles = std::mem::take(&mut self.profiles);
+        if let Some(first) = profiles.first() {
+            if profiles.iter().any(|profile| profile.dscp != first.dscp) {
+                return Err(anyhow!("Packet profiles in one session must share a DSCP"));
+            }
+            number_of_test_packets = profiles.iter().map(|profile| profile.packets).sum();
+        }
+        if self.announce_number_of_packets {
+            self.control_client = self
+                .control_client
+                .with_number_of_packets(number_of_test_packets);
+        }
+        let reflector_wait = check_reflector_timeout(reflector_timeout);
+        debug!(
+            target: CONTROL_TARGET,
+            ?reflector_wait,
+            "Session-Reflector keeps reflecting this long after Stop-Sessions"
+        );
+        let clock = self.check_clock()?;
+        let sent_profiles = if profiles.is_empty() {
+            vec![PacketProfile::new(number_of_test_packets)]
+        } else {
+            profiles.clone()
+        };
+        let reflector_addr = self.reflector_address.unwrap_or(responder_addr);
+        let sent_padding = sent_profiles
+            .iter()
+            .map(|profile| profile.sent_padding_length(reflector_addr))
+            .max()
+            .unwrap_or_default();
+        self.control_client = self.control_client.with_padding_length(sent_padding);
+        let sent_dscp = sent_profiles.first().map_or(0, |profile| profile.dscp);
+        let calibration = self.calibrate(&sent_profiles).await;
+        let twamp_control = until(
+            deadline,
+            self.control_client
+                .connect(SocketAddr::new(responder_addr, responder_port)),
+        )
+        .await
+        .ok_or_else(|| anyhow!("No connection to the Server within the maximum test duration"))??;
+        let udp_socket = self
+            .bind_sender(
+                reflector_addr,
+                controller_addr,
+                controller_port,
+                responder_reflect_port,
+            )
+            .await?;
+        controller_port = udp_socket.local_addr()?.port();
+        let flow_label = self.lease_flow_label(&udp_socket, reflector_addr)?;
+
+        let (start_session_tx, start_session_rx) = oneshot::channel::<Option<TestKeys>>();
+        let (twamp_test_complete_tx, twamp_test_complete_rx) = oneshot::channel::<()>();
+        let (reflector_port_tx, reflector_port_rx) = oneshot::channel::<u16>();
+        let control_client_handle = spawn(async move {
+            let control = self.control_client.do_twamp_control(
+                twamp_control,
+                start_session_tx,
+                reflector_port_tx,
+                responder_reflect_port,
+                controller_port,
+                reflector_timeout,
+                twamp_test_complete_rx,
+            );
+            let result = until(deadline.map(|d| d + STOP_SESSIONS_GRACE), control)
+                .await
+                .unwrap_or_else(|| {
+                    Err(anyhow!(
+                        "TWAMP-Control did not finish within the maximum test duration"
+                    ))
+                });
+            let timeline = self.control_client.timeline();
+            match result {
+                Ok(()) => Ok((
+                    ControlMetadata::from(&self.control_client),
+                    TestParameters::from(&self.control_client),
+                )),
+                Err(e) if !timeline.is_empty() => {
+                    Err(e.context(ControlTimeline(timeline.to_vec())))
+                }
+                Err(e) => Err(e),
+            }
+        });
+        // Allocate up front so the vector does not outgrow the memory budget when resized.
+        let reflected_pkts_vec: Arc<Mutex<Vec<(TwampTestPacketUnauthReflected, TimeStamp)>>> =
+            Arc::new(Mutex::new(match max_records {
+                Some(max) => Vec::with_capacity(max.min(number_of_test_packets as usize)),
+                None => Vec::new(),
+            }));
+        let reflected_pkts_vec_cloned = Arc::clone(&reflected_pkts_vec);
+        let session_sender_handle = spawn(async move {
+            // Wait until we get the Accept-Session's port.
+            let Ok(final_port) = reflector_port_rx.await else {
+                return None;
+            };
+            debug!(target: TEST_TARGET, port = final_port, "Connecting Session-Sender");
+            udp_socket
+                .connect(labeled(
+                    SocketAddr::new(reflector_addr, final_port),
+                    flow_label,
+                ))
+                .await
+                .unwrap();
+            // Wait until start-sessions is received
+            let Ok(test_keys) = start_session_rx.await else {
+                return None;
+            };
+            debug!(target: TEST_TARGET, "Starting Session-Sender");
+            let mut session_sender = SessionSender::new(
+                Arc::new(udp_socket),
+                SocketAddr::new(reflector_addr, final_port),
+            )
+            .await
+            .with_ecn_marking(ecn_marking)
+            .with_first_sequence_number(first_sequence_number)
+            .with_compliance(compliance)
+            .with_timestamp_format(timestamp_format)
+            .with_padding_pattern(padding_pattern)
+            .with_pacing_calibration(calibration);
+            if let Some(train) = train {
+                session_sender = session_sender.with_train(train);
+            }
+            if reflector_counter {
+                session_sender = session_sender.with_reflector_counter();
+            }
+            if let Some(callback) = on_measurement {
+                session_sender = session_sender.with_measurement_callback(callback);
+            }
+            if let Some(test_keys) = test_keys {
+                session_sender = session_sender.with_test_keys(test_keys);
+            }
+            if let Some(max_records) = max_records {
+                session_sender = session_sender.with_max_records(max_records);
+            }
+            if let Some(reflect_octets) = reflect_octets {
+                session_sender = session_sender.with_reflect_octets(reflect_octets);
+            }
+            let session_sender = Arc::new(session_sender);
+            let session_sender_send = Arc::clone(&session_sender);
+            let session_sender_recv = Arc::clone(&session_sender);
+            let mut send_task = spawn(async move {
+                if let Some(wait) =
+                    start_time.and_then(|t| t.duration_since(SystemTime::now()).ok())
+                {
+                    debug!(target: TEST_TARGET, ?wait, "Waiting for Start-Time");
+                    sleep(wait).await;
+                }
+                let started_at = Instant::now();
+                let mut first_seq = 0;
+                for profile in &sent_profiles {
+                    if let Err(e) = session_sender_send.send_profile(first_seq, profile).await {
+                        warn!(target: TEST_TARGET, "Sending test packets failed: {}", e);
+                        break;
+                    }
+                    first_seq += profile.packets;
+                }
+                let send_duration = started_at.elapsed();
+                // Stop-Sessions must not overtake test packets still queued on this host.
+                match session_sender_send.flush(SEND_FLUSH_TIMEOUT).await {
+                    Ok(()) => info!(target: TEST_TARGET, "Sent all test packets"),
+                    Err(e) => warn!(target: TEST_TARGET, "Test packets not flushed: {}", e),
+                }
+                send_duration
+            });
+            let recv_task = spawn(async move {
+                let _ = session_sender_recv
+                    .recv(number_of_test_packets, reflected_pkts_vec_cloned)
+                    .await;
+                info!(target: TEST_TARGET, "Received all reflected packets");
+            });
+            let recv_abort = recv_task.abort_handle();
+            // wait for all test pkts to be sent.
+            let send_duration = match until(deadline, &mut send_task).await {
+                Some(send_duration) => Some(send_duration.unwrap()),
+                None => {
+                    send_task.abort();
+                    None
+                }
+            };
+            let truncated = match send_duration {
+                Some(_) => until(deadline, stop_policy.drain(recv_task))
+                    .await
+                    .is_none(),
+                None => true,
+            };
+            if truncated {
+                warn!(
+                    target: TEST_TARGET,
+                    sent = session_sender.packets_sent(),
+                    "Maximum test duration reached, stopping the test"
+                );
+                recv_abort.abort();
+            }
+            if let Err(e) = sequence_start.save(session_sender.next_sequence_number()) {
+                warn!(target: TEST_TARGET, "Sequence number not saved: {}", e);
+            }
+            // Inform Control-Client to send Stop-Sessions
+            let _ = twamp_test_complete_tx.send(());
+            Some((
+                send_duration,
+                truncated,
+                session_sender.packets_sent(),
+                session_sender.received_ecn(),
+                session_sender.running_stats(),
+                session_sender.reflected_octets_mismatched(),
+                session_sender.pacing(),
+            ))
+        });
+        let (control_result, sent) = try_join!(control_client_handle, session_sender_handle)?;
+        let (
+            send_duration,
+            truncated,
+            received_ecn,
+            running_stats,
+            reflected_octets_mismatched,
+            pacing,
+        ) = match sent {
+            Some((
+                send_duration,
+                truncated,
+                packets_sent,
+                received_ecn,
+                stats,
+                mismatched,
+                pacing,
+            )) => {
+                if truncated {
+                    number_of_test_packets = packets_sent;
+                }
+                (
+                    send_duration,
+                    truncated,
+                    Some(received_ecn),
+                    stats,
+                    mismatched,
+                    pacing,
+                )
+            }
+            None => (None, false, None, None, None, Vec::new()),
+        };
+        // Losing control after the test ran still leaves a report worth returning.
+        let ((control, mut parameters), termination) = match control_result {
+            Ok(negotiated) => (negotiated, TerminationReason::Completed),
+            Err(e) if received_ecn.is_some() => {
+                warn!(target: CONTROL_TARGET, "Control connection lost after test: {}", e);
+                (Default::default(), TerminationReason::ControlLost)
+            }
+            Err(e) => return Err(e),
+        };
+        let termination = if truncated {
+            TerminationReason::Truncated
+        } else {
+            termination
+        };
+        let authenticated = matches!(control.mode, Mode::Authenticated | Mode::Encrypted);
+        parameters.sent_padding = if authenticated {
+            AUTH_PADDING_LENGTH as u32
+        } else {
+            sent_padding
+        };
+        parameters.sent_dscp = sent_dscp;
+        parameters.flow_label = flow_label;
+        parameters.sender_port = controller_port;
+        parameters.effective_rate = send_duration
+            .filter(|duration| !duration.is_zero())
+            .map(|duration| number_of_test_packets as f64 / duration.as_secs_f64());
+        debug!(target: CONTROL_TARGET, "Control-Client and Session-Sender ended");
+        let mut acquired_vec = reflected_pkts_vec.lock().await;
+        debug!(target: TEST_TARGET, received = acquired_vec.len(), "Building report");
+        // Reports count test packets from the start of the session.
+        for (pkt, _) in acquired_vec.iter_mut() {
+            pkt.sender_sequence_number = pkt
+                .sender_sequence_number
+                .wrapping_sub(first_sequence_number);
+        }
+        let mut report = match running_stats {
+            Some(stats) if stats.received() as usize > acquired_vec.len() => {
+                warn!(
+                    target: TEST_TARGET,
+                    kept = acquired_vec.len(),
+                    received = stats.received(),
+                    "Memory budget exceeded, reporting aggregates only"
+                );
+                TestReport::from_running_stats(&stats, number_of_test_packets)
+            }
+            _ => {
+                let mut report = TestReport::new(
+                    &acquired_vec,
+                    number_of_test_packets,
+                    train,
+                    if authenticated {
+                        TwampTestPacketAuth::MIN_LENGTH + AUTH_PADDING_LENGTH
+                    } else {
+                        TwampTestPacketUnauth::MIN_LENGTH + sent_padding as usize
+                    },
+                );
+                if !profiles.is_empty() {
+                    report = report.with_profiles(&acquired_vec, &profiles, reflector_addr);
+                }
+                report
+            }
+        };
+        report.reflected_octets_mismatched = reflected_octets_mismatched;
+        Ok(report
+            .with_clock(clock)
+            .with_control(control)
+            .with_parameters(parameters)
+            .with_ecn(received_ecn.unwrap_or_default())
+            .with_pacing(pacing)
+            .with_termination(termination))
+    }
+
+    /// Run a STAMP ([RFC 8762](https://datatracker.ietf.org/doc/html/rfc8762)) session against
+    /// the Session-Reflector at `reflector_addr`, usually on port
+    /// [`TWAMP_TEST_WELL_KNOWN_PORT`], without TWAMP-Control: test packets are sent straight to
+    /// it, so STAMP-only reflectors can be tested too.
+    ///
+    /// Profiles, trains, ECN marking, pacing calibration, the sender port, compliance,
+    /// measurement callbacks and the maximum test duration apply as to
+    /// [`do_twamp`](Self::do_twamp). Options of TWAMP-Control, and those needing the
+    /// Session-Reflector to agree to them, don't.
+    pub async fn do_stamp(
+        self,
+        reflector_addr: SocketAddr,
+        controller_addr: IpAddr,
+        controller_port: u16,
+        number_of_test_packets: u32,
+        stop_policy: StopPolicy,
+    ) -> Result<TestReport> {
+        self.do_without_control(
+            reflector_addr,
+            controller_addr,
+            controller_port,
+            number_of_test_packets,
+            stop_policy,
+            true,
+        )
+        .await
+    }
+
+    /// Run a TWAMP Light session ([RFC 5357 Appendix I](https://datatracker.ietf.org/doc/html/rfc5357#appendix-I))
+    /// against a Session-Reflector set up out of band to reflect the test packets sent to
+    /// `reflector_addr`, without TWAMP-Control.
+    ///
+    /// Options apply as to [`do_stamp`](Self::do_stamp), but test packets are TWAMP-Test ones,
+    /// of the padding and DSCP of the [profiles](Self::with_profiles).
+    pub async fn do_light(
+        self,
+        reflector_addr: SocketAddr,
+        controller_addr: IpAddr,
+        controller_port: u16,
+        number_of_test_packets: u32,
+        stop_policy: StopPolicy,
+    ) -> Result<TestReport> {
+        self.do_without_control(
+            reflector_addr,
+            controller_addr,
+            controller_port,
+            number_of_test_packets,
+            stop_policy,
+            false,
+        )
+        .await
+    }
+
+    /// Session of [`do_stamp`](Self::do_stamp) with `stamp`, of [`do_light`](Self::do_light)
+    /// without.
+    async fn do_without_control(
+        mut self,
+        reflector_addr: SocketAddr,
+        controller_addr: IpAddr,
+        controller_port: u16,
+        mut number_of_test_packets: u32,
+        stop_policy: StopPolicy,
+        stamp: bool,
+    ) -> Result<TestReport> {
+        let deadline = self.max_test_duration.map(|max| Instant::now() + max);
+        let profiles = std::mem::take(&mut self.profiles);
+        if let Some(first) = profiles.first() {
+            if profiles.iter().any(|profile| profile.dscp != first.dscp) {
+                return Err(anyhow!("Packet profiles in one session must share a DSCP"));
+            }
+            number_of_test_packets = profiles.iter().map(|profile| profile.packets).sum();
+        }
+        let clock = self.check_clock()?;
+        let sent_profiles = if profiles.is_empty() {
+            vec![PacketProfile::new(number_of_test_packets)]
+        } else {
+            profiles.clone()
+        };
+        let calibration = self.calibrate(&sent_profiles).await;
+        let udp_socket = self
+            .bind_sender(
+                reflector_addr.ip(),
+                controller_addr,
+                controller_port,
+                reflector_addr.port(),
+            )
+            .await?;
+        let controller_port = udp_socket.local_addr()?.port();
+        let flow_label = self.lease_flow_label(&udp_socket, reflector_addr.ip())?;
+        udp_socket
+            .connect(labeled(reflector_addr, flow_label))
+            .await?;
+        debug!(target: TEST_TARGET, %reflector_addr, stamp, "Starting Session-Sender without TWAMP-Control");
+        let mut session_sender = SessionSender::new(Arc::new(udp_socket), reflector_addr)
+            .await
+            .with_ecn_marking(self.ecn_marking)
+            .with_compliance(self.compliance)
+            .with_timestamp_format(self.timestamp_format)
+            .with_padding_pattern(self.padding_pattern)
+            .with_pacing_calibration(calibration);
+        if stamp {
+            session_sender = session_sender
+                .with_stamp()
+                .with_stamp_tlvs(self.stamp_tlvs.clone());
+        }
+        if let Some(train) = self.train {
+            session_sender = session_sender.with_train(train);
+        }
+        if self.reflector_counter {
+            session_sender = session_sender.with_reflector_counter();
+        }
+        if let Some(callback) = self.on_measurement.take() {
+            session_sender = session_sender.with_measurement_callback(callback);
+        }
+        let session_sender = Arc::new(session_sender);
+        let reflected_pkts_vec: Arc<Mutex<Vec<(TwampTestPacketUnauthReflected, TimeStamp)>>> =
+            Arc::default();
+        let recv_task = spawn({
+            let session_sender = Arc::clone(&session_sender);
+            let reflected_pkts_vec = Arc::clone(&reflected_pkts_vec);
+            async move {
+                session_sender
+                    .recv(number_of_test_packets, reflected_pkts_vec)
+                    .await
+            }
+        });
+        let recv_abort = recv_task.abort_handle();
+        let send = async {
+            let started_at = Instant::now();
+            let mut first_seq = 0;
+            for profile in &sent_profiles {
+                session_sender.send_profile(first_seq, profile).await?;
+                first_seq += profile.packets;
+            }
+            Ok::<_, anyhow::Error>(started_at.elapsed())
+        };
+        let send_duration = match until(deadline, send).await {
+            Some(Ok(send_duration)) => Some(send_duration),
+            Some(Err(e)) => {
+                recv_abort.abort();
+                return Err(e);
+            }
+            None => None,
+        };
+        let truncated = match send_duration {
+            Some(_) => until(deadline, stop_policy.drain(recv_task))
+                .await
+                .is_none(),
+            None => true,
+        };
+        if truncated {
+            warn!(
+                target: TEST_TARGET,
+                sent = session_sender.packets_sent(),
+                "Maximum test duration reached, stopping the test"
+            );
+            recv_abort.abort();
+            number_of_test_packets = session_sender.packets_sent();
+        }
+        let packet_length = if stamp {
+            StampTestPacket::LENGTH + Tlv::write_all(&self.stamp_tlvs).len()
+        } else {
+            sent_profiles[0].packet_length(reflector_addr.ip())
+        };
+        let parameters = TestParameters {
+            requested_port: reflector_addr.port(),
+            granted_port: reflector_addr.port(),
+            sender_port: controller_port,
+            sent_padding: (packet_length - TwampTestPacketUnauth::MIN_LENGTH) as u32,
+            sent_dscp: sent_profiles.first().map_or(0, |profile| profile.dscp),
+            flow_label,
+            effective_rate: send_duration
+                .filter(|duration| !duration.is_zero())
+                .map(|duration| number_of_test_packets as f64 / duration.as_secs_f64()),
+            ..Default::default()
+        };
+        let acquired_vec = reflected_pkts_vec.lock().await;
+        debug!(target: TEST_TARGET, received = acquired_vec.len(), "Building report");
+        let mut report = TestReport::new(
+            &acquired_vec,
+            number_of_test_packets,
+            self.train,
+            packet_length,
+        );
+        if !profiles.is_empty() {
+            report = report.with_profiles(&acquired_vec, &profiles, reflector_addr.ip());
+        }
+        Ok(report
+            .with_clock(clock)
+            .with_parameters(parameters)
+            .with_ecn(session_sender.received_ecn())
+            .with_pacing(session_sender.pacing())
+            .with_termination(if truncated {
+                TerminationReason::Truncated
+            } else {
+                TerminationReason::Completed
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server::config::ServerConfig;
+    use session_sender::PADDING_LENGTH;
+    use twamp_control::security_mode::ModeExtension;
+
+    #[test]
+    fn reflector_timeout_is_how_long_reflectors_wait() {
+        assert_eq!(
+            check_reflector_timeout(SessionTimeout::new(1).unwrap()),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            check_reflector_timeout(SessionTimeout::MAX),
+            Duration::from_secs(3600)
+        );
+    }
+
+    #[tokio::test]
+    async fn negotiate_closes_after_accept_session() {
+        let listener = twamp_runtime::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let responder_port = listener.local_addr().unwrap().port();
+        let responding = spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            crate::responder::Responder::new(socket)
+                .handle_controller(Refwait::new(1).unwrap())
+                .await
+        });
+
+        let negotiated = Controller::new()
+            .negotiate(
+                Ipv4Addr::LOCALHOST.into(),
+                responder_port,
+                Ipv4Addr::LOCALHOST.into(),
+                0,
+                0,
+                SessionTimeout::new(1).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(negotiated.control.mode, Mode::Unauthenticated);
+        assert_ne!(negotiated.parameters.granted_port, 0);
+        assert_ne!(negotiated.parameters.sender_port, 0);
+        let capabilities = &negotiated.control.capabilities;
+        assert_eq!(capabilities.modes, vec![Mode::Unauthenticated]);
+        assert_eq!(capabilities.extensions, vec![ModeExtension::PtpTimestamp]);
+        assert_eq!(capabilities.count, 1024);
+        assert!(capabilities.start_time.is_some());
+        assert!(capabilities.quirks.is_empty());
+        let summary = responding.await.unwrap().unwrap();
+        assert_eq!(summary.reason, TerminationReason::ControlLost);
+        assert_eq!(summary.reflected, 0);
+    }
+
+    #[tokio::test]
+    async fn stamp_session_needs_no_control_connection() {
+        let reflector = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let reflector_addr = reflector.local_addr().unwrap();
+        spawn(session_reflector::stamp::StampReflector::new(reflector).do_reflect());
+
+        let report = Controller::new()
+            .with_profiles(vec![
+                PacketProfile::new(5),
+                PacketProfile {
+                    interval: Duration::from_millis(5),
+                    ..PacketProfile::new(5)
+                },
+            ])
+            .do_stamp(
+                reflector_addr,
+                Ipv4Addr::LOCALHOST.into(),
+                0,
+                10,
+                StopPolicy::AfterAllReflected,
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.sent, 10);
+        assert_eq!(report.received, 10);
+        assert_eq!(report.termination, TerminationReason::Completed);
+        assert_eq!(report.profiles.len(), 2);
+        assert_eq!(report.parameters.granted_port, reflector_addr.port());
+        assert_eq!(
+            report.parameters.sent_padding as usize + TwampTestPacketUnauth::MIN_LENGTH,
+            StampTestPacket::LENGTH
+        );
+    }
+
+    #[tokio::test]
+    async fn light_session_needs_no_control_connection() {
+        // The Session-Reflector is set up out of band for the sender port.
+        let sender_port = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let reflector = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let reflector_addr = reflector.local_addr().unwrap();
+        reflector
+            .connect((Ipv4Addr::LOCALHOST, sender_port))
+            .await
+            .unwrap();
+        spawn(
+            session_reflector::SessionReflector::new(
+                reflector,
+                twamp_control::timers::Refwait::new(1).unwrap(),
+            )
+            .await
+            .do_reflect(),
+        );
+
+        let profile = PacketProfile {
+            dscp: 10,
+            ..PacketProfile::new(5)
+        };
+        let report = Controller::new()
+            .with_profiles(vec![profile])
+            .do_light(
+                reflector_addr,
+                Ipv4Addr::LOCALHOST.into(),
+                sender_port,
+                5,
+                StopPolicy::AfterTimeout(Duration::from_secs(5)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.received, 5);
+        assert_eq!(report.termination, TerminationReason::Completed);
+        assert_eq!(report.parameters.sender_port, sender_port);
+        assert_eq!(report.parameters.sent_padding, PADDING_LENGTH);
+        assert_eq!(report.parameters.sent_dscp, 10);
+    }
+
+    #[tokio::test]
+    async fn stamp_tlvs_lengthen_test_packets() {
+        let reflector = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let reflector_addr = reflector.local_addr().unwrap();
+        spawn(session_reflector::stamp::StampReflector::new(reflector).do_reflect());
+
+        let tlvs = vec![
+            Tlv::ExtraPadding(vec![0; 16]),
+            Tlv::DirectMeasurement(Default::default()),
+        ];
+        let report = Controller::new()
+            .with_stamp_tlvs(tlvs.clone())
+            .do_stamp(
+                reflector_addr,
+                Ipv4Addr::LOCALHOST.into(),
+                0,
+                5,
+                StopPolicy::AfterAllReflected,
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.received, 5);
+        assert_eq!(
+            report.parameters.sent_padding as usize + TwampTestPacketUnauth::MIN_LENGTH,
+            StampTestPacket::LENGTH + Tlv::write_all(&tlvs).len()
+        );
+    }
+
+    #[tokio::test]
+    async fn flow_label_is_recorded_in_report() {
+        let Ok(reflector) = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).await else {
+            // No IPv6 on this host.
+            return;
+        };
+        let reflector_addr = reflector.local_addr().unwrap();
+        spawn(session_reflector::stamp::StampReflector::new(reflector).do_reflect());
+
+        for flow_label in [FlowLabel::Fixed(0x2_468a), FlowLabel::Random] {
+            let report = Controller::new()
+                .with_flow_label(flow_label)
+                .do_stamp(
+                    reflector_addr,
+                    Ipv6Addr::LOCALHOST.into(),
+                    0,
+                    5,
+                    StopPolicy::AfterAllReflected,
+                )
+                .await
+                .unwrap();
+            assert_eq!(report.received, 5);
+            let label = report.parameters.flow_label.unwrap();
+            match flow_label {
+                FlowLabel::Fixed(fixed) => assert_eq!(label, fixed),
+                FlowLabel::Random => assert_ne!(label, 0),
+            }
+        }
+
+        let ipv4_reflector = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let result = Controller::new()
+            .with_flow_label(FlowLabel::Random)
+            .do_stamp(
+                ipv4_reflector.local_addr().unwrap(),
+                Ipv4Addr::LOCALHOST.into(),
+                0,
+                5,
+                StopPolicy::AfterAllReflected,
+            )
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn max_test_duration_truncates_report() {
+        let listener = twamp_runtime::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let responder_port = listener.local_addr().unwrap().port();
+        spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            crate::responder::Responder::new(socket)
+                .handle_controller(Refwait::new(1).unwrap())
+                .await
+        });
+
+        let report = Controller::new()
+            .with_profiles(vec![PacketProfile {
+                interval: Duration::from_millis(20),
+                ..PacketProfile::new(1000)
+            }])
+            .with_max_test_duration(Duration::from_millis(500))
+            .do_twamp(
+                Ipv4Addr::LOCALHOST.into(),
+                responder_port,
+                Ipv4Addr::LOCALHOST.into(),
+                0,
+                0,
+                1000,
+                SessionTimeout::new(1).unwrap(),
+                StopPolicy::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.termination, TerminationReason::Truncated);
+        assert!(report.sent > 0 && report.sent < 1000);
+        assert!(report.received <= report.sent);
+    }
+
+    #[tokio::test]
+    async fn announced_number_of_packets_needs_lenient_server() {
+        for compliance in [Compliance::Strict, Compliance::Lenient] {
+            let listener = twamp_runtime::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap();
+            let responder_port = listener.local_addr().unwrap().port();
+            spawn(async move {
+                let (socket, _) = listener.accept().await.unwrap();
+                crate::responder::Responder::new(socket)
+                    .with_server_config(ServerConfig {
+                        compliance,
+                        ..Default::default()
+                    })
+                    .handle_controller(Refwait::new(1).unwrap())
+                    .await
+            });
+
+            let result = Controller::new()
+                .with_number_of_packets_announced()
+                .do_twamp(
+                    Ipv4Addr::LOCALHOST.into(),
+                    responder_port,
+                    Ipv4Addr::LOCALHOST.into(),
+                    0,
+                    0,
+                    2,
+                    SessionTimeout::new(1).unwrap(),
+                    StopPolicy::default(),
+                )
+                .await;
+            match compliance {
+                Compliance::Strict => assert!(result.is_err()),
+                Compliance::Lenient => assert_eq!(result.unwrap().received, 2),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn tests_reflector_at_another_address() {
+        let listener = twamp_runtime::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let responder_port = listener.local_addr().unwrap().port();
+        spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                spawn(
+                    crate::responder::Responder::new(socket)
+                        .handle_controller(Refwait::new(1).unwrap()),
+                );
+            }
+        });
+
+        // Any address of 127.0.0.0/8 is local on Linux.
+        let reflector_addr = Ipv4Addr::new(127, 0, 0, 2);
+        let report = Controller::new()
+            .with_reflector_address(reflector_addr.into())
+            .do_twamp(
+                Ipv4Addr::LOCALHOST.into(),
+                responder_port,
+                Ipv4Addr::LOCALHOST.into(),
+                0,
+                0,
+                3,
+                SessionTimeout::new(1).unwrap(),
+                StopPolicy::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.received, 3);
+
+        let error = Controller::new()
+            .with_reflector_address(Ipv6Addr::LOCALHOST.into())
+            .negotiate(
+                Ipv4Addr::LOCALHOST.into(),
+                responder_port,
+                Ipv4Addr::LOCALHOST.into(),
+                0,
+                0,
+                SessionTimeout::new(1).unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("IP version"));
+    }
+
+    #[tokio::test]
+    async fn paced_profiles_report_achieved_intervals() {
+        let listener = twamp_runtime::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let responder_port = listener.local_addr().unwrap().port();
+        spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            crate::responder::Responder::new(socket)
+                .handle_controller(Refwait::new(1).unwrap())
+                .await
+        });
+
+        let report = Controller::new()
+            .with_pacing_calibration()
+            .with_profiles(vec![
+                PacketProfile::new(2),
+                PacketProfile {
+                    interval: Duration::from_millis(5),
+                    ..PacketProfile::new(5)
+                },
+            ])
+            .do_twamp(
+                Ipv4Addr::LOCALHOST.into(),
+                responder_port,
+                Ipv4Addr::LOCALHOST.into(),
+                0,
+                0,
+                7,
+                SessionTimeout::new(1).unwrap(),
+                StopPolicy::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.pacing.len(), 1);
+        let pacing = report.pacing[0];
+        assert_eq!(pacing.requested, 0.005);
+        assert_eq!(pacing.intervals, 4);
+        assert!(pacing.mean > 0.0);
+    }
+
+    #[tokio::test]
+    async fn persisted_sequence_continues_across_sessions() {
+        let listener = twamp_runtime::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let responder_port = listener.local_addr().unwrap().port();
+        spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                spawn(
+                    crate::responder::Responder::new(socket)
+                        .handle_controller(Refwait::new(1).unwrap()),
+                );
+            }
+        });
+        let path =
+            std::env::temp_dir().join(format!("twamp-controller-seq-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut first_seqs = Vec::new();
+        for _ in 0..2 {
+            let seqs = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let recorded = Arc::clone(&seqs);
+            let report = Controller::new()
+                .with_sequence_start(SequenceStart::Persisted(path.clone()))
+                .on_measurement(MeasurementCallback::new(move |measurement| {
+                    recorded.lock().unwrap().push(measurement.sequence_number);
+                    async {}
+                }))
+                .do_twamp(
+                    Ipv4Addr::LOCALHOST.into(),
+                    responder_port,
+                    Ipv4Addr::LOCALHOST.into(),
+                    0,
+                    0,
+                    3,
+                    SessionTimeout::new(1).unwrap(),
+                    StopPolicy::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(report.received, 3);
+            first_seqs.push(seqs.lock().unwrap().iter().copied().min().unwrap());
+        }
+        assert_eq!(first_seqs, vec![0, 3]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "6\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn random_sender_port_is_within_range() {
+        let policy = SenderPortPolicy::Random(40000..=40100);
+        let socket = policy.bind(Ipv4Addr::LOCALHOST.into(), 0).await.unwrap();
+        assert!((40000..=40100).contains(&socket.local_addr().unwrap().port()));
+    }
+
+    #[tokio::test]
+    async fn empty_sender_port_range_is_refused() {
+        #[allow(clippy::reversed_empty_ranges)]
+        let policy = SenderPortPolicy::Random(2..=1);
+        assert!(policy.bind(Ipv4Addr::LOCALHOST.into(), 0).await.is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! Render TWAMP-Control and TWAMP-Test messages field by field, for debugging captures.
+
+use std::fmt::{Debug, Write as _};
+
+use deku::prelude::*;
+use twamp_control::{
+    accept_session::AcceptSession, request_tw_session::RequestTwSession,
+    server_greeting::ServerGreeting, server_start::ServerStart, set_up_response::SetUpResponse,
+    start_ack::StartAck, start_sessions::StartSessions, stop_sessions::StopSessions,
+};
+use twamp_test::{
+    twamp_test_unauth::TwampTestPacketUnauth,
+    twamp_test_unauth_reflected::TwampTestPacketUnauthReflected,
+};
+
+/// Length in bytes of the HMAC ending some TWAMP-Control messages.
+const HMAC_LENGTH: usize = 16;
+
+/// Messages [`dissect_as`] can render.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageKind {
+    ServerGreeting,
+    SetUpResponse,
+    ServerStart,
+    RequestTwSession,
+    AcceptSession,
+    StartSessions,
+    StartAck,
+    StopSessions,
+    TestPacket,
+    ReflectedTestPacket,
+}
+
+impl MessageKind {
+    /// Guess which TWAMP-Control message `bytes` hold from their length and leading octets.
+    /// TWAMP-Test packets have overlapping lengths, so they are never guessed.
+    pub fn guess(bytes: &[u8]) -> Option<Self> {
+        let command_number = bytes.first().copied();
+        match (bytes.len(), command_number) {
+            (164, _) => Some(MessageKind::SetUpResponse),
+            (112, Some(5)) => Some(MessageKind::RequestTwSession),
+            (64, _) => Some(MessageKind::ServerGreeting),
+            (48, _) if bytes[..15].iter().all(|b| *b == 0) => Some(MessageKind::ServerStart),
+            (48, _) => Some(MessageKind::AcceptSession),
+            (32, Some(2)) => Some(MessageKind::StartSessions),
+            (32, Some(3)) => Some(MessageKind::StopSessions),
+            (32, _) => Some(MessageKind::StartAck),
+            _ => None,
+        }
+    }
+
+    /// Whether the message ends with an HMAC.
+    fn has_hmac(self) -> bool {
+        matches!(
+            self,
+            MessageKind::RequestTwSession
+                | MessageKind::AcceptSession
+                | MessageKind::StartSessions
+                | MessageKind::StartAck
+                | MessageKind::StopSessions
+        )
+    }
+}
+
+/// Render `bytes` as the TWAMP-Control message they [look like](MessageKind::guess).
+pub fn dissect(bytes: &[u8]) -> String {
+    match MessageKind::guess(bytes) {
+        Some(kind) => dissect_as(kind, bytes),
+        None => format!(
+            "Unrecognized message ({} bytes), TWAMP-Test packets need an explicit kind\n",
+            bytes.len()
+        ),
+    }
+}
+
+/// Render `bytes` as a `kind` message, one field per line, followed by notes on MBZ and HMAC
+/// fields.
+pub fn dissect_as(kind: MessageKind, bytes: &[u8]) -> String {
+    let mut out = format!("{:?} ({} bytes)\n", kind, bytes.len());
+    let parsed = match kind {
+        MessageKind::ServerGreeting => render::<ServerGreeting>(bytes),
+        MessageKind::SetUpResponse => render::<SetUpResponse>(bytes),
+        MessageKind::ServerStart => render::<ServerStart>(bytes),
+        MessageKind::RequestTwSession => render::<RequestTwSession>(bytes),
+        MessageKind::AcceptSession => render::<AcceptSession>(bytes),
+        MessageKind::StartSessions => render::<StartSessions>(bytes),
+        MessageKind::StartAck => render::<StartAck>(bytes),
+        MessageKind::StopSessions => render::<StopSessions>(bytes),
+        MessageKind::TestPacket => render::<TwampTestPacketUnauth>(bytes),
+        MessageKind::ReflectedTestPacket => render::<TwampTestPacketUnauthReflected>(bytes),
+    };
+    match parsed {
+        Ok(fields) => {
+            let _ = writeln!(out, "{}", fields);
+            let _ = writeln!(out, "note: MBZ fields are zero");
+        }
+        Err(DekuError::Assertion(assertion)) => {
+            let _ = writeln!(out, "error: {} (an MBZ or fixed field is wrong)", assertion);
+        }
+        Err(e) => {
+            let _ = writeln!(out, "error: {}", e);
+        }
+    }
+    if kind.has_hmac() && bytes.len() >= HMAC_LENGTH {
+        let hmac = &bytes[bytes.len() - HMAC_LENGTH..];
+        let note = if hmac.iter().all(|b| *b == 0) {
+            "HMAC is zero, as in unauthenticated mode"
+        } else {
+            "HMAC is set but not verified, unexpected in unauthenticated mode"
+        };
+        let _ = writeln!(out, "note: {}", note);
+    }
+    out
+}
+
+/// Decode a hex dump such as Wireshark's "Copy as Hex Stream", ignoring whitespace and `:`
+/// separators. `None` if it is not valid hex.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = hex
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b':')
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn render<'a, T>(bytes: &'a [u8]) -> Result<String, DekuError>
+where
+    T: DekuContainerRead<'a> + Debug,
+{
+    let (_rest, message) = T::from_bytes((bytes, 0))?;
+    Ok(format!("{:#?}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use twamp_control::{accept::Accept, security_mode::Mode};
+
+    #[test]
+    fn guess_control_messages() {
+        let greeting = ServerGreeting::new(&[Mode::Unauthenticated])
+            .to_bytes()
+            .unwrap();
+        let start_sessions = StartSessions::new().to_bytes().unwrap();
+        let start_ack = StartAck::new(Accept::Ok).to_bytes().unwrap();
+        assert_eq!(
+            MessageKind::guess(&greeting),
+            Some(MessageKind::ServerGreeting)
+        );
+        assert_eq!(
+            MessageKind::guess(&start_sessions),
+            Some(MessageKind::StartSessions)
+        );
+        assert_eq!(MessageKind::guess(&start_ack), Some(MessageKind::StartAck));
+        assert_eq!(MessageKind::guess(&[0; 41]), None);
+    }
+
+    #[test]
+    fn decode_hex_with_separators() {
+        assert_eq!(decode_hex("00:ff 1a"), Some(vec![0x00, 0xff, 0x1a]));
+        assert_eq!(decode_hex("0"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn notes_non_zero_mbz_and_hmac() {
+        let mut start_sessions = StartSessions::new().to_bytes().unwrap();
+        let rendered = dissect(&start_sessions);
+        assert!(rendered.contains("note: MBZ fields are zero"));
+        assert!(rendered.contains("HMAC is zero"));
+        start_sessions[1] = 1;
+        start_sessions[31] = 1;
+        let rendered = dissect(&start_sessions);
+        assert!(rendered.contains("an MBZ or fixed field is wrong"));
+        assert!(rendered.contains("HMAC is set but not verified"));
+    }
+}
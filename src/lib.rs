@@ -1 +1,170 @@
+//! TWAMP ([RFC 5357](https://datatracker.ietf.org/doc/rfc5357/)) Controller and Responder.
+//!
+//! [`Controller`](controller::Controller) and [`Responder`](responder::Responder) are async and
+//! expect to be driven by a tokio runtime. The [`blocking`] module wraps them for callers that
+//! don't want to run one themselves.
+//!
+//! [`run`] and [`serve`] cover the simplest integration, one test against a Responder and a
+//! Responder serving every Controller:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> anyhow::Result<()> {
+//! use twamp_rs::{controller::ControllerConfig, responder::ResponderConfig};
+//!
+//! tokio::spawn(twamp_rs::serve(ResponderConfig::new("127.0.0.1:4862".parse()?)));
+//! # // Let the Responder bind before connecting.
+//! # tokio::task::yield_now().await;
+//!
+//! let mut config = ControllerConfig::new("127.0.0.1".parse()?);
+//! config.responder_port = 4862;
+//! config.responder_reflect_port = 0;
+//! let report = twamp_rs::run(config).await?;
+//! assert_eq!(report.sent, 10);
+//! # Ok(())
+//! # }
+//! ```
 
+use std::{convert::Infallible, sync::Arc};
+
+use anyhow::Result;
+use session_reflector::{fair_queue::FairQueue, light::LightReflector, stamp::StampReflector};
+use tokio::sync::watch;
+use tracing::*;
+use twamp_control::constants::TRACING_TARGET;
+use twamp_runtime::{
+    net::{TcpListener, UdpSocket},
+    task::spawn,
+};
+
+use crate::{
+    controller::{Controller, ControllerConfig},
+    report::TestReport,
+    responder::{Responder, ResponderConfig},
+};
+
+pub mod agent;
+pub mod blocking;
+pub mod clock;
+pub mod controller;
+pub mod dissect;
+pub mod paths;
+pub mod push;
+pub mod report;
+pub mod responder;
+pub mod soak;
+pub mod textfile;
+
+/// Run one test as described by `config` with a default [`Controller`]. Build a `Controller`
+/// for anything more.
+pub async fn run(config: ControllerConfig) -> Result<TestReport> {
+    run_with(Controller::new(), config).await
+}
+
+/// Run one test as described by `config` with `controller`.
+pub(crate) async fn run_with(
+    controller: Controller,
+    config: ControllerConfig,
+) -> Result<TestReport> {
+    controller
+        .do_twamp(
+            config.responder_addr,
+            config.responder_port,
+            config.controller_addr,
+            config.controller_port,
+            config.responder_reflect_port,
+            config.number_of_test_packets,
+            config.reflector_timeout,
+            config.stop_policy,
+        )
+        .await
+}
+
+/// Listen on `config.addr` and serve each Controller with a [`Responder`] on its own task, and
+/// reflect STAMP on `config.stamp_addr` and TWAMP Light on `config.light_addr` if set. Only
+/// returns if listening fails.
+pub async fn serve(config: ResponderConfig) -> Result<Infallible> {
+    serve_reloadable(watch::channel(config).1).await
+}
+
+/// Like [`serve`], but each Controller is served under the latest config sent on `config`,
+/// e.g. on SIGHUP. Controllers already being served keep the config they started with. The
+/// addresses are only listened on once, so changing them takes a restart.
+pub async fn serve_reloadable(mut config: watch::Receiver<ResponderConfig>) -> Result<Infallible> {
+    let initial = config.borrow_and_update().clone();
+    let listener = initial.bind().await?;
+    serve_listener(listener, config).await
+}
+
+/// Serve each Controller connecting to `listener` under the latest config sent on `config`.
+pub(crate) async fn serve_listener(
+    listener: TcpListener,
+    mut config: watch::Receiver<ResponderConfig>,
+) -> Result<Infallible> {
+    let (addr, stamp_addr, light_addr, refwait, reflector_config, reflector_workers) = {
+        let initial = config.borrow();
+        (
+            initial.addr,
+            initial.stamp_addr,
+            initial.light_addr,
+            initial.refwait,
+            initial.reflector.clone(),
+            initial.reflector_workers,
+        )
+    };
+    let fair_queue = Arc::new(FairQueue::new(reflector_workers));
+    info!(target: TRACING_TARGET, addr = %listener.local_addr()?, "Listening");
+    if let Some(stamp_addr) = stamp_addr {
+        let reflector = StampReflector::new(UdpSocket::bind(stamp_addr).await?)
+            .with_config(reflector_config.clone());
+        info!(target: TRACING_TARGET, addr = %stamp_addr, "Reflecting STAMP");
+        spawn(async move {
+            if let Err(e) = reflector.do_reflect().await {
+                warn!(target: TRACING_TARGET, "STAMP reflector failed: {}", e);
+            }
+        });
+    }
+    if let Some(light_addr) = light_addr {
+        let reflector = LightReflector::new(UdpSocket::bind(light_addr).await?)
+            .with_refwait(refwait)
+            .with_config(reflector_config);
+        info!(target: TRACING_TARGET, addr = %light_addr, "Reflecting TWAMP Light");
+        spawn(async move {
+            if let Err(e) = reflector.do_reflect().await {
+                warn!(target: TRACING_TARGET, "TWAMP Light reflector failed: {}", e);
+            }
+        });
+    }
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        debug!(target: TRACING_TARGET, %peer, "Accepted TWAMP-Control connection");
+        if config.has_changed().unwrap_or(false) {
+            info!(target: TRACING_TARGET, "Using reloaded config for new Controllers");
+            if config.borrow_and_update().addr != addr {
+                warn!(target: TRACING_TARGET, %addr, "Still listening on the address started with");
+            }
+        }
+        let config = config.borrow().clone();
+        let mut responder = Responder::new(socket)
+            .with_server_config(config.server.clone())
+            .with_reflector_config(config.reflector.clone())
+            .with_fair_queue(Arc::clone(&fair_queue));
+        if let Some(ports) = &config.ports {
+            responder = responder.with_port_allocator(Arc::clone(ports));
+        }
+        let refwait = config.refwait;
+        spawn(async move {
+            match responder.handle_controller(refwait).await {
+                Ok(summary) => info!(
+                    target: TRACING_TARGET,
+                    %peer,
+                    reason = ?summary.reason,
+                    reflected = summary.reflected,
+                    dropped = summary.dropped,
+                    "Session ended"
+                ),
+                Err(e) => warn!(target: TRACING_TARGET, %peer, "Session failed: {}", e),
+            }
+        });
+    }
+}
@@ -0,0 +1,137 @@
+//! Multi-path exploration: several sessions to the same Session-Reflector run side by side,
+//! each from its own Session-Sender port, so ECMP hashing can spread them over different paths.
+//! Comparing their results shows path asymmetries a single session would average away.
+//!
+//! Varying the IPv6 flow label instead of the port awaits IPv6 test packets.
+
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Result};
+use tracing::*;
+use twamp_runtime::task::spawn;
+use twamp_test::constants::TRACING_TARGET;
+
+use crate::{
+    controller::{Controller, SessionTimeout, StopPolicy},
+    report::{ReportDiff, Significance, TestReport},
+};
+
+/// Results of a multi-path exploration.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathReport {
+    /// Report of each session, in the order the Controllers were passed.
+    pub paths: Vec<TestReport>,
+
+    /// Change from the path with the lowest average RTT to the one with the highest.
+    pub rtt_spread: ReportDiff,
+
+    /// Change from the path with the least loss to the one with the most.
+    pub loss_spread: ReportDiff,
+}
+
+impl PathReport {
+    pub fn new(paths: Vec<TestReport>) -> Self {
+        let by = |key: fn(&TestReport) -> f64| {
+            let min = paths.iter().min_by(|a, b| key(a).total_cmp(&key(b)));
+            let max = paths.iter().max_by(|a, b| key(a).total_cmp(&key(b)));
+            min.zip(max)
+                .map(|(min, max)| min.diff(max))
+                .unwrap_or_default()
+        };
+        let rtt_spread = by(|report| report.rtt_avg);
+        let loss_spread = by(|report| report.loss_percent);
+        PathReport {
+            paths,
+            rtt_spread,
+            loss_spread,
+        }
+    }
+
+    /// Whether the paths differ in RTT or loss by more than measurement noise.
+    pub fn is_asymmetric(&self) -> bool {
+        self.rtt_spread.hints.contains(&Significance::RttIncreased)
+            || self
+                .loss_spread
+                .hints
+                .contains(&Significance::LossIncreased)
+    }
+}
+
+/// Run a session with each of `controllers` at once against the same Responder, and compare
+/// them. Every session binds its own Session-Sender port, picked by the OS or by each
+/// Controller's [`SenderPortPolicy`](crate::controller::SenderPortPolicy), so Controllers
+/// with [symmetric ports](Controller::with_symmetric_ports) cannot be used.
+///
+/// The Server may grant each session a different Session-Reflector port if
+/// `responder_reflect_port` cannot be shared, see
+/// [`TestParameters::granted_port`](crate::report::TestParameters::granted_port).
+#[allow(clippy::too_many_arguments)]
+pub async fn explore_paths(
+    controllers: Vec<Controller>,
+    responder_addr: IpAddr,
+    responder_port: u16,
+    controller_addr: IpAddr,
+    responder_reflect_port: u16,
+    number_of_test_packets: u32,
+    reflector_timeout: SessionTimeout,
+    stop_policy: StopPolicy,
+) -> Result<PathReport> {
+    if controllers.is_empty() {
+        return Err(anyhow!("Exploring paths needs at least one Controller"));
+    }
+    debug!(target: TRACING_TARGET, paths = controllers.len(), "Exploring paths");
+    let sessions: Vec<_> = controllers
+        .into_iter()
+        .map(|controller| {
+            spawn(controller.do_twamp(
+                responder_addr,
+                responder_port,
+                controller_addr,
+                0,
+                responder_reflect_port,
+                number_of_test_packets,
+                reflector_timeout,
+                stop_policy,
+            ))
+        })
+        .collect();
+    let mut paths = Vec::with_capacity(sessions.len());
+    for session in sessions {
+        paths.push(session.await??);
+    }
+    Ok(PathReport::new(paths))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(rtt_avg: f64, jitter: f64, loss_percent: f64) -> TestReport {
+        TestReport {
+            sent: 100,
+            rtt_avg,
+            jitter,
+            loss_percent,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn spread_compares_extreme_paths() {
+        let report = PathReport::new(vec![
+            path(0.010, 0.001, 0.0),
+            path(0.030, 0.001, 0.0),
+            path(0.012, 0.001, 5.0),
+        ]);
+        assert!((report.rtt_spread.rtt_avg - 0.020).abs() < 1e-9);
+        assert_eq!(report.loss_spread.loss_percent, 5.0);
+        assert!(report.is_asymmetric());
+    }
+
+    #[test]
+    fn paths_within_noise_are_symmetric() {
+        let report = PathReport::new(vec![path(0.010, 0.002, 0.0), path(0.012, 0.002, 1.0)]);
+        assert!(!report.is_asymmetric());
+        assert!(!PathReport::new(Vec::new()).is_asymmetric());
+    }
+}
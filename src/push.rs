@@ -0,0 +1,212 @@
+//! Push live session metrics to a [Prometheus Pushgateway](https://github.com/prometheus/pushgateway),
+//! for Controllers that cannot be scraped, e.g. behind NAT.
+
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use session_sender::measurement::{Measurement, MeasurementCallback};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::*;
+use twamp_runtime::{
+    net::TcpStream,
+    task::{spawn, JoinHandle},
+    time::interval,
+};
+
+/// Statistics of the reflected packets received so far.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct LiveStats {
+    received: u64,
+    rtt_last: f64,
+    rtt_sum: f64,
+    rtt_min: f64,
+    rtt_max: f64,
+}
+
+impl LiveStats {
+    fn record(&mut self, rtt: f64) {
+        if self.received == 0 {
+            self.rtt_min = rtt;
+            self.rtt_max = rtt;
+        }
+        self.received += 1;
+        self.rtt_last = rtt;
+        self.rtt_sum += rtt;
+        self.rtt_min = self.rtt_min.min(rtt);
+        self.rtt_max = self.rtt_max.max(rtt);
+    }
+
+    /// Render in the Prometheus text exposition format.
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, value: f64| {
+            let _ = writeln!(text, "# TYPE {name} {kind}\n{name} {value}");
+        };
+        metric(
+            "twamp_packets_received_total",
+            "counter",
+            self.received as f64,
+        );
+        if self.received > 0 {
+            metric("twamp_rtt_last_seconds", "gauge", self.rtt_last);
+            metric(
+                "twamp_rtt_avg_seconds",
+                "gauge",
+                self.rtt_sum / self.received as f64,
+            );
+            metric("twamp_rtt_min_seconds", "gauge", self.rtt_min);
+            metric("twamp_rtt_max_seconds", "gauge", self.rtt_max);
+        }
+        text
+    }
+}
+
+/// Collects [`Measurement`]s and pushes them to a Pushgateway under `job`.
+///
+/// Only the Pushgateway HTTP API is supported, not Prometheus remote-write.
+///
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// use twamp_rs::{controller::Controller, push::PushExporter};
+///
+/// let exporter = PushExporter::new("127.0.0.1:9091".parse()?, "twamp");
+/// let controller = Controller::new().on_measurement(exporter.measurement_callback());
+/// let pushing = exporter.spawn();
+/// // controller.do_twamp(...).await?;
+/// pushing.abort();
+/// exporter.push().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PushExporter {
+    gateway: SocketAddr,
+    job: String,
+    interval: Duration,
+    stats: Arc<Mutex<LiveStats>>,
+}
+
+impl PushExporter {
+    pub fn new(gateway: SocketAddr, job: impl Into<String>) -> Self {
+        PushExporter {
+            gateway,
+            job: job.into(),
+            interval: Duration::from_secs(10),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Push every `interval` once [spawned](Self::spawn), instead of every 10 seconds.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Add a reflected packet to the pushed metrics.
+    pub fn record(&self, measurement: &Measurement) {
+        self.stats.lock().unwrap().record(measurement.rtt);
+    }
+
+    /// Callback that [records](Self::record) every measurement, to hand to
+    /// [`Controller::on_measurement`](crate::controller::Controller::on_measurement).
+    pub fn measurement_callback(&self) -> MeasurementCallback {
+        let exporter = self.clone();
+        MeasurementCallback::new(move |measurement| {
+            exporter.record(&measurement);
+            async {}
+        })
+    }
+
+    /// Push the current metrics once.
+    pub async fn push(&self) -> Result<()> {
+        let body = self.stats.lock().unwrap().to_text();
+        let request = format!(
+            "PUT /metrics/job/{} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.job,
+            self.gateway,
+            body.len(),
+            body
+        );
+        let mut stream = TcpStream::connect(self.gateway).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let status_line = String::from_utf8_lossy(&response)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            _ => Err(anyhow!("Pushgateway responded with: {}", status_line)),
+        }
+    }
+
+    /// Push the metrics every interval until the returned task is aborted. Failed pushes are
+    /// logged and retried on the next interval.
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let exporter = self.clone();
+        spawn(async move {
+            let mut ticks = interval(exporter.interval);
+            loop {
+                ticks.tick().await;
+                if let Err(e) = exporter.push().await {
+                    warn!("Failed to push metrics to {}: {}", exporter.gateway, e);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use twamp_runtime::net::TcpListener;
+
+    #[test]
+    fn no_rtt_metrics_before_first_packet() {
+        let text = LiveStats::default().to_text();
+        assert_eq!(
+            text,
+            "# TYPE twamp_packets_received_total counter\ntwamp_packets_received_total 0\n"
+        );
+    }
+
+    #[test]
+    fn stats_track_rtt() {
+        let mut stats = LiveStats::default();
+        stats.record(2.0);
+        stats.record(4.0);
+        stats.record(3.0);
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.rtt_last, 3.0);
+        assert_eq!(stats.rtt_min, 2.0);
+        assert_eq!(stats.rtt_max, 4.0);
+        assert!(stats.to_text().contains("twamp_rtt_avg_seconds 3\n"));
+    }
+
+    #[tokio::test]
+    async fn push_puts_metrics_under_job() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let exporter = PushExporter::new(listener.local_addr().unwrap(), "probe");
+        let gateway = spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let len = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..len]).to_string()
+        });
+        exporter.push().await.unwrap();
+        let request = gateway.await.unwrap();
+        assert!(request.starts_with("PUT /metrics/job/probe HTTP/1.1\r\n"));
+        assert!(request.ends_with("twamp_packets_received_total 0\n"));
+    }
+}
@@ -0,0 +1,783 @@
+//! Metrics produced by the [`Controller`](crate::controller::Controller) from reflected
+//! TWAMP-Test packets, and why sessions end.
+
+use crate::clock::ClockStatus;
+use control_client::{audit::ControlEvent, ControlClient};
+use session_sender::{measurement::RunningStats, pacing::PacingStats, PacketProfile, Train};
+use std::{net::IpAddr, time::SystemTime};
+use timestamp::timestamp::TimeStamp;
+use twamp_control::security_mode::{Mode, ModeExtension};
+use twamp_test::constants::IPV4_UDP_HEADER_LENGTH;
+use twamp_test::ecn::EcnCounts;
+use twamp_test::twamp_test_unauth_reflected::TwampTestPacketUnauthReflected;
+
+/// Results of a TWAMP-Test session. All durations are in seconds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestReport {
+    /// Number of test packets sent.
+    pub sent: u32,
+
+    /// Number of reflected packets received.
+    pub received: u32,
+
+    /// Percentage of test packets that were not reflected back.
+    pub loss_percent: f64,
+
+    pub rtt_min: f64,
+    pub rtt_max: f64,
+    pub rtt_avg: f64,
+
+    /// Average one-way delay from Session-Sender to Session-Reflector.
+    pub owd_forward_avg: f64,
+
+    /// Average one-way delay from Session-Reflector to Session-Sender.
+    pub owd_reverse_avg: f64,
+
+    /// Smoothed RTT variation, as in [RFC 3550](https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.8).
+    pub jitter: f64,
+
+    /// Per-train results, if test packets were sent in trains.
+    pub trains: Vec<TrainReport>,
+
+    /// Per-profile results, if the session ran several
+    /// [packet profiles](crate::controller::Controller::with_profiles).
+    pub profiles: Vec<ProfileReport>,
+
+    /// TWAMP-Control parameters the test ran under.
+    pub control: ControlMetadata,
+
+    /// Test parameters asked for against those granted and used.
+    pub parameters: TestParameters,
+
+    /// ECN codepoints of the reflected packets as they arrived, showing marking on the reverse
+    /// path. The forward path is counted by the Session-Reflector.
+    pub ecn: EcnCounts,
+
+    /// Why the session ended.
+    pub termination: TerminationReason,
+
+    /// Local clock status before the test, if checked under a
+    /// [`ClockPolicy`](crate::clock::ClockPolicy) and it could be queried.
+    pub clock: Option<ClockStatus>,
+
+    /// Reflected packets that did not carry back the
+    /// [octets to reflect](crate::controller::Controller::with_reflect_octets), `None` if they
+    /// were not checked.
+    pub reflected_octets_mismatched: Option<u32>,
+
+    /// Intervals achieved between test packets against those asked for, for each profile sent
+    /// with a non-zero interval.
+    pub pacing: Vec<PacingStats>,
+
+    /// Whether the [memory budget](crate::controller::Controller::with_memory_budget) ran out.
+    /// The report was then aggregated as packets arrived, without per-train or per-profile
+    /// results.
+    pub memory_budget_exceeded: bool,
+}
+
+/// Why a TWAMP-Test session ended.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TerminationReason {
+    /// All test packets were sent and the session was stopped as planned.
+    #[default]
+    Completed,
+
+    /// No test packet arrived at the Session-Reflector for REFWAIT seconds.
+    RefwaitExpired,
+
+    /// The Control-Client sent Stop-Sessions.
+    StopSessions,
+
+    /// Start-Sessions did not arrive within the Server's
+    /// [deadline](server::config::ServerConfig::start_sessions_deadline).
+    StartSessionsExpired,
+
+    /// No TWAMP-Control message arrived within the Server's
+    /// [SERVWAIT](server::config::ServerConfig::servwait) before the sessions started.
+    ServwaitExpired,
+
+    /// The TWAMP-Control connection closed or failed while the session was running.
+    ControlLost,
+
+    /// The session was cancelled locally.
+    Cancelled,
+
+    /// The Controller's [maximum test duration](crate::controller::Controller::with_max_test_duration)
+    /// elapsed, so the report only covers the test packets sent until then.
+    Truncated,
+
+    /// The session failed.
+    Error(ErrorKind),
+}
+
+/// Broad category of the error that ended a session.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// Reading from or writing to a socket failed.
+    Io,
+
+    /// A message could not be parsed.
+    Protocol,
+
+    Other,
+}
+
+impl From<&anyhow::Error> for ErrorKind {
+    fn from(error: &anyhow::Error) -> Self {
+        if error.is::<std::io::Error>() {
+            ErrorKind::Io
+        } else if error.is::<deku::DekuError>() {
+            ErrorKind::Protocol
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/// Parameters negotiated on TWAMP-Control, kept for audit trails.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ControlMetadata {
+    /// Security modes advertised in the Server Greeting.
+    pub server_modes: Vec<Mode>,
+
+    /// Security mode chosen by the Control-Client.
+    pub mode: Mode,
+
+    /// Count from the Server Greeting.
+    pub count: u32,
+
+    /// Every message exchanged, if the Controller
+    /// [audited TWAMP-Control](crate::controller::Controller::with_control_audit).
+    pub timeline: Vec<ControlEvent>,
+
+    /// What the Server showed of itself, for inventorying Responders.
+    pub capabilities: PeerCapabilities,
+}
+
+/// Capabilities a Server advertised on TWAMP-Control, and how it departs from the RFCs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeerCapabilities {
+    /// Security modes advertised in the Server Greeting.
+    pub modes: Vec<Mode>,
+
+    /// Extensions advertised in the Server Greeting.
+    pub extensions: Vec<ModeExtension>,
+
+    /// Raw Modes field of the Server Greeting, including bits twamp-rs does not know.
+    pub mode_bits: u32,
+
+    /// Count from the Server Greeting.
+    pub count: u32,
+
+    /// Start-Time of Server-Start, when the Server says it started. `None` if left zero.
+    pub start_time: Option<SystemTime>,
+
+    /// Departures from the RFCs spotted during negotiation.
+    pub quirks: Vec<Quirk>,
+}
+
+/// A departure from the RFCs spotted in a Server's TWAMP-Control messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quirk {
+    /// Count is below 1024 or not a power of two, which
+    /// [RFC 4656](https://datatracker.ietf.org/doc/html/rfc4656#section-3.1) requires.
+    InvalidCount,
+
+    /// Modes has bits set that are neither a known security mode nor a known extension.
+    UnknownModeBits,
+
+    /// Start-Time of Server-Start is zero.
+    NoStartTime,
+
+    /// Accept-Session granted another port than the one asked for.
+    AlternatePort,
+}
+
+/// Outcome of [negotiating](crate::controller::Controller::negotiate) a session without
+/// running it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NegotiationReport {
+    /// TWAMP-Control parameters the session was accepted under.
+    pub control: ControlMetadata,
+
+    /// Test parameters asked for against those granted. Nothing was sent, so only the sender
+    /// port of what was used is set.
+    pub parameters: TestParameters,
+}
+
+/// Test parameters requested in Request-TW-Session next to what was granted by the Server or
+/// actually used by the Session-Sender, so silent downgrades are visible.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestParameters {
+    /// Session-Reflector port asked for.
+    pub requested_port: u16,
+
+    /// Session-Reflector port granted in Accept-Session, which may be an alternate port.
+    pub granted_port: u16,
+
+    /// Session-Sender port test packets were sent from.
+    pub sender_port: u16,
+
+    /// Padding length asked for.
+    pub requested_padding: u32,
+
+    /// Largest padding length sent in test packets, after truncation to what fits in the
+    /// [MTU](PacketProfile::mtu).
+    pub sent_padding: u32,
+
+    /// DSCP asked for in the Type-P Descriptor.
+    pub requested_dscp: u8,
+
+    /// DSCP test packets were marked with.
+    pub sent_dscp: u8,
+
+    /// IPv6 flow label test packets were sent with, `None` if none was
+    /// [set](crate::controller::Controller::with_flow_label).
+    pub flow_label: Option<u32>,
+
+    /// Timeout asked for, in seconds.
+    pub timeout: u64,
+
+    /// Test packets sent per second, `None` if it could not be measured.
+    pub effective_rate: Option<f64>,
+}
+
+/// Results of the test packets sent under a single [`PacketProfile`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileReport {
+    pub profile: PacketProfile,
+
+    /// Sequence number of the first test packet of the profile.
+    pub first_seq: u32,
+
+    /// Size in bytes of each test packet sent, once its padding is fit in the
+    /// [MTU](PacketProfile::mtu).
+    pub packet_length: usize,
+
+    pub report: TestReport,
+}
+
+/// Results of a single train of test packets.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrainReport {
+    /// Position of the train in the session, starting at zero.
+    pub index: u32,
+
+    /// Number of test packets sent in the train.
+    pub sent: u32,
+
+    /// Number of those packets reflected back.
+    pub received: u32,
+
+    pub rtt_avg: f64,
+
+    /// Time between the first and last packet of the train arriving at the Session-Reflector.
+    /// `None` if fewer than two packets arrived.
+    pub dispersion: Option<f64>,
+
+    /// Bottleneck bandwidth of the forward path in bits per second, estimated from
+    /// [dispersion](Self::dispersion).
+    pub bottleneck_bandwidth: Option<f64>,
+}
+
+/// Timestamps of a reflected packet as seconds, in the order of RFC 5357 notation.
+struct Sample {
+    sender_sequence_number: u32,
+    t1: f64,
+    t2: f64,
+    t3: f64,
+    t4: f64,
+}
+
+impl Sample {
+    fn new(pkt: &TwampTestPacketUnauthReflected, received_at: TimeStamp) -> Self {
+        let (t1, t2, t3) = pkt.ntp_timestamps();
+        Sample {
+            sender_sequence_number: pkt.sender_sequence_number,
+            t1: t1.into(),
+            t2: t2.into(),
+            t3: t3.into(),
+            t4: received_at.into(),
+        }
+    }
+
+    fn rtt(&self) -> f64 {
+        (self.t4 - self.t1) - (self.t3 - self.t2)
+    }
+}
+
+impl TestReport {
+    /// Build a report from reflected packets, paired with the time each was received.
+    ///
+    /// `train` and `packet_length` (size of a test packet in bytes) are used to produce
+    /// [per-train results](Self::trains) when packets were sent in trains.
+    pub fn new(
+        pkts: &[(TwampTestPacketUnauthReflected, TimeStamp)],
+        sent: u32,
+        train: Option<Train>,
+        packet_length: usize,
+    ) -> Self {
+        let samples: Vec<Sample> = pkts.iter().map(|(pkt, ts)| Sample::new(pkt, *ts)).collect();
+        let received = samples.len() as f64;
+        let rtts: Vec<f64> = samples.iter().map(Sample::rtt).collect();
+
+        let mut jitter = 0.0;
+        for i in 1..rtts.len() {
+            let rtt_diff = (rtts[i] - rtts[i - 1]).abs();
+            jitter += (rtt_diff - jitter) / 16.0;
+        }
+
+        let trains = match train {
+            Some(train) if train.packets > 0 => {
+                train_reports(&samples, sent, train.packets, packet_length)
+            }
+            _ => vec![],
+        };
+
+        TestReport {
+            sent,
+            received: samples.len() as u32,
+            loss_percent: ((sent as f64 - received) / sent as f64) * 100.0,
+            rtt_min: rtts.iter().copied().fold(f64::INFINITY, f64::min),
+            rtt_max: rtts.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            rtt_avg: rtts.iter().sum::<f64>() / received,
+            owd_forward_avg: samples.iter().map(|s| s.t2 - s.t1).sum::<f64>() / received,
+            owd_reverse_avg: samples.iter().map(|s| s.t4 - s.t3).sum::<f64>() / received,
+            jitter,
+            trains,
+            profiles: vec![],
+            control: ControlMetadata::default(),
+            parameters: TestParameters::default(),
+            ecn: EcnCounts::default(),
+            termination: TerminationReason::default(),
+            clock: None,
+            pacing: vec![],
+            memory_budget_exceeded: false,
+            reflected_octets_mismatched: None,
+        }
+    }
+
+    /// Build a report from aggregates of reflected packets, when they could not all be kept.
+    pub fn from_running_stats(stats: &RunningStats, sent: u32) -> Self {
+        let received = stats.received();
+        TestReport {
+            sent,
+            received,
+            loss_percent: ((sent as f64 - received as f64) / sent as f64) * 100.0,
+            rtt_min: stats.rtt_min(),
+            rtt_max: stats.rtt_max(),
+            rtt_avg: stats.rtt_avg(),
+            owd_forward_avg: stats.owd_forward_avg(),
+            owd_reverse_avg: stats.owd_reverse_avg(),
+            jitter: stats.jitter(),
+            memory_budget_exceeded: true,
+            ..Default::default()
+        }
+    }
+
+    /// Compare this report against `other`, e.g. before and after a config change.
+    pub fn diff(&self, other: &TestReport) -> ReportDiff {
+        let mut hints = Vec::new();
+        let rtt_avg = other.rtt_avg - self.rtt_avg;
+        if rtt_avg.abs() > 2.0 * self.jitter.max(other.jitter) {
+            hints.push(if rtt_avg > 0.0 {
+                Significance::RttIncreased
+            } else {
+                Significance::RttDecreased
+            });
+        }
+        let loss_percent = other.loss_percent - self.loss_percent;
+        let one_packet_percent = 100.0 / self.sent.min(other.sent).max(1) as f64;
+        if loss_percent.abs() > one_packet_percent {
+            hints.push(if loss_percent > 0.0 {
+                Significance::LossIncreased
+            } else {
+                Significance::LossDecreased
+            });
+        }
+        ReportDiff {
+            loss_percent,
+            rtt_min: other.rtt_min - self.rtt_min,
+            rtt_max: other.rtt_max - self.rtt_max,
+            rtt_avg,
+            owd_forward_avg: other.owd_forward_avg - self.owd_forward_avg,
+            owd_reverse_avg: other.owd_reverse_avg - self.owd_reverse_avg,
+            jitter: other.jitter - self.jitter,
+            hints,
+        }
+    }
+
+    /// Attach a sub-report for each of `profiles`, sent back-to-back in order from sequence
+    /// number zero to `reflector`.
+    pub fn with_profiles(
+        mut self,
+        pkts: &[(TwampTestPacketUnauthReflected, TimeStamp)],
+        profiles: &[PacketProfile],
+        reflector: IpAddr,
+    ) -> Self {
+        let mut first_seq = 0;
+        self.profiles = profiles
+            .iter()
+            .map(|profile| {
+                let seqs = first_seq..first_seq + profile.packets;
+                let in_profile: Vec<_> = pkts
+                    .iter()
+                    .filter(|(pkt, _)| seqs.contains(&pkt.sender_sequence_number))
+                    .cloned()
+                    .collect();
+                let packet_length = profile.packet_length(reflector);
+                let report = ProfileReport {
+                    profile: *profile,
+                    first_seq,
+                    packet_length,
+                    report: TestReport::new(&in_profile, profile.packets, None, packet_length),
+                };
+                first_seq = seqs.end;
+                report
+            })
+            .collect();
+        self
+    }
+
+    /// Record why the session ended, instead of [`TerminationReason::Completed`].
+    pub fn with_termination(mut self, termination: TerminationReason) -> Self {
+        self.termination = termination;
+        self
+    }
+
+    /// Attach the local clock status the test ran under.
+    pub fn with_clock(mut self, clock: Option<ClockStatus>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Attach the ECN codepoints of the reflected packets.
+    pub fn with_ecn(mut self, ecn: EcnCounts) -> Self {
+        self.ecn = ecn;
+        self
+    }
+
+    /// Attach the intervals achieved between test packets.
+    pub fn with_pacing(mut self, pacing: Vec<PacingStats>) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Attach the requested and actual test parameters.
+    pub fn with_parameters(mut self, parameters: TestParameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// Attach the TWAMP-Control parameters the test ran under.
+    pub fn with_control(mut self, control: ControlMetadata) -> Self {
+        self.control = control;
+        self
+    }
+}
+
+/// Change from one [`TestReport`] to another, as produced by [`TestReport::diff`]. Each delta
+/// is the other report's value minus this report's value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReportDiff {
+    pub loss_percent: f64,
+    pub rtt_min: f64,
+    pub rtt_max: f64,
+    pub rtt_avg: f64,
+    pub owd_forward_avg: f64,
+    pub owd_reverse_avg: f64,
+    pub jitter: f64,
+
+    /// Changes that are likely to be more than measurement noise.
+    pub hints: Vec<Significance>,
+}
+
+/// A change between two reports that is likely to be more than measurement noise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Significance {
+    /// Average RTT moved by more than twice the larger jitter of the two reports.
+    RttIncreased,
+    RttDecreased,
+    /// Loss changed by more than one packet's worth of the smaller report.
+    LossIncreased,
+    LossDecreased,
+}
+
+impl ReportDiff {
+    /// Whether any change is likely to be more than measurement noise.
+    pub fn is_significant(&self) -> bool {
+        !self.hints.is_empty()
+    }
+}
+
+impl From<&ControlClient> for ControlMetadata {
+    fn from(control_client: &ControlClient) -> Self {
+        let greeting = control_client.server_greeting();
+        ControlMetadata {
+            server_modes: greeting.map(|g| g.modes()).unwrap_or_default(),
+            mode: control_client.mode(),
+            count: greeting.map(|g| g.count()).unwrap_or_default(),
+            timeline: control_client.timeline().to_vec(),
+            capabilities: PeerCapabilities::from(control_client),
+        }
+    }
+}
+
+impl From<&ControlClient> for PeerCapabilities {
+    fn from(control_client: &ControlClient) -> Self {
+        let mut capabilities = PeerCapabilities::default();
+        if let Some(greeting) = control_client.server_greeting() {
+            capabilities.modes = greeting.modes();
+            capabilities.extensions = greeting.extensions();
+            capabilities.mode_bits = greeting.mode_bits();
+            capabilities.count = greeting.count();
+            if greeting.count() < 1024 || !greeting.count().is_power_of_two() {
+                capabilities.quirks.push(Quirk::InvalidCount);
+            }
+            let known = capabilities
+                .modes
+                .iter()
+                .map(|mode| u32::from(*mode))
+                .chain(capabilities.extensions.iter().map(|e| u32::from(*e)))
+                .fold(0, |bits, bit| bits | bit);
+            if greeting.mode_bits() & !known != 0 {
+                capabilities.quirks.push(Quirk::UnknownModeBits);
+            }
+        }
+        if let Some(server_start) = control_client.server_start() {
+            let start_time = *server_start.start_time();
+            if start_time.integer_part_of_seconds() == 0
+                && start_time.fractional_part_of_seconds() == 0
+            {
+                capabilities.quirks.push(Quirk::NoStartTime);
+            } else {
+                capabilities.start_time = Some(SystemTime::from(start_time));
+            }
+        }
+        if let (Some(request), Some(accept)) = (
+            control_client.request_tw_session(),
+            control_client.accept_session(),
+        ) {
+            if request.receiver_port != 0 && accept.port != request.receiver_port {
+                capabilities.quirks.push(Quirk::AlternatePort);
+            }
+        }
+        capabilities
+    }
+}
+
+impl From<&ControlClient> for TestParameters {
+    /// Parameters requested and granted on TWAMP-Control. What was sent is left to the caller.
+    fn from(control_client: &ControlClient) -> Self {
+        let request = control_client.request_tw_session();
+        TestParameters {
+            requested_port: request.map(|r| r.receiver_port).unwrap_or_default(),
+            granted_port: control_client
+                .accept_session()
+                .map(|a| a.port)
+                .unwrap_or_default(),
+            requested_padding: request.map(|r| r.padding_length).unwrap_or_default(),
+            requested_dscp: request.map(|r| r.dscp()).unwrap_or_default(),
+            timeout: request
+                .and_then(|r| r.timeout().ok())
+                .map(u64::from)
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+fn train_reports(
+    samples: &[Sample],
+    sent: u32,
+    packets_per_train: u32,
+    packet_length: usize,
+) -> Vec<TrainReport> {
+    let bits_per_packet = ((packet_length + IPV4_UDP_HEADER_LENGTH) * 8) as f64;
+    (0..sent.div_ceil(packets_per_train))
+        .map(|index| {
+            let in_train: Vec<&Sample> = samples
+                .iter()
+                .filter(|s| s.sender_sequence_number / packets_per_train == index)
+                .collect();
+            let received = in_train.len() as u32;
+            let arrivals = in_train.iter().map(|s| s.t2);
+            let first = arrivals.clone().fold(f64::INFINITY, f64::min);
+            let last = arrivals.fold(f64::NEG_INFINITY, f64::max);
+            let dispersion = (received > 1).then_some(last - first);
+            let bottleneck_bandwidth = dispersion
+                .filter(|dispersion| *dispersion > 0.0)
+                .map(|dispersion| (received - 1) as f64 * bits_per_packet / dispersion);
+            TrainReport {
+                index,
+                sent: packets_per_train.min(sent - index * packets_per_train),
+                received,
+                rtt_avg: in_train.iter().map(|s| s.rtt()).sum::<f64>() / received as f64,
+                dispersion,
+                bottleneck_bandwidth,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use session_sender::measurement::Measurement;
+    use std::{net::Ipv4Addr, time::Duration};
+    use twamp_test::twamp_test_unauth::TwampTestPacketUnauth;
+
+    /// Reflected packet with T1..T4 at the provided offsets (seconds) from UNIX epoch.
+    fn reflected(
+        seq: u32,
+        t1: u64,
+        t2: u64,
+        t3: u64,
+        t4: u64,
+    ) -> (TwampTestPacketUnauthReflected, TimeStamp) {
+        let ts = |secs| TimeStamp::try_from(Duration::from_secs(secs)).unwrap();
+        let mut sender_pkt = TwampTestPacketUnauth::new(seq, 0, true);
+        sender_pkt.timestamp = ts(t1);
+        let mut pkt = TwampTestPacketUnauthReflected::new(seq, sender_pkt, ts(t2));
+        pkt.timestamp = ts(t3);
+        (pkt, ts(t4))
+    }
+
+    #[test]
+    fn loss_is_percentage_of_unreflected_packets() {
+        let pkts = vec![reflected(0, 0, 1, 1, 2), reflected(1, 0, 1, 1, 2)];
+        let report = TestReport::new(&pkts, 4, None, 41);
+        assert_eq!(report.received, 2);
+        assert_eq!(report.loss_percent, 50.0);
+    }
+
+    #[test]
+    fn rtt_excludes_reflector_processing_time() {
+        let pkts = vec![reflected(0, 100, 110, 115, 130)];
+        let report = TestReport::new(&pkts, 1, None, 41);
+        assert_eq!(report.rtt_avg, 25.0);
+        assert_eq!(report.owd_forward_avg, 10.0);
+        assert_eq!(report.owd_reverse_avg, 15.0);
+    }
+
+    #[test]
+    fn running_stats_match_stored_packets() {
+        let pkts = vec![
+            reflected(0, 100, 110, 115, 130),
+            reflected(1, 200, 205, 206, 240),
+            reflected(2, 300, 301, 310, 312),
+        ];
+        let mut stats = RunningStats::default();
+        for (pkt, ts) in &pkts {
+            stats.record(&Measurement::new(pkt, *ts));
+        }
+        let streamed = TestReport::from_running_stats(&stats, 4);
+        assert!(streamed.memory_budget_exceeded);
+        assert_eq!(
+            streamed,
+            TestReport {
+                memory_budget_exceeded: true,
+                ..TestReport::new(&pkts, 4, None, 41)
+            }
+        );
+    }
+
+    #[test]
+    fn no_trains_without_train_config() {
+        let pkts = vec![reflected(0, 0, 1, 1, 2)];
+        let report = TestReport::new(&pkts, 1, None, 41);
+        assert!(report.trains.is_empty());
+    }
+
+    #[test]
+    fn packets_are_grouped_into_trains() {
+        let pkts = vec![
+            reflected(0, 0, 10, 10, 20),
+            reflected(1, 0, 11, 11, 21),
+            reflected(2, 100, 110, 110, 120),
+        ];
+        let train = Train {
+            packets: 2,
+            gap: Duration::from_secs(100),
+        };
+        let report = TestReport::new(&pkts, 4, Some(train), 41);
+        assert_eq!(report.trains.len(), 2);
+        assert_eq!(report.trains[0].sent, 2);
+        assert_eq!(report.trains[0].received, 2);
+        assert_eq!(report.trains[1].received, 1);
+        assert_eq!(report.trains[1].dispersion, None);
+        assert_eq!(report.trains[1].bottleneck_bandwidth, None);
+    }
+
+    #[test]
+    fn bandwidth_is_estimated_from_dispersion() {
+        let pkts = vec![reflected(0, 0, 10, 10, 20), reflected(1, 0, 12, 12, 22)];
+        let train = Train {
+            packets: 2,
+            gap: Duration::ZERO,
+        };
+        let report = TestReport::new(&pkts, 2, Some(train), 97);
+        assert_eq!(report.trains[0].dispersion, Some(2.0));
+        // One 125 byte packet (with headers) every two seconds.
+        assert_eq!(report.trains[0].bottleneck_bandwidth, Some(500.0));
+    }
+
+    #[test]
+    fn packets_are_split_by_profile() {
+        let pkts = vec![
+            reflected(0, 0, 1, 1, 2),
+            reflected(1, 0, 1, 1, 2),
+            reflected(2, 0, 2, 2, 4),
+        ];
+        let profiles = [PacketProfile::new(2), PacketProfile::new(2)];
+        let report = TestReport::new(&pkts, 4, None, 41).with_profiles(
+            &pkts,
+            &profiles,
+            Ipv4Addr::LOCALHOST.into(),
+        );
+        assert_eq!(report.profiles.len(), 2);
+        assert_eq!(report.profiles[0].report.received, 2);
+        assert_eq!(report.profiles[0].report.rtt_avg, 2.0);
+        assert_eq!(report.profiles[1].first_seq, 2);
+        assert_eq!(report.profiles[1].report.received, 1);
+        assert_eq!(report.profiles[1].report.loss_percent, 50.0);
+        assert_eq!(report.profiles[1].report.rtt_avg, 4.0);
+    }
+
+    #[test]
+    fn error_kind_is_taken_from_error_source() {
+        let io = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+        assert_eq!(ErrorKind::from(&io), ErrorKind::Io);
+        assert_eq!(ErrorKind::from(&anyhow::anyhow!("boom")), ErrorKind::Other);
+    }
+
+    #[test]
+    fn diff_of_identical_reports_is_not_significant() {
+        let pkts = vec![reflected(0, 0, 1, 1, 2), reflected(1, 0, 1, 1, 2)];
+        let report = TestReport::new(&pkts, 2, None, 41);
+        let diff = report.diff(&report);
+        assert_eq!(diff.rtt_avg, 0.0);
+        assert!(!diff.is_significant());
+    }
+
+    #[test]
+    fn diff_flags_rtt_shift_beyond_jitter() {
+        let before = TestReport::new(&[reflected(0, 0, 1, 1, 2)], 1, None, 41);
+        let after = TestReport::new(&[reflected(0, 0, 1, 1, 5)], 1, None, 41);
+        let diff = before.diff(&after);
+        assert_eq!(diff.rtt_avg, 3.0);
+        assert_eq!(diff.hints, vec![Significance::RttIncreased]);
+    }
+
+    #[test]
+    fn diff_ignores_loss_change_within_one_packet() {
+        let pkts = vec![reflected(0, 0, 1, 1, 2), reflected(1, 0, 1, 1, 2)];
+        let before = TestReport::new(&pkts, 4, None, 41);
+        let after = TestReport::new(&pkts[..1], 4, None, 41);
+        assert_eq!(before.diff(&after).loss_percent, 25.0);
+        assert!(!before.diff(&after).is_significant());
+
+        let after = TestReport::new(&[], 4, None, 41);
+        assert_eq!(before.diff(&after).hints, vec![Significance::LossIncreased]);
+    }
+}
@@ -0,0 +1,609 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use server::{config::ServerConfig, error::ServerError, Server};
+use session_reflector::{
+    accounting::{Accounting, PacketSizes},
+    config::ReflectorConfig,
+    fair_queue::FairQueue,
+    ports::{PortAllocator, RequestedOrAny},
+    SessionReflector,
+};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot, watch},
+    try_join,
+};
+use tracing::*;
+use twamp_control::{
+    accept::Accept,
+    constants::TRACING_TARGET as CONTROL_TARGET,
+    request_tw_session::RequestTwSession,
+    rng::RngSource,
+    timers::{Refwait, SessionTimeout},
+};
+use twamp_runtime::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::{spawn, JoinSet},
+    time::{sleep, Instant},
+};
+use twamp_test::{constants::TRACING_TARGET as TEST_TARGET, ecn::EcnCounts, keys::TestKeys};
+
+use crate::report::{ErrorKind, TerminationReason};
+
+/// Start-Times less than this far past Start-Sessions are taken to mean straight away, so an
+/// offset between the clocks of the Controller and Responder does not drop the first test
+/// packets of sessions that were not meant to be deferred.
+const START_TIME_SLACK: Duration = Duration::from_secs(1);
+
+/// Outcome of the sessions served by a [`Responder`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectorSummary {
+    /// Number of test packets reflected.
+    pub reflected: u64,
+
+    /// Number of test packets dropped without being reflected.
+    pub dropped: u64,
+
+    /// ECN codepoints of the test packets as they arrived, showing marking on the forward path.
+    pub ecn: EcnCounts,
+
+    /// Sizes of the test packets each session received, in the order the sessions started.
+    /// Sizes other than the one negotiated point at fragmentation or middleboxes changing the
+    /// padding.
+    pub packet_sizes: Vec<PacketSizes>,
+
+    /// Why the session ended.
+    pub reason: TerminationReason,
+}
+
+/// Where to listen and how to serve each Controller, for [`crate::serve`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponderConfig {
+    /// Address to listen for TWAMP-Control on.
+    pub addr: SocketAddr,
+
+    /// Port to listen on instead if listening on `addr` is not permitted, e.g. on the
+    /// well-known port 862 without the privilege to.
+    pub fallback_port: Option<u16>,
+
+    /// How long the Session-Reflector waits for a test packet before ending the session.
+    pub refwait: Refwait,
+
+    pub server: ServerConfig,
+
+    pub reflector: ReflectorConfig,
+
+    /// Ports Session-Reflectors may be bound to, shared by every connection. The Receiver Port
+    /// asked for, or else any port, if `None`.
+    pub ports: Option<Arc<dyn PortAllocator>>,
+
+    /// Also reflect STAMP test packets arriving at this address, under the
+    /// [reflector config](Self::reflector) the Responder started with.
+    pub stamp_addr: Option<SocketAddr>,
+
+    /// Also reflect TWAMP Light test packets from any Session-Sender arriving at this address,
+    /// under the [reflector config](Self::reflector) and [REFWAIT](Self::refwait) the Responder
+    /// started with.
+    pub light_addr: Option<SocketAddr>,
+
+    /// Tasks sending the reflected packets of every session, which take turns at them, see
+    /// [`FairQueue`]. One per CPU if 0. Only the number the Responder started with is used.
+    pub reflector_workers: usize,
+}
+
+impl ResponderConfig {
+    /// Listen on `addr` with default configs and the RFC's REFWAIT.
+    pub fn new(addr: SocketAddr) -> Self {
+        ResponderConfig {
+            addr,
+            fallback_port: None,
+            refwait: Refwait::DEFAULT,
+            server: ServerConfig::default(),
+            reflector: ReflectorConfig::default(),
+            ports: None,
+            stamp_addr: None,
+            light_addr: None,
+            reflector_workers: 0,
+        }
+    }
+
+    /// Listen for TWAMP-Control on [`addr`](Self::addr), or on the
+    /// [fallback port](Self::fallback_port) if that is not permitted. The port listened on is
+    /// that of the returned listener's `local_addr`.
+    pub async fn bind(&self) -> Result<TcpListener> {
+        match (TcpListener::bind(self.addr).await, self.fallback_port) {
+            (Err(e), Some(fallback_port)) if e.kind() == io::ErrorKind::PermissionDenied => {
+                let fallback = SocketAddr::new(self.addr.ip(), fallback_port);
+                warn!(
+                    target: CONTROL_TARGET,
+                    addr = %self.addr,
+                    %fallback,
+                    "Not permitted to listen, falling back"
+                );
+                Ok(TcpListener::bind(fallback).await?)
+            }
+            (listener, _) => Ok(listener?),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Responder {
+    server: Server,
+    reflector_config: ReflectorConfig,
+    port_allocator: Arc<dyn PortAllocator>,
+    accounting: Arc<Accounting>,
+    fair_queue: Option<Arc<FairQueue>>,
+    shutdown: Option<watch::Receiver<bool>>,
+}
+
+impl Responder {
+    pub fn new(socket: TcpStream) -> Self {
+        Responder {
+            server: Server::new(socket),
+            reflector_config: ReflectorConfig::default(),
+            port_allocator: Arc::new(RequestedOrAny),
+            accounting: Arc::default(),
+            fair_queue: None,
+            shutdown: None,
+        }
+    }
+
+    /// Resources currently held for this TWAMP-Control connection. Stays valid after
+    /// [`handle_controller`](Self::handle_controller) has consumed the `Responder`.
+    pub fn accounting(&self) -> Arc<Accounting> {
+        Arc::clone(&self.accounting)
+    }
+
+    /// Use the provided config for the Session-Reflector instead of
+    /// [`ReflectorConfig::default`].
+    pub fn with_reflector_config(mut self, reflector_config: ReflectorConfig) -> Self {
+        self.reflector_config = reflector_config;
+        self
+    }
+
+    /// Bind Session-Reflectors to the ports `port_allocator` picks rather than the Receiver
+    /// Port asked for or else any port. Sessions none of its ports can be bound for are refused
+    /// with [`Accept::TemporaryResourceLimitation`].
+    pub fn with_port_allocator(mut self, port_allocator: Arc<dyn PortAllocator>) -> Self {
+        self.port_allocator = port_allocator;
+        self
+    }
+
+    /// Send reflected packets through `fair_queue`, shared with the sessions of other
+    /// connections, rather than a queue shared by the sessions of this connection only.
+    pub fn with_fair_queue(mut self, fair_queue: Arc<FairQueue>) -> Self {
+        self.fair_queue = Some(fair_queue);
+        self
+    }
+
+    /// Use the provided config for the Server instead of [`ServerConfig::default`].
+    pub fn with_server_config(mut self, server_config: ServerConfig) -> Self {
+        self.server = self.server.with_config(server_config);
+        self
+    }
+
+    /// Draw the nonces of TWAMP-Control from `rng`, see [`Server::with_rng`].
+    pub fn with_rng(mut self, rng: Arc<dyn RngSource>) -> Self {
+        self.server = self.server.with_rng(rng);
+        self
+    }
+
+    /// Stop once `shutdown` is `true`: the Server sends Stop-Sessions to the Controller if
+    /// sessions were started, see [`Server::stop_all_sessions`], and the Session-Reflectors
+    /// stop straight away, ending with [`TerminationReason::Cancelled`].
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.server = self.server.with_shutdown(shutdown.clone());
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Serve the TWAMP-Control connection and reflect its sessions until they end. Every
+    /// session accepted before Start-Sessions gets its own Session-Reflector.
+    pub async fn handle_controller(mut self, refwait: Refwait) -> Result<ReflectorSummary> {
+        let (req_tw_tx, mut req_tw_rx) = mpsc::unbounded_channel::<RequestTwSession>();
+        let (ref_port_tx, ref_port_rx) = mpsc::unbounded_channel::<Result<u16, Accept>>();
+        let (start_ack_tx, mut start_ack_rx) = oneshot::channel::<Vec<Option<TestKeys>>>();
+        let (stop_sessions_tx, stop_sessions_rx) = oneshot::channel::<()>();
+        let server_task = self.accounting.track_task();
+        let server_handle = spawn(async move {
+            let _server_task = server_task;
+            self.server
+                .handle_control_client(req_tw_tx, ref_port_rx, start_ack_tx, stop_sessions_tx)
+                .await
+        });
+        let reflector_config = self.reflector_config;
+        let port_allocator = self.port_allocator;
+        let accounting = self.accounting;
+        let fair_queue = self
+            .fair_queue
+            .unwrap_or_else(|| Arc::new(FairQueue::new(1)));
+        let mut shutdown = self.shutdown;
+        let reflector_task = accounting.track_task();
+        let reflector_accounting = Arc::clone(&accounting);
+        let session_reflector_handle = spawn(async move {
+            let accounting = reflector_accounting;
+            let _reflector_task = reflector_task;
+            // Sockets of the accepted sessions, how long to keep reflecting after Stop-Sessions,
+            // when they were asked to start, how many padding octets to reflect and how much
+            // padding test packets carry.
+            let mut sessions: Vec<(UdpSocket, SessionTimeout, SystemTime, u16, u32)> = Vec::new();
+            let test_keys = loop {
+                select! {
+                    biased;
+                    () = shutdown_requested(&mut shutdown) => return TerminationReason::Cancelled,
+                    Some(req_tw_session) = req_tw_rx.recv() => {
+                        // The Server refuses Timeouts out of bounds before passing requests on.
+                        let Ok(timeout) = req_tw_session.timeout() else {
+                            let _ = ref_port_tx.send(Err(Accept::NotSupported));
+                            continue;
+                        };
+                        let udp_socket =
+                            match bind_reflector(&req_tw_session, &*port_allocator).await {
+                                Ok(Some(udp_socket)) => udp_socket,
+                                Ok(None) => {
+                                    let _ = ref_port_tx
+                                        .send(Err(Accept::TemporaryResourceLimitation));
+                                    continue;
+                                }
+                                Err(e) => return TerminationReason::Error(ErrorKind::from(&e)),
+                            };
+                        let local_addr_port = udp_socket.local_addr().unwrap().port();
+                        let _ = ref_port_tx.send(Ok(local_addr_port));
+                        sessions.push((
+                            udp_socket,
+                            timeout,
+                            req_tw_session.start_time.into(),
+                            req_tw_session.length_of_padding_to_reflect(),
+                            req_tw_session.padding_length,
+                        ));
+                    }
+                    // Wait for signal to start reflecting.
+                    test_keys = &mut start_ack_rx => match test_keys {
+                        Ok(test_keys) => break test_keys,
+                        Err(_) if sessions.is_empty() => {
+                            debug!(
+                                target: CONTROL_TARGET,
+                                "No session accepted, not starting Session-Reflector"
+                            );
+                            return TerminationReason::ControlLost;
+                        }
+                        Err(_) => return TerminationReason::ControlLost,
+                    },
+                }
+            };
+
+            let (stopped_tx, stopped_rx) = watch::channel(false);
+            let mut reflect_tasks = JoinSet::new();
+            for ((udp_socket, timeout, start_time, reflect_octets, padding_length), test_keys) in
+                sessions.into_iter().zip(test_keys)
+            {
+                let mut session_reflector = SessionReflector::new(udp_socket, refwait)
+                    .await
+                    .with_config(reflector_config.clone())
+                    .with_accounting(Arc::clone(&accounting))
+                    .with_fair_queue(Arc::clone(&fair_queue))
+                    .with_reflect_octets(reflect_octets)
+                    .with_padding_length(padding_length);
+                if let Some(test_keys) = test_keys {
+                    session_reflector = session_reflector.with_test_keys(test_keys);
+                }
+                if let Some(start_at) = deferred_start(start_time) {
+                    session_reflector = session_reflector.with_start_at(start_at);
+                }
+                let mut stopped_rx = stopped_rx.clone();
+                let do_reflect_task = accounting.track_task();
+                reflect_tasks.spawn(async move {
+                    let _do_reflect_task = do_reflect_task;
+                    select! {
+                        reflect_result = session_reflector.do_reflect() => match reflect_result {
+                            Ok(()) => TerminationReason::RefwaitExpired,
+                            Err(e) => TerminationReason::Error(ErrorKind::from(&e)),
+                        },
+                        _ = async {
+                            let _ = stopped_rx.wait_for(|stopped| *stopped).await;
+                            sleep(timeout.as_duration()).await;
+                        } => {
+                            debug!(
+                                target: TEST_TARGET,
+                                timeout = timeout.secs(),
+                                "Shutting down Session-Reflector"
+                            );
+                            TerminationReason::StopSessions
+                        }
+                    }
+                });
+            }
+
+            select! {
+                biased;
+                () = shutdown_requested(&mut shutdown) => {
+                    debug!(target: TEST_TARGET, "Shutting down, stopping Session-Reflectors");
+                    reflect_tasks.abort_all();
+                    TerminationReason::Cancelled
+                }
+                reason = reflectors_ended(&mut reflect_tasks) => {
+                    debug!(target: TEST_TARGET, "Session-Reflectors ended");
+                    reason
+                }
+                stop_sessions = stop_sessions_rx => {
+                    if stop_sessions.is_err() {
+                        debug!(target: CONTROL_TARGET, "TWAMP-Control lost, stopping Session-Reflectors");
+                        reflect_tasks.abort_all();
+                        return TerminationReason::ControlLost;
+                    }
+                    debug!(
+                        target: CONTROL_TARGET,
+                        sessions = reflect_tasks.len(),
+                        "Stop-Sessions received, reflecting until timeout"
+                    );
+                    let _ = stopped_tx.send(true);
+                    reflectors_ended(&mut reflect_tasks).await
+                }
+            }
+        });
+        let (server_result, reason) = try_join!(server_handle, session_reflector_handle)?;
+        let reason = match server_result {
+            Err(e) => match e.downcast_ref::<ServerError>() {
+                Some(ServerError::StartSessionsExpired) => TerminationReason::StartSessionsExpired,
+                Some(ServerError::ServwaitExpired) => TerminationReason::ServwaitExpired,
+                _ => TerminationReason::Error(ErrorKind::from(&e)),
+            },
+            Ok(()) => reason,
+        };
+        debug!(target: CONTROL_TARGET, ?reason, "Server and Session-Reflectors ended");
+        Ok(ReflectorSummary {
+            reflected: accounting.packets_reflected(),
+            dropped: accounting.packets_dropped(),
+            ecn: accounting.received_ecn(),
+            packet_sizes: accounting
+                .sessions()
+                .iter()
+                .map(|session| session.packet_sizes())
+                .collect(),
+            reason,
+        })
+    }
+}
+
+/// Wait until `shutdown` is `true`, or forever without one.
+async fn shutdown_requested(shutdown: &mut Option<watch::Receiver<bool>>) {
+    if let Some(shutdown) = shutdown {
+        if shutdown.wait_for(|shutdown| *shutdown).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// When to start reflecting a session asked to start at `start_time`, if later than straight
+/// away.
+fn deferred_start(start_time: SystemTime) -> Option<Instant> {
+    let deferral = start_time.duration_since(SystemTime::now()).ok()?;
+    if deferral < START_TIME_SLACK {
+        return None;
+    }
+    debug!(target: TEST_TARGET, ?deferral, "Deferring session to its Start-Time");
+    Some(Instant::now() + deferral)
+}
+
+/// Bind the Session-Reflector of `req_tw_session` to the first port `port_allocator` picks
+/// that is free, and connect it to the Session-Sender. `None` if none of them are.
+async fn bind_reflector(
+    req_tw_session: &RequestTwSession,
+    port_allocator: &dyn PortAllocator,
+) -> Result<Option<UdpSocket>> {
+    let session_sender_addr =
+        SocketAddr::new(req_tw_session.sender_address(), req_tw_session.sender_port);
+    let addr = req_tw_session.receiver_address();
+    debug!(
+        target: TEST_TARGET,
+        %addr,
+        port = req_tw_session.receiver_port,
+        "Binding Session-Reflector"
+    );
+    for port in port_allocator.candidates(req_tw_session) {
+        match UdpSocket::bind(SocketAddr::new(addr, port)).await {
+            Ok(udp_socket) => {
+                udp_socket.connect(session_sender_addr).await?;
+                return Ok(Some(udp_socket));
+            }
+            Err(e) => debug!(target: TEST_TARGET, port, "Port not available: {}", e),
+        }
+    }
+    warn!(
+        target: TEST_TARGET,
+        port = req_tw_session.receiver_port,
+        "No port available for Session-Reflector"
+    );
+    Ok(None)
+}
+
+/// Wait for every Session-Reflector to end. The sessions ended for the first error if any, or
+/// else for the reason the last of them ended.
+async fn reflectors_ended(reflect_tasks: &mut JoinSet<TerminationReason>) -> TerminationReason {
+    let mut reason = TerminationReason::Cancelled;
+    while let Some(ended) = reflect_tasks.join_next().await {
+        if matches!(reason, TerminationReason::Error(_)) {
+            continue;
+        }
+        reason = ended.unwrap_or(TerminationReason::Cancelled);
+    }
+    reason
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deku::prelude::*;
+    use session_reflector::ports::{PortRange, RequestedOnly};
+    use std::net::Ipv4Addr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use twamp_control::{
+        accept_session::AcceptSession, security_mode::Mode, set_up_response::SetUpResponse,
+        start_sessions::StartSessions, stop_sessions::StopSessions, timers::Servwait,
+    };
+
+    #[tokio::test]
+    async fn shutdown_sends_stop_sessions_to_the_controller() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let responder = spawn(
+            Responder::new(socket)
+                .with_shutdown(shutdown_rx)
+                .handle_controller(Refwait::DEFAULT),
+        );
+
+        let mut greeting = [0u8; 64];
+        client.read_exact(&mut greeting).await.unwrap();
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        segment.extend(
+            RequestTwSession::new(
+                Ipv4Addr::LOCALHOST,
+                1,
+                Ipv4Addr::LOCALHOST,
+                0,
+                None,
+                SessionTimeout::DEFAULT,
+            )
+            .to_bytes()
+            .unwrap(),
+        );
+        segment.extend(StartSessions::new().to_bytes().unwrap());
+        client.write_all(&segment).await.unwrap();
+        // Server-Start, Accept-Session and Start-Ack.
+        let mut replies = [0u8; 48 + 48 + 32];
+        client.read_exact(&mut replies).await.unwrap();
+
+        shutdown_tx.send(true).unwrap();
+        let mut stop_sessions = [0u8; 32];
+        client.read_exact(&mut stop_sessions).await.unwrap();
+        let (_rest, stop_sessions) = StopSessions::from_bytes((&stop_sessions, 0)).unwrap();
+        assert_eq!(stop_sessions.accept(), Accept::Ok);
+        assert_eq!(stop_sessions.number_of_sessions(), 1);
+        let summary = responder.await.unwrap().unwrap();
+        assert_eq!(summary.reason, TerminationReason::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn idle_controller_ends_with_servwait_expired() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let responder = spawn(
+            Responder::new(socket)
+                .with_server_config(ServerConfig {
+                    servwait: Some(Servwait::new(Duration::from_millis(50)).unwrap()),
+                    ..Default::default()
+                })
+                .handle_controller(Refwait::DEFAULT),
+        );
+
+        let mut greeting = [0u8; 64];
+        client.read_exact(&mut greeting).await.unwrap();
+        let set_up_response = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        client.write_all(&set_up_response).await.unwrap();
+
+        let summary = responder.await.unwrap().unwrap();
+        assert_eq!(summary.reason, TerminationReason::ServwaitExpired);
+    }
+
+    /// Request a session for each of `receiver_ports` from a Responder binding reflectors with
+    /// `port_allocator`, returning the Accept-Sessions.
+    async fn accept_sessions(
+        port_allocator: Arc<dyn PortAllocator>,
+        receiver_ports: &[u16],
+    ) -> Vec<AcceptSession> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let responder = spawn(
+            Responder::new(socket)
+                .with_port_allocator(port_allocator)
+                .handle_controller(Refwait::new(1).unwrap()),
+        );
+
+        let mut greeting = [0u8; 64];
+        client.read_exact(&mut greeting).await.unwrap();
+        let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        for receiver_port in receiver_ports {
+            segment.extend(
+                RequestTwSession::new(
+                    Ipv4Addr::LOCALHOST,
+                    1,
+                    Ipv4Addr::LOCALHOST,
+                    *receiver_port,
+                    None,
+                    SessionTimeout::DEFAULT,
+                )
+                .to_bytes()
+                .unwrap(),
+            );
+        }
+        client.write_all(&segment).await.unwrap();
+        // Server-Start, then an Accept-Session each.
+        let mut replies = vec![0u8; 48 + 48 * receiver_ports.len()];
+        client.read_exact(&mut replies).await.unwrap();
+        drop(client);
+        let summary = responder.await.unwrap().unwrap();
+        assert_eq!(summary.reason, TerminationReason::ControlLost);
+        replies[48..]
+            .chunks(48)
+            .map(|bytes| AcceptSession::from_bytes((bytes, 0)).unwrap().1)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sessions_are_refused_when_no_allocated_port_is_free() {
+        let taken = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        let accepted = accept_sessions(Arc::new(RequestedOnly), &[taken_port, 0]).await;
+        assert_eq!(accepted[0].accept, Accept::TemporaryResourceLimitation);
+        assert_eq!(accepted[1].accept, Accept::Ok);
+        assert_ne!(accepted[1].port, 0);
+    }
+
+    #[tokio::test]
+    async fn busy_ports_are_moved_within_the_allowed_range() {
+        let taken = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        let free_port = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let accepted = accept_sessions(
+            Arc::new(PortRange(free_port..=free_port)),
+            &[taken_port, taken_port],
+        )
+        .await;
+        assert_eq!(accepted[0].accept, Accept::Ok);
+        assert_eq!(accepted[0].port, free_port);
+        // The range is used up by the first session.
+        assert_eq!(accepted[1].accept, Accept::TemporaryResourceLimitation);
+    }
+}
@@ -0,0 +1,286 @@
+//! Soak testing: many negotiate/test/stop cycles run back-to-back against the same Responder,
+//! sampling this process's resource usage after each one. Counters that keep growing point at
+//! sockets, threads or tasks left behind by finished sessions, which only show up as failures
+//! after a long-lived Controller or Responder has run for days.
+
+use std::sync::Arc;
+
+use tracing::*;
+use twamp_control::constants::TRACING_TARGET;
+
+use crate::controller::{Controller, ControllerConfig};
+
+/// Counts tasks still running, e.g. [`Accounting::tasks`](session_reflector::accounting::Accounting::tasks)
+/// summed over an in-process Responder's connections.
+pub type TaskProbe = Arc<dyn Fn() -> usize + Send + Sync>;
+
+/// Resource usage of this process at one point in time. Counters that cannot be read on this
+/// platform are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResourceCounters {
+    /// File descriptors open, sockets included.
+    pub open_fds: Option<u64>,
+
+    /// OS threads of the process.
+    pub threads: Option<u64>,
+
+    /// Resident memory, in bytes.
+    pub rss_bytes: Option<u64>,
+
+    /// Tasks counted by the [task probe](Soak::with_task_probe), if any.
+    pub tasks: Option<u64>,
+}
+
+impl ResourceCounters {
+    /// Sample the counters of this process, with `tasks` from `task_probe`.
+    pub fn sample(task_probe: Option<&TaskProbe>) -> Self {
+        let mut counters = Self::sample_process();
+        counters.tasks = task_probe.map(|probe| probe() as u64);
+        counters
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sample_process() -> Self {
+        let open_fds = std::fs::read_dir("/proc/self/fd")
+            .ok()
+            .map(|fds| fds.count() as u64);
+        let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+        };
+        ResourceCounters {
+            open_fds,
+            threads: field("Threads:"),
+            rss_bytes: field("VmRSS:").map(|kib| kib * 1024),
+            tasks: None,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn sample_process() -> Self {
+        ResourceCounters::default()
+    }
+}
+
+/// Outcome of one cycle of a soak.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoakIteration {
+    /// Test packets sent, 0 if the cycle failed.
+    pub sent: u32,
+
+    /// Reflected packets received, 0 if the cycle failed.
+    pub received: u32,
+
+    /// Why the cycle failed, if it did.
+    pub error: Option<String>,
+
+    /// Resource usage once the cycle ended.
+    pub counters: ResourceCounters,
+}
+
+/// A counter that grew over a soak, with how much it grew by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Leak {
+    OpenFds(u64),
+    Threads(u64),
+    Tasks(u64),
+}
+
+/// Results of a [`Soak`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SoakReport {
+    /// Resource usage before the first cycle.
+    pub baseline: ResourceCounters,
+
+    /// Every cycle, in the order they ran.
+    pub iterations: Vec<SoakIteration>,
+}
+
+impl SoakReport {
+    /// Number of cycles that failed.
+    pub fn failures(&self) -> usize {
+        self.iterations
+            .iter()
+            .filter(|iteration| iteration.error.is_some())
+            .count()
+    }
+
+    /// Counters higher after the last cycle than after the first. The first cycle is the
+    /// reference rather than the baseline, as runtimes and connection pools set themselves up
+    /// during it. Resident memory is left out, as allocators hold on to freed memory.
+    pub fn leaks(&self) -> Vec<Leak> {
+        let (Some(first), Some(last)) = (self.iterations.first(), self.iterations.last()) else {
+            return Vec::new();
+        };
+        let (first, last) = (first.counters, last.counters);
+        let grown = |first: Option<u64>, last: Option<u64>| {
+            first
+                .zip(last)
+                .map(|(first, last)| last.saturating_sub(first))
+                .filter(|growth| *growth > 0)
+        };
+        [
+            grown(first.open_fds, last.open_fds).map(Leak::OpenFds),
+            grown(first.threads, last.threads).map(Leak::Threads),
+            grown(first.tasks, last.tasks).map(Leak::Tasks),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Runs `iterations` tests one after the other, each with a fresh [`Controller`], as described
+/// by a [`ControllerConfig`]. A failed cycle is recorded and the soak carries on.
+pub struct Soak {
+    config: ControllerConfig,
+    iterations: u32,
+    make_controller: Box<dyn FnMut() -> Controller + Send>,
+    task_probe: Option<TaskProbe>,
+}
+
+impl Soak {
+    pub fn new(config: ControllerConfig, iterations: u32) -> Self {
+        Soak {
+            config,
+            iterations,
+            make_controller: Box::new(Controller::new),
+            task_probe: None,
+        }
+    }
+
+    /// Build the Controller of each cycle with `make_controller` rather than
+    /// [`Controller::new`].
+    pub fn with_controller(
+        mut self,
+        make_controller: impl FnMut() -> Controller + Send + 'static,
+    ) -> Self {
+        self.make_controller = Box::new(make_controller);
+        self
+    }
+
+    /// Record the tasks counted by `task_probe` after each cycle.
+    pub fn with_task_probe(mut self, task_probe: TaskProbe) -> Self {
+        self.task_probe = Some(task_probe);
+        self
+    }
+
+    pub async fn run(mut self) -> SoakReport {
+        let config = self.config;
+        let mut report = SoakReport {
+            baseline: ResourceCounters::sample(self.task_probe.as_ref()),
+            iterations: Vec::with_capacity(self.iterations as usize),
+        };
+        for iteration in 0..self.iterations {
+            let result = (self.make_controller)()
+                .do_twamp(
+                    config.responder_addr,
+                    config.responder_port,
+                    config.controller_addr,
+                    config.controller_port,
+                    config.responder_reflect_port,
+                    config.number_of_test_packets,
+                    config.reflector_timeout,
+                    config.stop_policy,
+                )
+                .await;
+            let counters = ResourceCounters::sample(self.task_probe.as_ref());
+            let (sent, received, error) = match result {
+                Ok(test) => (test.sent, test.received, None),
+                Err(e) => {
+                    warn!(target: TRACING_TARGET, iteration, "Soak cycle failed: {}", e);
+                    (0, 0, Some(e.to_string()))
+                }
+            };
+            debug!(
+                target: TRACING_TARGET,
+                iteration,
+                sent,
+                received,
+                open_fds = counters.open_fds,
+                threads = counters.threads,
+                rss_bytes = counters.rss_bytes,
+                tasks = counters.tasks,
+                "Soak cycle ended"
+            );
+            report.iterations.push(SoakIteration {
+                sent,
+                received,
+                error,
+                counters,
+            });
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{controller::SessionTimeout, responder::ResponderConfig};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use twamp_control::timers::Refwait;
+
+    fn iteration(open_fds: u64, threads: u64, tasks: u64) -> SoakIteration {
+        SoakIteration {
+            sent: 1,
+            received: 1,
+            error: None,
+            counters: ResourceCounters {
+                open_fds: Some(open_fds),
+                threads: Some(threads),
+                rss_bytes: Some(1 << 20),
+                tasks: Some(tasks),
+            },
+        }
+    }
+
+    #[test]
+    fn leaks_compare_last_cycle_to_first() {
+        let report = SoakReport {
+            baseline: ResourceCounters::default(),
+            iterations: vec![iteration(10, 4, 2), iteration(9, 4, 3), iteration(12, 4, 2)],
+        };
+        assert_eq!(report.leaks(), vec![Leak::OpenFds(2)]);
+        assert!(SoakReport::default().leaks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn soak_runs_every_cycle() {
+        let responder = ResponderConfig::new("127.0.0.1:0".parse().unwrap());
+        let listener = responder.bind().await.unwrap();
+        let responder_port = listener.local_addr().unwrap().port();
+        twamp_runtime::task::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                twamp_runtime::task::spawn(
+                    crate::responder::Responder::new(socket)
+                        .handle_controller(Refwait::new(1).unwrap()),
+                );
+            }
+        });
+
+        let mut config = ControllerConfig::new("127.0.0.1".parse().unwrap());
+        config.responder_port = responder_port;
+        config.responder_reflect_port = 0;
+        config.number_of_test_packets = 2;
+        config.reflector_timeout = SessionTimeout::new(1).unwrap();
+        let probed = Arc::new(AtomicUsize::new(0));
+        let probe_count = Arc::clone(&probed);
+        let report = Soak::new(config, 3)
+            .with_task_probe(Arc::new(move || {
+                probe_count.fetch_add(1, Ordering::Relaxed)
+            }))
+            .run()
+            .await;
+        assert_eq!(report.iterations.len(), 3);
+        assert_eq!(report.failures(), 0);
+        assert!(report.iterations.iter().all(|i| i.received == 2));
+        assert_eq!(probed.load(Ordering::Relaxed), 4);
+        #[cfg(target_os = "linux")]
+        assert!(report.baseline.open_fds.is_some());
+    }
+}
@@ -0,0 +1,237 @@
+//! Write Responder counters for the
+//! [node_exporter textfile collector](https://github.com/prometheus/node_exporter#textfile-collector),
+//! for Responders that should not run an HTTP server of their own.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use session_reflector::accounting::Accounting;
+use tracing::*;
+use twamp_runtime::{
+    task::{spawn, spawn_blocking, JoinHandle},
+    time::interval,
+};
+use twamp_test::ecn::EcnCounts;
+
+/// Counters of every connection tracked so far.
+#[derive(Debug, Default)]
+struct Totals {
+    /// Connections still being served.
+    live: Vec<Arc<Accounting>>,
+    /// Sums over connections that have ended.
+    reflected: u64,
+    dropped: u64,
+    ecn: EcnCounts,
+}
+
+impl Totals {
+    /// Fold the connections nobody else holds on to any more into the sums.
+    fn retire_ended(&mut self) {
+        let (ended, live) = self
+            .live
+            .drain(..)
+            .partition(|accounting| Arc::strong_count(accounting) == 1);
+        self.live = live;
+        for accounting in ended {
+            self.reflected += accounting.packets_reflected();
+            self.dropped += accounting.packets_dropped();
+            add_ecn(&mut self.ecn, &accounting.received_ecn());
+        }
+    }
+
+    /// Render in the Prometheus text exposition format.
+    fn to_text(&self) -> String {
+        let mut reflected = self.reflected;
+        let mut dropped = self.dropped;
+        let mut ecn = self.ecn;
+        for accounting in &self.live {
+            reflected += accounting.packets_reflected();
+            dropped += accounting.packets_dropped();
+            add_ecn(&mut ecn, &accounting.received_ecn());
+        }
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, value: u64| {
+            let _ = writeln!(text, "# TYPE {name} {kind}\n{name} {value}");
+        };
+        metric(
+            "twamp_reflector_connections",
+            "gauge",
+            self.live.len() as u64,
+        );
+        metric(
+            "twamp_reflector_packets_reflected_total",
+            "counter",
+            reflected,
+        );
+        metric("twamp_reflector_packets_dropped_total", "counter", dropped);
+        let name = "twamp_reflector_packets_received_ecn_total";
+        let _ = writeln!(text, "# TYPE {name} counter");
+        for (codepoint, count) in [
+            ("not_ect", ecn.not_ect),
+            ("ect1", ecn.ect1),
+            ("ect0", ecn.ect0),
+            ("ce", ecn.ce),
+            ("unknown", ecn.unknown),
+        ] {
+            let _ = writeln!(text, "{name}{{codepoint=\"{codepoint}\"}} {count}");
+        }
+        let name = "twamp_reflector_session_queue_delay_max_seconds";
+        let _ = writeln!(text, "# TYPE {name} gauge");
+        for session in self
+            .live
+            .iter()
+            .flat_map(|accounting| accounting.sessions())
+        {
+            let _ = writeln!(
+                text,
+                "{name}{{peer=\"{}\"}} {}",
+                session.peer(),
+                session.max_queue_delay().as_secs_f64()
+            );
+        }
+        let name = "twamp_reflector_reflected_per_second";
+        let reflected_per_second = self
+            .live
+            .iter()
+            .map(|accounting| accounting.runtime_stats().reflected_per_second)
+            .fold(0.0, |total, rate| total + rate);
+        let _ = writeln!(text, "# TYPE {name} gauge\n{name} {reflected_per_second}");
+        text
+    }
+}
+
+fn add_ecn(total: &mut EcnCounts, counts: &EcnCounts) {
+    total.not_ect += counts.not_ect;
+    total.ect1 += counts.ect1;
+    total.ect0 += counts.ect0;
+    total.ce += counts.ce;
+    total.unknown += counts.unknown;
+}
+
+/// Sums the [`Accounting`] of every tracked Responder and writes it to `path`, which should end
+/// in `.prom` and sit in the directory passed to node_exporter's
+/// `--collector.textfile.directory`.
+///
+/// ```no_run
+/// # async fn run(socket: twamp_runtime::net::TcpStream) -> anyhow::Result<()> {
+/// use twamp_control::timers::Refwait;
+/// use twamp_rs::{responder::Responder, textfile::TextfileExporter};
+///
+/// let exporter = TextfileExporter::new("/var/lib/node_exporter/twamp.prom");
+/// let writing = exporter.spawn();
+/// let responder = Responder::new(socket);
+/// exporter.track(responder.accounting());
+/// responder.handle_controller(Refwait::DEFAULT).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TextfileExporter {
+    path: PathBuf,
+    interval: Duration,
+    totals: Arc<Mutex<Totals>>,
+}
+
+impl TextfileExporter {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        TextfileExporter {
+            path: path.into(),
+            interval: Duration::from_secs(15),
+            totals: Arc::default(),
+        }
+    }
+
+    /// Write every `interval` once [spawned](Self::spawn), instead of every 15 seconds.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Include the counters of a connection, e.g. from [`Responder::accounting`]. They keep
+    /// counting towards the totals after it ends.
+    ///
+    /// [`Responder::accounting`]: crate::responder::Responder::accounting
+    pub fn track(&self, accounting: Arc<Accounting>) {
+        self.totals.lock().unwrap().live.push(accounting);
+    }
+
+    /// Write the current counters once. They are written to a temporary file next to the
+    /// target first and renamed over it, so node_exporter never reads a partial file.
+    pub async fn write(&self) -> Result<()> {
+        let text = {
+            let mut totals = self.totals.lock().unwrap();
+            totals.retire_ended();
+            totals.to_text()
+        };
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let path = self.path.clone();
+        spawn_blocking(move || {
+            fs::write(&temporary, text)?;
+            fs::rename(&temporary, path)
+        })
+        .await??;
+        Ok(())
+    }
+
+    /// Write the counters every interval until the returned task is aborted. Failed writes
+    /// are logged and retried on the next interval.
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let exporter = self.clone();
+        spawn(async move {
+            let mut ticks = interval(exporter.interval);
+            loop {
+                ticks.tick().await;
+                if let Err(e) = exporter.write().await {
+                    warn!(
+                        "Failed to write metrics to {}: {}",
+                        exporter.path.display(),
+                        e
+                    );
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ended_connections_keep_counting() {
+        let mut totals = Totals::default();
+        let accounting = Arc::new(Accounting::default());
+        totals.live.push(Arc::clone(&accounting));
+        totals.retire_ended();
+        assert_eq!(totals.live.len(), 1);
+        drop(accounting);
+        totals.retire_ended();
+        assert!(totals.live.is_empty());
+        totals.reflected = 3;
+        let text = totals.to_text();
+        assert!(text.contains("twamp_reflector_connections 0\n"));
+        assert!(text.contains("twamp_reflector_packets_reflected_total 3\n"));
+        assert!(text.contains("twamp_reflector_packets_received_ecn_total{codepoint=\"ce\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn write_replaces_file() {
+        let path = std::env::temp_dir().join(format!("twamp-{}.prom", std::process::id()));
+        let exporter = TextfileExporter::new(&path);
+        exporter.track(Arc::default());
+        exporter.write().await.unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(text.starts_with("# TYPE twamp_reflector_connections gauge\n"));
+        assert!(text.contains("twamp_reflector_packets_dropped_total 0\n"));
+        assert!(text.contains("# TYPE twamp_reflector_session_queue_delay_max_seconds gauge\n"));
+        assert!(text.contains("twamp_reflector_reflected_per_second 0\n"));
+    }
+}
@@ -0,0 +1,473 @@
+//! Full Controller to Responder runs over loopback, through real TCP and UDP sockets, across
+//! modes and options. Built with the `integration` feature:
+//!
+//! ```sh
+//! cargo test --features integration --test end_to_end
+//! ```
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use deku::{DekuContainerRead, DekuContainerWrite};
+
+use server::config::ServerConfig;
+use session_reflector::config::{Pacing, ReflectorConfig};
+use session_sender::PacketProfile;
+use timestamp::timestamp::TimestampFormat;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::watch,
+};
+use twamp_control::{
+    auth::SharedSecret, request_tw_session::RequestTwSession, security_mode::Mode,
+    set_up_response::SetUpResponse, start_sessions::StartSessions, timers::Refwait,
+};
+use twamp_rs::{
+    agent::{Agent, AgentConfig},
+    controller::{Controller, ControllerConfig, SessionTimeout, StopPolicy},
+    report::{TerminationReason, TestReport},
+    responder::{Responder, ResponderConfig},
+};
+use twamp_runtime::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::spawn,
+    time::{sleep, timeout},
+};
+use twamp_test::stamp::{StampTestPacket, StampTestPacketReflected};
+
+/// Longest any run in this suite may take before it is taken to hang.
+const RUN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A Responder serving on loopback in the background, configured by `configure`.
+async fn responder(addr: IpAddr, configure: impl FnOnce(&mut ResponderConfig)) -> Agent {
+    let mut config = AgentConfig::new(SocketAddr::new(addr, 0));
+    configure(&mut config.responder);
+    Agent::start(config).await.unwrap()
+}
+
+/// Send `packets` test packets to `agent` with `controller`, stopping once all are reflected.
+async fn run(agent: &Agent, controller: Controller, packets: u32) -> TestReport {
+    let responder_addr = agent.responder_addr();
+    let run = controller.do_twamp(
+        responder_addr.ip(),
+        responder_addr.port(),
+        responder_addr.ip(),
+        0,
+        0,
+        packets,
+        SessionTimeout::new(1).unwrap(),
+        StopPolicy::AfterTimeout(Duration::from_secs(2)),
+    );
+    timeout(RUN_TIMEOUT, run).await.unwrap().unwrap()
+}
+
+fn assert_all_reflected(report: &TestReport, packets: u32) {
+    assert_eq!(report.sent, packets);
+    assert_eq!(report.received, packets, "{:?}", report);
+    assert_eq!(report.termination, TerminationReason::Completed);
+}
+
+#[tokio::test]
+async fn every_mode_reflects_all_packets() {
+    let shared_secret = SharedSecret::new("probe", "secret").unwrap();
+    let agent = responder(Ipv4Addr::LOCALHOST.into(), |config| {
+        config.server = ServerConfig {
+            secrets: Some(Arc::new(shared_secret.clone())),
+            ..Default::default()
+        };
+    })
+    .await;
+    let modes = [
+        (Mode::Unauthenticated, agent.controller()),
+        (
+            Mode::Authenticated,
+            agent.controller().with_shared_secret(shared_secret.clone()),
+        ),
+        (
+            Mode::Encrypted,
+            agent
+                .controller()
+                .with_shared_secret(shared_secret.clone())
+                .with_encryption(),
+        ),
+    ];
+    for (mode, controller) in modes {
+        let report = run(&agent, controller, 5).await;
+        assert_all_reflected(&report, 5);
+        assert_eq!(report.control.mode, mode);
+    }
+}
+
+#[tokio::test]
+async fn padding_sizes_are_reflected() {
+    let agent = responder(Ipv4Addr::LOCALHOST.into(), |_| ()).await;
+    for padding_length in [27, 100, 512, 1400, 4000] {
+        let profile = PacketProfile {
+            padding_length,
+            ..PacketProfile::new(5)
+        };
+        let controller = agent.controller().with_profiles(vec![profile]);
+        let report = run(&agent, controller, 5).await;
+        assert_all_reflected(&report, 5);
+        assert_eq!(
+            report.parameters.sent_padding,
+            profile.sent_padding_length(Ipv4Addr::LOCALHOST.into())
+        );
+        assert_eq!(
+            report.parameters.requested_padding,
+            report.parameters.sent_padding
+        );
+    }
+}
+
+#[tokio::test]
+async fn padding_beyond_ethernet_mtu_needs_server_support() {
+    let profile = PacketProfile {
+        padding_length: 4000,
+        mtu: 9000,
+        ..PacketProfile::new(5)
+    };
+    let agent = responder(Ipv4Addr::LOCALHOST.into(), |_| ()).await;
+    let responder_addr = agent.responder_addr();
+    let refused = agent.controller().with_profiles(vec![profile]).do_twamp(
+        responder_addr.ip(),
+        responder_addr.port(),
+        responder_addr.ip(),
+        0,
+        0,
+        5,
+        SessionTimeout::new(1).unwrap(),
+        StopPolicy::Immediate,
+    );
+    assert!(timeout(RUN_TIMEOUT, refused).await.unwrap().is_err());
+
+    let agent = responder(Ipv4Addr::LOCALHOST.into(), |config| {
+        config.server = ServerConfig {
+            max_padding_length: 4000,
+            ..Default::default()
+        };
+    })
+    .await;
+    let controller = agent.controller().with_profiles(vec![profile]);
+    let report = run(&agent, controller, 5).await;
+    assert_all_reflected(&report, 5);
+    assert_eq!(report.parameters.sent_padding, 4000);
+}
+
+#[tokio::test]
+async fn test_packets_shorter_than_their_reflections_need_uncapped_reflectors() {
+    let profile = PacketProfile {
+        padding_length: 0,
+        ..PacketProfile::new(5)
+    };
+    for cap_to_request_size in [true, false] {
+        let agent = responder(Ipv4Addr::LOCALHOST.into(), |config| {
+            config.reflector = ReflectorConfig {
+                cap_to_request_size,
+                ..Default::default()
+            };
+        })
+        .await;
+        let controller = agent.controller().with_profiles(vec![profile]);
+        let report = run(&agent, controller, 5).await;
+        assert_eq!(report.sent, 5);
+        let expected = if cap_to_request_size { 0 } else { 5 };
+        assert_eq!(report.received, expected, "{:?}", report);
+    }
+}
+
+#[tokio::test]
+async fn paced_profiles_keep_their_intervals() {
+    let agent = responder(Ipv4Addr::LOCALHOST.into(), |_| ()).await;
+    let profiles = vec![
+        PacketProfile {
+            interval: Duration::from_millis(5),
+            ..PacketProfile::new(10)
+        },
+        PacketProfile {
+            interval: Duration::from_millis(20),
+            ..PacketProfile::new(5)
+        },
+    ];
+    let controller = agent
+        .controller()
+        .with_profiles(profiles)
+        .with_pacing_calibration();
+    let report = run(&agent, controller, 15).await;
+    assert_all_reflected(&report, 15);
+    assert_eq!(report.pacing.len(), 2);
+    for (pacing, requested) in report.pacing.iter().zip([0.005, 0.020]) {
+        assert_eq!(pacing.requested, requested);
+        assert!(pacing.mean >= requested / 2.0, "{:?}", pacing);
+    }
+}
+
+#[tokio::test]
+async fn reflector_pacing_holds_reflected_packets() {
+    let dwell = Duration::from_millis(20);
+    let agent = responder(Ipv4Addr::LOCALHOST.into(), |config| {
+        config.reflector = ReflectorConfig {
+            pacing: Pacing::FixedDwell(dwell),
+            ..Default::default()
+        };
+    })
+    .await;
+    let started = Instant::now();
+    let report = run(&agent, agent.controller(), 5).await;
+    assert_all_reflected(&report, 5);
+    assert!(started.elapsed() >= dwell);
+    // Time spent within the Session-Reflector is not part of the round trip.
+    assert!(report.rtt_max < dwell.as_secs_f64(), "{:?}", report);
+}
+
+#[tokio::test]
+async fn ntp_and_ptp_timestamps_mix() {
+    let formats = [TimestampFormat::Ntp, TimestampFormat::Ptp];
+    for reflector_format in formats {
+        let agent = responder(Ipv4Addr::LOCALHOST.into(), |config| {
+            config.reflector.timestamp_format = reflector_format;
+        })
+        .await;
+        for sender_format in formats {
+            let controller = agent.controller().with_timestamp_format(sender_format);
+            let report = run(&agent, controller, 5).await;
+            assert_all_reflected(&report, 5);
+            // Timestamps read in the wrong format would be 70 years apart.
+            assert!(report.owd_forward_avg.abs() < 1.0, "{:?}", report);
+        }
+    }
+}
+
+#[tokio::test]
+async fn concurrent_sessions_share_a_responder() {
+    let agent = Arc::new(responder(Ipv4Addr::LOCALHOST.into(), |_| ()).await);
+    let sessions: Vec<_> = (0..4)
+        .map(|_| {
+            let agent = Arc::clone(&agent);
+            spawn(async move { run(&agent, agent.controller(), 20).await })
+        })
+        .collect();
+    let mut sender_ports = Vec::new();
+    for session in sessions {
+        let report = session.await.unwrap();
+        assert_all_reflected(&report, 20);
+        sender_ports.push(report.parameters.sender_port);
+    }
+    sender_ports.sort();
+    sender_ports.dedup();
+    assert_eq!(sender_ports.len(), 4);
+}
+
+#[tokio::test]
+async fn ipv6_loopback() {
+    if std::net::UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).is_err() {
+        // No IPv6 on this host.
+        return;
+    }
+    let agent = responder(Ipv6Addr::LOCALHOST.into(), |_| ()).await;
+    let report = run(&agent, agent.controller(), 5).await;
+    assert_all_reflected(&report, 5);
+}
+
+#[tokio::test]
+async fn max_test_duration_truncates_the_run() {
+    let agent = responder(Ipv4Addr::LOCALHOST.into(), |_| ()).await;
+    let profile = PacketProfile {
+        interval: Duration::from_millis(50),
+        ..PacketProfile::new(1000)
+    };
+    let controller = agent
+        .controller()
+        .with_profiles(vec![profile])
+        .with_max_test_duration(Duration::from_millis(500));
+    let report = run(&agent, controller, 1000).await;
+    assert_eq!(report.termination, TerminationReason::Truncated);
+    assert!(report.sent < 1000);
+}
+
+#[tokio::test]
+async fn responder_shutdown_cancels_its_sessions() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let responder_addr = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let responding = spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        Responder::new(socket)
+            .with_shutdown(shutdown_rx)
+            .handle_controller(Refwait::DEFAULT)
+            .await
+    });
+    let profile = PacketProfile {
+        interval: Duration::from_millis(20),
+        ..PacketProfile::new(100)
+    };
+    let testing = spawn(Controller::new().with_profiles(vec![profile]).do_twamp(
+        responder_addr.ip(),
+        responder_addr.port(),
+        responder_addr.ip(),
+        0,
+        0,
+        100,
+        SessionTimeout::new(1).unwrap(),
+        StopPolicy::Immediate,
+    ));
+    sleep(Duration::from_millis(300)).await;
+    shutdown_tx.send(true).unwrap();
+    let summary = timeout(RUN_TIMEOUT, responding)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(summary.reason, TerminationReason::Cancelled);
+    assert!(summary.reflected > 0);
+    // The Controller finishes however it takes the session ending under it.
+    let _ = timeout(RUN_TIMEOUT, testing).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn controller_going_away_ends_the_session() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let responding = spawn(Responder::new(socket).handle_controller(Refwait::DEFAULT));
+
+    let mut greeting = [0u8; 64];
+    client.read_exact(&mut greeting).await.unwrap();
+    let mut segment = SetUpResponse::new(Mode::Unauthenticated)
+        .unwrap()
+        .to_bytes()
+        .unwrap();
+    segment.extend(
+        RequestTwSession::new(
+            Ipv4Addr::LOCALHOST,
+            1,
+            Ipv4Addr::LOCALHOST,
+            0,
+            None,
+            SessionTimeout::DEFAULT,
+        )
+        .to_bytes()
+        .unwrap(),
+    );
+    segment.extend(StartSessions::new().to_bytes().unwrap());
+    client.write_all(&segment).await.unwrap();
+    // Server-Start, Accept-Session and Start-Ack.
+    let mut replies = [0u8; 48 + 48 + 32];
+    client.read_exact(&mut replies).await.unwrap();
+    drop(client);
+
+    let summary = timeout(RUN_TIMEOUT, responding)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(summary.reason, TerminationReason::ControlLost);
+}
+
+#[tokio::test]
+async fn unreachable_responder_fails_without_hanging() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let responder_addr = listener.local_addr().unwrap();
+    drop(listener);
+    assert!(TcpStream::connect(responder_addr).await.is_err());
+    let run = Controller::new().do_twamp(
+        responder_addr.ip(),
+        responder_addr.port(),
+        responder_addr.ip(),
+        0,
+        0,
+        5,
+        SessionTimeout::new(1).unwrap(),
+        StopPolicy::Immediate,
+    );
+    assert!(timeout(RUN_TIMEOUT, run).await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn stamp_and_twamp_share_a_responder() {
+    // Ports just freed by the OS, to hand to `serve`.
+    let control_port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let stamp_addr = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut config =
+        ResponderConfig::new(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), control_port));
+    config.stamp_addr = Some(stamp_addr);
+    spawn(twamp_rs::serve(config));
+    sleep(Duration::from_millis(100)).await;
+
+    let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    sender.connect(stamp_addr).await.unwrap();
+    for seq in 0..3 {
+        let pkt = StampTestPacket::new(seq, true).to_bytes().unwrap();
+        sender.send(&pkt).await.unwrap();
+        let mut buf = [0u8; 1472];
+        let len = timeout(RUN_TIMEOUT, sender.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(len, StampTestPacket::LENGTH);
+        let (_rest, reflected) = StampTestPacketReflected::from_bytes((&buf[..len], 0)).unwrap();
+        assert_eq!(reflected.sender_sequence_number, seq);
+    }
+
+    let mut controller_config = ControllerConfig::new(Ipv4Addr::LOCALHOST.into());
+    controller_config.responder_port = control_port;
+    controller_config.responder_reflect_port = 0;
+    let packets = controller_config.number_of_test_packets;
+    let report = timeout(RUN_TIMEOUT, twamp_rs::run(controller_config))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_all_reflected(&report, packets);
+}
+
+#[tokio::test]
+async fn light_reflector_serves_light_senders() {
+    // Ports just freed by the OS, to hand to `serve`.
+    let control_port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let light_addr = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut config =
+        ResponderConfig::new(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), control_port));
+    config.light_addr = Some(light_addr);
+    spawn(twamp_rs::serve(config));
+    sleep(Duration::from_millis(100)).await;
+
+    // Concurrent sessions, told apart by their sender ports.
+    let sessions: Vec<_> = (0..2)
+        .map(|_| {
+            spawn(Controller::new().do_light(
+                light_addr,
+                Ipv4Addr::LOCALHOST.into(),
+                0,
+                10,
+                StopPolicy::AfterTimeout(Duration::from_secs(2)),
+            ))
+        })
+        .collect();
+    for session in sessions {
+        let report = timeout(RUN_TIMEOUT, session).await.unwrap().unwrap();
+        assert_all_reflected(&report.unwrap(), 10);
+    }
+}